@@ -0,0 +1,45 @@
+/* C API for simple_gc. Link against the library built by
+ * `cargo rustc --lib --release --crate-type staticlib` (or `cdylib`).
+ * See src/ffi.rs for the ownership rules. */
+
+#ifndef BABYGC_H
+#define BABYGC_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define BABYGC_OK              0
+#define BABYGC_OUT_OF_MEMORY   1
+#define BABYGC_GC_STARVED      2
+#define BABYGC_QUOTA_EXCEEDED  3
+#define BABYGC_STACK_UNDERFLOW 4
+#define BABYGC_TYPE_ERROR      5
+#define BABYGC_CANCELLED       6
+#define BABYGC_FREED           7
+#define BABYGC_OVERFLOW        8
+
+typedef struct BabygcVm BabygcVm;
+typedef struct BabygcHandle BabygcHandle;
+
+BabygcVm *babygc_vm_new(void);
+void babygc_vm_free(BabygcVm *vm);
+int32_t babygc_vm_last_error(const BabygcVm *vm);
+
+/* These return a new handle owned by the caller, or NULL on failure. */
+BabygcHandle *babygc_push_int(BabygcVm *vm, uint32_t val);
+BabygcHandle *babygc_push_pair(BabygcVm *vm);
+BabygcHandle *babygc_pop(BabygcVm *vm);
+
+size_t babygc_gc(BabygcVm *vm);
+size_t babygc_heap_len(const BabygcVm *vm);
+size_t babygc_object_size(void);
+
+void babygc_handle_release(BabygcHandle *h);
+BabygcHandle *babygc_handle_clone(const BabygcHandle *h);
+bool babygc_handle_is_pair(const BabygcHandle *h);
+int32_t babygc_handle_int(const BabygcHandle *h, uint32_t *out);
+BabygcHandle *babygc_handle_head(const BabygcHandle *h);
+BabygcHandle *babygc_handle_tail(const BabygcHandle *h);
+
+#endif
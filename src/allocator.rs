@@ -0,0 +1,40 @@
+// Allocation hooks: the VM asks an `ObjectAllocator` before creating each
+// object and tells it about each one it frees, and about each change to
+// the heap's capacity: the objects it may hold before the next full
+// collection, in bytes.
+//
+// Objects are still `Rc`s from the global allocator. Stable Rust can't put
+// an `Rc` in memory the caller provides, so an arena or fixed buffer is
+// modelled as a budget the allocator enforces, rather than real backing
+// storage.
+
+use core::fmt;
+
+/// Called from inside allocation and collection, so its methods get no VM
+/// to allocate with or collect.
+pub trait ObjectAllocator: fmt::Debug {
+  /// Whether another object of `bytes` bytes may be allocated. Refusing
+  /// runs a full collection and asks again, then fails the allocation with
+  /// `VmError::OutOfMemory`.
+  fn allocate(&self, bytes: usize) -> bool;
+
+  /// An object of `bytes` bytes was freed by the collector.
+  fn free(&self, _bytes: usize) {}
+
+  /// A full collection is about to change the heap's capacity from
+  /// `old_bytes` to `new_bytes`. Refusing growth keeps the old capacity,
+  /// so full collections come sooner; shrinking can't be refused.
+  fn resize(&self, _old_bytes: usize, _new_bytes: usize) -> bool {
+    true
+  }
+}
+
+/// The default: always allocates, straight from the global allocator.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GlobalAllocator;
+
+impl ObjectAllocator for GlobalAllocator {
+  fn allocate(&self, _bytes: usize) -> bool {
+    true
+  }
+}
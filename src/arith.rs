@@ -0,0 +1,180 @@
+// Integer arithmetic on the stack. Ints are u32s, and what happens when a
+// result doesn't fit is the VM's choice, set with `VMConfig::overflow`,
+// rather than whatever the host was compiled with:
+//
+//   VM::with_config(VMConfig::new().overflow(OverflowPolicy::Saturate))
+//
+// Results are computed exactly first, so every policy sees the true value.
+
+use core::str::FromStr;
+
+use {Sobject, VM, VmError};
+
+/// What `push_add`, `push_sub` and `push_mul` do with a result that
+/// doesn't fit in an int.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+  /// Wrap around, modulo 2^32.
+  Wrap,
+  /// Stick at 0 or `u32::MAX`.
+  Saturate,
+  /// Push the exact result as a pair of ints, high half first: sums and
+  /// products read back with `extract::<u64>`, differences with
+  /// `extract::<i64>`.
+  Promote,
+  /// Fail with `VmError::Overflow`, leaving the operands on the stack.
+  Error
+}
+
+impl OverflowPolicy {
+  pub fn name(&self) -> &'static str {
+    match *self {
+      OverflowPolicy::Wrap => "wrap",
+      OverflowPolicy::Saturate => "saturate",
+      OverflowPolicy::Promote => "promote",
+      OverflowPolicy::Error => "error"
+    }
+  }
+}
+
+impl FromStr for OverflowPolicy {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<OverflowPolicy, ()> {
+    match s {
+      "wrap" => Ok(OverflowPolicy::Wrap),
+      "saturate" => Ok(OverflowPolicy::Saturate),
+      "promote" => Ok(OverflowPolicy::Promote),
+      "error" => Ok(OverflowPolicy::Error),
+      _ => Err(())
+    }
+  }
+}
+
+impl VM {
+  /// Replaces the top two stack slots, both ints, with their sum. Fails
+  /// with `VmError::StackUnderflow` if there are fewer than two, or
+  /// `VmError::Type` if either isn't an int; on any failure the operands
+  /// are left in place.
+  pub fn push_add(&mut self) -> Result<Sobject, VmError> {
+    self.arith(|a, b| a + b)
+  }
+
+  /// Like `push_add`, subtracting the top slot from the one below it.
+  pub fn push_sub(&mut self) -> Result<Sobject, VmError> {
+    self.arith(|a, b| a - b)
+  }
+
+  /// Like `push_add`, for the product.
+  pub fn push_mul(&mut self) -> Result<Sobject, VmError> {
+    self.arith(|a, b| a * b)
+  }
+
+  fn arith(&mut self, op: fn(i128, i128) -> i128) -> Result<Sobject, VmError> {
+    let n = self.stack.len();
+    if n < 2 {
+      return Err(VmError::StackUnderflow);
+    }
+
+    let a = self.as_int(&self.stack[n - 2]).map_err(VmError::Type)?;
+    let b = self.as_int(&self.stack[n - 1]).map_err(VmError::Type)?;
+    let exact = op(a.into(), b.into());
+
+    let fits = exact >= 0 && exact <= u32::MAX.into();
+    if !fits && self.config.overflow == OverflowPolicy::Error {
+      return Err(VmError::Overflow);
+    }
+
+    // The operands stay rooted until the result is on the stack above them.
+    let result = match self.config.overflow {
+      _ if fits => self.push_int(exact as u32),
+      OverflowPolicy::Wrap => self.push_int(exact as u32),
+      OverflowPolicy::Saturate => self.push_int(if exact < 0 { 0 } else { u32::MAX }),
+      OverflowPolicy::Promote if exact < 0 => self.push_value(&(exact as i64)),
+      OverflowPolicy::Promote | OverflowPolicy::Error => self.push_value(&(exact as u64))
+    };
+    if result.is_ok() {
+      self.stack.drain(n - 2..n);
+    }
+    result
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {TypeError, VMConfig};
+
+  fn run(policy: OverflowPolicy, a: u32, b: u32, op: fn(&mut VM) -> Result<Sobject, VmError>) -> (VM, Result<Sobject, VmError>) {
+    let mut vm = VM::with_config(VMConfig::new().overflow(policy));
+    vm.push_int(a).unwrap();
+    vm.push_int(b).unwrap();
+    let result = op(&mut vm);
+    (vm, result)
+  }
+
+  #[test]
+  fn results_that_fit() {
+    println!("Results in range are the same under every policy.");
+
+    for policy in [OverflowPolicy::Wrap, OverflowPolicy::Saturate, OverflowPolicy::Promote, OverflowPolicy::Error] {
+      for &(op, want) in &[(VM::push_add as fn(&mut VM) -> _, 10), (VM::push_sub, 4), (VM::push_mul, 21)] {
+        let (vm, result) = run(policy, 7, 3, op);
+        assert!(vm.as_int(&result.unwrap()) == Ok(want));
+        assert!(vm.stack.len() == 1);
+      }
+    }
+  }
+
+  #[test]
+  fn wrap_and_saturate() {
+    println!("Wrapping goes round modulo 2^32; saturating sticks at the ends.");
+
+    let (vm, r) = run(OverflowPolicy::Wrap, u32::MAX, 2, VM::push_add);
+    assert!(vm.as_int(&r.unwrap()) == Ok(1));
+    let (vm, r) = run(OverflowPolicy::Wrap, 1, 2, VM::push_sub);
+    assert!(vm.as_int(&r.unwrap()) == Ok(u32::MAX));
+
+    let (vm, r) = run(OverflowPolicy::Saturate, u32::MAX, 2, VM::push_mul);
+    assert!(vm.as_int(&r.unwrap()) == Ok(u32::MAX));
+    let (vm, r) = run(OverflowPolicy::Saturate, 1, 2, VM::push_sub);
+    assert!(vm.as_int(&r.unwrap()) == Ok(0));
+  }
+
+  #[test]
+  fn promote_keeps_the_exact_value() {
+    println!("Promoting pushes the whole result as a pair.");
+
+    let (vm, r) = run(OverflowPolicy::Promote, u32::MAX, u32::MAX, VM::push_mul);
+    assert!(vm.extract::<u64>(&r.unwrap()) == Ok(u64::from(u32::MAX) * u64::from(u32::MAX)));
+    let (vm, r) = run(OverflowPolicy::Promote, 1, u32::MAX, VM::push_sub);
+    assert!(vm.extract::<i64>(&r.unwrap()) == Ok(1 - i64::from(u32::MAX)));
+    assert!(vm.stack.len() == 1);
+  }
+
+  #[test]
+  fn errors_leave_the_operands() {
+    println!("Overflow, type errors and underflow leave the stack as it was.");
+
+    let (vm, r) = run(OverflowPolicy::Error, u32::MAX, 1, VM::push_add);
+    assert!(r.unwrap_err() == VmError::Overflow && vm.stack.len() == 2);
+
+    let mut vm = VM::new();
+    vm.push_value(&(1u32, 2u32)).unwrap();
+    assert!(vm.push_add().unwrap_err() == VmError::StackUnderflow);
+    vm.push_int(3).unwrap();
+    assert!(vm.push_add().unwrap_err() == VmError::Type(TypeError { expected: "int", found: "pair" }));
+    assert!(vm.stack.len() == 2);
+
+    // Promoting needs three objects; there's room for one.
+    let mut vm = VM::with_config(VMConfig::new().overflow(OverflowPolicy::Promote).max_heap(3));
+    vm.push_int(u32::MAX).unwrap();
+    vm.push_int(2).unwrap();
+    assert!(vm.push_mul().unwrap_err() == VmError::OutOfMemory && vm.stack.len() == 2);
+  }
+}
@@ -0,0 +1,99 @@
+// Box-drawing pictures of the stack and heap, for test output and plain
+// terminals:
+//
+//   ┌─ stack ─┐
+//   │   0 ────┼──▶ #2
+//   └─────────┘
+//   #0   int 1
+//   #1   int 2
+//   #2   pair ─┬─ head ─▶ #0
+//              └─ tail ─▶ #1
+//
+// Objects are numbered as in `heap_dump_json`.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use dump::Ids;
+use {addr, Sobject, VM, Visitor, Vobject};
+
+fn name(ids: &Ids, obj: &Sobject) -> String {
+  match ids.get(&addr(obj)) {
+    Some(id) => format!("#{}", id),
+    None => "#?".to_string()
+  }
+}
+
+impl VM {
+  /// The stack and heap drawn with box-drawing characters.
+  pub fn render_ascii(&self) -> String {
+    let ids = self.object_ids();
+    let mut out = String::from("┌─ stack ─┐\n");
+
+    if self.stack.is_empty() {
+      out.push_str("│ (empty) │\n");
+    }
+    for (i, obj) in self.stack.iter().enumerate() {
+      let _ = writeln!(out, "│ {:>3} ────┼──▶ {}", i, name(&ids, obj));
+    }
+    out.push_str("└─────────┘\n");
+
+    for obj in &self.dumped() {
+      let _ = match obj.1.borrow().val {
+        Vobject::Int(n) => writeln!(out, "{:<4} int {}", name(&ids, obj), n),
+        Vobject::Pair(ref head, ref tail) => {
+          let prefix = format!("{:<4} pair ", name(&ids, obj));
+          let indent = " ".repeat(prefix.chars().count() + 1);
+          writeln!(out, "{}─┬─ head ─▶ {}\n{}└─ tail ─▶ {}",
+                   prefix, name(&ids, head), indent, name(&ids, tail))
+        }
+        Vobject::Native(ref native) => {
+          let mut held = Vec::new();
+          native.trace(&mut Visitor::new(&mut |obj| held.push(name(&ids, obj))));
+          writeln!(out, "{:<4} native {} ─▶ [{}]", name(&ids, obj), native.type_name(), held.join(", "))
+        }
+      };
+    }
+
+    out
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn renders_stack_and_heap() {
+    println!("The ASCII picture shows the stack's arrows and each pair's fields.");
+
+    let mut vm = VM::new();
+    assert!(vm.render_ascii() == "┌─ stack ─┐\n│ (empty) │\n└─────────┘\n");
+
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    if let Vobject::Pair(_, ref mut tail) = p.1.borrow_mut().val { *tail = p.clone() }
+    vm.push_int(3).unwrap();
+    println!("{}", vm.render_ascii());
+
+    assert!(vm.render_ascii() == "\
+┌─ stack ─┐
+│   0 ────┼──▶ #2
+│   1 ────┼──▶ #3
+└─────────┘
+#0   int 1
+#1   int 2
+#2   pair ─┬─ head ─▶ #0
+           └─ tail ─▶ #2
+#3   int 3
+");
+  }
+}
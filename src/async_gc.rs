@@ -0,0 +1,145 @@
+// Collecting from async code without blocking the executor. `gc_async`
+// is a full collection done a slice at a time, yielding to the executor
+// after each; `run_async` interleaves the host's own steps with slices of
+// whatever cycle is under way. Neither needs a runtime: each yield wakes
+// its own task and returns `Pending`, so any executor polls it again
+// after giving other tasks a turn.
+
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use {Phase, VM, GC_STEP_WORK};
+
+/// A full collection in slices; see `VM::gc_async`.
+#[derive(Debug)]
+pub struct GcAsync<'a> {
+  vm: &'a mut VM
+}
+
+/// Host steps with collection slices between them; see `VM::run_async`.
+#[derive(Debug)]
+pub struct RunAsync<'a, F> {
+  vm: &'a mut VM,
+  step: F
+}
+
+impl VM {
+  /// Collects the whole heap, finishing any cycle in progress, one slice
+  /// per poll. Resolves to the number of objects the cycle freed.
+  pub fn gc_async(&mut self) -> GcAsync<'_> {
+    if self.phase == Phase::Idle {
+      self.set_trigger("async");
+      self.start_cycle();
+    }
+    GcAsync { vm: self }
+  }
+
+  /// Calls `step` once per poll until it returns a result, doing a tick of
+  /// any collection cycle in progress after each call that doesn't. Meant
+  /// for `VMConfig::host_driven`, under which allocation only starts
+  /// cycles, so the program's steps and the collector take turns on the
+  /// executor.
+  pub fn run_async<F, R>(&mut self, step: F) -> RunAsync<'_, F>
+    where F: FnMut(&mut VM) -> Option<R>
+  {
+    RunAsync { vm: self, step }
+  }
+}
+
+impl<'a> Future for GcAsync<'a> {
+  type Output = usize;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<usize> {
+    let vm = &mut *self.get_mut().vm;
+    if vm.phase == Phase::Idle || vm.timed(|vm| vm.cycle_step(GC_STEP_WORK)) {
+      vm.after("gc_async");
+      return Poll::Ready(vm.cycle_freed);
+    }
+
+    cx.waker().wake_by_ref();
+    Poll::Pending
+  }
+}
+
+impl<'a, F, R> Future for RunAsync<'a, F>
+  where F: FnMut(&mut VM) -> Option<R> + Unpin
+{
+  type Output = R;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<R> {
+    let this = self.get_mut();
+    if let Some(result) = (this.step)(this.vm) {
+      return Poll::Ready(result);
+    }
+
+    this.vm.tick();
+    cx.waker().wake_by_ref();
+    Poll::Pending
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::vec::Vec;
+  use core::task::Waker;
+  use {GcStrategy, VMConfig};
+
+  // Polls `future` to completion, counting the times it yields.
+  fn block_on<F: Future>(future: F) -> (F::Output, usize) {
+    let mut future = core::pin::pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    let mut yields = 0;
+    loop {
+      if let Poll::Ready(out) = future.as_mut().poll(&mut cx) {
+        return (out, yields);
+      }
+      yields += 1;
+    }
+  }
+
+  #[test]
+  fn collections_yield_between_slices() {
+    println!("An async collection takes several polls and frees what a blocking one would.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).threshold(10_000));
+      let vals: Vec<u32> = (0..2000).collect();
+      vm.push_value(&vals).unwrap();
+      vm.push_value(&vals).unwrap();
+      vm.pop();
+
+      let (freed, yields) = block_on(vm.gc_async());
+      assert!(freed == 4001 && yields > 1);
+      assert!(!vm.collecting() && vm.objects() == 4001);
+      vm.verify().unwrap();
+      assert!(block_on(vm.gc_async()).0 == 0 && vm.stats().full_collections == 2);
+    }
+  }
+
+  #[test]
+  fn host_steps_and_slices_take_turns() {
+    println!("A host-driven program gets its collections done between its own steps.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(GC_STEP_WORK).threshold(32));
+    let mut i = 0;
+    let (result, _) = block_on(vm.run_async(|vm| {
+      vm.push_int(i).unwrap();
+      if i % 3 != 0 {
+        vm.pop();
+      }
+      i += 1;
+      if i == 1000 { Some(vm.stack_len()) } else { None }
+    }));
+
+    assert!(result == 334);
+    assert!(vm.stats().full_collections >= 3);
+    vm.verify().unwrap();
+  }
+}
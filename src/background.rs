@@ -0,0 +1,242 @@
+// A VM shared between the host's tasks and a background task that
+// collects for them, on any single-threaded executor (tokio's `LocalSet`
+// will do; objects are `Rc`s, so the VM can't cross threads). Build the
+// VM `host_driven`, so allocation only ever starts a cycle:
+//
+//   let vm = SharedVm::new(VM::with_config(VMConfig::new().host_driven(100)));
+//   tokio::task::spawn_local(vm.background());
+//   let n = vm.push_int(7).await?;
+//
+// The background task does a tick of any cycle under way each time it
+// runs, and starts a full collection itself once the host has gone a turn
+// without allocating and the heap is at least halfway to its threshold.
+// With nothing to do it sleeps until the next allocation. Allocating
+// through `alloc` (or `push_int` and `push_pair`) awaits, rather than
+// failing with `VmError::GcStarved`, if the host gets too far ahead of the
+// collector.
+
+use alloc::rc::Rc;
+use core::cell::{Cell, RefCell};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+
+use {Sobject, VM, VmError};
+
+/// A VM for tasks on one thread to share; see the module source.
+#[derive(Clone, Debug)]
+pub struct SharedVm {
+  shared: Rc<Shared>
+}
+
+#[derive(Debug)]
+struct Shared {
+  vm: RefCell<VM>,
+  // Allocations so far, so the background task can tell the host is idle.
+  allocs: Cell<u64>,
+  // The background task, asleep.
+  waker: RefCell<Option<Waker>>
+}
+
+/// An allocation that waits for the collector; see `SharedVm::alloc`.
+#[derive(Debug)]
+pub struct Alloc<F> {
+  shared: Rc<Shared>,
+  f: F
+}
+
+/// The background collector; see `SharedVm::background`.
+#[derive(Debug)]
+pub struct Background {
+  shared: Rc<Shared>,
+  seen: u64
+}
+
+impl SharedVm {
+  pub fn new(vm: VM) -> SharedVm {
+    SharedVm { shared: Rc::new(Shared { vm: RefCell::new(vm), allocs: Cell::new(0), waker: RefCell::new(None) }) }
+  }
+
+  /// Runs `f` on the VM. It counts as activity, as `alloc` does, but an
+  /// allocation in it fails rather than waiting if the collector is
+  /// behind.
+  pub fn with<R, F: FnOnce(&mut VM) -> R>(&self, f: F) -> R {
+    let result = f(&mut self.shared.vm.borrow_mut());
+    self.shared.wake();
+    result
+  }
+
+  /// Runs `f`, which allocates, once the collector has kept up enough for
+  /// it to succeed: each time it fails with `VmError::GcStarved` the task
+  /// yields, so the background task can catch up, and tries again.
+  pub fn alloc<R, F: FnMut(&mut VM) -> Result<R, VmError>>(&self, f: F) -> Alloc<F> {
+    Alloc { shared: self.shared.clone(), f }
+  }
+
+  pub fn push_int(&self, val: u32) -> Alloc<impl FnMut(&mut VM) -> Result<Sobject, VmError>> {
+    self.alloc(move |vm| vm.push_int(val))
+  }
+
+  pub fn push_pair(&self) -> Alloc<impl FnMut(&mut VM) -> Result<Sobject, VmError>> {
+    self.alloc(VM::push_pair)
+  }
+
+  /// The collector, to spawn as a local task. It runs until dropped.
+  pub fn background(&self) -> Background {
+    Background { shared: self.shared.clone(), seen: self.shared.allocs.get() }
+  }
+}
+
+impl Shared {
+  // Notes activity, waking the background task if it was asleep.
+  fn wake(&self) {
+    self.allocs.set(self.allocs.get() + 1);
+    if let Some(waker) = self.waker.borrow_mut().take() {
+      waker.wake();
+    }
+  }
+}
+
+impl<R, F: FnMut(&mut VM) -> Result<R, VmError> + Unpin> Future for Alloc<F> {
+  type Output = Result<R, VmError>;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<R, VmError>> {
+    let this = self.get_mut();
+    let result = (this.f)(&mut this.shared.vm.borrow_mut());
+    this.shared.wake();
+    match result {
+      Err(VmError::GcStarved) => {
+        cx.waker().wake_by_ref();
+        Poll::Pending
+      }
+      result => Poll::Ready(result)
+    }
+  }
+}
+
+impl Future for Background {
+  type Output = ();
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+    let this = self.get_mut();
+    let allocs = this.shared.allocs.get();
+    let idle = allocs == this.seen;
+    this.seen = allocs;
+
+    let mut vm = this.shared.vm.borrow_mut();
+    if !vm.collecting() && idle && vm.objects() * 2 >= vm.threshold() {
+      vm.set_trigger("idle");
+      vm.start_cycle();
+    }
+    vm.tick();
+
+    // Come back soon while there is work or the host is busy; otherwise
+    // sleep until it allocates.
+    if vm.collecting() || !idle {
+      cx.waker().wake_by_ref();
+    } else {
+      *this.shared.waker.borrow_mut() = Some(cx.waker().clone());
+    }
+    Poll::Pending
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::boxed::Box;
+  use alloc::vec::Vec;
+  use {GcStrategy, VMConfig};
+
+  // Polls `main` and `others` in turn until `main` is done.
+  fn run_local<F: Future>(main: F, others: Vec<Pin<Box<dyn Future<Output = ()>>>>) -> F::Output {
+    let mut main = core::pin::pin!(main);
+    let mut others = others;
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+      if let Poll::Ready(out) = main.as_mut().poll(&mut cx) {
+        return out;
+      }
+      for other in &mut others {
+        let _ = other.as_mut().poll(&mut cx);
+      }
+    }
+  }
+
+  type PushInt = Alloc<Box<dyn FnMut(&mut VM) -> Result<Sobject, VmError>>>;
+
+  // Pushes `n` ints, keeping every third, one at a time.
+  struct Program {
+    vm: SharedVm,
+    i: u32,
+    n: u32,
+    pending: Option<PushInt>
+  }
+
+  impl Future for Program {
+    type Output = Result<(), VmError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), VmError>> {
+      while self.i < self.n {
+        let i = self.i;
+        let vm = self.vm.clone();
+        let pending = self.pending.get_or_insert_with(|| vm.alloc(Box::new(move |vm: &mut VM| vm.push_int(i))));
+        match Pin::new(pending).poll(cx) {
+          Poll::Pending => return Poll::Pending,
+          Poll::Ready(result) => {
+            result?;
+            self.pending = None;
+            if !i.is_multiple_of(3) {
+              self.vm.with(|vm| vm.pop());
+            }
+            self.i += 1;
+          }
+        }
+      }
+      Poll::Ready(Ok(()))
+    }
+  }
+
+  #[test]
+  fn background_task_keeps_up() {
+    println!("Allocation waits for the background collector instead of starving it.");
+
+    for strategy in GcStrategy::ALL {
+      let vm = SharedVm::new(VM::with_config(VMConfig::new().strategy(strategy).host_driven(1).threshold(16)));
+      let program = Program { vm: vm.clone(), i: 0, n: 2000, pending: None };
+      run_local(program, vec![Box::pin(vm.background())]).unwrap();
+
+      vm.with(|vm| {
+        assert!(vm.stack_len() == 667);
+        assert!(vm.stats().full_collections > 0);
+        vm.verify().unwrap();
+      });
+    }
+  }
+
+  #[test]
+  fn idle_hosts_get_collected() {
+    println!("Once the host stops allocating, the background task collects on its own.");
+
+    let vm = SharedVm::new(VM::with_config(VMConfig::new().host_driven(100).threshold(16)));
+    for _ in 0..10 {
+      run_local(vm.push_int(1), Vec::new()).unwrap();
+    }
+    vm.with(|vm| vm.truncate_stack(0));
+
+    let mut background = Box::pin(vm.background());
+    let mut cx = Context::from_waker(Waker::noop());
+    for _ in 0..4 {
+      let _ = background.as_mut().poll(&mut cx);
+    }
+    // Asleep, until the host next touches the VM.
+    assert!(vm.shared.waker.borrow().is_some());
+    vm.with(|vm| assert!(vm.objects() == 0 && vm.stats().full_collections == 1));
+    assert!(vm.shared.waker.borrow().is_none());
+  }
+}
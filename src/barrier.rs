@@ -0,0 +1,59 @@
+// Read barriers, for trying out collectors that need to see the mutator's
+// reads (concurrent copying, incremental update). Nothing here needs one;
+// with the `read-barrier` feature off the hook compiles away.
+
+use core::fmt;
+
+use {Sobject, VM};
+
+pub trait ReadBarrier: fmt::Debug {
+  /// Called with each object read through `VM::as_int`, `VM::as_pair` or
+  /// `VM::extract`, before it is read.
+  fn read(&self, obj: &Sobject);
+}
+
+impl VM {
+  #[inline]
+  pub(crate) fn read_barrier(&self, obj: &Sobject) {
+    if let Some(ref barrier) = self.config.read_barrier {
+      barrier.read(obj);
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use core::cell::Cell;
+  use VMConfig;
+
+  #[test]
+  fn reads_are_reported() {
+    println!("The read barrier sees every read through the accessors.");
+
+    #[derive(Debug)]
+    struct Count(Rc<Cell<usize>>);
+
+    impl ReadBarrier for Count {
+      fn read(&self, _obj: &Sobject) {
+        self.0.set(self.0.get() + 1);
+      }
+    }
+
+    let reads = Rc::new(Cell::new(0));
+    let mut vm = VM::with_config(VMConfig::new().read_barrier(Count(reads.clone())));
+    let p = vm.push_value(&(1u32, 2u32)).unwrap();
+    assert!(reads.get() == 0);
+
+    let (head, _) = vm.as_pair(&p).unwrap();
+    vm.as_int(&head).unwrap();
+    vm.extract::<(u32, u32)>(&p).unwrap();
+    assert!(reads.get() == 3);
+  }
+}
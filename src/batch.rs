@@ -0,0 +1,129 @@
+// Allocating in bulk. Each allocation checks quotas, the heap limit and
+// whether a collection is due; `push_ints` and `push_pairs` do that once
+// for the whole batch, which then goes in regardless, so a batch can take
+// the heap past its threshold by up to its own size. Either every object
+// in a batch is allocated or none is.
+
+use {Object, Phase, VM, VmError, Vobject};
+
+impl VM {
+  /// Pushes an int for each of `vals`, in order. If they can't all be
+  /// allocated none are, and the stack is left as it was.
+  pub fn push_ints(&mut self, vals: &[u32]) -> Result<(), VmError> {
+    Object::reserve(self, vals.len())?;
+
+    self.stack.reserve(vals.len());
+    for &val in vals {
+      let obj = Object::place(self, Vobject::Int(val));
+      self.stack.push(obj);
+    }
+    self.after("push_ints");
+    Ok(())
+  }
+
+  /// Replaces the top `2 * n` stack slots with `n` pairs, pairing them off
+  /// from the bottom as `push_pair` would (head, then tail). Fails with
+  /// `VmError::StackUnderflow` if there are too few; on any failure the
+  /// stack is left as it was.
+  pub fn push_pairs(&mut self, n: usize) -> Result<(), VmError> {
+    let len = self.stack.len();
+    if len < 2 * n {
+      return Err(VmError::StackUnderflow);
+    }
+
+    let operands = self.stack.split_off(len - 2 * n);
+    let roots = self.temp_roots.len();
+    self.temp_roots.extend(operands.iter().cloned());
+    let reserved = Object::reserve(self, n);
+    self.temp_roots.truncate(roots);
+    if let Err(e) = reserved {
+      self.stack.extend(operands);
+      return Err(e);
+    }
+
+    // The new pairs are black if marking is under way, so their children
+    // have to be shaded.
+    if self.phase == Phase::Mark {
+      for child in &operands {
+        Object::mark(child, &mut self.gray);
+      }
+    }
+    for operands in operands.chunks(2) {
+      let obj = Object::place(self, Vobject::Pair(operands[0].clone(), operands[1].clone()));
+      self.stack.push(obj);
+    }
+    self.after("push_pairs");
+    Ok(())
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::vec::Vec;
+  use {GcStrategy, VMConfig};
+
+  #[test]
+  fn batches_check_once() {
+    println!("A batch collects at most once, however big it is.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).threshold(8));
+      let vals: Vec<u32> = (0..100).collect();
+      vm.push_ints(&vals).unwrap();
+      vm.push_pairs(50).unwrap();
+      assert!(vm.stats().pauses <= 1);
+
+      let pairs: Vec<(u32, u32)> = vm.stack.iter().map(|p| vm.extract(p).unwrap()).collect();
+      assert!(pairs.len() == 50 && pairs[0] == (0, 1) && pairs[49] == (98, 99));
+      vm.verify().unwrap();
+    }
+  }
+
+  #[test]
+  fn batches_are_all_or_nothing() {
+    println!("A batch that doesn't fit leaves the stack alone.");
+
+    let mut vm = VM::with_config(VMConfig::new().max_heap(10));
+    vm.push_ints(&[1, 2, 3, 4]).unwrap();
+    assert!(vm.push_ints(&[0; 7]) == Err(VmError::OutOfMemory));
+    assert!(vm.stack.len() == 4 && vm.objects() == 4);
+
+    assert!(vm.push_pairs(3) == Err(VmError::StackUnderflow));
+    vm.push_pairs(2).unwrap();
+    vm.push_ints(&[5, 6]).unwrap();
+    vm.push_pairs(2).unwrap();
+    assert!(vm.push_pairs(1) == Err(VmError::OutOfMemory));
+    assert!(vm.stack.len() == 2);
+    vm.verify().unwrap();
+  }
+
+  #[test]
+  fn batches_mid_cycle_shade_their_operands() {
+    println!("Pairs made in bulk while marking keep what they point to.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(1).threshold(8));
+    let p = vm.push_value(&(1u32, 2u32)).unwrap();
+    while !vm.collecting() {
+      vm.push_int(0).unwrap();
+      vm.pop();
+    }
+
+    // Move 1 from the still-gray pair to the stack, where only the new
+    // pair will hold it once push_pairs has taken it off.
+    let (one, _) = vm.as_pair(&p).unwrap();
+    vm.stack.push(one);
+    let three = vm.push_int(3).unwrap();
+    vm.set_head(&p, &three).unwrap();
+    vm.push_pairs(1).unwrap();
+
+    while !vm.tick() {}
+    vm.verify().unwrap();
+    assert!(vm.extract::<(u32, u32)>(&vm.stack[1]) == Ok((1, 3)));
+  }
+}
@@ -0,0 +1,91 @@
+// `babygc analyze`: a text summary of a GC log (see `VMConfig::gc_log`):
+// how often the collector ran and why, how fast the mutator allocated, how
+// long the pauses were, and which way the heap is heading.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+use simple_gc::GcLogLine;
+
+fn read(path: &Path) -> Vec<GcLogLine> {
+  let text = fs::read_to_string(path).unwrap_or_else(|e| {
+    eprintln!("{}: {}", path.display(), e);
+    process::exit(1);
+  });
+
+  text.lines().enumerate().filter(|&(_, line)| !line.trim().is_empty()).map(|(i, line)| {
+    line.parse().unwrap_or_else(|e| {
+      eprintln!("{}:{}: {}", path.display(), i + 1, e);
+      process::exit(1);
+    })
+  }).collect()
+}
+
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+  sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+// Least-squares slope of `ys` against `xs`.
+fn slope(xs: &[f64], ys: &[f64]) -> f64 {
+  let n = xs.len() as f64;
+  let (mx, my) = (xs.iter().sum::<f64>() / n, ys.iter().sum::<f64>() / n);
+  let cov: f64 = xs.iter().zip(ys).map(|(x, y)| (x - mx) * (y - my)).sum();
+  let var: f64 = xs.iter().map(|x| (x - mx) * (x - mx)).sum();
+  if var == 0.0 { 0.0 } else { cov / var }
+}
+
+fn per_sec(n: f64, secs: f64) -> String {
+  if secs > 0.0 { format!("{:.1}/s", n / secs) } else { "-".to_string() }
+}
+
+pub fn run(path: &Path) {
+  let lines = read(path);
+  if lines.is_empty() {
+    println!("{}: no collections", path.display());
+    return;
+  }
+
+  let (first, last) = (&lines[0], &lines[lines.len() - 1]);
+  let span = last.time.saturating_sub(first.time).as_secs_f64();
+
+  let mut kinds = BTreeMap::new();
+  let mut triggers = BTreeMap::new();
+  for line in &lines {
+    *kinds.entry(line.kind.as_str()).or_insert(0) += 1;
+    *triggers.entry(line.trigger.as_str()).or_insert(0) += 1;
+  }
+  let counts = |map: &BTreeMap<&str, usize>| {
+    map.iter().map(|(k, n)| format!("{} {}", n, k)).collect::<Vec<_>>().join(", ")
+  };
+
+  println!("{}: {} collections over {:.3}s ({}), {}",
+           path.display(), lines.len(), span, counts(&kinds), per_sec(lines.len() as f64 - 1.0, span));
+  println!("triggers: {}", counts(&triggers));
+
+  // Whatever the heap gained between one collection and the next was
+  // allocated in between.
+  let allocated: usize = lines.windows(2).map(|w| w[1].before.saturating_sub(w[0].after)).sum();
+  println!("allocation: {} objects between collections, {}", allocated, per_sec(allocated as f64, span));
+
+  let mut pauses: Vec<Duration> = lines.iter().map(|line| line.pause).collect();
+  pauses.sort();
+  let total: Duration = pauses.iter().sum();
+  println!("pauses: total {:.2?}, mean {:.2?}, p50 {:.2?}, p90 {:.2?}, p99 {:.2?}, max {:.2?}",
+           total, total / pauses.len() as u32, percentile(&pauses, 0.5), percentile(&pauses, 0.9),
+           percentile(&pauses, 0.99), pauses[pauses.len() - 1]);
+  for kind in kinds.keys() {
+    let mut pauses: Vec<Duration> = lines.iter().filter(|l| l.kind == *kind).map(|l| l.pause).collect();
+    pauses.sort();
+    println!("  {:<6} p50 {:.2?}, max {:.2?}", kind, percentile(&pauses, 0.5), pauses[pauses.len() - 1]);
+  }
+
+  let times: Vec<f64> = lines.iter().map(|l| l.time.saturating_sub(first.time).as_secs_f64()).collect();
+  let live: Vec<f64> = lines.iter().map(|l| l.after as f64).collect();
+  let peak = lines.iter().map(|l| l.after).max().unwrap_or(0);
+  println!("heap after collection: first {}, last {}, peak {}, trend {:+.1} objects/s",
+           first.after, last.after, peak, slope(&times, &live));
+  println!("threshold: {} -> {}", first.threshold, last.next_threshold);
+}
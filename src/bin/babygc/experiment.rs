@@ -0,0 +1,207 @@
+// `--experiment FILE` for `babygc stress` and `bench`: the workload mix,
+// its length, the collector settings and the seeds to run it from, in one
+// TOML file, so a run can be repeated, or handed on, as it stands:
+//
+//   strategy = "generational"
+//   threshold = 256
+//   stress = false
+//   seeds = [1, 2, 3]
+//   rounds = 5              # bench only
+//
+//   [workload]
+//   list = 2
+//   drop = 3
+//   depth = "10..100"       # or [10, 100]
+//   ops = 100_000
+//
+// Only that much TOML is read: settings at the top, a `[workload]` table
+// with `Workload`'s keys, integers, booleans, plain strings, arrays of
+// integers and `#` comments. The file overrides the environment and the
+// command line overrides the file. What a run resolves to is printed, in
+// the same form, ahead of its results.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use simple_gc::{GcStrategy, VMConfig, VM, Workload};
+
+#[derive(Clone, Debug, Default)]
+pub struct Experiment {
+  /// None runs every strategy where that makes sense.
+  pub strategy: Option<GcStrategy>,
+  pub threshold: Option<usize>,
+  pub stress: bool,
+  /// Run once from each; empty for the workload's own seed.
+  pub seeds: Vec<u64>,
+  pub rounds: Option<usize>,
+  pub workload: Workload
+}
+
+enum Value {
+  Int(u64),
+  Bool(bool),
+  Str(String),
+  Ints(Vec<u64>)
+}
+
+impl Experiment {
+  /// The workload once for each seed.
+  pub fn runs(&self) -> Vec<Workload> {
+    if self.seeds.is_empty() {
+      return vec![self.workload.clone()];
+    }
+    self.seeds.iter().map(|&seed| Workload { seed, ..self.workload.clone() }).collect()
+  }
+
+  /// `base` with the experiment's settings on top.
+  pub fn config(&self, mut base: VMConfig) -> VMConfig {
+    if let Some(strategy) = self.strategy {
+      base = base.strategy(strategy);
+    }
+    if let Some(n) = self.threshold {
+      base = base.threshold(n);
+    }
+    if self.stress {
+      base = base.stress(true);
+    }
+    base
+  }
+
+  /// Fills in the strategy and threshold `config` ends up with, wherever
+  /// they came from, so the printed experiment says what actually ran.
+  pub fn resolve(&mut self, config: &VMConfig) {
+    let vm = VM::with_config(config.clone());
+    self.strategy = self.strategy.or_else(|| vm.strategy_name().parse().ok());
+    self.threshold = Some(vm.threshold());
+  }
+}
+
+/// Reads `path`, or exits with where it went wrong.
+pub fn load(path: &Path) -> Experiment {
+  let text = fs::read_to_string(path).unwrap_or_else(|e| {
+    eprintln!("{}: {}", path.display(), e);
+    process::exit(1);
+  });
+
+  parse(&text).unwrap_or_else(|(line, e)| {
+    eprintln!("{}:{}: {}", path.display(), line, e);
+    process::exit(1);
+  })
+}
+
+fn parse(text: &str) -> Result<Experiment, (usize, String)> {
+  let mut experiment = Experiment::default();
+  let mut fields = Vec::new();
+  let mut in_workload = false;
+
+  for (i, line) in text.lines().enumerate() {
+    let err = |e: String| (i + 1, e);
+    let line = strip_comment(line).trim();
+    if line.is_empty() {
+      continue;
+    }
+    if line.starts_with('[') {
+      in_workload = match line {
+        "[workload]" => true,
+        _ => return Err(err(format!("unknown table {}", line)))
+      };
+      continue;
+    }
+
+    let (key, value) = line.split_once('=').ok_or_else(|| err(format!("expected key = value, found {:?}", line)))?;
+    let (key, value) = (key.trim(), parse_value(value.trim()).map_err(err)?);
+    let wrong = || err(format!("wrong type for {}", key));
+
+    if in_workload {
+      // Checked one at a time, for the line number, then read together.
+      let field = match value {
+        Value::Int(n) => format!("{}={}", key, n),
+        Value::Str(s) => format!("{}={}", key, s),
+        Value::Ints(ref ns) if ns.len() == 2 => format!("{}={}..{}", key, ns[0], ns[1]),
+        _ => return Err(wrong())
+      };
+      field.parse::<Workload>().map_err(|_| err(format!("bad workload setting {}", field)))?;
+      fields.push(field);
+      continue;
+    }
+
+    match (key, value) {
+      ("strategy", Value::Str(s)) =>
+        experiment.strategy = Some(s.parse().map_err(|_| err(format!("unknown strategy {:?}", s)))?),
+      ("threshold", Value::Int(n)) => experiment.threshold = Some(n as usize),
+      ("stress", Value::Bool(b)) => experiment.stress = b,
+      ("seeds", Value::Ints(ns)) => experiment.seeds = ns,
+      ("rounds", Value::Int(n)) => experiment.rounds = Some(n as usize),
+      ("strategy", _) | ("threshold", _) | ("stress", _) | ("seeds", _) | ("rounds", _) => return Err(wrong()),
+      _ => return Err(err(format!("unknown setting {}", key)))
+    }
+  }
+
+  experiment.workload = fields.join(" ").parse().expect("workload settings were checked one by one");
+  Ok(experiment)
+}
+
+// The line up to a `#` outside a string.
+fn strip_comment(line: &str) -> &str {
+  let mut in_string = false;
+  for (i, c) in line.char_indices() {
+    match c {
+      '"' => in_string = !in_string,
+      '#' if !in_string => return &line[..i],
+      _ => {}
+    }
+  }
+  line
+}
+
+fn parse_value(s: &str) -> Result<Value, String> {
+  let int = |s: &str| s.trim().replace('_', "").parse::<u64>().map_err(|_| format!("bad value {}", s.trim()));
+
+  if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+    return Ok(Value::Str(s[1..s.len() - 1].to_string()));
+  }
+  if s.starts_with('[') && s.ends_with(']') {
+    let items = s[1..s.len() - 1].split(',').filter(|item| !item.trim().is_empty());
+    return items.map(int).collect::<Result<_, _>>().map(Value::Ints);
+  }
+  match s {
+    "true" => Ok(Value::Bool(true)),
+    "false" => Ok(Value::Bool(false)),
+    _ => int(s).map(Value::Int)
+  }
+}
+
+impl fmt::Display for Experiment {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if let Some(strategy) = self.strategy {
+      writeln!(f, "strategy = {:?}", strategy.name())?;
+    }
+    if let Some(n) = self.threshold {
+      writeln!(f, "threshold = {}", n)?;
+    }
+    writeln!(f, "stress = {}", self.stress)?;
+    if !self.seeds.is_empty() {
+      let seeds: Vec<String> = self.seeds.iter().map(u64::to_string).collect();
+      writeln!(f, "seeds = [{}]", seeds.join(", "))?;
+    }
+    if let Some(n) = self.rounds {
+      writeln!(f, "rounds = {}", n)?;
+    }
+
+    let w = &self.workload;
+    writeln!(f, "\n[workload]")?;
+    for &(key, n) in &[("int", w.int), ("pair", w.pair), ("list", w.list), ("mutate", w.mutate), ("drop", w.drop),
+                       ("gc", w.gc)] {
+      writeln!(f, "{} = {}", key, n)?;
+    }
+    writeln!(f, "depth = \"{}..{}\"", w.depth.0, w.depth.1)?;
+    writeln!(f, "max_stack = {}", w.max_stack)?;
+    write!(f, "ops = {}", w.ops)?;
+    if self.seeds.is_empty() {
+      write!(f, "\nseed = {}", w.seed)?;
+    }
+    Ok(())
+  }
+}
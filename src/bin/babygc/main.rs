@@ -0,0 +1,481 @@
+// The `babygc` tool: the classic scenarios from the original article,
+// randomized, soak and benchmark workloads, a side-by-side comparison of
+// the collectors, a heap dumper, a GC log analyzer, a tutorial and, with
+// the `tui` feature, a terminal heap browser. The VM is configured from
+// BABYGC_* environment variables (see `VMConfig::from_env`), then from any flags.
+
+extern crate clap;
+#[cfg(feature = "tui")]
+extern crate ratatui;
+extern crate simple_gc;
+
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use clap::{Args, Parser, Subcommand};
+use simple_gc::{Action, ConfigError, GcStrategy, Vobject, VMConfig, VM, Workload};
+
+use experiment::Experiment;
+
+mod analyze;
+mod experiment;
+mod soak;
+mod tune;
+mod tutorial;
+#[cfg(feature = "tui")]
+mod tui;
+
+#[derive(Parser)]
+#[command(name = "babygc", about = "Baby's first garbage collector")]
+struct Cli {
+  #[command(subcommand)]
+  command: Command
+}
+
+#[derive(Subcommand)]
+enum Command {
+  /// Run the classic tests.
+  Demo(ConfigArgs),
+  /// Run a randomized workload, checking the heap after every collection.
+  Stress {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// The workload, as `key=value` settings (see `Workload`).
+    #[arg(long, value_parser = parse_workload, default_value = "")]
+    workload: Workload,
+    /// A TOML file with the workload, settings and seeds, in place of
+    /// `--workload`.
+    #[arg(long, conflicts_with = "workload")]
+    experiment: Option<PathBuf>,
+    /// Seed for the workload.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Operations to run.
+    #[arg(long)]
+    ops: Option<usize>
+  },
+  /// Run mixed workloads for a long time, checking the heap and reporting
+  /// drift in its size every so often.
+  Soak {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Seed for the workload.
+    #[arg(long, default_value_t = 1)]
+    seed: u64,
+    /// How long to run, in seconds.
+    #[arg(long, default_value_t = 3600)]
+    seconds: u64,
+    /// Seconds between checks.
+    #[arg(long, default_value_t = 10)]
+    check_every: u64
+  },
+  /// Time the performance workload under each strategy, split between
+  /// the mutator and the collector.
+  Bench {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// Times to run it [default: 10, or the experiment's].
+    #[arg(long)]
+    rounds: Option<usize>,
+    /// Run this workload (see `Workload`) instead.
+    #[arg(long, value_parser = parse_workload)]
+    workload: Option<Workload>,
+    /// Run the workload, settings and seeds in this TOML file instead.
+    #[arg(long, conflicts_with = "workload")]
+    experiment: Option<PathBuf>
+  },
+  /// Run one workload under every strategy and compare how each collector
+  /// did.
+  Compare {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// The workload, as `key=value` settings (see `Workload`).
+    #[arg(long, value_parser = parse_workload, default_value = "")]
+    workload: Workload
+  },
+  /// Replay a workload under a grid of thresholds and growth factors and
+  /// print the config that does best.
+  Tune {
+    #[command(flatten)]
+    config: ConfigArgs,
+    /// The workload, as `key=value` settings (see `Workload`).
+    #[arg(long, value_parser = parse_workload, default_value = "")]
+    workload: Workload,
+    /// gc-time, max-pause or peak-heap.
+    #[arg(long, default_value = "gc-time")]
+    objective: tune::Objective,
+    /// First thresholds to try.
+    #[arg(long, value_delimiter = ',', default_value = "16,64,256,1024,4096")]
+    thresholds: Vec<usize>,
+    /// Growth factors to try.
+    #[arg(long, value_delimiter = ',', default_value = "1.25,1.5,2,3,4")]
+    growth: Vec<f64>
+  },
+  /// Print a heap as JSON: an image saved with `VM::save_image`, or the
+  /// demo's cyclic heap.
+  Dump {
+    image: Option<PathBuf>
+  },
+  /// Summarize a GC log written through BABYGC_LOG_FILE.
+  Analyze {
+    log: PathBuf
+  },
+  /// Learn how the collector works, predicting what each collection keeps.
+  Tutorial {
+    /// mark-sweep or generational.
+    #[arg(long, value_parser = parse_strategy, default_value = "mark-sweep")]
+    strategy: GcStrategy
+  },
+  /// Step through a session in a terminal UI, watching the collector.
+  #[cfg(feature = "tui")]
+  Tui(ConfigArgs)
+}
+
+#[derive(Args)]
+struct ConfigArgs {
+  /// mark-sweep or generational.
+  #[arg(long, value_parser = parse_strategy)]
+  strategy: Option<GcStrategy>,
+  /// Heap size that triggers the first collection.
+  #[arg(long)]
+  threshold: Option<usize>,
+  /// Collect on every allocation.
+  #[arg(long)]
+  stress: bool
+}
+
+fn parse_strategy(s: &str) -> Result<GcStrategy, String> {
+  s.parse().map_err(|_| format!("unknown strategy {:?}", s))
+}
+
+fn parse_workload(s: &str) -> Result<Workload, String> {
+  s.parse().map_err(|e: ConfigError| e.value)
+}
+
+impl ConfigArgs {
+  fn config(&self) -> VMConfig {
+    let mut config = env_config();
+
+    if let Some(strategy) = self.strategy {
+      config = config.strategy(strategy);
+    }
+    if let Some(n) = self.threshold {
+      config = config.threshold(n);
+    }
+    if self.stress {
+      config = config.stress(true);
+    }
+    config
+  }
+
+  // Puts the flags given over the file's settings.
+  fn override_experiment(&self, experiment: &mut Experiment) {
+    experiment.strategy = self.strategy.or(experiment.strategy);
+    experiment.threshold = self.threshold.or(experiment.threshold);
+    experiment.stress |= self.stress;
+  }
+}
+
+fn env_config() -> VMConfig {
+  VMConfig::from_env().unwrap_or_else(|e| {
+    eprintln!("{}", e);
+    process::exit(2);
+  })
+}
+
+fn test1(config: &VMConfig) {
+  println!("Test 1: Objects on stack are preserved.");
+
+  let mut vm = VM::with_config(config.clone());
+  vm.push_int(1).unwrap();
+  vm.push_int(2).unwrap();
+
+  println!("  collected {} objects", vm.gc());
+}
+
+fn test2(config: &VMConfig) {
+  println!("Test 2: Unreachable objects are collected.");
+
+  let mut vm = VM::with_config(config.clone());
+  vm.push_int(1).unwrap();
+  vm.push_int(2).unwrap();
+
+  vm.pop();
+  vm.pop();
+
+  println!("  collected {} objects", vm.gc());
+}
+
+fn test3(config: &VMConfig) {
+  println!("Test 3: Nested objects are reachable.");
+
+  let mut vm = VM::with_config(config.clone());
+  vm.push_int(1).unwrap();
+  vm.push_int(2).unwrap();
+  vm.push_pair().unwrap();
+
+  vm.push_int(3).unwrap();
+  vm.push_int(4).unwrap();
+  vm.push_pair().unwrap();
+
+  vm.push_pair().unwrap();
+
+  println!("  collected {} objects", vm.gc());
+}
+
+// Two pairs whose tails point back at themselves.
+fn cycles(config: &VMConfig) -> VM {
+  let mut vm = VM::with_config(config.clone());
+  vm.push_int(1).unwrap();
+  vm.push_int(2).unwrap();
+  let a = vm.push_pair().unwrap();
+
+  vm.push_int(3).unwrap();
+  vm.push_int(4).unwrap();
+  let b = vm.push_pair().unwrap();
+
+  // set up a cycle
+  if let Vobject::Pair(_, ref mut x) = a.1.borrow_mut().val { *x = a.clone() }
+  if let Vobject::Pair(_, ref mut x) = b.1.borrow_mut().val { *x = b.clone() }
+  vm.write_barrier(&a);
+  vm.write_barrier(&b);
+  vm
+}
+
+fn test4(config: &VMConfig) {
+  println!("Test 4: Handle cycles.");
+
+  let mut vm = cycles(config);
+  println!("  collected {} objects", vm.gc());
+}
+
+fn perftest(config: &VMConfig) -> usize {
+  let mut vm = VM::with_config(config.clone());
+
+  for i in 0..1000 {
+    for _ in 0..20 {
+      vm.push_int(i).unwrap();
+    }
+
+    for _ in 0..20 {
+      vm.pop();
+    }
+  }
+
+  vm.gc()
+}
+
+fn demo(config: &VMConfig) {
+  test1(config);
+  test2(config);
+  test3(config);
+  test4(config);
+  println!("Performance Test.");
+  println!("  collected {} objects", perftest(config));
+  println!("Demo completed successfully!");
+}
+
+// xorshift64*, so runs are reproducible without a dependency.
+struct Rng(u64);
+
+impl Rng {
+  fn below(&mut self, n: usize) -> usize {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 33) as usize % n
+  }
+}
+
+fn stress(config: &VMConfig, workload: &Workload) {
+  let mut vm = VM::with_config(config.clone());
+  let mut done = 0;
+
+  let result = workload.run(&mut vm, |vm, action| {
+    done += 1;
+    if action == Action::Gc {
+      let (live, heap) = (vm.iter_live().count(), vm.iter_heap().count());
+      if live != heap {
+        eprintln!("after op {}: {} objects survived a full collection but only {} are live", done, heap, live);
+        process::exit(1);
+      }
+    }
+  });
+
+  if let Err(e) = result {
+    eprintln!("after op {}: {}", done, e);
+    process::exit(1);
+  }
+  println!("{} operations, {} collections, {} objects left", workload.ops, vm.stats().pauses, vm.iter_heap().count());
+}
+
+// Runs the performance workload, or `workload`, `rounds` times and
+// splits the time taken between the mutator and the collector.
+fn bench(config: &VMConfig, strategy: GcStrategy, rounds: usize, workload: Option<&Workload>) {
+  let mut vm = VM::with_config(config.clone().strategy(strategy));
+  let start = Instant::now();
+
+  for _ in 0..rounds.max(1) {
+    if let Some(workload) = workload {
+      if let Err(e) = workload.run(&mut vm, |_, _| {}) {
+        eprintln!("{}: {}", strategy.name(), e);
+        process::exit(1);
+      }
+      continue;
+    }
+
+    for i in 0..1000 {
+      for _ in 0..20 {
+        vm.push_int(i).unwrap();
+      }
+
+      for _ in 0..20 {
+        vm.pop();
+      }
+    }
+  }
+  vm.gc();
+
+  let elapsed = start.elapsed();
+  let stats = vm.stats();
+  let gc = stats.total_pause;
+  let allocs = vm.quota_used().0;
+  println!("{:<14} {:>10.2?} {:>10.2?} {:>10.2?} {:>5.1}% {:>12.0} {:>8} {:>10.2?}",
+           strategy.name(), elapsed, elapsed.saturating_sub(gc), gc,
+           100.0 * gc.as_secs_f64() / elapsed.as_secs_f64(), allocs as f64 / elapsed.as_secs_f64(),
+           stats.pauses, stats.max_pause);
+}
+
+// Benchmarks `strategy`, or each of them.
+fn bench_strategies(config: &VMConfig, strategy: Option<GcStrategy>, rounds: usize, workload: Option<&Workload>) {
+  let strategies = match strategy {
+    Some(strategy) => vec![strategy],
+    None => GcStrategy::ALL.to_vec()
+  };
+
+  match workload {
+    Some(workload) => println!("{} rounds of {}", rounds, workload),
+    None => println!("{} rounds", rounds)
+  }
+  println!("{:<14} {:>10} {:>10} {:>10} {:>6} {:>12} {:>8} {:>10}",
+           "strategy", "total", "mutator", "gc", "gc%", "allocs/s", "pauses", "longest");
+  for strategy in strategies {
+    bench(config, strategy, rounds, workload);
+  }
+}
+
+// Reads an experiment file, puts the flags over it and works out the
+// VMConfig it comes to.
+fn load_experiment(path: &Path, args: &ConfigArgs) -> (Experiment, VMConfig) {
+  let mut experiment = experiment::load(path);
+  args.override_experiment(&mut experiment);
+  let config = experiment.config(env_config());
+  (experiment, config)
+}
+
+// `stress` from each of an experiment's seeds, after printing what it
+// resolved to.
+fn stress_experiment(path: &Path, args: &ConfigArgs, seed: Option<u64>, ops: Option<usize>) {
+  let (mut experiment, config) = load_experiment(path, args);
+  if let Some(seed) = seed {
+    experiment.seeds = vec![seed];
+  }
+  experiment.workload.ops = ops.unwrap_or(experiment.workload.ops);
+  experiment.resolve(&config);
+  println!("{}\n", experiment);
+
+  for workload in experiment.runs() {
+    print!("seed {}: ", workload.seed);
+    stress(&config, &workload);
+  }
+}
+
+// `bench` from each of an experiment's seeds, likewise. With no strategy
+// set anywhere, every strategy runs, as it does without a file.
+fn bench_experiment(path: &Path, args: &ConfigArgs, rounds: Option<usize>) {
+  let (mut experiment, config) = load_experiment(path, args);
+  let rounds = rounds.or(experiment.rounds).unwrap_or(10);
+  experiment.rounds = Some(rounds);
+  if experiment.strategy.is_some() {
+    experiment.resolve(&config);
+  }
+  println!("{}\n", experiment);
+
+  for workload in experiment.runs() {
+    bench_strategies(&config, experiment.strategy, rounds, Some(&workload));
+  }
+}
+
+// Runs `workload` under each strategy, from the same seed, and lines up
+// what the collectors cost and how much heap they needed. `--strategy` is
+// ignored; the rest of the config applies to every run.
+fn compare(args: &ConfigArgs, workload: &Workload) {
+  println!("{}", workload);
+  println!("{:<14} {:>10} {:>10} {:>10} {:>8} {:>6} {:>6}",
+           "strategy", "total", "gc", "longest", "peak", "full", "minor");
+
+  let config = args.config();
+  for strategy in GcStrategy::ALL {
+    let mut vm = VM::with_config(config.clone().strategy(strategy));
+    let start = Instant::now();
+    if let Err(e) = workload.run(&mut vm, |_, _| {}) {
+      eprintln!("{}: {}", strategy.name(), e);
+      process::exit(1);
+    }
+
+    let elapsed = start.elapsed();
+    let stats = vm.stats();
+    println!("{:<14} {:>10.2?} {:>10.2?} {:>10.2?} {:>8} {:>6} {:>6}",
+             strategy.name(), elapsed, stats.total_pause, stats.max_pause, stats.peak_objects,
+             stats.full_collections, stats.minor_collections);
+  }
+}
+
+fn dump(image: Option<PathBuf>) {
+  let vm = match image {
+    Some(path) => VM::load_image(&path).unwrap_or_else(|e| {
+      eprintln!("{}: {}", path.display(), e);
+      process::exit(1);
+    }),
+    None => cycles(&VMConfig::new())
+  };
+
+  println!("{}", vm.heap_dump_json());
+}
+
+
+//---------------------------------------------------------------------
+// Main program
+//---------------------------------------------------------------------
+
+fn main() {
+  match Cli::parse().command {
+    Command::Demo(config) => demo(&config.config()),
+    Command::Stress { config, experiment: Some(path), seed, ops, .. } =>
+      stress_experiment(&path, &config, seed, ops),
+    Command::Stress { config, mut workload, seed, ops, .. } => {
+      workload.seed = seed.unwrap_or(workload.seed);
+      workload.ops = ops.unwrap_or(workload.ops);
+      stress(&config.config(), &workload)
+    }
+    Command::Soak { config, seed, seconds, check_every } =>
+      soak::run(config.config(), seed, Duration::from_secs(seconds), Duration::from_secs(check_every.max(1))),
+    Command::Bench { config, rounds, experiment: Some(path), .. } => bench_experiment(&path, &config, rounds),
+    Command::Bench { config, rounds, workload, .. } =>
+      bench_strategies(&config.config(), config.strategy, rounds.unwrap_or(10), workload.as_ref()),
+    Command::Compare { config, workload } => compare(&config, &workload),
+    Command::Tune { config, workload, objective, thresholds, growth } =>
+      tune::run(&config.config(), config.strategy, &workload, &thresholds, &growth, objective),
+    Command::Dump { image } => dump(image),
+    Command::Analyze { log } => analyze::run(&log),
+    Command::Tutorial { strategy } => tutorial::run(strategy),
+    #[cfg(feature = "tui")]
+    Command::Tui(config) => {
+      if let Err(e) = tui::run(config.config()) {
+        eprintln!("{}", e);
+        process::exit(1);
+      }
+    }
+  }
+}
@@ -0,0 +1,183 @@
+// `babygc soak`: hours of mixed workloads against one VM, to catch slow
+// leaks and accounting bugs that short tests miss. Every so often it runs
+// a full collection, checks the heap against itself and the allocator, and
+// prints how far the live set has drifted since the first check. The stack
+// is trimmed back regularly, so a steady workload should show no drift.
+
+use std::cell::Cell;
+use std::process;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use simple_gc::{ObjectAllocator, Sobject, VM, VMConfig, VmError, Vobject};
+
+use Rng;
+
+// Stack slots kept when trimming, which happens at twice as many.
+const KEEP: usize = 32;
+
+// Counts the bytes the VM has allocated and not yet freed.
+#[derive(Debug, Default)]
+struct Counting {
+  outstanding: Cell<usize>,
+  object_size: Cell<usize>
+}
+
+impl ObjectAllocator for Counting {
+  fn allocate(&self, bytes: usize) -> bool {
+    self.outstanding.set(self.outstanding.get() + bytes);
+    self.object_size.set(bytes);
+    true
+  }
+
+  fn free(&self, bytes: usize) {
+    self.outstanding.set(self.outstanding.get() - bytes);
+  }
+}
+
+struct Soak {
+  vm: VM,
+  rng: Rng,
+  allocator: Rc<Counting>,
+  allocated: u64,
+  rounds: u64
+}
+
+fn fail(what: &str) -> ! {
+  eprintln!("soak: {}", what);
+  process::exit(1);
+}
+
+impl Soak {
+  fn allocated(&mut self, result: Result<Sobject, VmError>) {
+    if let Err(e) = result {
+      fail(&format!("allocation failed: {}", e));
+    }
+    self.allocated += 1;
+  }
+
+  fn int(&mut self, n: u32) {
+    let result = self.vm.push_int(n);
+    self.allocated(result);
+  }
+
+  fn pair(&mut self) {
+    let result = self.vm.push_pair();
+    self.allocated(result);
+  }
+
+  // A list of `len` pairs, built up on top of the stack.
+  fn list(&mut self, len: usize) {
+    self.int(0);
+    for i in 0..len {
+      self.int(i as u32);
+      self.pair();
+    }
+  }
+
+  fn tree(&mut self, depth: usize) {
+    if depth == 0 {
+      let n = self.rng.below(1000) as u32;
+      self.int(n);
+    } else {
+      self.tree(depth - 1);
+      self.tree(depth - 1);
+      self.pair();
+    }
+  }
+
+  // Points heads and tails of pairs on the stack at other stack slots,
+  // making and breaking cycles.
+  fn mutate(&mut self, times: usize) {
+    let roots: Vec<Sobject> = self.vm.iter_roots().cloned().collect();
+    if roots.is_empty() {
+      return;
+    }
+
+    for _ in 0..times {
+      let target = roots[self.rng.below(roots.len())].clone();
+      let pair = &roots[self.rng.below(roots.len())];
+      let head = self.rng.below(2) == 0;
+
+      if let Vobject::Pair(ref mut h, ref mut t) = pair.1.borrow_mut().val {
+        *(if head { h } else { t }) = target;
+      }
+      self.vm.write_barrier(pair);
+    }
+  }
+
+  fn pop(&mut self, n: usize) {
+    for _ in 0..n.min(self.vm.iter_roots().count()) {
+      self.vm.pop();
+    }
+  }
+
+  fn round(&mut self) {
+    match self.rng.below(4) {
+      0 => { let len = self.rng.below(200) + 1; self.list(len) }
+      1 => { let depth = self.rng.below(8); self.tree(depth) }
+      2 => { let times = self.rng.below(50) + 1; self.mutate(times) }
+      _ => { let n = self.rng.below(8); self.pop(n) }
+    }
+
+    let depth = self.vm.iter_roots().count();
+    if depth >= 2 * KEEP {
+      self.pop(depth - KEEP);
+    }
+    self.rounds += 1;
+  }
+
+  // Collects everything and checks that what's left is exactly what's
+  // live, and that the VM and the allocator agree on how much that is.
+  // Returns the live object count.
+  fn check(&mut self) -> usize {
+    self.vm.gc_full();
+
+    let (live, heap) = (self.vm.iter_live().count(), self.vm.iter_heap().count());
+    if live != heap {
+      fail(&format!("{} objects survived a full collection but only {} are live", heap, live));
+    }
+
+    if self.vm.quota_used().0 != self.allocated {
+      fail(&format!("{} objects allocated but the VM counted {}", self.allocated, self.vm.quota_used().0));
+    }
+
+    let bytes = heap * self.allocator.object_size.get();
+    if self.allocator.outstanding.get() != bytes {
+      fail(&format!("the allocator holds {} bytes but the heap's {} objects need {}",
+                    self.allocator.outstanding.get(), heap, bytes));
+    }
+
+    heap
+  }
+}
+
+pub fn run(config: VMConfig, seed: u64, duration: Duration, every: Duration) {
+  let allocator = Rc::new(Counting::default());
+  let mut soak = Soak {
+    vm: VM::with_config(config.allocator(allocator.clone())),
+    rng: Rng(seed.max(1)),
+    allocator,
+    allocated: 0,
+    rounds: 0
+  };
+
+  let start = Instant::now();
+  let mut next_check = start + every;
+  let mut baseline = None;
+
+  while start.elapsed() < duration {
+    soak.round();
+
+    if Instant::now() >= next_check || start.elapsed() >= duration {
+      let live = soak.check();
+      let first = *baseline.get_or_insert(live);
+      let stats = soak.vm.stats();
+
+      println!("{:>7.0?}  {} rounds  {} live ({:+} since first check), {} bytes  {} pauses, longest {:?}",
+               start.elapsed(), soak.rounds, live, live as i64 - first as i64,
+               soak.allocator.outstanding.get(), stats.pauses, stats.max_pause);
+      next_check = Instant::now() + every;
+    }
+  }
+}
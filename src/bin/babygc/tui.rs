@@ -0,0 +1,161 @@
+// `babygc tui`: steps through a recorded session one operation at a time,
+// showing the stack, the heap and the collector. Collections run in small
+// host-driven ticks, so marking and sweeping can be watched: objects found
+// live so far are highlighted, and swept ones disappear.
+
+use std::io;
+
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, List, ListItem, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+
+use simple_gc::{Sobject, VM, VMConfig, Vobject};
+
+enum Op {
+  Int(u32),
+  Pair,
+  Pop,
+  // Point the top pair's tail back at itself.
+  Cycle
+}
+
+const SESSION: &[Op] = &[
+  Op::Int(1), Op::Int(2), Op::Pair,
+  Op::Int(3), Op::Int(4), Op::Pair,
+  Op::Pair,
+  Op::Int(5), Op::Int(6), Op::Pair, Op::Cycle,
+  Op::Pop,
+  Op::Int(7), Op::Int(8), Op::Pop, Op::Pop,
+  Op::Int(9), Op::Int(10), Op::Pair, Op::Pop,
+  Op::Int(11), Op::Int(12), Op::Pair, Op::Cycle,
+  Op::Int(13), Op::Pair,
+  Op::Pop, Op::Pop,
+  Op::Int(14), Op::Int(15), Op::Int(16), Op::Int(17)
+];
+
+struct Browser {
+  config: VMConfig,
+  vm: VM,
+  next: usize,
+  last: String
+}
+
+impl Browser {
+  fn new(config: VMConfig) -> Browser {
+    Browser {
+      vm: VM::with_config(config.clone()),
+      config,
+      next: 0,
+      last: "ready".to_string()
+    }
+  }
+
+  fn name(&self, obj: &Sobject) -> String {
+    format!("#{}", self.vm.object_id(obj))
+  }
+
+  // Ticks the collection under way, or runs the next operation.
+  fn step(&mut self) {
+    if self.vm.collecting() {
+      self.last = if self.vm.tick() { "collection finished" } else { "collecting" }.to_string();
+      return;
+    }
+
+    let op = match SESSION.get(self.next) {
+      Some(op) => op,
+      None => {
+        self.last = "end of session (r restarts)".to_string();
+        return;
+      }
+    };
+    self.next += 1;
+
+    self.last = match *op {
+      Op::Int(n) => {
+        let obj = self.vm.push_int(n).unwrap();
+        format!("push {} -> {}", n, self.name(&obj))
+      }
+      Op::Pair => {
+        let obj = self.vm.push_pair().unwrap();
+        format!("pair -> {}", self.name(&obj))
+      }
+      Op::Pop => {
+        let obj = self.vm.pop();
+        format!("pop {}", self.name(&obj))
+      }
+      Op::Cycle => {
+        let obj = self.vm.iter_roots().last().unwrap().clone();
+        if let Vobject::Pair(_, ref mut tail) = obj.1.borrow_mut().val { *tail = obj.clone() }
+        self.vm.write_barrier(&obj);
+        format!("tail of {} = {}", self.name(&obj), self.name(&obj))
+      }
+    };
+
+    if self.vm.collecting() {
+      self.last.push_str(", collection started");
+    }
+  }
+
+  fn render(&self, frame: &mut Frame) {
+    let [main, status] = Layout::vertical([Constraint::Min(3), Constraint::Length(3)]).areas(frame.area());
+    let [stack, heap] = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]).areas(main);
+
+    let roots: Vec<ListItem> = self.vm.iter_roots().enumerate().map(|(i, obj)| {
+      let value = self.vm.display(obj).max_depth(3).max_width(4);
+      ListItem::new(format!("{:>2}  {:<5} {}", i, self.name(obj), value))
+    }).collect();
+    frame.render_widget(List::new(roots).block(Block::bordered().title(" stack ")), stack);
+
+    let objects: Vec<ListItem> = self.vm.iter_heap().map(|obj| {
+      let text = match (self.vm.as_int(obj), self.vm.as_pair(obj)) {
+        (Ok(n), _) => format!("{:<5} int {}", self.name(obj), n),
+        (_, Ok((head, tail))) => format!("{:<5} pair {} {}", self.name(obj), self.name(&head), self.name(&tail)),
+        _ => unreachable!()
+      };
+
+      let mut style = Style::default();
+      if self.vm.is_marked(obj) {
+        style = style.fg(Color::Yellow).add_modifier(Modifier::BOLD);
+      }
+      if self.vm.age(obj) > 0 {
+        style = style.add_modifier(Modifier::ITALIC);
+      }
+      ListItem::new(text).style(style)
+    }).collect();
+    let title = format!(" heap: {} objects ", objects.len());
+    frame.render_widget(List::new(objects).block(Block::bordered().title(title)), heap);
+
+    let help = "space: step  r: restart  q: quit";
+    let text = vec![Line::from(self.last.as_str()), Line::from(help)];
+    let title = format!(" step {}/{}, {} collector pauses ", self.next, SESSION.len(), self.vm.stats().pauses);
+    frame.render_widget(Paragraph::new(text).block(Block::bordered().title(title)), status);
+  }
+
+  fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+    loop {
+      terminal.draw(|frame| self.render(frame))?;
+
+      if let Event::Key(key) = event::read()? {
+        if key.kind != KeyEventKind::Press {
+          continue;
+        }
+
+        match key.code {
+          KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+          KeyCode::Char(' ') | KeyCode::Char('n') | KeyCode::Right => self.step(),
+          KeyCode::Char('r') => *self = Browser::new(self.config.clone()),
+          _ => {}
+        }
+      }
+    }
+  }
+}
+
+// Collections tick two objects at a time, to be slow enough to follow.
+pub fn run(config: VMConfig) -> io::Result<()> {
+  let mut browser = Browser::new(config.host_driven(2));
+  ratatui::run(|terminal| browser.run(terminal))
+}
@@ -0,0 +1,87 @@
+// `babygc tune`: replays a workload under a grid of first thresholds and
+// growth factors and picks the setting that does best on one measure:
+// time spent collecting, the longest pause, or the peak heap. Workloads
+// are reproducible, so a description stands in for a recorded trace; each
+// setting sees exactly the same operations. The winner is printed as the
+// `VMConfig` to build.
+
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
+
+use simple_gc::{GcStrategy, GrowthPolicy, VMConfig, VM, Workload};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Objective {
+  GcTime,
+  MaxPause,
+  PeakHeap
+}
+
+impl FromStr for Objective {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Objective, String> {
+    match s {
+      "gc-time" => Ok(Objective::GcTime),
+      "max-pause" => Ok(Objective::MaxPause),
+      "peak-heap" => Ok(Objective::PeakHeap),
+      _ => Err(format!("unknown objective {:?}; expected gc-time, max-pause or peak-heap", s))
+    }
+  }
+}
+
+// How one setting did.
+struct Trial {
+  threshold: usize,
+  growth: f64,
+  gc: Duration,
+  max_pause: Duration,
+  peak: usize
+}
+
+impl Trial {
+  // Lower is better. Times are compared in nanoseconds.
+  fn score(&self, objective: Objective) -> u128 {
+    match objective {
+      Objective::GcTime => self.gc.as_nanos(),
+      Objective::MaxPause => self.max_pause.as_nanos(),
+      Objective::PeakHeap => self.peak as u128
+    }
+  }
+}
+
+// `strategy` is the one `config` was given on the command line, if any,
+// so the printed config can say so.
+pub fn run(config: &VMConfig, strategy: Option<GcStrategy>, workload: &Workload, thresholds: &[usize], growths: &[f64],
+           objective: Objective) {
+  println!("{}", workload);
+  println!("{:>10} {:>7} {:>10} {:>10} {:>8}", "threshold", "growth", "gc", "longest", "peak");
+
+  let mut best: Option<Trial> = None;
+  for &threshold in thresholds {
+    for &growth in growths {
+      let mut vm = VM::with_config(config.clone().threshold(threshold).sizing(GrowthPolicy(growth)));
+      if let Err(e) = workload.run(&mut vm, |_, _| {}) {
+        eprintln!("threshold {}, growth {}: {}", threshold, growth, e);
+        process::exit(1);
+      }
+
+      let stats = vm.stats();
+      let trial = Trial { threshold, growth, gc: stats.total_pause, max_pause: stats.max_pause, peak: stats.peak_objects };
+      println!("{:>10} {:>7} {:>10.2?} {:>10.2?} {:>8}", threshold, growth, trial.gc, trial.max_pause, trial.peak);
+      if best.as_ref().is_none_or(|best| trial.score(objective) < best.score(objective)) {
+        best = Some(trial);
+      }
+    }
+  }
+
+  if let Some(best) = best {
+    let strategy = match strategy {
+      Some(GcStrategy::MarkSweep) | None => "",
+      Some(GcStrategy::Generational) => ".strategy(GcStrategy::Generational)"
+    };
+    println!();
+    println!("VMConfig::new(){}.threshold({}).sizing(GrowthPolicy({:?}))", strategy, best.threshold, best.growth);
+  }
+}
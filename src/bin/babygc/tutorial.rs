@@ -0,0 +1,134 @@
+// `babygc tutorial`: the collector explained one step at a time. Each stage
+// builds a small heap, draws it, and asks how many objects a collection
+// will keep before running one and showing what it did.
+
+use std::io::{self, BufRead, Write};
+
+use simple_gc::{GcStrategy, VMConfig, VM};
+
+struct Stage {
+  title: &'static str,
+  lesson: &'static str,
+  build: fn(&mut VM)
+}
+
+const STAGES: [Stage; 6] = [
+  Stage {
+    title: "Objects on the stack",
+    lesson: "The stack is the root set: everything on it is in use.",
+    build: |vm| {
+      vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+    }
+  },
+  Stage {
+    title: "Garbage",
+    lesson: "Popping an object doesn't free it. It stays in the heap until a\n\
+             collection finds nothing pointing at it.",
+    build: |vm| {
+      vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+      vm.pop();
+      vm.pop();
+    }
+  },
+  Stage {
+    title: "Pairs",
+    lesson: "A pair keeps its head and tail alive, even off the stack.",
+    build: |vm| {
+      vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+      vm.push_pair().unwrap();
+      vm.push_int(3).unwrap();
+      vm.pop();
+    }
+  },
+  Stage {
+    title: "Nesting",
+    lesson: "Marking follows pointers as deep as they go.",
+    build: |vm| {
+      vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+      vm.push_pair().unwrap();
+      vm.push_int(3).unwrap();
+      vm.push_int(4).unwrap();
+      vm.push_pair().unwrap();
+      vm.push_pair().unwrap();
+    }
+  },
+  Stage {
+    title: "Cycles",
+    lesson: "Each pair's tail points back at itself. Reference counting would\n\
+             never free the one popped; tracing from the roots doesn't care.",
+    build: |vm| {
+      for n in [1, 3] {
+        vm.push_int(n).unwrap();
+        vm.push_int(n + 1).unwrap();
+        let pair = vm.push_pair().unwrap();
+        vm.set_tail(&pair, &pair).unwrap();
+      }
+      vm.pop();
+    }
+  },
+  Stage {
+    title: "Stores",
+    lesson: "Pointing a pair somewhere new can strand what it pointed at.",
+    build: |vm| {
+      vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+      let pair = vm.push_pair().unwrap();
+      let three = vm.push_int(3).unwrap();
+      vm.set_tail(&pair, &three).unwrap();
+      vm.pop();
+    }
+  }
+];
+
+// Asks for the number of survivors. None if the answer wasn't a number,
+// or there was no answer at all.
+fn ask(input: &mut impl BufRead) -> Option<usize> {
+  print!("How many objects will survive a collection? ");
+  let _ = io::stdout().flush();
+
+  let mut line = String::new();
+  match input.read_line(&mut line) {
+    Ok(0) | Err(_) => {
+      println!();
+      None
+    }
+    Ok(_) => line.trim().parse().ok()
+  }
+}
+
+pub fn run(strategy: GcStrategy) {
+  // Room for every stage's objects, so nothing is collected before the
+  // question.
+  let config = VMConfig::new().strategy(strategy).threshold(100);
+  let stdin = io::stdin();
+  let mut input = stdin.lock();
+  let mut right = 0;
+
+  for (i, stage) in STAGES.iter().enumerate() {
+    println!("== {}/{}: {} ==\n{}\n", i + 1, STAGES.len(), stage.title, stage.lesson);
+
+    let mut vm = VM::with_config(config.clone());
+    vm.gc_paused(stage.build);
+    print!("{}", vm.render_ascii());
+
+    let guess = ask(&mut input);
+    let freed = vm.gc_full();
+    let kept = vm.iter_heap().count();
+
+    match guess {
+      Some(n) if n == kept => {
+        right += 1;
+        println!("Right: {} kept, {} freed.", kept, freed);
+      }
+      Some(n) => println!("Not quite: you said {}, but {} were kept and {} freed.", n, kept, freed),
+      None => println!("{} kept, {} freed.", kept, freed)
+    }
+    println!("{}", vm.render_ascii());
+  }
+
+  println!("{} of {} right.", right, STAGES.len());
+}
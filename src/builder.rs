@@ -0,0 +1,192 @@
+// Builders that gather a list in host memory and put it on the heap in
+// one go. Appending to a list already on the heap means copying it, and
+// every copy but the last is garbage; a builder only allocates the final
+// list, makes room for all of it at once (collecting at most once, as a
+// batch does) and pushes it, so it is rooted from the start. Lists are
+// encoded as `Vec` is, pairs ending in 0, and a string is a list of its
+// chars, so `extract::<Vec<u32>>` and `extract::<Vec<char>>` read them back.
+//
+// Builders hold ints and chars only: a handle kept in host memory isn't
+// rooted, and could be collected before the list is built. Lists of
+// objects are built on the stack, with `push_pairs`.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::FromIterator;
+
+use {Object, Sobject, VM, VmError, Vobject};
+
+/// A list of ints built up outside the heap.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ListBuilder {
+  items: Vec<u32>
+}
+
+impl ListBuilder {
+  pub fn new() -> ListBuilder {
+    ListBuilder::default()
+  }
+
+  pub fn with_capacity(n: usize) -> ListBuilder {
+    ListBuilder { items: Vec::with_capacity(n) }
+  }
+
+  pub fn push(&mut self, n: u32) -> &mut ListBuilder {
+    self.items.push(n);
+    self
+  }
+
+  pub fn len(&self) -> usize {
+    self.items.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.items.is_empty()
+  }
+
+  /// Allocates the list and pushes it. If it can't all be allocated none
+  /// of it is, and the builder can be tried again.
+  pub fn build(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+    Object::reserve(vm, 2 * self.items.len() + 1)?;
+
+    // Every object here is new, so during marking all of them are black
+    // and there is nothing older to shade.
+    let mut list = Object::place(vm, Vobject::Int(0));
+    for &n in self.items.iter().rev() {
+      let head = Object::place(vm, Vobject::Int(n));
+      list = Object::place(vm, Vobject::Pair(head, list));
+    }
+    vm.stack.push(list.clone());
+    vm.after("build_list");
+    Ok(list)
+  }
+}
+
+impl Extend<u32> for ListBuilder {
+  fn extend<I: IntoIterator<Item = u32>>(&mut self, iter: I) {
+    self.items.extend(iter);
+  }
+}
+
+impl FromIterator<u32> for ListBuilder {
+  fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> ListBuilder {
+    ListBuilder { items: iter.into_iter().collect() }
+  }
+}
+
+/// A string built up outside the heap, with `write!` or a piece at a time.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StringBuilder {
+  chars: ListBuilder
+}
+
+impl StringBuilder {
+  pub fn new() -> StringBuilder {
+    StringBuilder::default()
+  }
+
+  pub fn push(&mut self, c: char) -> &mut StringBuilder {
+    self.chars.push(c as u32);
+    self
+  }
+
+  pub fn push_str(&mut self, s: &str) -> &mut StringBuilder {
+    self.chars.extend(s.chars().map(|c| c as u32));
+    self
+  }
+
+  /// Chars so far.
+  pub fn len(&self) -> usize {
+    self.chars.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.chars.is_empty()
+  }
+
+  /// Allocates the string and pushes it, as `ListBuilder::build` does.
+  pub fn build(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+    self.chars.build(vm)
+  }
+}
+
+impl fmt::Write for StringBuilder {
+  fn write_str(&mut self, s: &str) -> fmt::Result {
+    self.push_str(s);
+    Ok(())
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use core::fmt::Write;
+  use {GcStrategy, VMConfig};
+
+  #[test]
+  fn lists_are_built_in_one_go() {
+    println!("A built list is allocated at once, collecting at most once.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).threshold(8));
+      let mut builder: ListBuilder = (0..100).collect();
+      builder.push(100);
+      let list = builder.build(&mut vm).unwrap();
+      assert!(vm.stats().pauses <= 1);
+      assert!(vm.objects() == 2 * 101 + 1 && vm.stack.len() == 1);
+
+      vm.gc_full();
+      vm.verify().unwrap();
+      assert!(vm.extract::<Vec<u32>>(&list) == Ok((0..101).collect()));
+      let empty = ListBuilder::new().build(&mut vm).unwrap();
+      assert!(vm.extract::<Vec<u32>>(&empty) == Ok(vec![]));
+    }
+  }
+
+  #[test]
+  fn strings_read_back_as_chars() {
+    println!("A string builder takes pieces and formatting.");
+
+    let mut vm = VM::new();
+    let mut builder = StringBuilder::new();
+    builder.push_str("gc ").push('#');
+    write!(builder, "{}", 42).unwrap();
+    assert!(builder.len() == 6);
+    let s = builder.build(&mut vm).unwrap();
+    assert!(vm.extract::<Vec<char>>(&s).map(|cs| cs.into_iter().collect::<String>()) == Ok("gc #42".into()));
+  }
+
+  #[test]
+  fn builds_are_all_or_nothing() {
+    println!("A list that doesn't fit isn't built at all.");
+
+    let mut vm = VM::with_config(VMConfig::new().max_heap(12));
+    vm.push_ints(&[1, 2]).unwrap();
+    let builder: ListBuilder = (0..5).collect();
+    assert!(builder.build(&mut vm).err() == Some(VmError::OutOfMemory));
+    assert!(vm.stack.len() == 2 && vm.objects() == 2);
+    vm.truncate_stack(0);
+    builder.build(&mut vm).unwrap();
+    assert!(vm.objects() == 11);
+  }
+
+  #[test]
+  fn building_mid_cycle() {
+    println!("A list built while marking survives the cycle.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(1).threshold(4));
+    while !vm.collecting() {
+      vm.push_int(0).unwrap();
+    }
+    let list = (0..10).collect::<ListBuilder>().build(&mut vm).unwrap();
+    while !vm.tick() {}
+    vm.verify().unwrap();
+    vm.gc();
+    assert!(vm.extract::<Vec<u32>>(&list) == Ok((0..10).collect()));
+  }
+}
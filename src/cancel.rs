@@ -0,0 +1,114 @@
+// Cancellation from the host. A `CancelToken` is shared between the VM's
+// config and any thread the host likes; once cancelled, allocation fails
+// with `VmError::Cancelled`, a collection stops between slices of work,
+// and `Workload::run` stops before its next operation.
+//
+// A collection cut short is left in progress, just like one `gc_step`
+// ran out of time on, so the VM stays consistent: the next collection
+// picks it up where it stopped.
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use VM;
+
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+  pub fn new() -> CancelToken {
+    CancelToken::default()
+  }
+
+  /// Asks every VM sharing this token to stop. Safe from any thread.
+  pub fn cancel(&self) {
+    self.0.store(true, Ordering::Relaxed);
+  }
+
+  /// Lets the VMs run again, once the host has dealt with the
+  /// cancellation.
+  pub fn reset(&self) {
+    self.0.store(false, Ordering::Relaxed);
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.0.load(Ordering::Relaxed)
+  }
+}
+
+impl VM {
+  /// Whether the host has cancelled this VM through its `CancelToken`.
+  pub fn cancelled(&self) -> bool {
+    self.config.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::thread;
+  use time::Duration;
+  use {VMConfig, VmError, Workload};
+
+  #[test]
+  fn cancelled_vms_refuse_to_allocate() {
+    println!("A cancelled VM fails allocations and recovers once reset.");
+
+    let token = CancelToken::new();
+    let mut vm = VM::with_config(VMConfig::new().cancel_token(token.clone()));
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+
+    token.cancel();
+    assert!(vm.push_int(3).unwrap_err() == VmError::Cancelled);
+    assert!(vm.push_pair().unwrap_err() == VmError::Cancelled);
+    assert!(vm.iter_roots().count() == 2);
+
+    token.reset();
+    vm.push_pair().unwrap();
+    assert!(vm.iter_roots().count() == 1);
+  }
+
+  #[test]
+  fn cancelled_collections_stop_partway() {
+    println!("A cancelled collection is left in progress and finished later.");
+
+    let token = CancelToken::new();
+    let mut vm = VM::with_config(VMConfig::new().cancel_token(token.clone()));
+    vm.gc_paused(|vm| {
+      for i in 0..1000 {
+        vm.push_int(i).unwrap();
+      }
+    });
+    vm.stack.clear();
+
+    token.cancel();
+    vm.gc_full();
+    assert!(vm.collecting() && vm.iter_heap().count() > 0);
+
+    token.reset();
+    vm.gc_full();
+    assert!(!vm.collecting() && vm.iter_heap().count() == 0);
+  }
+
+  #[test]
+  fn another_thread_can_cancel() {
+    println!("A host thread stops a runaway workload.");
+
+    let token = CancelToken::new();
+    let mut vm = VM::with_config(VMConfig::new().cancel_token(token.clone()));
+    let canceller = thread::spawn(move || {
+      thread::sleep(Duration::from_millis(10));
+      token.cancel();
+    });
+
+    let forever: Workload = "ops=18446744073709551615".parse().unwrap();
+    assert!(forever.run(&mut vm, |_, _| {}) == Err(VmError::Cancelled));
+    canceller.join().unwrap();
+  }
+}
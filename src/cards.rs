@@ -0,0 +1,170 @@
+// The card table remembers which stretches of the old generation were
+// written to since the last collection, so a minor collection only has to
+// scan those for pointers into the nursery. The old generation is `heap`
+// in order; each old object knows its index there, and card n covers
+// indices n * CARD_SIZE up to (n + 1) * CARD_SIZE.
+//
+// The table is cleared whenever a collection leaves nothing young for old
+// objects to point at. Stores into old objects still waiting to be swept
+// can't be carded until the sweep gives them an index, so they wait in
+// `unswept_writes`.
+
+use generations::mark_young;
+use tracer::Source;
+use {children, Phase, Sobject, VM};
+
+// Old objects per card.
+pub(crate) const CARD_SIZE: usize = 16;
+
+impl VM {
+  // Moves a survivor into the old generation, a collection older, and counts
+  // it in the age histogram. If it still points at young objects, its card
+  // is dirtied as a store would have done: nothing else tells the next
+  // minor collection about those pointers.
+  pub(crate) fn promote(&mut self, obj: Sobject) {
+    let gch = obj.0.get().with_marked(false).with_old().with_slot(self.heap.len()).aged();
+    obj.0.set(gch);
+    let ages = &mut self.stats.age_histogram;
+    ages[(gch.age() as usize).min(ages.len() - 1)] += 1;
+
+    // Held by the host, it may point anywhere.
+    let mut blocked = false;
+    let young = children(&obj, &mut blocked).any(|child| !child.0.get().old() && !child.0.get().constant());
+    self.heap.push(obj.clone());
+    if young || blocked {
+      self.dirty_card(&obj);
+    }
+  }
+
+  // Notes a store into `obj` for the next minor collection.
+  pub(crate) fn dirty_card(&mut self, obj: &Sobject) {
+    let gch = obj.0.get();
+
+    // Marked objects in the sweep haven't been given their new index yet.
+    // Young ones among them are about to be promoted, and may already
+    // point at objects allocated since the sweep began.
+    if self.phase == Phase::Sweep && gch.marked() {
+      self.unswept_writes.push(obj.clone());
+      return;
+    }
+    if !gch.old() {
+      return;
+    }
+
+    let card = gch.slot() / CARD_SIZE;
+    if self.cards.len() <= card {
+      self.cards.resize(card + 1, false);
+    }
+    self.cards[card] = true;
+  }
+
+  // Once the sweep is done, cards the stores made during it.
+  pub(crate) fn card_unswept_writes(&mut self) {
+    for obj in core::mem::take(&mut self.unswept_writes) {
+      self.dirty_card(&obj);
+    }
+  }
+
+  // Marks the objects in generation `k` or younger that the old objects on
+  // dirty cards point to.
+  pub(crate) fn scan_dirty_cards(&mut self, k: usize) {
+    let mut scanned = 0;
+
+    for (card, _) in self.cards.iter().enumerate().filter(|&(_, &dirty)| dirty) {
+      let start = card * CARD_SIZE;
+      let end = self.heap.len().min(start + CARD_SIZE);
+
+      for obj in &self.heap[start.min(end)..end] {
+        for child in children(obj, &mut self.blocked) {
+          self.note_mark(&child, Source::Object(obj.2), Some(k));
+          mark_young(&child, k, &mut self.gray);
+        }
+      }
+      scanned += 1;
+    }
+
+    self.stats.cards_scanned += scanned;
+    self.stats.last_cards_scanned = scanned;
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {GcStrategy, Generation, VMConfig, Vobject};
+
+  #[test]
+  fn minor_scans_only_dirty_cards() {
+    println!("A minor collection scans the cards written since the last one.");
+
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational));
+    let list = vm.push_value(&vec![0u32; 4 * CARD_SIZE]).unwrap();
+    vm.gc_minor();
+    assert!(vm.stats().last_cards_scanned == 0);
+
+    // Point the list's last pair at a new young object.
+    let mut last = list;
+    while let Some((_, tail)) = vm.as_pair(&last).ok().filter(|(_, tail)| vm.as_pair(tail).is_ok()) {
+      last = tail;
+    }
+    let young = vm.push_int(7).unwrap();
+    vm.pop();
+    if let Vobject::Pair(ref mut head, _) = last.1.borrow_mut().val { *head = young.clone() }
+    vm.write_barrier(&last);
+
+    assert!(vm.gc_minor() == 0);
+    assert!(vm.stats().last_cards_scanned == 1);
+    assert!(vm.as_int(&young) == Ok(7) && young.0.get().old());
+
+    vm.gc_minor();
+    assert!(vm.stats().last_cards_scanned == 0);
+    assert!(vm.stats().cards_scanned == 1);
+  }
+
+  #[test]
+  fn stores_during_a_sweep_are_carded() {
+    println!("Stores into old objects the sweep hasn't reached still get carded.");
+
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational).host_driven(1));
+    let pair = vm.push_value(&(1u32, 2u32)).unwrap();
+    vm.gc_full();
+
+    vm.start_cycle();
+    while vm.phase != Phase::Sweep {
+      vm.tick();
+    }
+    let young = vm.push_int(3).unwrap();
+    vm.pop();
+    if let Vobject::Pair(ref mut head, _) = pair.1.borrow_mut().val { *head = young.clone() }
+    vm.write_barrier(&pair);
+    while !vm.tick() {}
+
+    assert!(vm.gc_minor() == 0);
+    assert!(vm.as_int(&young) == Ok(3));
+  }
+
+  #[test]
+  fn promotion_cards_young_children() {
+    println!("An object promoted while pointing at younger ones gets its card dirtied.");
+
+    let young = [Generation { size: 64, promotion_age: 2 }];
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational).generations(&young));
+    let a = vm.push_value(&(1u32, 2u32)).unwrap();
+    vm.gc_minor();
+    let b = vm.push_int(3).unwrap();
+    vm.set_tail(&a, &b).unwrap();
+    vm.pop();
+
+    // `a` is promoted by the second, `b` not until the third.
+    vm.gc_minor();
+    vm.gc_minor();
+    vm.gc_minor();
+    vm.verify().unwrap();
+    assert!(vm.extract::<(u32, u32)>(&a) == Ok((1, 3)));
+  }
+}
@@ -0,0 +1,183 @@
+// A heap census, after GHC's: the live objects grouped by shape, with how
+// many there are of each and what they cost, to show which structures
+// dominate memory. An object's shape is its kind and its children's:
+// `Int`, `Pair(Int,Pair)`, `Native(regex::Regex)`. The spine of a list in
+// the `Vec` encoding (pairs ending in 0) counts once, as a `Vec` of its
+// length's bucket, rather than as so many pairs; its elements count as
+// themselves.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+use graph::walk;
+use {addr, Sobject, VM, Vobject};
+
+// Upper bounds of the list length buckets, the last open-ended.
+const VEC_BUCKETS: [usize; 3] = [8, 64, 512];
+
+/// One shape's line in a `Census`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CensusEntry {
+  pub shape: String,
+  pub count: usize,
+  pub bytes: usize
+}
+
+/// The live heap by shape, as returned by `VM::census`: the biggest shapes
+/// by bytes first.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Census {
+  pub entries: Vec<CensusEntry>,
+  pub objects: usize,
+  pub bytes: usize
+}
+
+fn kind(obj: &Sobject) -> String {
+  match obj.1.borrow().val {
+    Vobject::Int(_) => String::from("Int"),
+    Vobject::Pair(..) => String::from("Pair"),
+    Vobject::Native(ref native) => format!("Native({})", native.type_name())
+  }
+}
+
+fn shape(obj: &Sobject) -> String {
+  match obj.1.borrow().val {
+    Vobject::Pair(ref head, ref tail) => format!("Pair({},{})", kind(head), kind(tail)),
+    _ => kind(obj)
+  }
+}
+
+fn vec_shape(len: usize) -> String {
+  let mut low = 1;
+  for &high in &VEC_BUCKETS {
+    if len <= high {
+      return format!("Vec[len {}-{}]", low, high);
+    }
+    low = high + 1;
+  }
+  format!("Vec[len {}+]", low)
+}
+
+fn tail(obj: &Sobject) -> Option<Sobject> {
+  match obj.1.borrow().val {
+    Vobject::Pair(_, ref tail) => Some(tail.clone()),
+    _ => None
+  }
+}
+
+impl VM {
+  /// Groups the objects a collection right now would keep by shape.
+  pub fn census(&self) -> Census {
+    let live = walk(self.roots().map(|(_, obj)| obj));
+    let tails: BTreeSet<usize> = live.iter().filter_map(tail).map(|obj| addr(&obj)).collect();
+
+    // Claim list spines first, from pairs no other pair has as its tail.
+    let mut claimed = BTreeSet::new();
+    let mut groups: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    for obj in live.iter().filter(|obj| !tails.contains(&addr(obj))) {
+      let mut spine = Vec::new();
+      let mut next = obj.clone();
+      while let Some(rest) = tail(&next) {
+        if claimed.contains(&addr(&next)) || spine.iter().any(|o| addr(o) == addr(&next)) {
+          break;
+        }
+        spine.push(next);
+        next = rest;
+      }
+      if spine.is_empty() || !matches!(next.1.borrow().val, Vobject::Int(0)) {
+        continue;
+      }
+
+      let group = groups.entry(vec_shape(spine.len())).or_insert((0, 0));
+      group.0 += 1;
+      group.1 += spine.iter().map(|o| self.size_of(o)).sum::<usize>();
+      claimed.extend(spine.iter().map(addr));
+    }
+
+    for obj in live.iter().filter(|obj| !claimed.contains(&addr(obj))) {
+      let group = groups.entry(shape(obj)).or_insert((0, 0));
+      group.0 += 1;
+      group.1 += self.size_of(obj);
+    }
+
+    let mut entries: Vec<CensusEntry> =
+      groups.into_iter().map(|(shape, (count, bytes))| CensusEntry { shape, count, bytes }).collect();
+    entries.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.shape.cmp(&b.shape)));
+    Census { entries, objects: live.len(), bytes: live.iter().map(|obj| self.size_of(obj)).sum() }
+  }
+}
+
+impl fmt::Display for Census {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "{:<32} {:>8} {:>10}", "shape", "count", "bytes")?;
+    for entry in &self.entries {
+      writeln!(f, "{:<32} {:>8} {:>10}", entry.shape, entry.count, entry.bytes)?;
+    }
+    write!(f, "{:<32} {:>8} {:>10}", "total objects", self.objects, self.bytes)
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::string::ToString;
+  use Object;
+
+  fn entry(census: &Census, shape: &str) -> Option<(usize, usize)> {
+    census.entries.iter().find(|e| e.shape == shape).map(|e| (e.count, e.bytes))
+  }
+
+  #[test]
+  fn census_groups_by_shape() {
+    println!("Live objects are grouped by shape, list spines by length.");
+
+    let size = Object::size();
+    let mut vm = VM::new();
+    vm.push_value(&vec![1u32, 2, 3]).unwrap();
+    vm.push_value(&(4u32, (5u32, 6u32))).unwrap();
+    let long: Vec<u32> = (0..20).collect();
+    vm.push_value(&long).unwrap();
+    vm.pop();
+    vm.push_int(7).unwrap();
+    vm.pop();
+
+    let census = vm.census();
+    assert!(entry(&census, "Vec[len 1-8]") == Some((1, 3 * size)));
+    assert!(entry(&census, "Vec[len 9-64]").is_none());
+    // (5 . 6) ends in 6, not 0, so it is no list.
+    assert!(entry(&census, "Pair(Int,Pair)") == Some((1, size)));
+    assert!(entry(&census, "Pair(Int,Int)") == Some((1, size)));
+    assert!(entry(&census, "Int") == Some((7, 7 * size)));
+    assert!(census.objects == 12 && census.bytes == 12 * size);
+    assert!(census.entries[0].shape == "Int");
+    assert!(census.to_string().lines().count() == census.entries.len() + 2);
+  }
+
+  #[test]
+  fn census_handles_cycles_and_shared_tails() {
+    println!("Cyclic and shared spines count as pairs, not lists.");
+
+    let mut vm = VM::new();
+    vm.push_value(&vec![1u32, 2]).unwrap();
+    let (_, shared) = vm.as_pair(&vm.stack[0]).unwrap();
+    vm.push_int(0).unwrap();
+    vm.stack.push(shared);
+    vm.push_pair().unwrap();
+    let p = vm.push_value(&(3u32, 0u32)).unwrap();
+    vm.set_tail(&p, &p).unwrap();
+
+    let census = vm.census();
+    // The shared tail goes to whichever spine reached it first.
+    let lists: usize = census.entries.iter().filter(|e| e.shape.starts_with("Vec")).map(|e| e.count).sum();
+    assert!(lists == 1);
+    assert!(entry(&census, "Pair(Int,Pair)").map(|e| e.0) == Some(2));
+  }
+}
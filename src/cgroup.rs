@@ -0,0 +1,128 @@
+// Sizing the heap to the container. Under a cgroup memory limit the
+// kernel kills a process that goes over it, so a VM that knows the limit
+// would rather collect and, failing that, report `VmError::OutOfMemory`.
+// `VMConfig::cgroup_max_heap` reads the limit and sets `max_heap` to a
+// fraction of it, counted at `Object::size()` per object; allocation then
+// runs full collections as the heap nears it. `VM::refresh_cgroup_limit`
+// reads it again, for limits changed while the process runs.
+//
+// The limit is the cgroup v2 `memory.max` of the process's own cgroup,
+// found through /proc/self/cgroup, or failing that of the root, or the v1
+// `memory.limit_in_bytes`. Off Linux, or with no limit set, `max_heap` is
+// left alone.
+
+use std::fs;
+use std::path::Path;
+use std::string::ToString;
+use std::vec::Vec;
+
+use {Object, VM, VMConfig};
+
+// v1 reports no limit as a huge number rather than "max".
+const UNLIMITED: u64 = 1 << 60;
+
+impl VMConfig {
+  /// Limit the heap to `fraction` of the cgroup memory limit, if there is
+  /// one; see the module source.
+  pub fn cgroup_max_heap(mut self, fraction: f64) -> VMConfig {
+    self.cgroup_fraction = Some(fraction);
+    if let Some(max) = max_objects(Path::new("/"), fraction) {
+      self.max_heap = Some(max);
+    }
+    self
+  }
+}
+
+impl VM {
+  /// Reads the cgroup memory limit again and resets `max_heap` from it,
+  /// returning the new limit in objects. Does nothing unless the VM was
+  /// configured with `cgroup_max_heap`, or if there is no longer a limit.
+  pub fn refresh_cgroup_limit(&mut self) -> Option<usize> {
+    let max = max_objects(Path::new("/"), self.config.cgroup_fraction?)?;
+    self.config.max_heap = Some(max);
+    Some(max)
+  }
+}
+
+// `fraction` of the limit of the cgroup under `root`, in objects.
+fn max_objects(root: &Path, fraction: f64) -> Option<usize> {
+  limit(root).map(|bytes| (bytes as f64 * fraction) as usize / Object::size())
+}
+
+// The memory limit in bytes, as the files under `root` have it.
+fn limit(root: &Path) -> Option<u64> {
+  let own = fs::read_to_string(root.join("proc/self/cgroup")).ok()
+    .and_then(|cgroups| cgroups.lines().find_map(|line| line.strip_prefix("0::").map(str::to_string)));
+  let v2 = root.join("sys/fs/cgroup");
+  let mut candidates = Vec::new();
+  if let Some(own) = own {
+    candidates.push(v2.join(own.trim_start_matches('/')).join("memory.max"));
+  }
+  candidates.push(v2.join("memory.max"));
+  candidates.push(v2.join("memory/memory.limit_in_bytes"));
+
+  // The first of them there decides.
+  candidates.iter()
+    .filter_map(|path| fs::read_to_string(path).ok())
+    .map(|text| text.trim().parse::<u64>().ok().filter(|&bytes| bytes < UNLIMITED))
+    .next()?
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::path::PathBuf;
+  use std::process;
+
+  fn fake_root(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let root = env::temp_dir().join(format!("babygc-cgroup-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&root);
+    for &(path, text) in files {
+      let path = root.join(path);
+      fs::create_dir_all(path.parent().unwrap()).unwrap();
+      fs::write(path, text).unwrap();
+    }
+    root
+  }
+
+  #[test]
+  fn limits_are_found() {
+    println!("The limit comes from the process's own cgroup, the root's, or v1's.");
+
+    let own = fake_root("own", &[
+      ("proc/self/cgroup", "0::/app.slice/vm\n"),
+      ("sys/fs/cgroup/app.slice/vm/memory.max", "1048576\n"),
+      ("sys/fs/cgroup/memory.max", "max\n")
+    ]);
+    assert!(limit(&own) == Some(1 << 20));
+    assert!(max_objects(&own, 0.5) == Some((1 << 19) / Object::size()));
+
+    let unlimited = fake_root("unlimited", &[("sys/fs/cgroup/memory.max", "max\n")]);
+    assert!(limit(&unlimited).is_none());
+    let v1 = fake_root("v1", &[("sys/fs/cgroup/memory/memory.limit_in_bytes", "9223372036854771712\n")]);
+    assert!(limit(&v1).is_none());
+    let v1_set = fake_root("v1-set", &[("sys/fs/cgroup/memory/memory.limit_in_bytes", "4096\n")]);
+    assert!(limit(&v1_set) == Some(4096));
+
+    for root in [own, unlimited, v1, v1_set] {
+      fs::remove_dir_all(root).unwrap();
+    }
+  }
+
+  #[test]
+  fn refreshing_needs_a_fraction() {
+    println!("Only VMs sized to their cgroup re-read its limit.");
+
+    let mut vm = VM::new();
+    assert!(vm.refresh_cgroup_limit().is_none());
+    let mut vm = VM::with_config(VMConfig::new().cgroup_max_heap(0.5));
+    assert!(vm.config.max_heap == max_objects(Path::new("/"), 0.5));
+    assert!(vm.refresh_cgroup_limit() == max_objects(Path::new("/"), 0.5));
+  }
+}
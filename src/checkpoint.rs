@@ -0,0 +1,241 @@
+// Checkpoints: the VM's state at one moment, to roll back to after a
+// speculative evaluation or a failed transaction. A checkpoint is a
+// shallow copy: the stack, the persistent handles, and each object's
+// value, tag and frozen bit, all still pointing at the same objects. Rolling back puts those
+// back in place, so handles the host holds stay valid and see the old
+// values again; objects allocated since are left unreachable, for the next
+// collection.
+//
+// Objects a collection freed after the checkpoint are kept alive by the
+// checkpoint itself, and rolling back gives them back to the VM, as new
+// nursery objects counted against the allocator again. A native value
+// that can't be copied is left in place by a rollback, but if it was freed
+// there is nothing to give back, and the rollback fails.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+
+use marshal::mismatch;
+use {addr, GCHeader, Object, Phase, Sobject, TypeError, VM, VmError, Vobject};
+
+// An object, its value or why that couldn't be copied, its tag and its
+// frozen bit.
+type Saved = (Sobject, Result<Vobject, TypeError>, Option<u64>, bool);
+
+#[derive(Debug)]
+pub struct Checkpoint {
+  vm: Rc<()>,
+  stack: Vec<Sobject>,
+  persistent: BTreeMap<u64, Sobject>,
+  objects: Vec<Saved>
+}
+
+fn copy(val: &Vobject) -> Result<Vobject, TypeError> {
+  val.copy_with(&mut |obj| obj.clone()).ok_or_else(|| mismatch(val, "copyable value"))
+}
+
+impl VM {
+  /// Records the stack, persistent handles and every object's contents,
+  /// for `rollback`.
+  pub fn checkpoint(&self) -> Checkpoint {
+    Checkpoint {
+      vm: self.identity.clone(),
+      stack: self.stack.clone(),
+      persistent: self.persistent.clone(),
+      objects: self.iter_objects().map(|obj| {
+        let o = obj.1.borrow();
+        (obj.clone(), copy(&o.val), o.tag, obj.0.get().frozen())
+      }).collect()
+    }
+  }
+
+  /// Restores the state `checkpoint` recorded. The same checkpoint can be
+  /// rolled back to any number of times. Any collection in progress is
+  /// finished first. Fails with `VmError::ForeignCheckpoint`, changing
+  /// nothing, if the checkpoint was taken from another VM (or from this
+  /// one before a `reset`), with `VmError::OutOfMemory`, changing nothing,
+  /// if the allocator refuses to
+  /// take back the objects freed since, with `VmError::Type`, changing
+  /// nothing, if one of those held a native value that can't be copied,
+  /// or with `VmError::Cancelled` if the host cancels that collection.
+  pub fn rollback(&mut self, checkpoint: &Checkpoint) -> Result<(), VmError> {
+    if !Rc::ptr_eq(&checkpoint.vm, &self.identity) {
+      return Err(VmError::ForeignCheckpoint);
+    }
+    if self.phase != Phase::Idle {
+      self.set_trigger("explicit");
+      self.timed(VM::collect_full);
+      if self.phase != Phase::Idle {
+        return Err(VmError::Cancelled);
+      }
+    }
+
+    let owned: BTreeSet<usize> = self.iter_objects().map(addr).collect();
+    let freed: Vec<_> = checkpoint.objects.iter().filter(|o| !owned.contains(&addr(&o.0))).collect();
+    if let Some(&e) = freed.iter().find_map(|o| o.1.as_ref().err()) {
+      return Err(VmError::Type(e));
+    }
+    let freed: Vec<&Sobject> = freed.into_iter().map(|o| &o.0).collect();
+
+    for i in 0..freed.len() {
+      if !self.config.allocator.allocate(Object::size()) {
+        for _ in 0..i {
+          self.config.allocator.free(Object::size());
+        }
+        return Err(VmError::OutOfMemory);
+      }
+    }
+
+    let revived: BTreeSet<usize> = freed.iter().map(|obj| addr(obj)).collect();
+    for obj in freed {
+      obj.0.set(GCHeader::new(false));
+      self.nursery.push(obj.clone());
+    }
+
+    for &(ref obj, ref val, tag, frozen) in &checkpoint.objects {
+      obj.0.set(obj.0.get().with_frozen(frozen));
+      {
+        let mut o = obj.1.borrow_mut();
+        if let Some(val) = val.as_ref().ok().and_then(|val| copy(val).ok()) {
+          o.val = val;
+        }
+        o.tag = tag;
+      }
+      self.bind_cells(obj);
+      self.dirty_card(obj);
+      if revived.contains(&addr(obj)) {
+        self.record_alloc(obj);
+      } else {
+        self.record_store(obj);
+      }
+    }
+
+    self.stack = checkpoint.stack.clone();
+    self.persistent = checkpoint.persistent.clone();
+    Ok(())
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use core::cell::Cell;
+  use {GcStrategy, NativeObject, ObjectAllocator, Trace, VMConfig, Visitor};
+
+  #[test]
+  fn rollback_restores_values_in_place() {
+    println!("Rolling back undoes stores, pushes and pops on the same objects.");
+
+    for strategy in [GcStrategy::MarkSweep, GcStrategy::Generational] {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy));
+      vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+      let p = vm.push_pair().unwrap();
+      vm.gc();
+      vm.set_tag(&p, 5);
+      let cp = vm.checkpoint();
+
+      let x = vm.push_int(3).unwrap();
+      if let Vobject::Pair(ref mut head, _) = p.1.borrow_mut().val { *head = x }
+      vm.write_barrier(&p);
+      vm.set_tag(&p, 6);
+      vm.freeze(&p);
+      vm.push_int(4).unwrap();
+      vm.gc_full();
+      assert!(vm.extract::<(u32, u32)>(&p) == Ok((3, 2)));
+
+      vm.rollback(&cp).unwrap();
+      assert!(vm.iter_roots().count() == 1 && vm.get_tag(&p) == Some(5) && !vm.is_frozen(&p));
+      assert!(vm.extract::<(u32, u32)>(&p) == Ok((1, 2)));
+
+      // The 1 was freed after the checkpoint; rolling back readopted it.
+      assert!(vm.gc_full() == 2);
+      assert!(vm.iter_heap().count() == 3 && vm.iter_live().count() == 3);
+    }
+  }
+
+  #[test]
+  fn rollback_can_be_refused() {
+    println!("A rollback the allocator can't pay for changes nothing.");
+
+    #[derive(Debug)]
+    struct Full(Cell<bool>);
+
+    impl ObjectAllocator for Full {
+      fn allocate(&self, _bytes: usize) -> bool {
+        !self.0.get()
+      }
+    }
+
+    let full = Rc::new(Full(Cell::new(false)));
+    let mut vm = VM::with_config(VMConfig::new().allocator(full.clone()));
+    vm.push_int(1).unwrap();
+    let cp = vm.checkpoint();
+    vm.pop();
+    vm.gc_full();
+
+    full.0.set(true);
+    assert!(vm.rollback(&cp).unwrap_err() == VmError::OutOfMemory);
+    assert!(vm.iter_roots().count() == 0 && vm.iter_heap().count() == 0);
+
+    full.0.set(false);
+    vm.rollback(&cp).unwrap();
+    vm.rollback(&cp).unwrap();
+    assert!(vm.iter_roots().count() == 1 && vm.iter_heap().count() == 1);
+  }
+
+  #[test]
+  fn checkpoints_belong_to_their_vm() {
+    println!("A checkpoint from one VM can't be rolled back on another.");
+
+    let mut a = VM::new();
+    a.push_int(1).unwrap();
+    let cp = a.checkpoint();
+
+    let mut b = VM::new();
+    b.push_int(2).unwrap();
+    assert!(b.rollback(&cp) == Err(VmError::ForeignCheckpoint));
+    assert!(b.iter_heap().count() == 1 && b.iter_roots().count() == 1);
+    b.verify().unwrap();
+
+    a.reset();
+    assert!(a.rollback(&cp) == Err(VmError::ForeignCheckpoint));
+    assert!(a.iter_heap().count() == 0);
+  }
+
+  #[test]
+  fn freed_natives_refuse_rollback() {
+    println!("A native value that can't be copied survives a rollback, unless it was freed.");
+
+    struct Opaque;
+
+    impl Trace for Opaque {
+      fn trace(&self, _visitor: &mut Visitor<'_>) {}
+    }
+
+    impl NativeObject for Opaque {
+      fn type_name(&self) -> &'static str {
+        "opaque"
+      }
+    }
+
+    let mut vm = VM::new();
+    let native = vm.push_native(Opaque).unwrap();
+    let cp = vm.checkpoint();
+    vm.push_int(1).unwrap();
+    vm.rollback(&cp).unwrap();
+    assert!(vm.with_native(&native, |_: &Opaque| ()).is_ok());
+
+    vm.pop();
+    vm.gc_full();
+    let expected = TypeError { expected: "copyable value", found: "opaque" };
+    assert!(vm.rollback(&cp) == Err(VmError::Type(expected)));
+    assert!(vm.iter_roots().count() == 0 && vm.iter_heap().count() == 0);
+  }
+}
@@ -0,0 +1,178 @@
+// Compaction for idle time. Objects never move (each is its own `Rc`), so
+// the only room there is to get back is in the VM's own lists: the old
+// generation, the nursery, the middle generations, the stack and the gray
+// worklist keep whatever capacity their busiest moment needed. `compact`
+// reallocates them to fit, moving the handles they hold into the smaller
+// buffers, outside any collection; `VMConfig::compact_per_cycle` does a
+// bounded amount of the same at the end of every full collection instead.
+// Lists are never shrunk below the room `VMConfig::reserve_objects` asked
+// for.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::mem;
+
+use {nursery_reserve, Phase, Sobject, VM};
+
+/// What a call to `VM::compact` did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Compaction {
+  /// Handles moved into smaller lists.
+  pub moved: usize,
+  /// Bytes of list capacity handed back to the allocator.
+  pub recovered: usize,
+  /// Bytes of spare capacity left for a later call.
+  pub remaining: usize
+}
+
+impl VM {
+  /// Shrinks the VM's lists to fit, biggest surplus first, moving at most
+  /// `budget` handles. A list longer than what is left of the budget is
+  /// skipped for a later call with a bigger one. Meant for the host's idle
+  /// time; it doesn't collect, and is safe mid-cycle.
+  pub fn compact(&mut self, budget: usize) -> Compaction {
+    let result = self.compact_lists(budget);
+    self.after("compact");
+    result
+  }
+
+  // Also run by the sweep under `VMConfig::compact_per_cycle`.
+  pub(crate) fn compact_lists(&mut self, budget: usize) -> Compaction {
+    let nursery = nursery_reserve(&self.config);
+    let mut lists: Vec<(&mut Vec<Sobject>, usize)> =
+      vec![(&mut self.heap, self.config.reserve), (&mut self.nursery, nursery), (&mut self.stack, 0)];
+    lists.extend(self.middle.iter_mut().map(|gen| (gen, 0)));
+    // Marking takes care of its own worklist; see `trim_gray`.
+    if self.phase == Phase::Idle {
+      lists.push((&mut self.gray, 0));
+    }
+    lists.sort_by_key(|&(ref list, floor)| Reverse(surplus(list, floor)));
+
+    let mut result = Compaction::default();
+    for (list, floor) in lists {
+      let before = surplus(list, floor);
+      if before == 0 {
+        continue;
+      }
+      if list.len() > budget - result.moved {
+        result.remaining += before * mem::size_of::<Sobject>();
+        continue;
+      }
+
+      list.shrink_to(floor);
+      result.moved += list.len();
+      result.recovered += (before - surplus(list, floor)) * mem::size_of::<Sobject>();
+      result.remaining += surplus(list, floor) * mem::size_of::<Sobject>();
+    }
+    result
+  }
+}
+
+// Capacity past what `list` holds and is meant to keep in reserve.
+fn surplus(list: &Vec<Sobject>, floor: usize) -> usize {
+  list.capacity().saturating_sub(list.len().max(floor))
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {GcStrategy, VMConfig};
+
+  #[test]
+  fn compacting_returns_spare_room() {
+    println!("Idle compaction gives back what the lists don't use.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).threshold(1000));
+      let vals: Vec<u32> = (0..200).collect();
+      vm.push_ints(&vals).unwrap();
+      vm.truncate_stack(10);
+      vm.gc_full();
+
+      let capacity = vm.heap_capacity();
+      let done = vm.compact(usize::MAX);
+      assert!(done.moved >= 10 && done.recovered > 0 && done.remaining == 0);
+      assert!(vm.heap_capacity() < capacity);
+      vm.verify().unwrap();
+      assert!(vm.iter_stack().map(|obj| vm.as_int(obj).unwrap()).eq(0..10));
+
+      assert!(vm.compact(usize::MAX) == Compaction::default());
+    }
+  }
+
+  #[test]
+  fn compacting_keeps_to_its_budget() {
+    println!("A small budget skips the longer lists until a bigger one comes.");
+
+    let mut vm = VM::with_config(VMConfig::new().threshold(1000));
+    let vals: Vec<u32> = (0..200).collect();
+    vm.push_ints(&vals).unwrap();
+    vm.truncate_stack(100);
+
+    let partial = vm.compact(50);
+    assert!(partial.moved <= 50 && partial.remaining > 0);
+    let rest = vm.compact(usize::MAX);
+    assert!(rest.moved >= 100 && rest.recovered > 0 && rest.remaining == 0);
+  }
+
+  #[test]
+  fn compacting_respects_the_reservation() {
+    println!("Lists keep the room reserve_objects asked for.");
+
+    let mut vm = VM::with_config(VMConfig::new().reserve_objects(64));
+    vm.push_int(1).unwrap();
+    vm.gc();
+    vm.compact(usize::MAX);
+    assert!(vm.heap_capacity() >= 64);
+  }
+
+  #[test]
+  fn compacting_a_little_each_cycle() {
+    println!("Per-cycle compaction wears the spare room down over several collections.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).threshold(1000).compact_per_cycle(16));
+      let vals: Vec<u32> = (0..300).collect();
+      vm.push_ints(&vals).unwrap();
+      vm.truncate_stack(40);
+      vm.push_ints(&[0; 60]).unwrap();
+      vm.truncate_stack(40);
+
+      let mut capacities = Vec::new();
+      for _ in 0..4 {
+        vm.gc_full();
+        capacities.push(vm.heap_capacity());
+      }
+      assert!(capacities.windows(2).all(|w| w[1] <= w[0]));
+      assert!(vm.stats().bytes_compacted > 0);
+      // The stack is longer than the budget, so it is left as it was.
+      assert!(vm.compact(16).remaining > 0);
+      vm.verify().unwrap();
+    }
+  }
+
+  #[test]
+  fn compacting_mid_cycle() {
+    println!("Compaction between ticks leaves an incremental cycle intact.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(1).threshold(64));
+    for i in 0..100 {
+      vm.push_int(i).unwrap();
+      if i % 2 == 1 {
+        vm.pop();
+      }
+      vm.compact(8);
+    }
+    while !vm.tick() {
+      vm.compact(usize::MAX);
+    }
+    vm.verify().unwrap();
+    assert!(vm.iter_stack().map(|obj| vm.as_int(obj).unwrap()).eq((0..100).step_by(2)));
+  }
+}
@@ -0,0 +1,185 @@
+// Structural comparison of values. Two values are equal if no sequence of
+// heads and tails leads to different ints, or to an int in one and a pair in
+// the other; that terminates on cycles because each pair of objects only
+// needs comparing once. A native value is opaque, and only equal to
+// itself.
+//
+// Hashing looks at a fixed number of nodes of the value unfolded into a
+// tree. Equal values unfold to the same tree, so they hash the same however
+// their cycles are laid out, and big values don't cost more than small ones.
+
+use alloc::collections::BTreeSet;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+
+use {addr, Sobject, VM, Vobject};
+
+impl VM {
+  /// Deep structural equality. Cyclic values are equal if they unfold to
+  /// the same infinite tree.
+  pub fn equals(&self, a: &Sobject, b: &Sobject) -> bool {
+    let mut assumed = BTreeSet::new();
+    let mut todo = Vec::new();
+    todo.push((a.clone(), b.clone()));
+
+    while let Some((a, b)) = todo.pop() {
+      if Rc::ptr_eq(&a, &b) || !assumed.insert((addr(&a), addr(&b))) {
+        continue;
+      }
+
+      match (&a.1.borrow().val, &b.1.borrow().val) {
+        (Vobject::Int(x), Vobject::Int(y)) => {
+          if x != y {
+            return false;
+          }
+        }
+        (Vobject::Pair(ah, at), Vobject::Pair(bh, bt)) => {
+          todo.push((at.clone(), bt.clone()));
+          todo.push((ah.clone(), bh.clone()));
+        }
+        _ => return false
+      }
+    }
+
+    true
+  }
+
+  /// Structural hash, consistent with `equals` and the same from run to
+  /// run. Values that only differ beyond their first `HASH_NODES` nodes
+  /// (in depth-first order) collide.
+  pub fn hash(&self, obj: &Sobject) -> u64 {
+    let mut h = Fnv::new();
+    let mut todo = Vec::new();
+    todo.push(obj.clone());
+
+    for _ in 0..HASH_NODES {
+      let obj = match todo.pop() {
+        Some(obj) => obj,
+        None => break
+      };
+      let val = obj.1.borrow();
+
+      match val.val {
+        Vobject::Int(n) => {
+          h.write(&[0]);
+          h.write(&n.to_le_bytes());
+        }
+        Vobject::Pair(ref head, ref tail) => {
+          h.write(&[1]);
+          todo.push(tail.clone());
+          todo.push(head.clone());
+        }
+        Vobject::Native(ref native) => {
+          h.write(&[2]);
+          h.write(native.type_name().as_bytes());
+        }
+      }
+    }
+
+    h.0
+  }
+
+  /// Hash of the object's identity rather than its contents, for keying
+  /// on handles. Stable while the object is alive.
+  pub fn identity_hash(&self, obj: &Sobject) -> u64 {
+    let mut h = Fnv::new();
+    h.write(&(addr(obj) as u64).to_le_bytes());
+    h.0
+  }
+}
+
+// Nodes of the unfolded value `VM::hash` looks at.
+pub const HASH_NODES: usize = 64;
+
+// 64-bit FNV-1a: simple, and unlike std's hasher not seeded per process.
+struct Fnv(u64);
+
+impl Fnv {
+  fn new() -> Fnv {
+    Fnv(0xcbf2_9ce4_8422_2325)
+  }
+
+  fn write(&mut self, bytes: &[u8]) {
+    for &b in bytes {
+      self.0 ^= u64::from(b);
+      self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // (n . self)
+  fn cycle(vm: &mut VM, n: u32) -> Sobject {
+    vm.push_int(n).unwrap();
+    vm.push_int(0).unwrap();
+    let p = vm.push_pair().unwrap();
+    if let Vobject::Pair(_, ref mut x) = p.1.borrow_mut().val { *x = p.clone() }
+    p
+  }
+
+  #[test]
+  fn equal_structure() {
+    println!("Values compare by structure, not identity.");
+
+    let mut vm = VM::new();
+    let a = vm.push_value(&vec![(1u32, 2u32), (3, 4)]).unwrap();
+    let b = vm.push_value(&vec![(1u32, 2u32), (3, 4)]).unwrap();
+    let c = vm.push_value(&vec![(1u32, 2u32), (3, 5)]).unwrap();
+    let n = vm.push_int(1).unwrap();
+
+    assert!(vm.equals(&a, &b));
+    assert!(!vm.equals(&a, &c));
+    assert!(!vm.equals(&a, &n));
+    assert!(vm.equals(&n, &n));
+  }
+
+  #[test]
+  fn equal_cycles() {
+    println!("Comparing cyclic values terminates.");
+
+    let mut vm = VM::new();
+    let a = cycle(&mut vm, 1);
+    let b = cycle(&mut vm, 1);
+    let c = cycle(&mut vm, 2);
+
+    assert!(vm.equals(&a, &b));
+    assert!(!vm.equals(&a, &c));
+
+    // (1 . (1 . (1 . ...))) unrolled once is still the same infinite list.
+    vm.push_int(1).unwrap();
+    vm.stack.push(a.clone());
+    let d = vm.push_pair().unwrap();
+    assert!(vm.equals(&a, &d));
+  }
+
+  #[test]
+  fn hashes_agree_with_equality() {
+    println!("Equal values hash alike, cycles included.");
+
+    let mut vm = VM::new();
+    let a = cycle(&mut vm, 1);
+    let b = cycle(&mut vm, 1);
+    let c = cycle(&mut vm, 2);
+    assert!(vm.hash(&a) == vm.hash(&b));
+    assert!(vm.hash(&a) != vm.hash(&c));
+    assert!(vm.identity_hash(&a) != vm.identity_hash(&b));
+
+    let x = vm.push_value(&vec![1u32, 2, 3]).unwrap();
+    let y = vm.push_value(&vec![1u32, 2, 3]).unwrap();
+    let z = vm.push_value(&vec![3u32, 2, 1]).unwrap();
+    assert!(vm.hash(&x) == vm.hash(&y));
+    assert!(vm.hash(&x) != vm.hash(&z));
+
+    // Fixed across runs and platforms.
+    let one = vm.push_int(1).unwrap();
+    assert!(vm.hash(&one) == 0x44c1_a3d0_cef6_2cbe);
+  }
+}
@@ -0,0 +1,509 @@
+// VM configuration, built up with chained setters:
+//
+//   VM::with_config(VMConfig::new().strategy(GcStrategy::Generational))
+//
+// or read from the environment with `VMConfig::from_env`.
+
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::str::FromStr;
+#[cfg(feature = "std")]
+use std::env;
+#[cfg(feature = "std")]
+use std::fs::File;
+#[cfg(feature = "std")]
+use std::io::{LineWriter, Write};
+#[cfg(feature = "std")]
+use std::sync::mpsc::Sender;
+#[cfg(feature = "std")]
+use core::cell::RefCell;
+#[cfg(feature = "std")]
+use time::Duration;
+
+use allocator::{GlobalAllocator, ObjectAllocator};
+use arith::OverflowPolicy;
+use cancel::CancelToken;
+#[cfg(feature = "read-barrier")]
+use barrier::ReadBarrier;
+use error::ConfigError;
+#[cfg(feature = "std")]
+use gclog::GcLog;
+use generations::Generation;
+use sizing::{DoublingPolicy, SizingPolicy};
+#[cfg(feature = "std")]
+use telemetry::{GcEvent, Telemetry};
+#[cfg(feature = "std")]
+use tracer::PrintTracer;
+#[cfg(feature = "std")]
+use visual::Visual;
+use tracer::Tracer;
+use Object;
+
+const INITIAL_GC_THRESHOLD: usize = 10;
+const DEFAULT_NURSERY_SIZE: usize = 10;
+
+/// Which collector `VM::gc` (and allocation-triggered collection) runs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GcStrategy {
+  /// Classic stop-the-world mark-sweep over the whole heap.
+  MarkSweep,
+  /// Frequent nursery scavenges with occasional full collections.
+  Generational
+}
+
+impl GcStrategy {
+  /// Every strategy, for running the same thing under each.
+  pub const ALL: [GcStrategy; 2] = [GcStrategy::MarkSweep, GcStrategy::Generational];
+
+  pub fn name(&self) -> &'static str {
+    match *self {
+      GcStrategy::MarkSweep => "mark-sweep",
+      GcStrategy::Generational => "generational"
+    }
+  }
+}
+
+impl FromStr for GcStrategy {
+  type Err = ();
+
+  fn from_str(s: &str) -> Result<GcStrategy, ()> {
+    match s {
+      "mark-sweep" => Ok(GcStrategy::MarkSweep),
+      "generational" => Ok(GcStrategy::Generational),
+      _ => Err(())
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct VMConfig {
+  pub(crate) strategy: GcStrategy,
+  pub(crate) sizing: Rc<dyn SizingPolicy>,
+  pub(crate) allocator: Rc<dyn ObjectAllocator>,
+  pub(crate) threshold: usize,
+  pub(crate) generations: Vec<Generation>,
+  pub(crate) max_heap: Option<usize>,
+  #[cfg(feature = "std")]
+  pub(crate) cgroup_fraction: Option<f64>,
+  pub(crate) skip_survival: Option<f64>,
+  pub(crate) stress: bool,
+  pub(crate) log: bool,
+  #[cfg(feature = "std")]
+  pub(crate) gc_log: Option<GcLog>,
+  pub(crate) history: usize,
+  pub(crate) tick_work: Option<usize>,
+  #[cfg(feature = "std")]
+  pub(crate) pause_target: Option<Duration>,
+  pub(crate) object_quota: Option<u64>,
+  pub(crate) byte_quota: Option<u64>,
+  pub(crate) cancel: Option<CancelToken>,
+  pub(crate) reserve: usize,
+  pub(crate) compact_budget: Option<usize>,
+  pub(crate) shuffle: Option<u64>,
+  pub(crate) overflow: OverflowPolicy,
+  pub(crate) tracer: Option<Rc<dyn Tracer>>,
+  #[cfg(feature = "std")]
+  pub(crate) visual: Option<Visual>,
+  #[cfg(feature = "std")]
+  pub(crate) timeline: bool,
+  #[cfg(feature = "std")]
+  pub(crate) telemetry: Option<Telemetry>,
+  #[cfg(feature = "std")]
+  pub(crate) paranoid: bool,
+  #[cfg(feature = "read-barrier")]
+  pub(crate) read_barrier: Option<Rc<dyn ReadBarrier>>
+}
+
+impl VMConfig {
+  pub fn new() -> VMConfig {
+    VMConfig {
+      strategy: GcStrategy::MarkSweep,
+      sizing: Rc::new(DoublingPolicy),
+      allocator: Rc::new(GlobalAllocator),
+      threshold: INITIAL_GC_THRESHOLD,
+      generations: [Generation { size: DEFAULT_NURSERY_SIZE, promotion_age: 1 }].to_vec(),
+      max_heap: None,
+      #[cfg(feature = "std")]
+      cgroup_fraction: None,
+      skip_survival: None,
+      stress: false,
+      log: false,
+      #[cfg(feature = "std")]
+      gc_log: None,
+      history: 0,
+      tick_work: None,
+      #[cfg(feature = "std")]
+      pause_target: None,
+      object_quota: None,
+      byte_quota: None,
+      cancel: None,
+      reserve: 0,
+      compact_budget: None,
+      shuffle: None,
+      overflow: OverflowPolicy::Error,
+      tracer: None,
+      #[cfg(feature = "std")]
+      visual: None,
+      #[cfg(feature = "std")]
+      timeline: false,
+      #[cfg(feature = "std")]
+      telemetry: None,
+      #[cfg(feature = "std")]
+      paranoid: false,
+      #[cfg(feature = "read-barrier")]
+      read_barrier: None
+    }
+  }
+
+  /// Reads `BABYGC_THRESHOLD`, `BABYGC_STRESS`, `BABYGC_STRATEGY`,
+  /// `BABYGC_OVERFLOW`, `BABYGC_SHUFFLE`, `BABYGC_LOG`, `BABYGC_TRACE`,
+  /// `BABYGC_PARANOID`, `BABYGC_VISUAL` and `BABYGC_LOG_FILE` on top of
+  /// the defaults.
+  #[cfg(feature = "std")]
+  pub fn from_env() -> Result<VMConfig, ConfigError> {
+    VMConfig::from_vars(|var| env::var(var).ok())
+  }
+
+  /// Like `from_env`, but looks variables up with `lookup`.
+  pub fn from_vars<F>(lookup: F) -> Result<VMConfig, ConfigError>
+    where F: Fn(&str) -> Option<String>
+  {
+    let mut config = VMConfig::new();
+
+    if let Some(value) = lookup("BABYGC_THRESHOLD") {
+      config.threshold = parse("BABYGC_THRESHOLD", value)?;
+    }
+
+    if let Some(value) = lookup("BABYGC_STRESS") {
+      config.stress = parse_flag("BABYGC_STRESS", value)?;
+    }
+
+    if let Some(value) = lookup("BABYGC_STRATEGY") {
+      config.strategy = parse("BABYGC_STRATEGY", value)?;
+    }
+
+    if let Some(value) = lookup("BABYGC_OVERFLOW") {
+      config.overflow = parse("BABYGC_OVERFLOW", value)?;
+    }
+
+    if let Some(value) = lookup("BABYGC_SHUFFLE") {
+      config.shuffle = Some(parse("BABYGC_SHUFFLE", value)?);
+    }
+
+    if let Some(value) = lookup("BABYGC_LOG") {
+      config.log = parse_flag("BABYGC_LOG", value)?;
+    }
+
+    #[cfg(feature = "std")]
+    {
+      if let Some(value) = lookup("BABYGC_TRACE") {
+        if parse_flag("BABYGC_TRACE", value)? {
+          config = config.tracer(PrintTracer);
+        }
+      }
+
+      if let Some(value) = lookup("BABYGC_PARANOID") {
+        config = config.paranoid(parse_flag("BABYGC_PARANOID", value)?);
+      }
+
+      if let Some(value) = lookup("BABYGC_VISUAL") {
+        config = match value.trim() {
+          "" => config,
+          "ascii" => config.visual(Visual::Ascii),
+          dir => config.visual(Visual::Dot(dir.into()))
+        };
+      }
+
+      if let Some(path) = lookup("BABYGC_LOG_FILE") {
+        match File::create(&path) {
+          Ok(file) => config = config.gc_log(LineWriter::new(file)),
+          Err(_) => return Err(ConfigError { var: "BABYGC_LOG_FILE", value: path })
+        }
+      }
+    }
+
+    Ok(config)
+  }
+
+  pub fn strategy(mut self, strategy: GcStrategy) -> VMConfig {
+    self.strategy = strategy;
+    self
+  }
+
+  /// What arithmetic does with results that don't fit in an int; the
+  /// default is `OverflowPolicy::Error`.
+  pub fn overflow(mut self, policy: OverflowPolicy) -> VMConfig {
+    self.overflow = policy;
+    self
+  }
+
+  /// Replaces the default `DoublingPolicy`.
+  pub fn sizing<P: SizingPolicy + 'static>(mut self, policy: P) -> VMConfig {
+    self.sizing = Rc::new(policy);
+    self
+  }
+
+  /// Replaces the default `GlobalAllocator`. The VM shares `allocator`
+  /// with the caller, who can keep a clone to read its state.
+  pub fn allocator<A: ObjectAllocator + 'static>(mut self, allocator: Rc<A>) -> VMConfig {
+    self.allocator = allocator;
+    self
+  }
+
+  /// Heap size that triggers the first full collection.
+  pub fn threshold(mut self, n: usize) -> VMConfig {
+    self.threshold = n;
+    self
+  }
+
+  /// Number of young objects that triggers a minor collection under the
+  /// generational strategy.
+  pub fn nursery_size(mut self, n: usize) -> VMConfig {
+    self.generations[0].size = n;
+    self
+  }
+
+  /// The young generations, nursery first, replacing the default of a
+  /// nursery whose survivors go straight to the old generation. Full
+  /// collections still promote everything. Panics unless there are
+  /// between 1 and 16.
+  pub fn generations(mut self, young: &[Generation]) -> VMConfig {
+    assert!(!young.is_empty() && young.len() <= 16, "between 1 and 16 young generations");
+    self.generations = young.to_vec();
+    self
+  }
+
+  /// Hard limit on live objects. Allocating past it runs a full collection
+  /// and fails with `VmError::OutOfMemory` if that doesn't free anything.
+  /// `MAX_OBJECTS` is a limit too, whether or not this is set.
+  pub fn max_heap(mut self, n: usize) -> VMConfig {
+    self.max_heap = Some(n);
+    self
+  }
+
+  /// Never collect spontaneously: allocation only starts a cycle, and each
+  /// `VM::tick` traces or sweeps `work` objects of it. Allocation fails
+  /// with `VmError::GcStarved` if the host falls too far behind.
+  pub fn host_driven(mut self, work: usize) -> VMConfig {
+    self.tick_work = Some(work);
+    self
+  }
+
+  /// Put off a full collection that comes due when the last one kept at
+  /// least `ratio` of the heap, as another would likely free as little.
+  /// The threshold is raised instead, as if the skipped collection had run
+  /// and freed nothing, and `GcStats::skipped_collections` counts it. Only
+  /// one collection in a row is skipped, so the heap is still measured.
+  pub fn skip_survival(mut self, ratio: f64) -> VMConfig {
+    self.skip_survival = Some(ratio);
+    self
+  }
+
+  /// Keep collector pauses under `target` where possible. Full collections
+  /// triggered by allocation then run incrementally, starting early, and
+  /// `GcStats::pause_target_misses` counts the pauses that overran.
+  #[cfg(feature = "std")]
+  pub fn pause_target(mut self, target: Duration) -> VMConfig {
+    self.pause_target = Some(target);
+    self
+  }
+
+  /// Cap on objects allocated (live or dead) until `VM::reset_quota`.
+  /// Allocations past it fail with `VmError::QuotaExceeded`.
+  pub fn object_quota(mut self, n: u64) -> VMConfig {
+    self.object_quota = Some(n);
+    self
+  }
+
+  /// Like `object_quota`, counted in bytes.
+  pub fn byte_quota(mut self, n: u64) -> VMConfig {
+    self.byte_quota = Some(n);
+    self
+  }
+
+  /// Room for `n` objects in the VM's own lists, made up front and kept
+  /// across collections, so allocation doesn't reallocate them until the
+  /// heap holds more. The objects themselves still come from the
+  /// allocator one at a time. `GcStats::reservation_exceeded` counts the
+  /// allocations past it.
+  pub fn reserve_objects(mut self, n: usize) -> VMConfig {
+    self.reserve = n;
+    self
+  }
+
+  /// Like `reserve_objects`, for as many objects as fit in `n` bytes.
+  pub fn reserve_bytes(mut self, n: usize) -> VMConfig {
+    self.reserve = n / Object::size();
+    self
+  }
+
+  /// Compact the VM's lists a little at the end of every full collection,
+  /// as `VM::compact` would with `budget`: those with the most spare room
+  /// first, moving at most `budget` handles, so the pause grows by a
+  /// bounded amount and the spare room goes down over several cycles.
+  /// `GcStats::bytes_compacted` counts what it gives back.
+  pub fn compact_per_cycle(mut self, budget: usize) -> VMConfig {
+    self.compact_budget = Some(budget);
+    self
+  }
+
+  /// Tell `tracer` about every object each collection marks, passes over
+  /// or sweeps, for watching the algorithm at work.
+  pub fn tracer<T: Tracer + 'static>(mut self, tracer: T) -> VMConfig {
+    self.tracer = Some(Rc::new(tracer));
+    self
+  }
+
+  /// Draw the stack and heap after every push, pop, store and requested
+  /// collection, numbering the pictures in order.
+  #[cfg(feature = "std")]
+  pub fn visual(mut self, visual: Visual) -> VMConfig {
+    self.visual = Some(visual);
+    self
+  }
+
+  /// Record every allocation, store and free, and each collection's
+  /// phases, with timestamps, for `VM::timeline_json`. The timeline grows
+  /// for as long as the VM lives.
+  #[cfg(feature = "std")]
+  pub fn timeline(mut self, timeline: bool) -> VMConfig {
+    self.timeline = timeline;
+    self
+  }
+
+  /// Send a `GcEvent` down `sender` for every allocation, collection and
+  /// threshold change, for a monitoring thread to read.
+  #[cfg(feature = "std")]
+  pub fn telemetry(mut self, sender: Sender<GcEvent>) -> VMConfig {
+    self.telemetry = Some(sender);
+    self
+  }
+
+  /// Check the heap after every push, pop, store and requested collection
+  /// (see `VM::verify`), and that nothing changed behind the write
+  /// barrier's back, panicking at the first problem. Very slow, and only
+  /// honoured in debug builds unless the `paranoid` feature is on.
+  #[cfg(feature = "std")]
+  pub fn paranoid(mut self, paranoid: bool) -> VMConfig {
+    self.paranoid = paranoid;
+    self
+  }
+
+  /// Let the host stop the VM by cancelling `token`, from any thread.
+  /// Full collections then run in slices, checking it between them.
+  pub fn cancel_token(mut self, token: CancelToken) -> VMConfig {
+    self.cancel = Some(token);
+    self
+  }
+
+  /// Scramble the order of objects in the VM's lists from `seed`, to flush
+  /// out code that relies on allocation or sweep order; see
+  /// `VM::shuffle_seed`. For tests: it costs a little on every allocation
+  /// and sweep.
+  pub fn shuffle(mut self, seed: u64) -> VMConfig {
+    self.shuffle = Some(seed);
+    self
+  }
+
+  /// Run `barrier` on every read through `VM::as_int`, `as_pair` and
+  /// `extract`.
+  #[cfg(feature = "read-barrier")]
+  pub fn read_barrier<B: ReadBarrier + 'static>(mut self, barrier: B) -> VMConfig {
+    self.read_barrier = Some(Rc::new(barrier));
+    self
+  }
+
+  /// Collect on every allocation. Slow, but flushes out rooting bugs.
+  pub fn stress(mut self, stress: bool) -> VMConfig {
+    self.stress = stress;
+    self
+  }
+
+  /// Keep a snapshot of the heap after each of the last `n` collections,
+  /// for `VM::history`. Every collection then also walks the whole heap.
+  pub fn history(mut self, n: usize) -> VMConfig {
+    self.history = n;
+    self
+  }
+
+  /// Print a line to stderr after every collection. Ignored without `std`.
+  pub fn log(mut self, log: bool) -> VMConfig {
+    self.log = log;
+    self
+  }
+
+  /// Write a line to `out` after every collection, for analyzing long
+  /// runs later: space-separated `time`, `kind`, `trigger`, `before`,
+  /// `after`, `freed`, `pause_us`, `threshold` and `next_threshold`
+  /// fields as `key=value`, always in that order. Write errors are
+  /// ignored. VMs built from clones of this config share `out`.
+  #[cfg(feature = "std")]
+  pub fn gc_log<W: Write + 'static>(mut self, out: W) -> VMConfig {
+    self.gc_log = Some(GcLog(Rc::new(RefCell::new(out))));
+    self
+  }
+}
+
+impl Default for VMConfig {
+  fn default() -> VMConfig {
+    VMConfig::new()
+  }
+}
+
+fn parse<T: FromStr>(var: &'static str, value: String) -> Result<T, ConfigError> {
+  value.trim().parse().map_err(|_| ConfigError { var, value })
+}
+
+fn parse_flag(var: &'static str, value: String) -> Result<bool, ConfigError> {
+  match value.trim() {
+    "" | "0" | "false" | "no" | "off" => Ok(false),
+    "1" | "true" | "yes" | "on" => Ok(true),
+    _ => Err(ConfigError { var, value })
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn vars(pairs: &'static [(&'static str, &'static str)]) -> impl Fn(&str) -> Option<String> {
+    move |var| pairs.iter().find(|p| p.0 == var).map(|p| p.1.to_string())
+  }
+
+  #[test]
+  fn reads_variables() {
+    println!("Environment variables override the defaults.");
+
+    let config = VMConfig::from_vars(vars(&[
+      ("BABYGC_THRESHOLD", "64"),
+      ("BABYGC_STRESS", "1"),
+      ("BABYGC_STRATEGY", "generational"),
+      ("BABYGC_OVERFLOW", "saturate"),
+      ("BABYGC_LOG", "off")
+    ])).unwrap();
+
+    assert!(config.threshold == 64);
+    assert!(config.stress);
+    assert!(config.strategy == GcStrategy::Generational);
+    assert!(config.overflow == OverflowPolicy::Saturate);
+    assert!(!config.log);
+  }
+
+  #[test]
+  fn rejects_bad_values() {
+    println!("Malformed variables name the culprit.");
+
+    let err = VMConfig::from_vars(vars(&[("BABYGC_STRATEGY", "copying")])).unwrap_err();
+    assert!(err.var == "BABYGC_STRATEGY");
+    assert!(err.value == "copying");
+
+    assert!(VMConfig::from_vars(vars(&[("BABYGC_THRESHOLD", "-1")])).is_err());
+    assert!(VMConfig::from_vars(vars(&[("BABYGC_LOG", "maybe")])).is_err());
+  }
+}
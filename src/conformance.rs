@@ -0,0 +1,346 @@
+// A conformance kit for collector configurations. The collector itself
+// is fixed, but a `VMConfig` plugs in a strategy, generations, a sizing
+// policy, an allocator and barriers; `conformance::run` puts a VM built
+// from any of them through the scenarios every collector has to get
+// right, so a student's sizing policy or an embedder's allocator can be
+// checked against the same battery the crate's own settings pass.
+//
+// Each scenario checks two things after every collection: nothing
+// reachable from the roots was dropped from the heap, and values read
+// back as they were written. The VM has no finalizers, so there is no
+// finalizer ordering to check. Nothing ticks a host-driven VM but the
+// incremental scenario, which sets `host_driven` itself, so host-driven
+// configurations starve in the others.
+//
+// `differential` checks two configurations against each other instead:
+// the same workload, run on both, has to leave the same values reachable
+// after every operation, whatever each collector did meanwhile.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt;
+
+use {addr, Action, SnapshotValue, Sobject, VMConfig, VM, VmError, Vobject, Workload};
+
+/// A scenario: builds VMs from the config, failing with what went wrong.
+pub type Scenario = fn(&VMConfig) -> Result<(), String>;
+
+/// Every scenario, by name, in the order `run` runs them.
+pub const SCENARIOS: [(&str, Scenario); 6] = [
+  ("roots are preserved", roots_preserved),
+  ("garbage is collected", garbage_collected),
+  ("cycles are collected", cycles_collected),
+  ("persistent handles are roots", persistent_handles),
+  ("stores during incremental cycles", incremental_stores),
+  ("random workload", random_workload)
+];
+
+/// A scenario a configuration failed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Failure {
+  pub scenario: &'static str,
+  pub problem: String
+}
+
+impl fmt::Display for Failure {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}: {}", self.scenario, self.problem)
+  }
+}
+
+/// Runs every scenario against VMs built from `config`, returning the
+/// ones that failed.
+pub fn run(config: &VMConfig) -> Vec<Failure> {
+  SCENARIOS.iter().filter_map(|&(scenario, f)| {
+    f(config).err().map(|problem| Failure { scenario, problem })
+  }).collect()
+}
+
+/// Runs `workload` on a VM built from each of `a` and `b`, failing at the
+/// first operation after which the stacks, or what they reach, differ.
+/// Objects are compared by id, which both VMs hand out in allocation
+/// order, so sharing and cycles have to match as well as values. Once the
+/// workload is done, full collections have to leave both heaps holding
+/// the same objects.
+pub fn differential(a: &VMConfig, b: &VMConfig, workload: &Workload) -> Result<(), String> {
+  let mut views = Vec::new();
+  let mut vm = VM::with_config(a.clone());
+  let end_a = workload.run(&mut vm, |vm, _| views.push(view(vm)));
+  let heap_a = settle(&mut vm);
+
+  let mut vm = VM::with_config(b.clone());
+  let mut ops = 0;
+  let mut problem = None;
+  let end_b = workload.run(&mut vm, |vm, action| {
+    if problem.is_none() && views.get(ops) != Some(&view(vm)) {
+      problem = Some(format!("the heaps differ after operation {} ({:?})", ops, action));
+    }
+    ops += 1;
+  });
+  if let Some(problem) = problem {
+    return Err(problem);
+  }
+  if (views.len(), end_a) != (ops, end_b) {
+    return Err(format!("the first {} and the second {}", ending(views.len(), end_a), ending(ops, end_b)));
+  }
+  expect(settle(&mut vm) == heap_a, "full collections left different objects")
+}
+
+// The stack's ids, and every object reachable from the roots by id.
+type View = (Vec<u64>, BTreeMap<u64, SnapshotValue>);
+
+fn view(vm: &VM) -> View {
+  let stack = vm.iter_roots().map(|obj| vm.object_id(obj)).collect();
+  let mut objects = BTreeMap::new();
+  let mut todo: Vec<Sobject> = vm.iter_roots().chain(vm.iter_persistent()).cloned().collect();
+  while let Some(obj) = todo.pop() {
+    let id = vm.object_id(&obj);
+    if objects.contains_key(&id) {
+      continue;
+    }
+    let o = obj.1.borrow();
+    todo.extend(o.val.children());
+    let value = match o.val {
+      Vobject::Int(n) => SnapshotValue::Int(n),
+      Vobject::Pair(ref head, ref tail) => SnapshotValue::Pair(vm.object_id(head), vm.object_id(tail)),
+      Vobject::Native(ref native) => SnapshotValue::Native(native.type_name())
+    };
+    objects.insert(id, value);
+  }
+  (stack, objects)
+}
+
+// The ids left in the heap after full collections. The first may only
+// finish a cycle that was already under way.
+fn settle(vm: &mut VM) -> BTreeSet<u64> {
+  vm.gc_full();
+  vm.gc_full();
+  vm.iter_heap().map(|obj| vm.object_id(obj)).collect()
+}
+
+fn ending(ops: usize, end: Result<(), VmError>) -> String {
+  match end {
+    Ok(()) => format!("ran {} operations", ops),
+    Err(e) => format!("failed after {} operations: {}", ops, e)
+  }
+}
+
+fn alloc<T>(result: Result<T, VmError>) -> Result<T, String> {
+  result.map_err(|e| e.to_string())
+}
+
+// Fails if anything reachable from the roots has left the heap.
+fn check(vm: &VM) -> Result<(), String> {
+  let heap: BTreeSet<usize> = vm.iter_heap().map(addr).collect();
+  let mut seen = BTreeSet::new();
+  let mut todo: Vec<Sobject> = vm.iter_roots().chain(vm.iter_persistent()).cloned().collect();
+
+  while let Some(obj) = todo.pop() {
+    if vm.is_constant(&obj) || !seen.insert(addr(&obj)) {
+      continue;
+    }
+    if !heap.contains(&addr(&obj)) {
+      return Err(format!("#{} is reachable but was freed", vm.object_id(&obj)));
+    }
+    todo.extend(obj.1.borrow().val.children());
+  }
+  Ok(())
+}
+
+fn expect(ok: bool, problem: &str) -> Result<(), String> {
+  if ok { Ok(()) } else { Err(problem.to_string()) }
+}
+
+fn roots_preserved(config: &VMConfig) -> Result<(), String> {
+  let mut vm = VM::with_config(config.clone());
+  let one = alloc(vm.push_int(1))?;
+  let pair = alloc(vm.push_value(&((2u32, 3u32), 4u32)))?;
+
+  for collect in [VM::gc, VM::gc_minor, VM::gc_full] {
+    collect(&mut vm);
+    check(&vm)?;
+    expect(vm.extract::<u32>(&one) == Ok(1) && vm.extract::<((u32, u32), u32)>(&pair) == Ok(((2, 3), 4)),
+           "a root's value changed")?;
+  }
+  expect(vm.iter_heap().count() == 6, "a collection freed part of a root")
+}
+
+fn garbage_collected(config: &VMConfig) -> Result<(), String> {
+  let mut vm = VM::with_config(config.clone());
+  let kept = alloc(vm.push_int(1))?;
+  for i in 0..20 {
+    alloc(vm.push_value(&(i, i)))?;
+    vm.pop();
+  }
+
+  // The first may only finish a cycle that was already under way.
+  vm.gc_full();
+  vm.gc_full();
+  check(&vm)?;
+  expect(vm.iter_heap().count() == 1 && vm.extract::<u32>(&kept) == Ok(1),
+         "a full collection left garbage behind")
+}
+
+fn cycles_collected(config: &VMConfig) -> Result<(), String> {
+  let mut vm = VM::with_config(config.clone());
+  for kept in [true, false] {
+    alloc(vm.push_int(1))?;
+    alloc(vm.push_int(2))?;
+    let a = alloc(vm.push_pair())?;
+    alloc(vm.push_int(3))?;
+    alloc(vm.push_int(4))?;
+    let b = alloc(vm.push_pair())?;
+    alloc(vm.set_tail(&a, &b))?;
+    alloc(vm.set_tail(&b, &a))?;
+    vm.pop();
+    if !kept {
+      vm.pop();
+    }
+  }
+
+  vm.gc_full();
+  vm.gc_full();
+  check(&vm)?;
+  expect(vm.iter_heap().count() == 4, "an unreachable cycle survived a full collection")
+}
+
+fn persistent_handles(config: &VMConfig) -> Result<(), String> {
+  let mut vm = VM::with_config(config.clone());
+  let pair = alloc(vm.push_value(&(1u32, 2u32)))?;
+  let handle = vm.persist(&pair);
+  vm.pop();
+
+  vm.gc_minor();
+  vm.gc_full();
+  check(&vm)?;
+  expect(vm.extract::<(u32, u32)>(&pair) == Ok((1, 2)), "a persistent value changed")?;
+
+  vm.release(handle);
+  vm.gc_full();
+  expect(vm.iter_heap().count() == 0, "a released value survived a full collection")
+}
+
+// Stores a new object into an old pair, and moves an unmarked one behind
+// it, at every point of a cycle run one object at a time. Stress mode is
+// off, or the cycle would start before there was anything to store.
+fn incremental_stores(config: &VMConfig) -> Result<(), String> {
+  for after in 0..12 {
+    let mut vm = VM::with_config(config.clone().host_driven(1).threshold(8).stress(false));
+    let pair = alloc(vm.push_value(&((1u32, 2u32), 3u32)))?;
+    let loose = alloc(vm.push_int(4))?;
+    while !vm.collecting() {
+      alloc(vm.push_int(0))?;
+      vm.pop();
+    }
+    for _ in 0..after {
+      vm.tick();
+    }
+
+    let new = alloc(vm.push_int(5))?;
+    let (inner, _) = vm.as_pair(&pair).map_err(|e| e.to_string())?;
+    alloc(vm.set_head(&inner, &new))?;
+    alloc(vm.set_tail(&pair, &loose))?;
+    vm.stack.truncate(1);
+
+    while !vm.tick() {}
+    check(&vm)?;
+    expect(vm.extract::<((u32, u32), u32)>(&pair) == Ok(((5, 2), 4)), "a store made mid-cycle was lost")?;
+  }
+  Ok(())
+}
+
+fn random_workload(config: &VMConfig) -> Result<(), String> {
+  let workload = Workload { list: 1, ops: 1_000, seed: 17, ..Workload::default() };
+  let mut vm = VM::with_config(config.clone());
+  let mut problem = None;
+  let mut pauses = 0;
+
+  // Checking the whole heap is slow, so it's only after collections.
+  alloc(workload.run(&mut vm, |vm, action| {
+    if problem.is_none() && (action == Action::Gc || vm.stats().pauses != pauses) {
+      problem = check(vm).err();
+    }
+    pauses = vm.stats().pauses;
+  }))?;
+  match problem {
+    Some(problem) => Err(problem),
+    None => Ok(())
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[cfg(feature = "std")]
+  use time::Duration;
+  #[cfg(feature = "std")]
+  use {Generation, GcStrategy};
+
+  #[test]
+  #[cfg(feature = "std")]
+  fn shipped_configurations_conform() {
+    println!("Every strategy and mode passes the conformance scenarios.");
+
+    let configs = [
+      VMConfig::new(),
+      VMConfig::new().strategy(GcStrategy::Generational),
+      VMConfig::new().strategy(GcStrategy::Generational).generations(&[Generation { size: 8, promotion_age: 2 }]),
+      VMConfig::new().strategy(GcStrategy::Generational)
+        .generations(&[Generation { size: 4, promotion_age: 2 }, Generation { size: 16, promotion_age: 3 }]),
+      VMConfig::new().stress(true),
+      VMConfig::new().pause_target(Duration::from_micros(1))
+    ];
+    for (i, config) in configs.iter().enumerate() {
+      let failures = run(config);
+      assert!(failures.is_empty(), "{}: {:?}", i, failures);
+    }
+  }
+
+  #[test]
+  #[cfg(feature = "std")]
+  fn strategies_agree_on_random_workloads() {
+    println!("Every strategy leaves the same values reachable, operation by operation.");
+
+    let configs = [
+      VMConfig::new().strategy(GcStrategy::Generational),
+      VMConfig::new().strategy(GcStrategy::Generational).generations(&[Generation { size: 8, promotion_age: 2 }]),
+      VMConfig::new().strategy(GcStrategy::Generational)
+        .generations(&[Generation { size: 4, promotion_age: 2 }, Generation { size: 16, promotion_age: 3 }]),
+      VMConfig::new().stress(true),
+      VMConfig::new().pause_target(Duration::from_micros(1))
+    ];
+    for seed in 1..9 {
+      let workload = Workload { list: 1, depth: (1, 8), max_stack: 32, ops: 300, seed, ..Workload::default() };
+      for (i, config) in configs.iter().enumerate() {
+        let result = differential(&VMConfig::new().threshold(16), &config.clone().threshold(16), &workload);
+        assert!(result.is_ok(), "seed {}, config {}: {:?}", seed, i, result);
+      }
+    }
+  }
+
+  #[test]
+  fn divergence_is_reported() {
+    println!("A configuration that behaves differently is caught.");
+
+    let workload = Workload { gc: 0, drop: 0, ops: 100, ..Workload::default() };
+    let result = differential(&VMConfig::new(), &VMConfig::new().max_heap(20), &workload);
+    assert!(result == Err(String::from("the first ran 100 operations and the second failed after 21 operations: out of memory")));
+  }
+
+  #[test]
+  fn failures_name_their_scenario() {
+    println!("A failing configuration is reported scenario by scenario.");
+
+    // A quota too small for any scenario.
+    let failures = run(&VMConfig::new().max_heap(0));
+    assert!(failures.len() == SCENARIOS.len());
+    assert!(failures[0].to_string() == "roots are preserved: out of memory");
+  }
+}
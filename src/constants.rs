@@ -0,0 +1,203 @@
+// A shared space of constants, for hosts running many VMs on one thread
+// (one per request or actor). Each constant is created once, in the
+// space, and any number of VMs can hold it: it lives in no VM's heap, so
+// no VM ever sweeps it, and its header is permanently marked, so marking
+// stops there instead of walking the space over and over. Constants only
+// point at other constants, and must never be stored into.
+//
+// Dumps and images list the constants a VM refers to after its own
+// objects, as ordinary objects; a VM loaded from an image has its own
+// copies.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+
+use {addr, GCHeader, Object, Sobject, VM, VmError, Vobject, CONSTANT, MARKED};
+
+// Constant ids have the top bit set, so they never clash with a VM's own.
+const CONSTANT_IDS: u64 = 1 << 63;
+
+/// Interned ints and pairs shared by every VM that uses them. Dropping
+/// the space drops its constants once no VM refers to them any more.
+#[derive(Debug, Default)]
+pub struct ConstantSpace {
+  ints: RefCell<BTreeMap<u32, Sobject>>,
+  pairs: RefCell<BTreeMap<(usize, usize), Sobject>>,
+  next_id: Cell<u64>
+}
+
+impl ConstantSpace {
+  pub fn new() -> ConstantSpace {
+    ConstantSpace::default()
+  }
+
+  fn make(&self, val: Vobject) -> Sobject {
+    let id = CONSTANT_IDS | self.next_id.get();
+    self.next_id.set(self.next_id.get() + 1);
+    Rc::new((Cell::new(GCHeader(MARKED | CONSTANT)), RefCell::new(Object { val, tag: None }), id))
+  }
+
+  /// The constant for `n`, made the first time it is asked for.
+  pub fn int(&self, n: u32) -> Sobject {
+    if let Some(obj) = self.ints.borrow().get(&n) {
+      return obj.clone();
+    }
+
+    let obj = self.make(Vobject::Int(n));
+    self.ints.borrow_mut().insert(n, obj.clone());
+    obj
+  }
+
+  /// The constant pair of `head` and `tail`, which must be constants
+  /// themselves.
+  pub fn pair(&self, head: &Sobject, tail: &Sobject) -> Sobject {
+    assert!(head.0.get().constant() && tail.0.get().constant(), "constant pairs can only hold constants");
+
+    let key = (addr(head), addr(tail));
+    if let Some(obj) = self.pairs.borrow().get(&key) {
+      return obj.clone();
+    }
+
+    let obj = self.make(Vobject::Pair(head.clone(), tail.clone()));
+    self.pairs.borrow_mut().insert(key, obj.clone());
+    obj
+  }
+
+  pub fn len(&self) -> usize {
+    self.ints.borrow().len() + self.pairs.borrow().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+impl VM {
+  /// Pushes a constant from a `ConstantSpace`. Nothing is allocated, so
+  /// this can't fail or trigger a collection.
+  pub fn push_constant(&mut self, obj: &Sobject) {
+    assert!(self.try_push_constant(obj).is_ok(), "push_constant needs a constant");
+  }
+
+  /// Like `push_constant`, but fails with `VmError::NotConstant` rather
+  /// than panicking.
+  pub fn try_push_constant(&mut self, obj: &Sobject) -> Result<(), VmError> {
+    if !obj.0.get().constant() {
+      return Err(VmError::NotConstant);
+    }
+
+    self.stack.push(obj.clone());
+    self.after("push_constant");
+    Ok(())
+  }
+
+  /// Whether `obj` lives in a `ConstantSpace` rather than a VM's heap.
+  pub fn is_constant(&self, obj: &Sobject) -> bool {
+    obj.0.get().constant()
+  }
+
+  // The constants the stack and heap refer to, directly or not, each
+  // once.
+  fn constants_in_use(&self) -> Vec<Sobject> {
+    let mut seen = BTreeSet::new();
+    let mut found = Vec::new();
+    let mut todo: Vec<Sobject> = self.roots().map(|(_, obj)| obj).filter(|obj| obj.0.get().constant()).collect();
+
+    for obj in self.iter_objects() {
+      todo.extend(obj.1.borrow().val.children().filter(|obj| obj.0.get().constant()));
+    }
+
+    while let Some(obj) = todo.pop() {
+      if !seen.insert(addr(&obj)) {
+        continue;
+      }
+
+      let at = todo.len();
+      todo.extend(obj.1.borrow().val.children());
+      todo[at..].reverse();
+      found.push(obj);
+    }
+
+    found
+  }
+
+  // Everything dumps and images describe: the VM's own objects, then the
+  // constants they use.
+  pub(crate) fn dumped(&self) -> Vec<Sobject> {
+    let mut objs: Vec<Sobject> = self.iter_objects().cloned().collect();
+    objs.extend(self.constants_in_use());
+    objs
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {GcStrategy, VMConfig};
+
+  #[test]
+  fn constants_are_interned() {
+    println!("Asking twice for the same constant gives the same object.");
+
+    let space = ConstantSpace::new();
+    let one = space.int(1);
+    assert!(Rc::ptr_eq(&one, &space.int(1)));
+    let p = space.pair(&one, &space.int(2));
+    assert!(Rc::ptr_eq(&p, &space.pair(&one, &space.int(2))));
+    assert!(space.len() == 3);
+
+    let vm = VM::new();
+    assert!(vm.is_constant(&p) && vm.object_id(&p) >= CONSTANT_IDS);
+  }
+
+  #[test]
+  fn vms_share_without_sweeping() {
+    println!("Constants survive every VM's collections and count in none.");
+
+    let space = ConstantSpace::new();
+    let shared = space.pair(&space.int(1), &space.int(2));
+
+    let mut a = VM::new();
+    let mut b = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational));
+    for vm in [&mut a, &mut b] {
+      vm.push_constant(&shared);
+      vm.push_int(3).unwrap();
+      vm.push_pair().unwrap();
+      vm.push_constant(&shared);
+      vm.gc();
+      vm.gc_full();
+      assert_heap!(vm, heap: 2, live: 2);
+      assert!(vm.reachable_from(&shared).count() == 3);
+    }
+
+    a.pop();
+    a.pop();
+    assert!(a.gc_full() == 2);
+    assert!(Rc::ptr_eq(b.iter_roots().last().unwrap(), &shared));
+    assert!(b.extract::<(u32, u32)>(&shared) == Ok((1, 2)));
+  }
+
+  #[test]
+  fn dumps_copy_constants() {
+    println!("Dumps and images include the constants a VM uses.");
+
+    let space = ConstantSpace::new();
+    let mut vm = VM::new();
+    vm.push_constant(&space.int(7));
+    vm.push_int(8).unwrap();
+    vm.push_pair().unwrap();
+    assert!(vm.dumped().len() == 3);
+
+    let copy = VM::from_image(&vm.to_image().unwrap()).unwrap();
+    assert!(copy.iter_heap().count() == 3);
+    assert!(!copy.iter_heap().any(|obj| copy.is_constant(obj)));
+    assert!(copy.equals(copy.iter_roots().next().unwrap(), vm.iter_roots().next().unwrap()));
+  }
+}
@@ -0,0 +1,144 @@
+// `list!` and `pair!`, for building nested values in one expression:
+//
+//   list!(vm, 1, 2, (3, 4), [5, 6])
+//
+// Each element is an int, a handle, a `(head, tail)` pair or a `[...]`
+// list, nested as deeply as needed; anything else has to be in
+// parentheses. Lists are encoded as `Vec` is, pairs ending in 0. The
+// macros push the parts in order and pair them off as they go, so
+// everything built so far is on the stack, and rooted, while the rest is
+// allocated. The value built ends up on top of the stack, and is
+// returned; if an allocation fails the stack is put back as it was.
+
+use {Sobject, VM, VmError};
+
+/// Pushes a list built from its elements; see the module source.
+#[macro_export]
+macro_rules! list {
+  ($vm:expr $(, $x:tt)*) => {{
+    let vm: &mut $crate::VM = &mut $vm;
+    $crate::construct::build(vm, |vm| {
+      list!(@list vm $(, $x)*);
+      Ok(())
+    })
+  }};
+  (@list $vm:ident $(, $x:tt)*) => {
+    $( list!(@item $vm, $x); )*
+    $vm.push_int(0)?;
+    $( list!(@cons $vm, $x); )*
+  };
+  (@cons $vm:ident, $x:tt) => {
+    $vm.push_pair()?;
+  };
+  (@item $vm:ident, ($head:tt, $tail:tt)) => {
+    list!(@item $vm, $head);
+    list!(@item $vm, $tail);
+    $vm.push_pair()?;
+  };
+  (@item $vm:ident, [$($x:tt),*]) => {
+    list!(@list $vm $(, $x)*);
+  };
+  (@item $vm:ident, $x:expr) => {
+    $crate::construct::Element::push_element($x, $vm)?;
+  };
+}
+
+/// Pushes a pair of two elements, as `list!` takes them.
+#[macro_export]
+macro_rules! pair {
+  ($vm:expr, $head:tt, $tail:tt) => {{
+    let vm: &mut $crate::VM = &mut $vm;
+    $crate::construct::build(vm, |vm| {
+      list!(@item vm, ($head, $tail));
+      Ok(())
+    })
+  }};
+}
+
+// What `list!` and `pair!` accept as a leaf: an int, allocated, or a
+// handle, pushed as it is.
+#[doc(hidden)]
+pub trait Element {
+  fn push_element(self, vm: &mut VM) -> Result<(), VmError>;
+}
+
+impl Element for u32 {
+  fn push_element(self, vm: &mut VM) -> Result<(), VmError> {
+    vm.push_int(self).map(|_| ())
+  }
+}
+
+impl Element for &Sobject {
+  fn push_element(self, vm: &mut VM) -> Result<(), VmError> {
+    if vm.is_freed(self) {
+      return Err(VmError::Freed);
+    }
+    vm.stack.push(self.clone());
+    Ok(())
+  }
+}
+
+impl Element for Sobject {
+  fn push_element(self, vm: &mut VM) -> Result<(), VmError> {
+    (&self).push_element(vm)
+  }
+}
+
+// Runs the pushes a macro expanded to, leaving the stack as it was if one
+// fails.
+#[doc(hidden)]
+pub fn build<F>(vm: &mut VM, f: F) -> Result<Sobject, VmError>
+  where F: FnOnce(&mut VM) -> Result<(), VmError>
+{
+  let n = vm.stack.len();
+  match f(vm) {
+    Ok(()) => Ok(vm.stack.last().unwrap().clone()),
+    Err(e) => {
+      vm.truncate_stack(n);
+      Err(e)
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use alloc::vec::Vec;
+  use {GcStrategy, VMConfig, VM, VmError};
+
+  #[test]
+  fn macros_build_nested_values() {
+    println!("list! and pair! build what they say, rooted all the way.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).stress(true));
+      let list = list!(vm, 1, 2, 3).unwrap();
+      assert!(vm.extract::<Vec<u32>>(&list) == Ok(vec![1, 2, 3]));
+
+      let n = 7;
+      let p = pair!(vm, (n + 1), [4, 5]).unwrap();
+      assert!(vm.extract::<(u32, Vec<u32>)>(&p) == Ok((8, vec![4, 5])));
+
+      let nested = list!(vm, (1, (2, 3)), [], list, p).unwrap();
+      let back = vm.extract::<((u32, (u32, u32)), (Vec<u32>, (Vec<u32>, ((u32, Vec<u32>), u32))))>(&nested);
+      assert!(back == Ok(((1, (2, 3)), (vec![], (vec![1, 2, 3], ((8, vec![4, 5]), 0))))));
+      assert!(vm.stack.len() == 3);
+      vm.verify().unwrap();
+    }
+  }
+
+  #[test]
+  fn failed_macros_leave_the_stack_alone() {
+    println!("A structure that doesn't fit leaves nothing half-built on the stack.");
+
+    let mut vm = VM::with_config(VMConfig::new().max_heap(6));
+    vm.push_int(9).unwrap();
+    assert!(list!(vm, 1, 2, 3).err() == Some(VmError::OutOfMemory));
+    assert!(vm.stack.len() == 1);
+    assert!(pair!(vm, 1, 2).is_ok());
+  }
+}
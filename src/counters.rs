@@ -0,0 +1,121 @@
+// The collector's running totals in one place, for a host that polls them:
+// how many collections have finished, what they freed, how long they took,
+// when the last one ended and which strategy is in use. Reading them never
+// starts a collection.
+
+use alloc::string::String;
+use core::fmt::Write;
+
+#[cfg(feature = "std")]
+use time::Instant;
+use time::Duration;
+
+use VM;
+
+/// What `VM::counters` returns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GcCounters {
+  /// Full and minor collections finished.
+  pub collections: u64,
+  pub objects_freed: u64,
+  pub total_pause: Duration,
+  /// When the last collection finished, if one has.
+  #[cfg(feature = "std")]
+  pub last_collection: Option<Instant>,
+  /// `GcStrategy::name` of the configured strategy.
+  pub strategy: &'static str
+}
+
+impl VM {
+  pub fn counters(&self) -> GcCounters {
+    GcCounters {
+      collections: self.stats.full_collections + self.stats.minor_collections,
+      objects_freed: self.stats.objects_freed,
+      total_pause: self.stats.total_pause,
+      #[cfg(feature = "std")]
+      last_collection: self.stats.last_collection,
+      strategy: self.strategy_name()
+    }
+  }
+
+  pub fn strategy_name(&self) -> &'static str {
+    self.config.strategy.name()
+  }
+
+  /// The counters, with the heap's size and threshold, as a JSON object.
+  pub fn counters_json(&self) -> String {
+    let counters = self.counters();
+    let mut out = String::new();
+    let _ = write!(out, "{{\"strategy\": \"{}\", \"objects\": {}, \"bytes\": {}, \"threshold\": {}, ",
+                   counters.strategy, self.heap_len(), self.bytes_allocated(), self.threshold());
+    let _ = write!(out, "\"collections\": {}, \"objects_freed\": {}, \"pauses\": {}, ",
+                   counters.collections, counters.objects_freed, self.stats.pauses);
+    let _ = write!(out, "\"total_pause\": {}, \"max_pause\": {}, \"peak_objects\": {}}}",
+                   counters.total_pause.as_secs_f64(), self.stats.max_pause.as_secs_f64(), self.stats.peak_objects);
+    out
+  }
+
+  // Called as each collection, full or minor, finishes.
+  pub(crate) fn count_collection(&mut self, freed: usize) {
+    self.stats.objects_freed += freed as u64;
+    #[cfg(feature = "std")]
+    {
+      self.stats.last_collection = Some(Instant::now());
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[cfg(feature = "std")]
+  use {GcStrategy, VMConfig};
+
+  #[test]
+  #[cfg(feature = "std")]
+  fn counters_add_up() {
+    println!("Counters total every collection and can be read at any time.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy));
+      let counters = vm.counters();
+      assert!(counters.collections == 0 && counters.objects_freed == 0);
+      assert!(counters.last_collection.is_none());
+      assert!(counters.strategy == strategy.name());
+
+      for i in 0..3 {
+        vm.push_value(&(1u32, i)).unwrap();
+        vm.pop();
+        vm.gc_full();
+      }
+      let counters = vm.counters();
+      assert!(counters.collections == vm.stats().full_collections + vm.stats().minor_collections);
+      assert!(counters.collections >= 3 && counters.objects_freed == 9);
+      assert!(counters.total_pause == vm.stats().total_pause);
+      let last = counters.last_collection.unwrap();
+
+      // Reading them again changes nothing.
+      assert!(vm.counters() == counters);
+      assert!(last <= Instant::now());
+    }
+  }
+
+  #[test]
+  fn counters_as_json() {
+    extern crate serde_json;
+
+    println!("The counters serialize as one JSON object.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.gc();
+    let doc: serde_json::Value = serde_json::from_str(&vm.counters_json()).unwrap();
+    assert!(doc["strategy"] == "mark-sweep" && doc["objects"] == 1 && doc["collections"] == 1);
+    assert!(doc["total_pause"].as_f64().unwrap() >= 0.0);
+  }
+}
@@ -0,0 +1,248 @@
+// Collector work bounded by wall-clock time: gc_step, notify_idle and
+// pause-target pacing. All of it needs a clock, so it only exists with the
+// `std` feature.
+
+use core::cmp;
+
+use time::{Duration, Instant};
+#[cfg(feature = "metrics-facade")]
+use facade;
+use stats::PAUSE_BUCKETS;
+use {GcStrategy, Phase, VM, GC_STEP_WORK, STARVATION_FACTOR};
+
+impl VM {
+  /// Performs as much of a full collection cycle as fits in `budget`,
+  /// starting a new cycle if none is in progress. Returns true if the
+  /// cycle completed. Objects allocated mid-cycle survive it.
+  pub fn gc_step(&mut self, budget: Duration) -> bool {
+    if self.phase == Phase::Idle {
+      self.set_trigger("step");
+      self.start_cycle();
+    }
+
+    self.timed(|vm| vm.run_until(Instant::now() + budget))
+  }
+
+  /// Hint that the host is idle until `deadline`. Continues any cycle in
+  /// progress, or starts one if the heap is at least halfway to its next
+  /// collection, but never works past the deadline. Returns true if no
+  /// collection work is left pending.
+  pub fn notify_idle(&mut self, deadline: Instant) -> bool {
+    if Instant::now() >= deadline {
+      return self.phase == Phase::Idle;
+    }
+
+    if self.phase == Phase::Idle {
+      if self.objects() * 2 < self.heap_max {
+        return true;
+      }
+
+      self.set_trigger("idle");
+      self.start_cycle();
+    }
+
+    self.timed(|vm| vm.run_until(deadline))
+  }
+
+  fn run_until(&mut self, deadline: Instant) -> bool {
+    loop {
+      if self.cycle_step(GC_STEP_WORK) {
+        return true;
+      }
+
+      if self.blocked || Instant::now() >= deadline {
+        return false;
+      }
+    }
+  }
+
+  // With a pause target, full collections run incrementally: each
+  // allocation does just enough work for the cycle to finish before the
+  // heap reaches its threshold, and never more than `target` worth. Cycles
+  // start at half the threshold to leave room for that. Minor collections
+  // are short and still run in one go.
+  pub(crate) fn paced_collect(&mut self, target: Duration, due: bool) {
+    if self.phase == Phase::Idle {
+      if due && self.config.strategy == GcStrategy::Generational {
+        self.timed(VM::collect_young);
+      }
+
+      let live = if self.config.stress { self.heap_max } else { self.objects() * 2 };
+      if !self.config.sizing.should_collect(live, self.heap_max) {
+        return;
+      }
+
+      self.start_cycle();
+
+      // Marking and sweeping are about one unit of work per object each.
+      let headroom = self.heap_max.saturating_sub(self.cycle_len).max(1);
+      self.pace = 2 * self.cycle_len / headroom + 1;
+    }
+
+    if self.objects() >= self.heap_max.saturating_mul(STARVATION_FACTOR) {
+      // Too far behind to keep pacing; finish in one pause.
+      self.timed(VM::collect_full);
+    } else {
+      let work = self.pace;
+      self.timed(|vm| vm.run_for(work, Instant::now() + target));
+    }
+  }
+
+  fn run_for(&mut self, mut work: usize, deadline: Instant) -> bool {
+    while work > 0 {
+      let n = cmp::min(work, GC_STEP_WORK);
+
+      if self.cycle_step(n) {
+        return true;
+      }
+
+      work -= n;
+
+      if Instant::now() >= deadline {
+        break;
+      }
+    }
+
+    false
+  }
+
+  // Folds one pause into the stats.
+  pub(crate) fn record_pause(&mut self, pause: Duration) {
+    let bucket = PAUSE_BUCKETS.iter().position(|&max| pause <= max).unwrap_or(PAUSE_BUCKETS.len());
+    self.stats.pause_histogram[bucket] += 1;
+    self.stats.total_pause += pause;
+    #[cfg(feature = "hdr")]
+    self.stats.pause_hdr.record(pause);
+    #[cfg(feature = "metrics-facade")]
+    facade::paused(pause);
+
+    if pause > self.stats.max_pause {
+      self.stats.max_pause = pause;
+    }
+    if self.config.pause_target.is_some_and(|target| pause > target) {
+      self.stats.pause_target_misses += 1;
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use {VMConfig, Vobject};
+
+  #[test]
+  fn gc_step_runs_incrementally() {
+    println!("gc_step spreads a cycle over several calls.");
+
+    let mut vm = VM::new();
+    vm.gc_paused(|vm| {
+      for i in 0..1000 {
+        vm.push_int(i).unwrap();
+        if i % 2 == 0 {
+          vm.pop();
+        }
+      }
+    });
+    vm.gc_full();
+    vm.stack.truncate(100);
+
+    let mut steps = 1;
+    while !vm.gc_step(Duration::new(0, 0)) {
+      steps += 1;
+    }
+
+    assert!(steps > 1);
+    assert!(vm.heap.len() == 100);
+    assert!(vm.gc_step(Duration::from_secs(1)));
+  }
+
+  #[test]
+  fn write_barrier_during_incremental_mark() {
+    println!("Storing a white object into a black pair keeps it alive.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    let x = vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    for i in 0..200 {
+      vm.push_int(i).unwrap();
+    }
+    vm.push_int(3).unwrap();
+    vm.push_int(4).unwrap();
+    let a = vm.push_pair().unwrap();
+
+    // `a` is traced first; `p`, and so `x`, are still waiting.
+    assert!(!vm.gc_step(Duration::new(0, 0)));
+    assert!(!x.0.get().marked());
+
+    if let Vobject::Pair(_, ref mut tail) = a.1.borrow_mut().val { *tail = x.clone() }
+    vm.write_barrier(&a);
+    if let Vobject::Pair(_, ref mut tail) = p.1.borrow_mut().val { *tail = a.clone() }
+    vm.write_barrier(&p);
+
+    while !vm.gc_step(Duration::new(0, 0)) {}
+
+    assert!(vm.heap.iter().any(|obj| Rc::ptr_eq(obj, &x)));
+  }
+
+  #[test]
+  fn notify_idle_collects_before_the_deadline() {
+    println!("Idle notifications collect opportunistically.");
+
+    let mut vm = VM::with_config(VMConfig::new().threshold(100));
+    for i in 0..60 {
+      vm.push_int(i).unwrap();
+      vm.pop();
+    }
+
+    assert!(vm.notify_idle(Instant::now() - Duration::from_secs(1)));
+    assert!(vm.objects() == 60);
+
+    assert!(vm.notify_idle(Instant::now() + Duration::from_secs(1)));
+    assert!(vm.objects() == 0);
+
+    // Nothing worth doing on a small heap.
+    vm.push_int(1).unwrap();
+    vm.pop();
+    assert!(vm.notify_idle(Instant::now() + Duration::from_secs(1)));
+    assert!(vm.objects() == 1);
+  }
+
+  #[test]
+  fn pause_target_paces_collection() {
+    println!("A pause target turns allocation-triggered collection incremental.");
+
+    let mut vm = VM::with_config(VMConfig::new().pause_target(Duration::from_secs(3600)));
+    let mut incremental = false;
+    for i in 0..1000 {
+      vm.push_int(i).unwrap();
+      vm.pop();
+      incremental |= vm.phase != Phase::Idle;
+    }
+
+    assert!(incremental);
+    assert!(vm.objects() < 40);
+    assert!(vm.stats().pauses > 0);
+    assert!(vm.stats().pause_target_misses == 0);
+  }
+
+  #[test]
+  fn pause_target_misses_are_counted() {
+    println!("Pauses over the target are reported.");
+
+    let mut vm = VM::with_config(VMConfig::new().pause_target(Duration::new(0, 0)));
+    for i in 0..100 {
+      vm.push_int(i).unwrap();
+    }
+    vm.gc();
+
+    assert!(vm.stats().pauses > 1);
+    assert!(vm.stats().pause_target_misses == vm.stats().pauses);
+  }
+}
@@ -0,0 +1,137 @@
+// The stack as a tree of plain host values, for test failures and bug
+// reports: each slot with the structure under it, down to a depth limit.
+// An object reached a second time anywhere in the dump is shown by id
+// only, so cycles and sharing stay finite. With the `serde` feature the
+// tree serializes, e.g. to JSON.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use {Sobject, VM, Vobject};
+
+/// An object in `VM::debug_stack`, with its id and any debug label.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct DebugNode {
+  pub id: u64,
+  #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+  pub label: Option<String>,
+  pub value: DebugValue
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "lowercase"))]
+pub enum DebugValue {
+  Int { value: u32 },
+  Pair { head: Box<DebugNode>, tail: Box<DebugNode> },
+  /// A native value, by type name; what it holds isn't followed.
+  Native { name: &'static str },
+  /// Shown in full earlier in the dump.
+  Seen,
+  /// Past the depth limit.
+  Elided,
+  Freed
+}
+
+impl VM {
+  /// Each stack slot, bottom first, with what it points to. Objects more
+  /// than `max_depth` pairs below a slot are elided.
+  pub fn debug_stack(&self, max_depth: usize) -> Vec<DebugNode> {
+    let mut seen = BTreeSet::new();
+    self.stack.iter().map(|obj| self.debug_node(obj, max_depth, &mut seen)).collect()
+  }
+
+  fn debug_node(&self, obj: &Sobject, depth: usize, seen: &mut BTreeSet<u64>) -> DebugNode {
+    let value = if obj.0.get().freed() {
+      DebugValue::Freed
+    } else if seen.contains(&obj.2) {
+      DebugValue::Seen
+    } else {
+      match obj.1.borrow().val {
+        Vobject::Int(value) => {
+          seen.insert(obj.2);
+          DebugValue::Int { value }
+        }
+        Vobject::Pair(..) if depth == 0 => DebugValue::Elided,
+        Vobject::Pair(ref head, ref tail) => {
+          seen.insert(obj.2);
+          let head = Box::new(self.debug_node(head, depth - 1, seen));
+          let tail = Box::new(self.debug_node(tail, depth - 1, seen));
+          DebugValue::Pair { head, tail }
+        }
+        Vobject::Native(ref native) => {
+          seen.insert(obj.2);
+          DebugValue::Native { name: native.type_name() }
+        }
+      }
+    };
+
+    DebugNode { id: obj.2, label: self.label_of(obj.2).map(ToString::to_string), value }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn int(id: u64, value: u32) -> DebugNode {
+    DebugNode { id, label: None, value: DebugValue::Int { value } }
+  }
+
+  fn bare(id: u64, value: DebugValue) -> DebugNode {
+    DebugNode { id, label: None, value }
+  }
+
+  #[test]
+  fn stack_dumps_as_a_tree() {
+    println!("The stack dumps as a finite tree, cycles, sharing and all.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let a = vm.push_pair().unwrap();
+    vm.set_tail(&a, &a).unwrap();
+    vm.set_label(&a, "a");
+    let (one, _) = vm.as_pair(&a).unwrap();
+    vm.stack.push(one);
+    vm.push_value(&(3u32, (4u32, 5u32))).unwrap();
+
+    let mut a_node = bare(2, DebugValue::Pair { head: Box::new(int(0, 1)), tail: Box::new(bare(2, DebugValue::Seen)) });
+    a_node.label = Some("a".to_string());
+    if let DebugValue::Pair { ref mut tail, .. } = a_node.value {
+      tail.label = Some("a".to_string());
+    }
+    let nested = bare(7, DebugValue::Pair { head: Box::new(int(3, 3)), tail: Box::new(bare(6, DebugValue::Elided)) });
+
+    assert!(vm.debug_stack(1) == vec![a_node, bare(0, DebugValue::Seen), nested]);
+    match vm.debug_stack(2)[2].value {
+      DebugValue::Pair { ref tail, .. } => assert!(tail.value != DebugValue::Elided),
+      _ => panic!("expected a pair")
+    }
+    assert!(vm.debug_stack(0)[0].value == DebugValue::Elided);
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn stack_dumps_serialize() {
+    extern crate serde_json;
+
+    println!("A stack dump serializes to JSON.");
+
+    let mut vm = VM::new();
+    vm.push_value(&(1u32, 2u32)).unwrap();
+    let json = serde_json::to_string(&vm.debug_stack(4)).unwrap();
+    assert!(json.starts_with(r#"[{"id":2,"value":{"kind":"pair","head":{"id":0,"value":{"kind":"int","value":1}}"#));
+  }
+}
@@ -0,0 +1,214 @@
+// Heap snapshots in the `.heapsnapshot` format Chrome DevTools loads
+// (Memory tab, "Load profile"). Node 0 is a synthetic root whose elements
+// are the stack slots; ints are number nodes named by their value and
+// pairs are objects with "head" and "tail" properties. Objects nothing
+// reaches anymore show up as unreachable until the next collection.
+// Objects with a debug label have it after their name, as `Pair <list
+// head>`, so it shows up in retainer paths.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use image::Node;
+use {Object, VM};
+
+const META: &str = concat!(
+  "{\"node_fields\":[\"type\",\"name\",\"id\",\"self_size\",\"edge_count\",\"trace_node_id\",\"detachedness\"],",
+  "\"node_types\":[[\"hidden\",\"array\",\"string\",\"object\",\"code\",\"closure\",\"regexp\",\"number\",",
+  "\"native\",\"synthetic\",\"concatenated string\",\"sliced string\",\"symbol\",\"bigint\",\"object shape\"],",
+  "\"string\",\"number\",\"number\",\"number\",\"number\",\"number\"],",
+  "\"edge_fields\":[\"type\",\"name_or_index\",\"to_node\"],",
+  "\"edge_types\":[[\"context\",\"element\",\"property\",\"internal\",\"hidden\",\"shortcut\",\"weak\"],",
+  "\"string_or_number\",\"node\"],",
+  "\"trace_function_info_fields\":[\"function_id\",\"name\",\"script_name\",\"script_id\",\"line\",\"column\"],",
+  "\"trace_node_fields\":[\"id\",\"function_info_index\",\"count\",\"size\",\"children\"],",
+  "\"sample_fields\":[\"timestamp_us\",\"last_assigned_id\"],",
+  "\"location_fields\":[\"object_index\",\"script_id\",\"line\",\"column\"]}"
+);
+
+const NODE_FIELDS: usize = 7;
+
+// Indexes into the node_types and edge_types tables above.
+const NODE_OBJECT: usize = 3;
+const NODE_NUMBER: usize = 7;
+const NODE_SYNTHETIC: usize = 9;
+const EDGE_ELEMENT: usize = 1;
+const EDGE_PROPERTY: usize = 2;
+
+// The strings section; nodes and edges refer to names by index.
+#[derive(Default)]
+struct Strings {
+  list: Vec<String>,
+  index: BTreeMap<String, usize>
+}
+
+impl Strings {
+  fn get(&mut self, s: &str) -> usize {
+    if let Some(&i) = self.index.get(s) {
+      return i;
+    }
+
+    self.list.push(s.to_string());
+    self.index.insert(s.to_string(), self.list.len() - 1);
+    self.list.len() - 1
+  }
+}
+
+// `s` as a JSON string.
+fn write_str(out: &mut String, s: &str) {
+  out.push('"');
+  for c in s.chars() {
+    let _ = match c {
+      '"' | '\\' => write!(out, "\\{}", c),
+      c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32),
+      c => write!(out, "{}", c)
+    };
+  }
+  out.push('"');
+}
+
+fn write_list(out: &mut String, name: &str, values: &[usize]) {
+  let _ = write!(out, ",\n\"{}\":[", name);
+  for (i, v) in values.iter().enumerate() {
+    if i > 0 {
+      out.push(',');
+    }
+    let _ = write!(out, "{}", v);
+  }
+  out.push(']');
+}
+
+impl VM {
+  /// The heap as a Chrome DevTools `.heapsnapshot` document.
+  pub fn heap_snapshot_json(&self) -> String {
+    let (stack, objects) = self.nodes();
+    let mut strings = Strings::default();
+    let mut nodes = Vec::with_capacity(NODE_FIELDS * (objects.len() + 1));
+    let mut edges = Vec::new();
+
+    // Node ids are odd, as in V8's own snapshots; node `i` of `objects` is
+    // row `i + 1`, after the root.
+    let offset = |i: usize| (i + 1) * NODE_FIELDS;
+    let size = Object::size();
+
+    let root = strings.get("");
+    nodes.extend_from_slice(&[NODE_SYNTHETIC, root, 1, 0, stack.len(), 0, 0]);
+    for (i, &id) in stack.iter().enumerate() {
+      edges.extend_from_slice(&[EDGE_ELEMENT, i, offset(id)]);
+    }
+
+    let (head, tail) = (strings.get("head"), strings.get("tail"));
+    for (i, (node, obj)) in objects.iter().zip(self.dumped()).enumerate() {
+      let id = 2 * i + 3;
+      let mut name = match *node {
+        Node::Int { value, .. } => value.to_string(),
+        Node::Pair { .. } => "Pair".to_string(),
+        Node::Native { ref name, .. } => name.clone()
+      };
+      if let Some(label) = self.label(&obj) {
+        let _ = write!(name, " <{}>", label);
+      }
+      let name = strings.get(&name);
+
+      match *node {
+        Node::Int { .. } => {
+          nodes.extend_from_slice(&[NODE_NUMBER, name, id, size, 0, 0, 0]);
+        }
+        Node::Pair { head: h, tail: t, .. } => {
+          nodes.extend_from_slice(&[NODE_OBJECT, name, id, size, 2, 0, 0]);
+          edges.extend_from_slice(&[EDGE_PROPERTY, head, offset(h)]);
+          edges.extend_from_slice(&[EDGE_PROPERTY, tail, offset(t)]);
+        }
+        Node::Native { ref held, .. } => {
+          nodes.extend_from_slice(&[NODE_OBJECT, name, id, size, held.len(), 0, 0]);
+          for (j, &h) in held.iter().enumerate() {
+            edges.extend_from_slice(&[EDGE_ELEMENT, j, offset(h)]);
+          }
+        }
+      }
+    }
+
+    let mut out = String::from("{\"snapshot\":{\"meta\":");
+    out.push_str(META);
+    let _ = write!(out, ",\"node_count\":{},\"edge_count\":{},\"trace_function_count\":0}}",
+                   nodes.len() / NODE_FIELDS, edges.len() / 3);
+    write_list(&mut out, "nodes", &nodes);
+    write_list(&mut out, "edges", &edges);
+    out.push_str(",\n\"trace_function_infos\":[],\n\"trace_tree\":[],\n\"samples\":[],\n\"locations\":[]");
+
+    out.push_str(",\n\"strings\":[");
+    for (i, s) in strings.list.iter().enumerate() {
+      if i > 0 {
+        out.push(',');
+      }
+      write_str(&mut out, s);
+    }
+    out.push_str("]}\n");
+    out
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  extern crate serde_json;
+
+  use self::serde_json::Value;
+  use super::*;
+  use Vobject;
+
+  #[test]
+  fn snapshot_sections_line_up() {
+    println!("Snapshot nodes, edges and strings refer to one another.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let a = vm.push_pair().unwrap();
+    if let Vobject::Pair(_, ref mut x) = a.1.borrow_mut().val { *x = a.clone() }
+
+    let snapshot: Value = serde_json::from_str(&vm.heap_snapshot_json()).unwrap();
+    let nodes = snapshot["nodes"].as_array().unwrap();
+    let edges = snapshot["edges"].as_array().unwrap();
+    let strings = snapshot["strings"].as_array().unwrap();
+
+    assert!(snapshot["snapshot"]["node_count"] == 4);
+    assert!(nodes.len() == 4 * NODE_FIELDS);
+    assert!(snapshot["snapshot"]["edge_count"] == 3);
+    assert!(edges.len() == 3 * 3);
+
+    // root -> stack[0], the pair, which is the fourth node.
+    assert!(edges[2] == 3 * NODE_FIELDS);
+    let pair = &nodes[3 * NODE_FIELDS..4 * NODE_FIELDS];
+    assert!(pair[0] == NODE_OBJECT && strings[pair[1].as_u64().unwrap() as usize] == "Pair");
+    assert!(pair[4] == 2);
+
+    // Its tail edge points back at itself.
+    assert!(strings[edges[7].as_u64().unwrap() as usize] == "tail");
+    assert!(edges[8] == 3 * NODE_FIELDS);
+  }
+
+  #[test]
+  fn labels_name_their_nodes() {
+    println!("Debug labels show in node names, escaped for JSON.");
+
+    let mut vm = VM::new();
+    let one = vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    vm.set_label(&one, "count");
+    vm.set_label(&p, "the \"list\"");
+
+    let snapshot: Value = serde_json::from_str(&vm.heap_snapshot_json()).unwrap();
+    let nodes = snapshot["nodes"].as_array().unwrap();
+    let strings = snapshot["strings"].as_array().unwrap();
+    let name = |i: usize| strings[nodes[i * NODE_FIELDS + 1].as_u64().unwrap() as usize].clone();
+    assert!(name(1) == "1 <count>" && name(2) == "2" && name(3) == "Pair <the \"list\">");
+  }
+}
@@ -0,0 +1,146 @@
+// Graphviz pictures of the stack and heap, for `dot -Tsvg`:
+//
+//   digraph heap {
+//     node [shape=record];
+//     stack [label="<s0> 0"];
+//     n0 [label="int 1"];
+//     n1 [label="int 2"];
+//     n2 [label="<h> pair|<t>"];
+//     stack:s0 -> n2;
+//     n2:h -> n0;
+//     n2:t -> n1;
+//   }
+//
+// Objects are numbered as in `heap_dump_json`. Debug labels go in front
+// of the value, as in `n0 [label="count: int 1"]`.
+
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use dump::Ids;
+use {addr, Sobject, VM, Visitor, Vobject};
+
+fn node(ids: &Ids, obj: &Sobject) -> String {
+  match ids.get(&addr(obj)) {
+    Some(id) => format!("n{}", id),
+    None => "unknown".to_string()
+  }
+}
+
+// `s` escaped for a record label, where braces, bars and angle brackets
+// mean something.
+fn escape(s: &str) -> String {
+  let mut out = String::new();
+  for c in s.chars() {
+    if "\\\"{}|<>".contains(c) {
+      out.push('\\');
+    }
+    out.push(c);
+  }
+  out
+}
+
+impl VM {
+  /// The stack and heap as a Graphviz graph.
+  pub fn render_dot(&self) -> String {
+    self.dot(None)
+  }
+
+  // As `render_dot`, captioned with `label`.
+  pub(crate) fn dot(&self, label: Option<&str>) -> String {
+    let ids = self.object_ids();
+    let mut out = String::from("digraph heap {\n");
+
+    if let Some(label) = label {
+      let _ = writeln!(out, "  label=\"{}\";", label.replace('"', "\\\""));
+    }
+    out.push_str("  node [shape=record];\n");
+
+    let slots: Vec<String> = (0..self.stack.len()).map(|i| format!("<s{}> {}", i, i)).collect();
+    let _ = writeln!(out, "  stack [label=\"{}\"];", if slots.is_empty() { "(empty)".to_string() } else { slots.join("|") });
+
+    let objects = self.dumped();
+    for obj in &objects {
+      let name = match self.label(obj) {
+        Some(name) => format!("{}: ", escape(name)),
+        None => String::new()
+      };
+      let _ = match obj.1.borrow().val {
+        Vobject::Int(n) => writeln!(out, "  {} [label=\"{}int {}\"];", node(&ids, obj), name, n),
+        Vobject::Pair(..) => writeln!(out, "  {} [label=\"<h> {}pair|<t>\"];", node(&ids, obj), name),
+        Vobject::Native(ref native) =>
+          writeln!(out, "  {} [label=\"{}native {}\"];", node(&ids, obj), name, escape(native.type_name()))
+      };
+    }
+
+    for (i, obj) in self.stack.iter().enumerate() {
+      let _ = writeln!(out, "  stack:s{} -> {};", i, node(&ids, obj));
+    }
+    for obj in &objects {
+      match obj.1.borrow().val {
+        Vobject::Pair(ref head, ref tail) => {
+          let _ = writeln!(out, "  {}:h -> {};", node(&ids, obj), node(&ids, head));
+          let _ = writeln!(out, "  {}:t -> {};", node(&ids, obj), node(&ids, tail));
+        }
+        Vobject::Native(ref native) =>
+          native.trace(&mut Visitor::new(&mut |held| {
+            let _ = writeln!(out, "  {} -> {};", node(&ids, obj), node(&ids, held));
+          })),
+        Vobject::Int(_) => {}
+      }
+    }
+
+    out.push_str("}\n");
+    out
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn renders_stack_and_heap() {
+    println!("The DOT graph has a node per object and an edge per pointer.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    vm.push_pair().unwrap();
+
+    assert!(vm.render_dot() == "digraph heap {
+  node [shape=record];
+  stack [label=\"<s0> 0\"];
+  n0 [label=\"int 1\"];
+  n1 [label=\"int 2\"];
+  n2 [label=\"<h> pair|<t>\"];
+  stack:s0 -> n2;
+  n2:h -> n0;
+  n2:t -> n1;
+}
+");
+  }
+
+  #[test]
+  fn labels_are_escaped() {
+    println!("Debug labels show in their nodes, escaped for records.");
+
+    let mut vm = VM::new();
+    let one = vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    vm.set_label(&one, "count");
+    vm.set_label(&p, "<a|b>");
+
+    let dot = vm.render_dot();
+    assert!(dot.contains("  n0 [label=\"count: int 1\"];\n"));
+    assert!(dot.contains("  n2 [label=\"<h> \\<a\\|b\\>: pair|<t>\"];\n"));
+  }
+}
@@ -0,0 +1,101 @@
+// Heap dumps as JSON, for visualizers and post-mortems:
+//
+//   {"stack": [2],
+//    "objects": [{"id": 0, "kind": "int", "value": 1, "marked": false, "old": false},
+//                {"id": 1, "kind": "int", "value": 2, "marked": false, "old": false},
+//                {"id": 2, "kind": "pair", "head": 0, "tail": 1, "marked": false, "old": false}]}
+//
+// Ids are positions in the dump and only mean something within it.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+use {addr, Sobject, VM, Visitor, Vobject};
+
+pub(crate) type Ids = BTreeMap<usize, usize>;
+
+fn id(ids: &Ids, obj: &Sobject) -> String {
+  match ids.get(&addr(obj)) {
+    Some(id) => id.to_string(),
+    None => "null".to_string()
+  }
+}
+
+impl VM {
+  // Numbers every object the VM holds, and the constants they use, in
+  // `dumped` order.
+  pub(crate) fn object_ids(&self) -> Ids {
+    let mut ids = Ids::new();
+    for (i, obj) in self.dumped().iter().enumerate() {
+      ids.insert(addr(obj), i);
+    }
+    ids
+  }
+
+  /// The stack and every object the VM holds, as a JSON document.
+  pub fn heap_dump_json(&self) -> String {
+    let ids = self.object_ids();
+
+    let mut out = String::from("{\"stack\": [");
+    for (i, obj) in self.stack.iter().enumerate() {
+      if i > 0 {
+        out.push_str(", ");
+      }
+      out.push_str(&id(&ids, obj));
+    }
+
+    out.push_str("],\n \"objects\": [");
+    for (i, obj) in self.dumped().iter().enumerate() {
+      let gch = obj.0.get();
+
+      if i > 0 {
+        out.push_str(",\n             ");
+      }
+
+      let _ = match obj.1.borrow().val {
+        Vobject::Int(n) => write!(out, "{{\"id\": {}, \"kind\": \"int\", \"value\": {}", i, n),
+        Vobject::Pair(ref head, ref tail) =>
+          write!(out, "{{\"id\": {}, \"kind\": \"pair\", \"head\": {}, \"tail\": {}",
+                 i, id(&ids, head), id(&ids, tail)),
+        Vobject::Native(ref native) => {
+          let mut held = Vec::new();
+          native.trace(&mut Visitor::new(&mut |obj| held.push(id(&ids, obj))));
+          write!(out, "{{\"id\": {}, \"kind\": \"native\", \"type\": \"{}\", \"held\": [{}]",
+                 i, native.type_name(), held.join(", "))
+        }
+      };
+      let _ = write!(out, ", \"marked\": {}, \"old\": {}}}", gch.marked(), gch.old());
+    }
+
+    out.push_str("]}\n");
+    out
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn dumps_cycles_by_id() {
+    println!("Heap dumps refer to objects by id, so cycles are fine.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let a = vm.push_pair().unwrap();
+    if let Vobject::Pair(_, ref mut x) = a.1.borrow_mut().val { *x = a.clone() }
+
+    assert!(vm.heap_dump_json() == "{\"stack\": [2],\n \"objects\": [\
+      {\"id\": 0, \"kind\": \"int\", \"value\": 1, \"marked\": false, \"old\": false},\n             \
+      {\"id\": 1, \"kind\": \"int\", \"value\": 2, \"marked\": false, \"old\": false},\n             \
+      {\"id\": 2, \"kind\": \"pair\", \"head\": 0, \"tail\": 2, \"marked\": false, \"old\": false}]}\n");
+  }
+}
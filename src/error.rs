@@ -0,0 +1,140 @@
+use alloc::string::String;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VmError {
+  /// The heap is at its configured `max_heap`, or `MAX_OBJECTS`, even
+  /// after a full collection.
+  OutOfMemory,
+  /// In host-driven mode, the heap outgrew its threshold because the host
+  /// stopped calling `tick`.
+  GcStarved,
+  /// The VM used up its allocation quota.
+  QuotaExceeded,
+  /// The host cancelled the VM through its `CancelToken`.
+  Cancelled,
+  /// Popped, or paired, more than the stack holds.
+  StackUnderflow,
+  /// A store into a frozen object or a constant.
+  Frozen,
+  /// A store into something that wasn't a pair.
+  Type(TypeError),
+  /// `try_push_constant` was given an object from a VM's heap.
+  NotConstant,
+  /// A handle to an object a collection already freed.
+  Freed,
+  /// An arithmetic result didn't fit in an int under
+  /// `OverflowPolicy::Error`.
+  Overflow,
+  /// `rollback` was given a checkpoint taken from another VM.
+  ForeignCheckpoint
+}
+
+impl fmt::Display for VmError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      VmError::OutOfMemory => write!(f, "out of memory"),
+      VmError::GcStarved => write!(f, "collector starved: call tick() more often"),
+      VmError::QuotaExceeded => write!(f, "allocation quota exceeded"),
+      VmError::Cancelled => write!(f, "cancelled by the host"),
+      VmError::StackUnderflow => write!(f, "stack underflow"),
+      VmError::Frozen => write!(f, "object is frozen"),
+      VmError::Type(e) => e.fmt(f),
+      VmError::NotConstant => write!(f, "not a constant"),
+      VmError::Freed => write!(f, "object was freed by a collection"),
+      VmError::Overflow => write!(f, "integer overflow"),
+      VmError::ForeignCheckpoint => write!(f, "checkpoint was taken from another VM")
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl Error for VmError {}
+
+/// An environment variable held a value `VMConfig::from_env` can't use,
+/// or (with `var` "workload") a `Workload` setting didn't parse.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigError {
+  pub var: &'static str,
+  pub value: String
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "invalid value for {}: {:?}", self.var, self.value)
+  }
+}
+
+#[cfg(feature = "std")]
+impl Error for ConfigError {}
+
+/// An object didn't have the shape `FromValue` or `TryFrom` expected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TypeError {
+  pub expected: &'static str,
+  /// What the object was: "int", "pair", "freed" for a stale handle, or
+  /// "cycle" for a list whose spine comes back on itself.
+  pub found: &'static str
+}
+
+impl fmt::Display for TypeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "expected {}, found {}", self.expected, self.found)
+  }
+}
+
+#[cfg(feature = "std")]
+impl Error for TypeError {}
+
+/// Why `VM::from_image` refused an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageError {
+  /// Not a heap image at all.
+  BadMagic,
+  /// An image format this build doesn't read.
+  UnsupportedVersion(u8),
+  /// The image ends partway through.
+  Truncated,
+  /// An object of unknown kind.
+  BadTag(u8),
+  /// A pair or stack slot names an object the image doesn't contain.
+  DanglingId(usize),
+  /// Data after the last stack slot.
+  TrailingBytes,
+  /// The VM holds a native value of this type, which can't be saved.
+  Native(&'static str)
+}
+
+impl fmt::Display for ImageError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      ImageError::BadMagic => write!(f, "not a heap image"),
+      ImageError::UnsupportedVersion(v) => write!(f, "unsupported image version {}", v),
+      ImageError::Truncated => write!(f, "image is truncated"),
+      ImageError::BadTag(tag) => write!(f, "bad object tag {:#x}", tag),
+      ImageError::DanglingId(id) => write!(f, "no object with id {}", id),
+      ImageError::TrailingBytes => write!(f, "trailing bytes after image"),
+      ImageError::Native(name) => write!(f, "a {} can't be saved in an image", name)
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl Error for ImageError {}
+
+/// A GC log line was missing a field, or had one that didn't parse.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LogLineError {
+  pub field: &'static str
+}
+
+impl fmt::Display for LogLineError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "missing or malformed {} field", self.field)
+  }
+}
+
+#[cfg(feature = "std")]
+impl Error for LogLineError {}
@@ -0,0 +1,61 @@
+// Collector telemetry through the `metrics` crate facade. Nothing is
+// recorded unless the host installs a recorder.
+
+use metrics_facade::{counter, gauge, histogram};
+
+use time::Duration;
+use VM;
+
+pub(crate) fn collected(vm: &VM, kind: &'static str, freed: usize) {
+  counter!("babygc_collections_total", "kind" => kind).increment(1);
+  counter!("babygc_objects_freed_total", "kind" => kind).increment(freed as u64);
+  gauge!("babygc_objects").set(vm.objects() as f64);
+  gauge!("babygc_heap_bytes").set(vm.bytes_allocated() as f64);
+}
+
+pub(crate) fn paused(pause: Duration) {
+  histogram!("babygc_gc_pause_seconds").record(pause.as_secs_f64());
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  extern crate metrics_util;
+
+  use self::metrics_util::debugging::{DebugValue, DebuggingRecorder};
+  use self::metrics_util::MetricKind;
+  use metrics_facade::with_local_recorder;
+
+  use VM;
+
+  #[test]
+  fn collections_reach_the_recorder() {
+    println!("Collections and pauses are reported through the metrics facade.");
+
+    let recorder = DebuggingRecorder::new();
+    let snapshotter = recorder.snapshotter();
+
+    with_local_recorder(&recorder, || {
+      let mut vm = VM::new();
+      vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+      vm.pop();
+      vm.gc();
+      vm.gc();
+    });
+
+    let metrics = snapshotter.snapshot().into_vec();
+    let find = |kind: MetricKind, name: &str| metrics.iter()
+      .find(|m| m.0.kind() == kind && m.0.key().name() == name)
+      .map(|m| &m.3);
+
+    assert!(matches!(find(MetricKind::Counter, "babygc_collections_total"), Some(&DebugValue::Counter(2))));
+    assert!(matches!(find(MetricKind::Counter, "babygc_objects_freed_total"), Some(&DebugValue::Counter(1))));
+    assert!(matches!(find(MetricKind::Gauge, "babygc_objects"), Some(&DebugValue::Gauge(n)) if n.0 == 1.0));
+    assert!(matches!(find(MetricKind::Histogram, "babygc_gc_pause_seconds"), Some(DebugValue::Histogram(v)) if v.len() == 2));
+  }
+}
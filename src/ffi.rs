@@ -0,0 +1,291 @@
+// C embedding API; see include/babygc.h for the C side.
+//
+// Ownership rules:
+//
+// - `babygc_vm_new` returns a VM owned by the caller, freed with
+//   `babygc_vm_free`.
+// - Every function returning a `BabygcHandle *` hands out a new handle owned
+//   by the caller, freed with `babygc_handle_release`. NULL means failure;
+//   `babygc_vm_last_error` says why for functions that take a VM.
+// - Handles do not root their object: only the VM stack does. Once a
+//   collection frees it, `babygc_handle_int` returns `BABYGC_FREED` and
+//   the object reads as no pair. A handle may outlive its VM.
+
+use alloc::boxed::Box;
+use core::ptr;
+
+use {Object, Sobject, VM, VMConfig, Vobject, VmError};
+
+pub const BABYGC_OK: i32 = 0;
+pub const BABYGC_OUT_OF_MEMORY: i32 = 1;
+pub const BABYGC_GC_STARVED: i32 = 2;
+pub const BABYGC_QUOTA_EXCEEDED: i32 = 3;
+pub const BABYGC_STACK_UNDERFLOW: i32 = 4;
+pub const BABYGC_TYPE_ERROR: i32 = 5;
+pub const BABYGC_CANCELLED: i32 = 6;
+pub const BABYGC_FREED: i32 = 7;
+pub const BABYGC_OVERFLOW: i32 = 8;
+
+/// Opaque VM type handed to C.
+pub struct BabygcVm {
+  vm: VM,
+  last_error: i32
+}
+
+/// Opaque object handle handed to C.
+pub struct BabygcHandle(Sobject);
+
+fn error_code(e: VmError) -> i32 {
+  match e {
+    VmError::OutOfMemory => BABYGC_OUT_OF_MEMORY,
+    VmError::GcStarved => BABYGC_GC_STARVED,
+    VmError::QuotaExceeded => BABYGC_QUOTA_EXCEEDED,
+    VmError::Cancelled => BABYGC_CANCELLED,
+    VmError::StackUnderflow => BABYGC_STACK_UNDERFLOW,
+    VmError::Frozen | VmError::Type(_) | VmError::NotConstant | VmError::ForeignCheckpoint => BABYGC_TYPE_ERROR,
+    VmError::Freed => BABYGC_FREED,
+    VmError::Overflow => BABYGC_OVERFLOW
+  }
+}
+
+fn handle(obj: Sobject) -> *mut BabygcHandle {
+  Box::into_raw(Box::new(BabygcHandle(obj)))
+}
+
+impl BabygcVm {
+  fn finish(&mut self, result: Result<Sobject, VmError>) -> *mut BabygcHandle {
+    match result {
+      Ok(obj) => {
+        self.last_error = BABYGC_OK;
+        handle(obj)
+      }
+      Err(e) => {
+        self.last_error = error_code(e);
+        ptr::null_mut()
+      }
+    }
+  }
+}
+
+/// Creates a VM with the default configuration.
+#[no_mangle]
+pub extern "C" fn babygc_vm_new() -> *mut BabygcVm {
+  Box::into_raw(Box::new(BabygcVm { vm: VM::with_config(VMConfig::new()), last_error: BABYGC_OK }))
+}
+
+/// # Safety
+///
+/// `vm` must come from `babygc_vm_new` and not have been freed, or be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_vm_free(vm: *mut BabygcVm) {
+  if !vm.is_null() {
+    drop(Box::from_raw(vm));
+  }
+}
+
+/// Error code from the last operation on `vm` that can fail.
+///
+/// # Safety
+///
+/// `vm` must be a live VM from `babygc_vm_new`.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_vm_last_error(vm: *const BabygcVm) -> i32 {
+  (*vm).last_error
+}
+
+/// # Safety
+///
+/// `vm` must be a live VM from `babygc_vm_new`.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_push_int(vm: *mut BabygcVm, val: u32) -> *mut BabygcHandle {
+  let vm = &mut *vm;
+  let result = vm.vm.push_int(val);
+  vm.finish(result)
+}
+
+/// # Safety
+///
+/// `vm` must be a live VM from `babygc_vm_new`.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_push_pair(vm: *mut BabygcVm) -> *mut BabygcHandle {
+  let vm = &mut *vm;
+  let result = vm.vm.push_pair();
+  vm.finish(result)
+}
+
+/// Pops the top of the stack, returning a handle to it.
+///
+/// # Safety
+///
+/// `vm` must be a live VM from `babygc_vm_new`.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_pop(vm: *mut BabygcVm) -> *mut BabygcHandle {
+  let vm = &mut *vm;
+  let result = vm.vm.try_pop();
+  vm.finish(result)
+}
+
+/// Collects per the VM's strategy, returning the number of objects freed.
+///
+/// # Safety
+///
+/// `vm` must be a live VM from `babygc_vm_new`.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_gc(vm: *mut BabygcVm) -> usize {
+  (*vm).vm.gc()
+}
+
+/// Number of objects the VM holds, live or not yet collected.
+///
+/// # Safety
+///
+/// `vm` must be a live VM from `babygc_vm_new`.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_heap_len(vm: *const BabygcVm) -> usize {
+  (*vm).vm.objects()
+}
+
+/// # Safety
+///
+/// `h` must be a live handle, or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_handle_release(h: *mut BabygcHandle) {
+  if !h.is_null() {
+    drop(Box::from_raw(h));
+  }
+}
+
+/// A second, independently released handle to the same object.
+///
+/// # Safety
+///
+/// `h` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_handle_clone(h: *const BabygcHandle) -> *mut BabygcHandle {
+  handle((*h).0.clone())
+}
+
+/// # Safety
+///
+/// `h` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_handle_is_pair(h: *const BabygcHandle) -> bool {
+  let h = &*h;
+  matches!(h.0.1.borrow().val, Vobject::Pair(..))
+}
+
+/// Stores the object's integer in `out`. Returns `BABYGC_TYPE_ERROR`,
+/// leaving `out` alone, if it isn't an int, or `BABYGC_FREED` if a
+/// collection freed it.
+///
+/// # Safety
+///
+/// `h` must be a live handle and `out` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_handle_int(h: *const BabygcHandle, out: *mut u32) -> i32 {
+  let h = &*h;
+  if h.0.0.get().freed() {
+    return BABYGC_FREED;
+  }
+  match h.0.1.borrow().val {
+    Vobject::Int(n) => {
+      *out = n;
+      BABYGC_OK
+    }
+    _ => BABYGC_TYPE_ERROR
+  }
+}
+
+/// Head of a pair, or NULL if the object isn't one.
+///
+/// # Safety
+///
+/// `h` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_handle_head(h: *const BabygcHandle) -> *mut BabygcHandle {
+  child(&(*h).0, true)
+}
+
+/// Tail of a pair, or NULL if the object isn't one.
+///
+/// # Safety
+///
+/// `h` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn babygc_handle_tail(h: *const BabygcHandle) -> *mut BabygcHandle {
+  child(&(*h).0, false)
+}
+
+fn child(obj: &Sobject, head: bool) -> *mut BabygcHandle {
+  match obj.1.borrow().val {
+    Vobject::Pair(ref h, _) if head => handle(h.clone()),
+    Vobject::Pair(_, ref t) => handle(t.clone()),
+    _ => ptr::null_mut()
+  }
+}
+
+/// Bytes one heap object costs.
+#[no_mangle]
+pub extern "C" fn babygc_object_size() -> usize {
+  Object::size()
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn round_trip_through_the_c_api() {
+    println!("The C API builds, inspects and collects objects.");
+
+    unsafe {
+      let vm = babygc_vm_new();
+      babygc_handle_release(babygc_push_int(vm, 1));
+      babygc_handle_release(babygc_push_int(vm, 2));
+      let pair = babygc_push_pair(vm);
+      assert!(babygc_handle_is_pair(pair));
+
+      let head = babygc_handle_head(pair);
+      let mut n = 0;
+      assert!(babygc_handle_int(head, &mut n) == BABYGC_OK);
+      assert!(n == 1);
+      assert!(babygc_handle_int(pair, &mut n) == BABYGC_TYPE_ERROR);
+      assert!(babygc_handle_head(head).is_null());
+
+      babygc_handle_release(babygc_pop(vm));
+      assert!(babygc_gc(vm) == 3);
+      assert!(babygc_heap_len(vm) == 0);
+
+      // Stale handles say so, and outlive the VM.
+      assert!(babygc_handle_int(head, &mut n) == BABYGC_FREED && n == 1);
+      babygc_vm_free(vm);
+      assert!(babygc_handle_tail(pair).is_null());
+
+      babygc_handle_release(head);
+      babygc_handle_release(pair);
+    }
+  }
+
+  #[test]
+  fn errors_are_reported_not_panicked() {
+    println!("Failures come back as NULL plus an error code.");
+
+    unsafe {
+      let vm = babygc_vm_new();
+      assert!(babygc_pop(vm).is_null());
+      assert!(babygc_vm_last_error(vm) == BABYGC_STACK_UNDERFLOW);
+
+      babygc_handle_release(babygc_push_int(vm, 1));
+      assert!(babygc_vm_last_error(vm) == BABYGC_OK);
+      assert!(babygc_push_pair(vm).is_null());
+      assert!(babygc_vm_last_error(vm) == BABYGC_STACK_UNDERFLOW);
+
+      babygc_vm_free(vm);
+    }
+  }
+}
@@ -0,0 +1,85 @@
+// Header bits for the embedder. Each object has `USER_FLAGS` of them,
+// packed in beside the collector's own, for marks a language runtime
+// would otherwise keep in a side table: visited during a traversal, dirty
+// since the last save. The collector carries them through collections
+// and promotion untouched. Unlike tags, nothing else copies them: clones,
+// transfers and images start with every flag clear, and checkpoints don't
+// record them.
+
+use {Sobject, VM, USER_FLAGS};
+
+fn check(flag: u32) {
+  assert!(flag < USER_FLAGS, "flag {} out of range; there are {}", flag, USER_FLAGS);
+}
+
+impl VM {
+  /// Sets or clears flag `flag` of `obj`. Panics unless `flag` is below
+  /// `USER_FLAGS`.
+  pub fn set_flag(&self, obj: &Sobject, flag: u32, on: bool) {
+    check(flag);
+    obj.0.set(obj.0.get().with_flag(flag, on));
+  }
+
+  /// Whether flag `flag` of `obj` is set. Panics unless `flag` is below
+  /// `USER_FLAGS`.
+  pub fn test_flag(&self, obj: &Sobject, flag: u32) -> bool {
+    check(flag);
+    obj.0.get().flag(flag)
+  }
+
+  /// Clears flag `flag` on every object the VM holds, as before a fresh
+  /// traversal.
+  pub fn clear_flag(&self, flag: u32) {
+    check(flag);
+    for obj in self.iter_objects() {
+      obj.0.set(obj.0.get().with_flag(flag, false));
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {GcStrategy, VMConfig};
+
+  #[test]
+  fn flags_survive_collections() {
+    println!("Flags stay put through collections and promotion.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy));
+      let p = vm.push_value(&(1u32, 2u32)).unwrap();
+      vm.set_flag(&p, 0, true);
+      vm.set_flag(&p, USER_FLAGS - 1, true);
+      vm.freeze(&p);
+
+      for _ in 0..3 {
+        vm.gc();
+      }
+      vm.gc_full();
+      vm.verify().unwrap();
+      assert!(vm.test_flag(&p, 0) && !vm.test_flag(&p, 1) && vm.test_flag(&p, USER_FLAGS - 1));
+      assert!(vm.is_frozen(&p));
+
+      vm.set_flag(&p, 0, false);
+      assert!(!vm.test_flag(&p, 0) && vm.test_flag(&p, USER_FLAGS - 1));
+      vm.clear_flag(USER_FLAGS - 1);
+      assert!(!vm.test_flag(&p, USER_FLAGS - 1) && vm.is_frozen(&p));
+    }
+  }
+
+  #[test]
+  #[should_panic(expected = "out of range")]
+  fn flags_are_bounded() {
+    println!("Only USER_FLAGS flags exist.");
+
+    let mut vm = VM::new();
+    let one = vm.push_int(1).unwrap();
+    vm.set_flag(&one, USER_FLAGS, true);
+  }
+}
@@ -0,0 +1,149 @@
+// What a collection does to the objects it frees. The handles `push_int`
+// and friends return are `Rc` clones, so a host still holding one keeps
+// the object's allocation; but the collector empties it, dropping what it
+// pointed to, and sets a header bit saying it is gone. A stale handle then
+// pins one object rather than everything once reachable from it, garbage
+// cycles come apart instead of leaking, and using the handle fails:
+// reads with a `TypeError` that found "freed", stores and clones with
+// `VmError::Freed`, and `persist` and `write_barrier` by panicking.
+//
+// In debug builds the emptied object is poisoned too: its int reads as
+// `POISON` and its tag as `POISON` repeated, so code reading the `RefCell`
+// directly, round the VM's checks, sees something that stands out rather
+// than a plausible 0. The printer shows freed objects as `#<freed>`.
+//
+// Rolling back to a checkpoint taken while an object lived brings it back,
+// contents and all, so its handles work again.
+
+use {Object, Sobject, VM, Vobject};
+
+/// What a freed object holds in debug builds.
+pub const POISON: u32 = 0xdead_beef;
+
+impl VM {
+  /// Whether a collection has freed `obj`, so this handle is stale.
+  pub fn is_freed(&self, obj: &Sobject) -> bool {
+    obj.0.get().freed()
+  }
+
+  // Frees `obj`, which the VM no longer lists anywhere. A host holding a
+  // `Ref` into it keeps its contents, but not its header.
+  pub(crate) fn free(&mut self, obj: &Sobject) {
+    self.unlabel(obj);
+    self.forget_metadata(obj);
+    self.record_free(obj);
+
+    obj.0.set(obj.0.get().with_marked(false).with_freed());
+    if let Ok(mut o) = obj.1.try_borrow_mut() {
+      if cfg!(debug_assertions) {
+        o.val = Vobject::Int(POISON);
+        o.tag = Some(u64::from(POISON) << 32 | u64::from(POISON));
+      } else {
+        o.val = Vobject::Int(0);
+      }
+    }
+    self.config.allocator.free(Object::size());
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use alloc::string::ToString;
+  use {GcStrategy, TypeError, VMConfig, VmError};
+
+  #[test]
+  fn stale_handles_release_and_fail() {
+    println!("A freed object lets go of its children and refuses to be used.");
+
+    for strategy in [GcStrategy::MarkSweep, GcStrategy::Generational] {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy));
+      let kept = vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+      vm.push_int(3).unwrap();
+      let p = vm.push_pair().unwrap();
+      let (two, _) = vm.as_pair(&p).unwrap();
+      vm.pop();
+
+      vm.gc_minor();
+      vm.gc_full();
+      assert!(vm.is_freed(&p) && vm.is_freed(&two) && !vm.is_freed(&kept));
+      assert!(Rc::strong_count(&two) == 1);
+
+      assert!(vm.as_pair(&p).unwrap_err() == TypeError { expected: "pair", found: "freed" });
+      assert!(vm.extract::<u32>(&two).unwrap_err().found == "freed");
+      assert!(vm.set_tail(&p, &kept) == Err(VmError::Freed));
+      vm.push_int(4).unwrap();
+      let q = vm.push_pair().unwrap();
+      assert!(vm.set_head(&q, &two) == Err(VmError::Freed));
+      assert!(vm.deep_clone(&p).unwrap_err() == VmError::Freed);
+      vm.verify().unwrap();
+    }
+  }
+
+  #[test]
+  fn garbage_cycles_come_apart() {
+    println!("A freed cycle no longer keeps itself alive.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    vm.set_tail(&p, &p).unwrap();
+    let weak = Rc::downgrade(&p);
+    drop(p);
+    vm.pop();
+
+    vm.gc();
+    assert!(weak.upgrade().is_none());
+  }
+
+  #[test]
+  fn rollback_revives_freed_objects() {
+    println!("Rolling back makes a freed object's handles usable again.");
+
+    let mut vm = VM::new();
+    let one = vm.push_int(1).unwrap();
+    let checkpoint = vm.checkpoint();
+    vm.pop();
+    vm.gc();
+    assert!(vm.is_freed(&one));
+
+    vm.rollback(&checkpoint).unwrap();
+    assert!(!vm.is_freed(&one) && vm.as_int(&one) == Ok(1));
+  }
+
+  #[test]
+  #[cfg_attr(not(debug_assertions), ignore)]
+  fn debug_builds_poison_freed_objects() {
+    println!("A freed object read round the VM holds the poison pattern.");
+
+    let mut vm = VM::new();
+    let one = vm.push_int(1).unwrap();
+    vm.set_tag(&one, 7);
+    vm.pop();
+    vm.gc();
+
+    assert!(matches!(one.1.borrow().val, Vobject::Int(POISON)));
+    assert!(vm.get_tag(&one) == Some(0xdead_beef_dead_beef));
+    assert!(vm.display(&one).to_string() == "#<freed>");
+  }
+
+  #[test]
+  #[should_panic(expected = "freed objects can't be persisted")]
+  fn persisting_a_freed_object_panics() {
+    println!("persist refuses a stale handle.");
+
+    let mut vm = VM::new();
+    let one = vm.push_int(1).unwrap();
+    vm.pop();
+    vm.gc();
+    vm.persist(&one);
+  }
+}
@@ -0,0 +1,93 @@
+// Frozen objects: a header bit that makes `set_head`, `set_tail` and the
+// write barrier refuse stores, so a structure can be handed to other code
+// or cached as a canonical value without anyone changing it underneath.
+// Constants are frozen from the start. Freezing is one-way, apart from
+// rolling back to a checkpoint taken before it; images and clones leave
+// the bit behind, like tags.
+
+use alloc::collections::BTreeSet;
+use alloc::vec;
+
+use {addr, Sobject, VM};
+
+impl VM {
+  /// Freezes `obj`, but not what it points to.
+  pub fn freeze(&mut self, obj: &Sobject) {
+    obj.0.set(obj.0.get().with_frozen(true));
+  }
+
+  /// Freezes `obj` and everything reachable from it.
+  pub fn freeze_deep(&mut self, obj: &Sobject) {
+    let mut seen = BTreeSet::new();
+    let mut todo = vec![obj.clone()];
+
+    while let Some(obj) = todo.pop() {
+      if obj.0.get().constant() || !seen.insert(addr(&obj)) {
+        continue;
+      }
+
+      self.freeze(&obj);
+      todo.extend(obj.1.borrow().val.children());
+    }
+  }
+
+  /// Whether stores into `obj` are refused: it was frozen, or is a
+  /// constant.
+  pub fn is_frozen(&self, obj: &Sobject) -> bool {
+    let gch = obj.0.get();
+    gch.frozen() || gch.constant()
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {ConstantSpace, GcStrategy, TypeError, VMConfig, VmError};
+
+  #[test]
+  fn frozen_objects_refuse_stores() {
+    println!("Stores into a frozen pair fail and change nothing.");
+
+    for strategy in [GcStrategy::MarkSweep, GcStrategy::Generational] {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy));
+      let p = vm.push_value(&(1u32, 2u32)).unwrap();
+      let x = vm.push_int(3).unwrap();
+      vm.push_int(4).unwrap();
+      vm.push_int(5).unwrap();
+      let q = vm.push_pair().unwrap();
+
+      vm.freeze(&p);
+      assert!(vm.set_head(&p, &x).unwrap_err() == VmError::Frozen);
+      assert!(vm.try_write_barrier(&p).unwrap_err() == VmError::Frozen);
+      assert!(vm.extract::<(u32, u32)>(&p) == Ok((1, 2)));
+
+      // Collections keep the bit.
+      vm.gc_minor();
+      vm.gc_full();
+      assert!(vm.is_frozen(&p) && !vm.is_frozen(&x) && !vm.is_frozen(&q));
+      vm.set_tail(&q, &x).unwrap();
+      assert!(vm.extract::<(u32, u32)>(&q) == Ok((4, 3)));
+      assert!(vm.set_tail(&x, &x).unwrap_err() == VmError::Type(TypeError { expected: "pair", found: "int" }));
+      assert!(vm.is_frozen(&ConstantSpace::new().int(1)));
+    }
+  }
+
+  #[test]
+  fn deep_freezing_follows_pointers() {
+    println!("freeze_deep reaches the whole structure, cycles included.");
+
+    let mut vm = VM::new();
+    let list = vm.push_value(&vec![1u32, 2, 3]).unwrap();
+    let (_, rest) = vm.as_pair(&list).unwrap();
+    vm.set_tail(&rest, &list).unwrap();
+
+    vm.freeze_deep(&list);
+    assert!(vm.iter_live().count() == 4 && vm.iter_live().all(|obj| vm.is_frozen(obj)));
+    assert!(vm.set_head(&rest, &list).unwrap_err() == VmError::Frozen);
+  }
+}
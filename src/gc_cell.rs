@@ -0,0 +1,206 @@
+// Interior mutability for native values. A `GcCell` inside a native value
+// is bound to the object holding it when the VM first sees it: when the
+// value is allocated, copied, or handed to `with_native_mut`. From then on
+// each `borrow_mut` notes that object, and before the collector next runs
+// the VM puts it through the write barrier, so changing what a payload
+// holds in place, even through a handle the host kept, can't hide a new
+// reference from an incremental or minor collection. A cell not yet bound
+// belongs to no VM and needs no barrier.
+//
+// A cell mutably borrowed can't be traced, so a `GcRefMut` mustn't be
+// held across a VM call.
+
+use alloc::rc::{Rc, Weak};
+use alloc::vec::Vec;
+use core::cell::{Cell, Ref, RefCell, RefMut};
+use core::fmt;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+
+use {GCHeader, Object, Sobject, Trace, VM, Visitor};
+
+// The objects whose cells were written to since the collector last ran.
+pub(crate) type Writes = Rc<RefCell<Vec<Sobject>>>;
+
+// The object a cell is in, and where to note writes to it. Neither keeps
+// anything alive: the object holds the cell, and the VM the list.
+#[derive(Clone)]
+pub(crate) struct Binding {
+  owner: Weak<(Cell<GCHeader>, RefCell<Object>, u64)>,
+  writes: Weak<RefCell<Vec<Sobject>>>
+}
+
+impl Binding {
+  pub(crate) fn new(owner: &Sobject, writes: &Writes) -> Binding {
+    Binding { owner: Rc::downgrade(owner), writes: Rc::downgrade(writes) }
+  }
+
+  fn note(&self) {
+    if let (Some(owner), Some(writes)) = (self.owner.upgrade(), self.writes.upgrade()) {
+      writes.borrow_mut().push(owner);
+    }
+  }
+}
+
+/// A `RefCell` for use inside native values, whose `borrow_mut` runs the
+/// write barrier for the object holding it. Tracing the value holding it
+/// must trace the cell.
+pub struct GcCell<T> {
+  value: RefCell<T>,
+  binding: RefCell<Option<Binding>>
+}
+
+/// A mutable borrow of a `GcCell`'s contents. The holding object goes
+/// through the write barrier once it is dropped.
+pub struct GcRefMut<'a, T> {
+  value: RefMut<'a, T>,
+  binding: Option<Binding>
+}
+
+impl<T> GcCell<T> {
+  pub fn new(value: T) -> GcCell<T> {
+    GcCell { value: RefCell::new(value), binding: RefCell::new(None) }
+  }
+
+  pub fn borrow(&self) -> Ref<'_, T> {
+    self.value.borrow()
+  }
+
+  /// Panics if the cell is already borrowed.
+  pub fn borrow_mut(&self) -> GcRefMut<'_, T> {
+    GcRefMut { value: self.value.borrow_mut(), binding: self.binding.borrow().clone() }
+  }
+
+  pub fn into_inner(self) -> T {
+    self.value.into_inner()
+  }
+}
+
+impl<T: Trace> Trace for GcCell<T> {
+  fn trace(&self, visitor: &mut Visitor<'_>) {
+    if let Some(binding) = visitor.binding() {
+      *self.binding.borrow_mut() = Some(binding.clone());
+    }
+    self.value.borrow().trace(visitor);
+  }
+}
+
+impl<T: fmt::Debug> fmt::Debug for GcCell<T> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("GcCell").field("value", &self.value).finish()
+  }
+}
+
+impl<'a, T> Deref for GcRefMut<'a, T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.value
+  }
+}
+
+impl<'a, T> DerefMut for GcRefMut<'a, T> {
+  fn deref_mut(&mut self) -> &mut T {
+    &mut self.value
+  }
+}
+
+impl<'a, T> Drop for GcRefMut<'a, T> {
+  fn drop(&mut self) {
+    if let Some(ref binding) = self.binding {
+      binding.note();
+    }
+  }
+}
+
+impl VM {
+  // Runs the write barrier for the objects whose cells were written to.
+  pub(crate) fn apply_cell_writes(&mut self) {
+    let writes = mem::take(&mut *self.cell_writes.borrow_mut());
+    for obj in &writes {
+      if !self.is_freed(obj) {
+        self.barrier(obj);
+      }
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {GcStrategy, NativeObject, VMConfig};
+
+  struct Env {
+    slots: GcCell<Vec<Sobject>>
+  }
+
+  impl Trace for Env {
+    fn trace(&self, visitor: &mut Visitor<'_>) {
+      self.slots.trace(visitor);
+    }
+  }
+
+  impl NativeObject for Env {}
+
+  #[test]
+  fn cell_writes_reach_minor_collections() {
+    println!("An old value's cell given a young object keeps it through minor collections.");
+
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational));
+    let env = vm.push_native(Env { slots: GcCell::new(Vec::new()) }).unwrap();
+    vm.gc_full();
+    assert!(env.0.get().old());
+
+    // Straight through the handle, without `with_native_mut`.
+    let young = vm.push_int(7).unwrap();
+    vm.pop();
+    if let ::Vobject::Native(ref native) = env.1.borrow().val {
+      native.downcast_ref::<Env>().unwrap().slots.borrow_mut().push(young);
+    }
+    vm.gc();
+    vm.verify().unwrap();
+    let slot = vm.with_native(&env, |env: &Env| env.slots.borrow()[0].clone()).unwrap();
+    assert!(vm.as_int(&slot) == Ok(7));
+  }
+
+  #[test]
+  fn cell_writes_reach_incremental_marking() {
+    println!("A cell written to after its object was marked is traced again.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(1).threshold(8));
+    let p = vm.push_value(&(1u32, 2u32)).unwrap();
+    let env = vm.push_native(Env { slots: GcCell::new(Vec::new()) }).unwrap();
+    while !vm.collecting() {
+      vm.push_int(0).unwrap();
+      vm.pop();
+    }
+    while !vm.is_marked(&env) || vm.gray.iter().any(|obj| Rc::ptr_eq(obj, &env)) {
+      vm.tick();
+    }
+
+    // Move 1 from the still-gray pair into the black native value.
+    assert!(vm.gray.iter().any(|obj| Rc::ptr_eq(obj, &p)));
+    let (one, _) = vm.as_pair(&p).unwrap();
+    vm.set_head(&p, 3).unwrap();
+    vm.with_native(&env, |env: &Env| env.slots.borrow_mut().push(one)).unwrap();
+
+    while !vm.tick() {}
+    vm.verify().unwrap();
+    let one = vm.with_native(&env, |env: &Env| env.slots.borrow()[0].clone()).unwrap();
+    assert!(vm.as_int(&one) == Ok(1));
+  }
+
+  #[test]
+  fn unbound_cells_need_no_vm() {
+    println!("A cell outside any VM is an ordinary RefCell.");
+
+    let cell = GcCell::new(1);
+    *cell.borrow_mut() += 1;
+    assert!(*cell.borrow() == 2 && cell.into_inner() == 2);
+  }
+}
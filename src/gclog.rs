@@ -0,0 +1,246 @@
+// The GC log: one line per collection, for analyzing long runs after the
+// fact. Each line is space-separated `key=value` fields, always in this
+// order:
+//
+//   time=1760438400.123456 kind=full trigger=threshold before=40 after=12 freed=28 pause_us=153 threshold=20 next_threshold=24
+//
+// - `time`: when the collection finished, in seconds since the Unix epoch
+// - `kind`: `minor` or `full`
+// - `trigger`: what started it: `explicit` (a call to `gc`, `gc_minor` or
+//   `gc_full`), `threshold`, `stress`, `deferred` (held up by `gc_paused`),
+//   `max-heap`, `allocator` (the allocator refused), `step` (`gc_step`) or
+//   `idle` (`notify_idle`)
+// - `before`, `after`: objects in the VM before and after. A full
+//   collection that ran incrementally counts from when it started, so
+//   `after` includes objects allocated since.
+// - `freed`: objects freed
+// - `pause_us`: time spent collecting, in microseconds, summed over every
+//   slice of an incremental collection
+// - `threshold`, `next_threshold`: the full-collection threshold before
+//   and after
+//
+// New fields only ever go on the end. `GcLogLine` reads lines back,
+// skipping fields it doesn't know.
+
+use core::cell::RefCell;
+use core::fmt;
+use core::str::FromStr;
+use std::io::Write;
+
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+use error::LogLineError;
+use time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use {Phase, VM};
+
+#[derive(Clone)]
+pub(crate) struct GcLog(pub(crate) Rc<RefCell<dyn Write>>);
+
+impl fmt::Debug for GcLog {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("GcLog")
+  }
+}
+
+/// One line of the GC log, parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GcLogLine {
+  /// Since the Unix epoch.
+  pub time: Duration,
+  pub kind: String,
+  pub trigger: String,
+  pub before: usize,
+  pub after: usize,
+  pub freed: usize,
+  pub pause: Duration,
+  pub threshold: usize,
+  pub next_threshold: usize
+}
+
+impl FromStr for GcLogLine {
+  type Err = LogLineError;
+
+  fn from_str(line: &str) -> Result<GcLogLine, LogLineError> {
+    let fields: Vec<(&str, &str)> = line.split_whitespace().filter_map(|f| f.split_once('=')).collect();
+    let get = |field: &'static str| {
+      fields.iter().find(|f| f.0 == field).map(|f| f.1).ok_or(LogLineError { field })
+    };
+    let num = |field: &'static str| get(field)?.parse::<u64>().map_err(|_| LogLineError { field });
+
+    let time = get("time")?;
+    let time = match time.split_once('.') {
+      Some((secs, micros)) if micros.len() == 6 => match (secs.parse(), micros.parse::<u32>()) {
+        (Ok(secs), Ok(micros)) => Duration::new(secs, micros * 1000),
+        _ => return Err(LogLineError { field: "time" })
+      },
+      _ => return Err(LogLineError { field: "time" })
+    };
+
+    Ok(GcLogLine {
+      time,
+      kind: get("kind")?.to_string(),
+      trigger: get("trigger")?.to_string(),
+      before: num("before")? as usize,
+      after: num("after")? as usize,
+      freed: num("freed")? as usize,
+      pause: Duration::from_micros(num("pause_us")?),
+      threshold: num("threshold")? as usize,
+      next_threshold: num("next_threshold")? as usize
+    })
+  }
+}
+
+impl VM {
+  pub(crate) fn write_gc_log(&mut self, kind: &'static str, freed: usize, threshold: usize) {
+    let log = match self.config.gc_log {
+      Some(ref log) => log.clone(),
+      None => return
+    };
+
+    let trigger = if kind == "full" { self.cycle_trigger } else { self.trigger };
+    let pause = self.cycle_pause + self.slice_start.elapsed();
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let after = self.objects();
+    let before = if kind == "full" { self.cycle_len } else { after + freed };
+
+    // A collection never fails over its log line.
+    let _ = writeln!(log.0.borrow_mut(),
+                     "time={}.{:06} kind={} trigger={} before={} after={} freed={} pause_us={} threshold={} next_threshold={}",
+                     time.as_secs(), time.subsec_micros(), kind, trigger, before, after, freed,
+                     pause.as_micros(), threshold, self.heap_max);
+
+    // Anything else collected in this slice gets its own pause.
+    self.slice_start = Instant::now();
+    self.cycle_pause = Duration::ZERO;
+  }
+
+  // Carries the time spent so far on an unfinished cycle into the next
+  // slice's log line.
+  pub(crate) fn end_slice(&mut self) {
+    self.cycle_pause = if self.phase == Phase::Idle {
+      Duration::ZERO
+    } else {
+      self.cycle_pause + self.slice_start.elapsed()
+    };
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io;
+  use {GcStrategy, VMConfig};
+
+  #[derive(Clone)]
+  struct Shared(Rc<RefCell<Vec<u8>>>);
+
+  impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  fn lines(out: &Shared) -> Vec<Vec<(String, String)>> {
+    let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+    text.lines().map(|line| {
+      line.split(' ').map(|field| {
+        let (key, value) = field.split_once('=').unwrap();
+        (key.to_string(), value.to_string())
+      }).collect()
+    }).collect()
+  }
+
+  fn field<'a>(line: &'a [(String, String)], key: &str) -> &'a str {
+    &line.iter().find(|(k, _)| k == key).unwrap().1
+  }
+
+  #[test]
+  fn one_line_per_collection() {
+    println!("Each collection writes one line of key=value fields.");
+
+    let out = Shared(Rc::new(RefCell::new(Vec::new())));
+    let mut vm = VM::with_config(VMConfig::new().threshold(4).gc_log(out.clone()));
+
+    for i in 0..3 {
+      vm.push_int(i).unwrap();
+    }
+    vm.pop();
+    vm.gc();
+
+    let lines = lines(&out);
+    assert!(lines.len() == 1);
+    let keys: Vec<&str> = lines[0].iter().map(|(k, _)| k.as_str()).collect();
+    assert!(keys == ["time", "kind", "trigger", "before", "after", "freed", "pause_us", "threshold", "next_threshold"]);
+    assert!(field(&lines[0], "kind") == "full");
+    assert!(field(&lines[0], "trigger") == "explicit");
+    assert!(field(&lines[0], "before") == "3");
+    assert!(field(&lines[0], "after") == "2");
+    assert!(field(&lines[0], "freed") == "1");
+    assert!(field(&lines[0], "threshold") == "4");
+    assert!(field(&lines[0], "next_threshold") == "6");
+    assert!(field(&lines[0], "time").parse::<f64>().unwrap() > 0.0);
+  }
+
+  #[test]
+  fn lines_parse_back() {
+    println!("GcLogLine reads back what the log writes.");
+
+    let out = Shared(Rc::new(RefCell::new(Vec::new())));
+    let mut vm = VM::with_config(VMConfig::new().gc_log(out.clone()));
+    vm.push_int(1).unwrap();
+    vm.gc();
+
+    let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+    let line: GcLogLine = text.trim_end().parse().unwrap();
+    assert!(line.kind == "full" && line.trigger == "explicit");
+    assert!((line.before, line.after, line.freed) == (1, 1, 0));
+    assert!((line.threshold, line.next_threshold) == (10, 2));
+
+    let line: GcLogLine = "time=5.000020 kind=minor trigger=stress before=3 after=1 freed=2 pause_us=7 threshold=4 next_threshold=4 later=1"
+      .parse().unwrap();
+    assert!(line.time == Duration::from_micros(5_000_020));
+    assert!(line.pause == Duration::from_micros(7));
+
+    assert!("time=5.000020 kind=minor".parse::<GcLogLine>() == Err(LogLineError { field: "trigger" }));
+    assert!("time=5 kind=minor".parse::<GcLogLine>() == Err(LogLineError { field: "time" }));
+  }
+
+  #[test]
+  fn triggers_are_recorded() {
+    println!("The log says what started each collection.");
+
+    let out = Shared(Rc::new(RefCell::new(Vec::new())));
+    let config = VMConfig::new().strategy(GcStrategy::Generational).nursery_size(2).gc_log(out.clone());
+    let mut vm = VM::with_config(config);
+
+    for i in 0..3 {
+      vm.push_int(i).unwrap();
+    }
+    vm.gc_paused(|vm| {
+      vm.pop();
+      vm.push_int(3).unwrap();
+      vm.push_int(4).unwrap();
+    });
+    vm.gc_full();
+
+    let triggers: Vec<(String, String)> = lines(&out).iter()
+      .map(|line| (field(line, "kind").to_string(), field(line, "trigger").to_string()))
+      .collect();
+    assert!(triggers == [
+      ("minor".to_string(), "threshold".to_string()),
+      ("minor".to_string(), "deferred".to_string()),
+      ("full".to_string(), "explicit".to_string())
+    ]);
+  }
+}
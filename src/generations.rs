@@ -0,0 +1,254 @@
+// Young generations for the generational strategy. Generation 0 is the
+// nursery, where objects are allocated; `VMConfig::generations` can add
+// more between it and the old generation (`heap`). Collecting generation k
+// collects it and every younger one. Each survivor gets a collection older,
+// and moves up a generation once its age reaches that generation's
+// promotion age.
+//
+// Objects in generations older than k are roots: old ones through the card
+// table, the rest by scanning them whole. Young generations are small, and
+// this way only stores into old objects need remembering.
+
+use alloc::vec::Vec;
+
+use tracer::Source;
+use {children, nursery_reserve, Phase, Sobject, VM};
+
+/// A young generation: collected once it holds `size` objects, with
+/// survivors moving on once they have survived `promotion_age`
+/// collections in all.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Generation {
+  pub size: usize,
+  pub promotion_age: u32
+}
+
+// Like `Object::mark`, but stops at objects older than generation `k`:
+// anything they point to is found from the roots instead.
+pub(crate) fn mark_young(obj: &Sobject, k: usize, gray: &mut Vec<Sobject>) {
+  let gch = obj.0.get();
+
+  if gch.marked() || gch.old() || gch.generation() > k {
+    return;
+  }
+
+  obj.0.set(gch.with_marked(true));
+  gray.push(obj.clone());
+}
+
+impl VM {
+  fn generation_mut(&mut self, g: usize) -> &mut Vec<Sobject> {
+    if g == 0 { &mut self.nursery } else { &mut self.middle[g - 1] }
+  }
+
+  fn generation_len(&self, g: usize) -> usize {
+    if g == 0 { self.nursery.len() } else { self.middle[g - 1].len() }
+  }
+
+  // Collects the nursery, then each older young generation that has
+  // outgrown its size.
+  pub(crate) fn collect_young(&mut self) -> usize {
+    let mut freed = self.collect_minor();
+
+    for g in 1..self.config.generations.len() {
+      if self.generation_len(g) >= self.config.generations[g].size {
+        freed += self.collect_generation(g);
+      }
+    }
+    freed
+  }
+
+  pub(crate) fn collect_minor(&mut self) -> usize {
+    self.collect_generation(0)
+  }
+
+  fn collect_generation(&mut self, k: usize) -> usize {
+    if self.phase != Phase::Idle {
+      return self.collect_full();
+    }
+
+    self.record_mark("minor");
+    self.blocked = false;
+    self.mark_generation(k);
+    self.trim_gray();
+    if self.blocked {
+      // Left for the next one, when the host has let go.
+      self.abandon_generation(k);
+      self.record_done("minor", 0);
+      return 0;
+    }
+
+    self.record_sweep("minor");
+    let mut freed = 0;
+    for g in (0..k + 1).rev() {
+      freed += self.sweep_generation(g);
+    }
+    self.unmark_region();
+
+    // Old objects can only point at young ones while there are some.
+    if self.nursery.is_empty() && self.middle.iter().all(Vec::is_empty) {
+      self.cards.clear();
+    }
+
+    let threshold = self.heap_max;
+    self.stats.minor_collections += 1;
+    self.count_collection(freed);
+    self.record_done("minor", freed);
+    self.log("minor", freed, threshold);
+    self.record_history("minor");
+    freed
+  }
+
+  fn mark_generation(&mut self, k: usize) {
+    let roots: Vec<(Source, Sobject)> = self.roots().collect();
+    for (source, obj) in &roots {
+      self.note_mark(obj, *source, Some(k));
+      mark_young(obj, k, &mut self.gray);
+    }
+
+    self.scan_dirty_cards(k);
+
+    for gen in &self.middle[k.min(self.middle.len())..] {
+      for obj in gen {
+        for child in children(obj, &mut self.blocked) {
+          self.note_mark(&child, Source::Object(obj.2), Some(k));
+          mark_young(&child, k, &mut self.gray);
+        }
+      }
+    }
+
+    loop {
+      self.gray_peak = self.gray_peak.max(self.gray.len());
+      let obj = match self.gray.pop() {
+        Some(obj) => obj,
+        None => break
+      };
+      for child in children(&obj, &mut self.blocked) {
+        self.note_mark(&child, Source::Object(obj.2), Some(k));
+        mark_young(&child, k, &mut self.gray);
+      }
+    }
+  }
+
+  // Undoes a minor collection's marking, which met an object the host
+  // holds mutably borrowed and so can't know what it points to.
+  fn abandon_generation(&mut self, k: usize) {
+    for g in 0..k + 1 {
+      for obj in self.generation_mut(g).iter() {
+        obj.0.set(obj.0.get().with_marked(false));
+      }
+    }
+    self.unmark_region();
+    self.blocked = false;
+  }
+
+  // Frees generation `g`'s dead and ages its survivors, moving up those
+  // old enough. Returns the number freed.
+  fn sweep_generation(&mut self, g: usize) -> usize {
+    let promotion_age = self.config.generations[g].promotion_age;
+    let last = g + 1 == self.config.generations.len();
+    let mut freed = 0;
+
+    let reserve = if g == 0 { nursery_reserve(&self.config) } else { 0 };
+    let mut objs = core::mem::replace(self.generation_mut(g), Vec::with_capacity(reserve));
+    if let Some(ref mut shuffle) = self.shuffle {
+      shuffle.shuffle(&mut objs);
+    }
+    for obj in objs {
+      self.note_sweep(&obj);
+      let gch = obj.0.get();
+      if !gch.marked() {
+        self.free(&obj);
+        freed += 1;
+        continue;
+      }
+
+      let gch = gch.with_marked(false).aged();
+      if gch.age() < promotion_age {
+        obj.0.set(gch);
+        self.generation_mut(g).push(obj);
+      } else if last {
+        self.promote(obj);
+      } else {
+        obj.0.set(gch.with_generation(g + 1));
+        self.generation_mut(g + 1).push(obj);
+      }
+    }
+
+    freed
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {GcStrategy, VMConfig, Vobject};
+
+  fn three_generations() -> VM {
+    let young = [Generation { size: 4, promotion_age: 2 }, Generation { size: 4, promotion_age: 4 }];
+    VM::with_config(VMConfig::new().strategy(GcStrategy::Generational).generations(&young).threshold(100))
+  }
+
+  #[test]
+  fn survivors_move_up_by_age() {
+    println!("Survivors stay young until they reach their promotion age.");
+
+    let mut vm = three_generations();
+    let a = vm.push_int(1).unwrap();
+
+    vm.gc_minor();
+    assert!(vm.nursery.len() == 1 && vm.age(&a) == 1);
+    vm.gc_minor();
+    assert!(vm.nursery.is_empty() && vm.middle[0].len() == 1);
+
+    // Minor collections leave the middle generation alone.
+    vm.gc_minor();
+    assert!(vm.age(&a) == 2);
+
+    vm.collect_generation(1);
+    vm.collect_generation(1);
+    assert!(vm.middle[0].is_empty() && vm.heap.len() == 1 && vm.age(&a) == 4);
+  }
+
+  #[test]
+  fn older_young_generations_are_roots() {
+    println!("Collecting the nursery keeps what the middle generation points to.");
+
+    let mut vm = three_generations();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    vm.gc_minor();
+    vm.gc_minor();
+    assert!(vm.middle[0].len() == 3);
+
+    let x = vm.push_int(3).unwrap();
+    vm.pop();
+    if let Vobject::Pair(_, ref mut tail) = p.1.borrow_mut().val { *tail = x.clone() }
+    vm.write_barrier(&p);
+
+    assert!(vm.gc_minor() == 0);
+    assert!(vm.as_int(&x) == Ok(3));
+  }
+
+  #[test]
+  fn full_generations_are_collected() {
+    println!("A young generation that fills up is collected after the nursery.");
+
+    let mut vm = three_generations();
+    for i in 0..40 {
+      vm.push_int(i).unwrap();
+      if i % 3 != 0 {
+        vm.pop();
+      }
+    }
+
+    assert!(vm.middle[0].len() < 4 + 4);
+    assert!(!vm.heap.is_empty());
+  }
+}
@@ -0,0 +1,176 @@
+// Golden traces: operation scripts in src/golden/*.trace, replayed against
+// the heap states recorded beside them in *.golden, so a change to the
+// collector's insides can be checked to leave what it observably does
+// exactly as it was. After each collection, and at the end, the record
+// has what the collection freed, the stack, which objects the VM still holds and
+// their values, and the census of the live ones. Objects are named by id
+// and listed in id order, so neither list order nor object size shows.
+//
+// A trace starts with `config` and `VMConfig` settings, then one
+// operation per line; slots count from the bottom of the stack and
+// handles from the first `persist`:
+//
+//   config strategy=generational threshold=8 nursery=4 host_driven=2
+//   int 7          push_int                 pair      push_pair
+//   cons 0 2       push_pair_from slots     list 1 2  push_value of a Vec
+//   head 3 0       set_head of slot 3       tail 3 0  set_tail
+//   pop            truncate 2               persist 1     release 0
+//   gc             minor          full      tick
+//
+// After a deliberate change in behaviour, `BABYGC_BLESS=1 cargo test
+// golden` rewrites the golden files from what the collector does now.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::string::String;
+use std::vec::Vec;
+
+use {GcStrategy, PersistentHandle, Sobject, VMConfig, VM, Vobject};
+
+// Name, trace and golden record.
+const TRACES: [(&str, &str, &str); 4] = [
+  ("classic", include_str!("golden/classic.trace"), include_str!("golden/classic.golden")),
+  ("generations", include_str!("golden/generations.trace"), include_str!("golden/generations.golden")),
+  ("incremental", include_str!("golden/incremental.trace"), include_str!("golden/incremental.golden")),
+  ("lists", include_str!("golden/lists.trace"), include_str!("golden/lists.golden"))
+];
+
+fn config(settings: &[&str]) -> Result<VMConfig, String> {
+  let mut config = VMConfig::new();
+  for setting in settings {
+    let (key, value) = setting.split_once('=').ok_or_else(|| format!("bad setting {}", setting))?;
+    let n = || value.parse::<usize>().map_err(|_| format!("bad setting {}", setting));
+    config = match key {
+      "strategy" => config.strategy(value.parse::<GcStrategy>().map_err(|()| format!("bad setting {}", setting))?),
+      "threshold" => config.threshold(n()?),
+      "nursery" => config.nursery_size(n()?),
+      "host_driven" => config.host_driven(n()?),
+      _ => return Err(format!("unknown setting {}", key))
+    };
+  }
+  Ok(config)
+}
+
+fn value(obj: &Sobject) -> String {
+  match obj.1.borrow().val {
+    Vobject::Int(n) => n.to_string(),
+    Vobject::Pair(ref head, ref tail) => format!("(#{} #{})", head.2, tail.2),
+    Vobject::Native(ref native) => format!("#<{}>", native.type_name())
+  }
+}
+
+// The heap as the golden files record it.
+fn record(out: &mut String, vm: &VM) {
+  let live: BTreeSet<u64> = vm.iter_live().map(|obj| obj.2).collect();
+  let held: BTreeMap<u64, &Sobject> = vm.iter_heap().map(|obj| (obj.2, obj)).collect();
+
+  out.push_str("  stack");
+  for obj in vm.iter_stack() {
+    write!(out, " #{}", obj.2).unwrap();
+  }
+  out.push_str("\n  live");
+  for (id, obj) in held.iter().filter(|&(id, _)| live.contains(id)) {
+    write!(out, " #{}={}", id, value(obj)).unwrap();
+  }
+  out.push_str("\n  dead");
+  for id in held.keys().filter(|id| !live.contains(id)) {
+    write!(out, " #{}", id).unwrap();
+  }
+  out.push_str("\n  census");
+  for entry in vm.census().entries {
+    write!(out, " {} x{}", entry.shape, entry.count).unwrap();
+  }
+  out.push('\n');
+}
+
+/// Runs `trace`, returning the record of the heap it leaves after each
+/// collection and at the end.
+fn replay(trace: &str) -> Result<String, String> {
+  let mut lines = trace.lines().enumerate()
+    .map(|(i, line)| (i + 1, line.split('#').next().unwrap_or("").trim()))
+    .filter(|&(_, line)| !line.is_empty());
+
+  let mut vm = match lines.next() {
+    Some((_, line)) if line.starts_with("config") => VM::with_config(config(&line.split_whitespace().skip(1).collect::<Vec<_>>())?),
+    _ => return Err(String::from("a trace starts with config"))
+  };
+  let mut handles: Vec<PersistentHandle> = Vec::new();
+  let mut out = String::new();
+
+  for (n, line) in lines {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let args: Vec<usize> = words[1..].iter().map(|w| w.parse().map_err(|_| format!("line {}: bad argument {}", n, w)))
+      .collect::<Result<_, _>>()?;
+    let slot = |vm: &VM, i: usize| vm.stack_get(args[i]).cloned().ok_or_else(|| format!("line {}: no slot {}", n, args[i]));
+    let fail = |e| format!("line {}: {}", n, e);
+
+    let collected = match (words[0], args.len()) {
+      ("int", 1) => { vm.push_int(args[0] as u32).map_err(fail)?; None }
+      ("pair", 0) => { vm.push_pair().map_err(fail)?; None }
+      ("cons", 2) => { let (h, t) = (slot(&vm, 0)?, slot(&vm, 1)?); vm.push_pair_from(&h, &t).map_err(fail)?; None }
+      ("list", _) => { let items: Vec<u32> = args.iter().map(|&a| a as u32).collect(); vm.push_value(&items).map_err(fail)?; None }
+      ("head", 2) => { let (p, v) = (slot(&vm, 0)?, slot(&vm, 1)?); vm.set_head(&p, &v).map_err(fail)?; None }
+      ("tail", 2) => { let (p, v) = (slot(&vm, 0)?, slot(&vm, 1)?); vm.set_tail(&p, &v).map_err(fail)?; None }
+      ("pop", 0) => { vm.pop(); None }
+      ("truncate", 1) => { vm.truncate_stack(args[0]); None }
+      ("persist", 1) => { let obj = slot(&vm, 0)?; handles.push(vm.persist(&obj)); None }
+      ("release", 1) => {
+        let handle = *handles.get(args[0]).ok_or_else(|| format!("line {}: no handle {}", n, args[0]))?;
+        vm.release(handle);
+        None
+      }
+      ("gc", 0) => Some(format!("freed {}", vm.gc())),
+      ("minor", 0) => Some(format!("freed {}", vm.gc_minor())),
+      ("full", 0) => Some(format!("freed {}", vm.gc_full())),
+      ("tick", 0) => if vm.tick() { Some(String::from("cycle done")) } else { None },
+      _ => return Err(format!("line {}: can't read {:?}", n, line))
+    };
+
+    vm.verify().map_err(|e| format!("line {}: {}", n, e))?;
+    if let Some(collected) = collected {
+      writeln!(out, "{} {}: {}", n, line, collected).unwrap();
+      record(&mut out, &vm);
+    }
+  }
+
+  writeln!(out, "end").unwrap();
+  record(&mut out, &vm);
+  Ok(out)
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn golden_traces_replay_exactly() {
+    println!("Every checked-in trace leaves the heap states recorded for it.");
+
+    let bless = env::var("BABYGC_BLESS").is_ok_and(|v| v == "1");
+    for &(name, trace, golden) in &TRACES {
+      let got = replay(trace).unwrap_or_else(|e| panic!("{}.trace: {}", name, e));
+      if bless {
+        fs::write(format!("{}/src/golden/{}.golden", env!("CARGO_MANIFEST_DIR"), name), &got).unwrap();
+        continue;
+      }
+      assert!(got == golden, "{} no longer matches {}.golden:\n{}", name, name, got);
+    }
+  }
+
+  #[test]
+  fn bad_traces_are_refused() {
+    println!("A trace the harness can't read says where.");
+
+    assert!(replay("int 1").is_err());
+    assert!(replay("config\nint 1\nfrob").err() == Some(String::from("line 3: can't read \"frob\"")));
+    assert!(replay("config\ncons 0 1").err() == Some(String::from("line 2: no slot 0")));
+    assert!(replay("config wat=1").is_err());
+  }
+}
@@ -0,0 +1,205 @@
+// Whole-graph operations on values, and iterating over what the VM holds.
+// The iterators borrow the VM, so nothing can allocate, and so nothing can
+// be collected, until they are dropped.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+use marshal::mismatch;
+use {addr, Sobject, VM, VmError, Vobject};
+
+// Everything reachable from `roots`, in depth-first order, each object
+// once. This is the collector's trace, but with its own visited set rather
+// than mark bits, so it can run in the middle of an incremental cycle.
+pub(crate) fn walk<I: IntoIterator<Item = Sobject>>(roots: I) -> Vec<Sobject> {
+  let mut seen = BTreeSet::new();
+  let mut found = Vec::new();
+  let mut todo: Vec<Sobject> = roots.into_iter().collect();
+  todo.reverse();
+
+  while let Some(obj) = todo.pop() {
+    if !seen.insert(addr(&obj)) {
+      continue;
+    }
+
+    let at = todo.len();
+    todo.extend(obj.1.borrow().val.children());
+    todo[at..].reverse();
+    found.push(obj);
+  }
+
+  found
+}
+
+// The values of `originals`, copied with `translate`, or the type error
+// for the first native value that can't be copied.
+pub(crate) fn copy_values(originals: &[Sobject], translate: &mut dyn FnMut(&Sobject) -> Sobject)
+                          -> Result<Vec<Vobject>, VmError> {
+  originals.iter().map(|orig| {
+    let o = orig.1.borrow();
+    o.val.copy_with(translate).ok_or_else(|| VmError::Type(mismatch(&o.val, "copyable value")))
+  }).collect()
+}
+
+impl VM {
+  /// Every object the VM holds, live or awaiting collection.
+  pub fn iter_heap<'a>(&'a self) -> impl Iterator<Item = &'a Sobject> + 'a {
+    self.iter_objects()
+  }
+
+  /// The stack, bottom first. Persistent handles are roots too; see
+  /// `iter_persistent`.
+  pub fn iter_roots<'a>(&'a self) -> impl Iterator<Item = &'a Sobject> + 'a {
+    self.stack.iter()
+  }
+
+  /// The objects a collection right now would keep, in `iter_heap` order.
+  pub fn iter_live<'a>(&'a self) -> impl Iterator<Item = &'a Sobject> + 'a {
+    let live: BTreeSet<usize> = walk(self.roots().map(|(_, obj)| obj)).iter().map(addr).collect();
+    self.iter_objects().filter(move |obj| live.contains(&addr(obj)))
+  }
+
+  /// `obj` and everything reachable from it, each once, `obj` first.
+  pub fn reachable_from(&self, obj: &Sobject) -> impl Iterator<Item = Sobject> {
+    walk(Some(obj.clone())).into_iter()
+  }
+
+  /// Copies everything reachable from `obj`, keeping its sharing and
+  /// cycles, and pushes the copy. If the heap runs out partway the stack
+  /// is left as it was. Fails with `VmError::Freed` for a stale handle,
+  /// and with `VmError::Type` if it reaches a native value that can't be
+  /// copied.
+  pub fn deep_clone(&mut self, obj: &Sobject) -> Result<Sobject, VmError> {
+    if self.is_freed(obj) {
+      return Err(VmError::Freed);
+    }
+    // The original is a temporary root until the copy is done, so the
+    // collections allocating the copy sets off can't free what it reads.
+    let roots = self.temp_roots.len();
+    self.temp_roots.push(obj.clone());
+    let copy = self.copy_graph(walk(Some(obj.clone())));
+    self.temp_roots.truncate(roots);
+    copy
+  }
+
+  fn copy_graph(&mut self, originals: Vec<Sobject>) -> Result<Sobject, VmError> {
+    let n = self.stack.len();
+
+    // The copies stay on the stack, and so rooted, until all of them exist.
+    let mut index = BTreeMap::new();
+    for (i, orig) in originals.iter().enumerate() {
+      if let Err(e) = self.push_int(0) {
+        self.stack.truncate(n);
+        return Err(e);
+      }
+      index.insert(addr(orig), i);
+    }
+
+    let copies = self.stack.split_off(n);
+    let vals = copy_values(&originals, &mut |obj| copies[index[&addr(obj)]].clone());
+    for (copy, val) in copies.iter().zip(vals?) {
+      copy.1.borrow_mut().val = val;
+      self.bind_cells(copy);
+    }
+    for copy in &copies {
+      self.write_barrier(copy);
+    }
+
+    self.stack.push(copies[0].clone());
+    Ok(copies[0].clone())
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use VMConfig;
+
+  #[test]
+  fn deep_clone_roots_the_original() {
+    println!("A deep clone reads the original whatever its allocations collect.");
+
+    let mut vm = VM::with_config(VMConfig::new().stress(true));
+    let p = vm.push_value(&(1u32, (2u32, 3u32))).unwrap();
+    vm.pop();
+
+    let copy = vm.deep_clone(&p).unwrap();
+    vm.verify().unwrap();
+    assert!(vm.extract::<(u32, (u32, u32))>(&copy) == Ok((1, (2, 3))));
+  }
+
+  #[test]
+  fn deep_clone_keeps_shape() {
+    println!("Deep clones share and cycle where the original did.");
+
+    let mut vm = VM::with_config(VMConfig::new().stress(true));
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let a = vm.push_pair().unwrap();
+    if let Vobject::Pair(_, ref mut x) = a.1.borrow_mut().val { *x = a.clone() }
+    vm.stack.push(a.clone());
+    let b = vm.push_pair().unwrap();
+
+    let copy = vm.deep_clone(&b).unwrap();
+    assert!(vm.display(&copy).to_string() == vm.display(&b).to_string());
+    assert!(vm.equals(&copy, &b));
+
+    // Nothing is shared with the original.
+    let orig = walk(Some(b.clone()));
+    assert!(walk(Some(copy.clone())).iter().all(|c| orig.iter().all(|o| !Rc::ptr_eq(c, o))));
+
+    vm.stack.retain(|obj| Rc::ptr_eq(obj, &copy));
+    vm.gc();
+    assert!(vm.heap.len() == 3);
+  }
+
+  #[test]
+  fn deep_clone_out_of_memory() {
+    println!("A clone that doesn't fit leaves the stack alone.");
+
+    let mut vm = VM::with_config(VMConfig::new().max_heap(4));
+    let list = vm.push_value(&(1u32, 2u32)).unwrap();
+
+    assert!(vm.deep_clone(&list).unwrap_err() == VmError::OutOfMemory);
+    assert!(vm.stack.len() == 1);
+  }
+
+  #[test]
+  fn iterators() {
+    println!("The heap, roots and live objects can be walked.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    vm.push_pair().unwrap();
+    let three = vm.push_int(3).unwrap();
+    vm.pop();
+
+    assert_heap!(vm, heap: 4, roots: 1, live: 3, unreachable: [three]);
+
+    vm.gc();
+    assert_heap!(vm, heap: 3);
+  }
+
+  #[test]
+  fn reachable_from_follows_pairs() {
+    println!("reachable_from finds each reachable object once.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let a = vm.push_pair().unwrap();
+    if let Vobject::Pair(_, ref mut x) = a.1.borrow_mut().val { *x = a.clone() }
+
+    let found: Vec<Sobject> = vm.reachable_from(&a).collect();
+    assert!(found.len() == 2);
+    assert!(Rc::ptr_eq(&found[0], &a));
+    assert!(matches!(found[1].1.borrow().val, Vobject::Int(1)));
+  }
+}
@@ -0,0 +1,94 @@
+// Persistent handles: roots outside the stack, for hosts that keep values
+// in their own data structures between calls. The VM holds each target in
+// a table, and marking starts from the table as well as the stack, until
+// the handle is released. A handle is just a number, so it can be stored
+// anywhere, and one that was released (or came from another VM) finds
+// nothing rather than some other object. Images don't record handles.
+
+use {Sobject, VM};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PersistentHandle(u64);
+
+impl VM {
+  /// Keeps `obj` alive until the handle is released. Panics if a
+  /// collection already freed it.
+  pub fn persist(&mut self, obj: &Sobject) -> PersistentHandle {
+    assert!(!self.is_freed(obj), "freed objects can't be persisted");
+    let handle = PersistentHandle(self.next_handle);
+    self.next_handle += 1;
+    self.persistent.insert(handle.0, obj.clone());
+    handle
+  }
+
+  /// The object `handle` keeps alive, or None once it has been released.
+  pub fn persistent(&self, handle: PersistentHandle) -> Option<&Sobject> {
+    self.persistent.get(&handle.0)
+  }
+
+  /// Stops rooting `handle`'s object, returning it if the handle was
+  /// still live. The object is freed by a later collection unless
+  /// something else keeps it.
+  pub fn release(&mut self, handle: PersistentHandle) -> Option<Sobject> {
+    self.persistent.remove(&handle.0)
+  }
+
+  /// Objects rooted by persistent handles, oldest handle first.
+  pub fn iter_persistent<'a>(&'a self) -> impl Iterator<Item = &'a Sobject> + 'a {
+    self.persistent.values()
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use {GcStrategy, VMConfig};
+
+  #[test]
+  fn handles_root_until_released() {
+    println!("A persistent handle keeps its object alive off the stack.");
+
+    for strategy in [GcStrategy::MarkSweep, GcStrategy::Generational] {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy));
+      vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+      let pair = vm.push_pair().unwrap();
+      let handle = vm.persist(&pair);
+      vm.pop();
+      drop(pair);
+
+      vm.gc();
+      vm.gc_full();
+      assert!(vm.iter_heap().count() == 3 && vm.iter_live().count() == 3);
+      assert!(vm.extract::<(u32, u32)>(vm.persistent(handle).unwrap()) == Ok((1, 2)));
+
+      assert!(vm.release(handle).is_some());
+      assert!(vm.persistent(handle).is_none() && vm.release(handle).is_none());
+      assert!(vm.gc_full() == 3);
+    }
+  }
+
+  #[test]
+  fn handles_are_roots_mid_cycle() {
+    println!("Persisting an object during an incremental cycle keeps it.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(1));
+    vm.push_int(7).unwrap();
+    let obj = vm.pop();
+    while !vm.collecting() {
+      vm.push_int(0).unwrap();
+    }
+
+    let handle = vm.persist(&obj);
+    drop(obj);
+    while !vm.tick() {}
+    assert!(vm.extract::<u32>(vm.persistent(handle).unwrap()) == Ok(7));
+    assert!(vm.iter_heap().any(|obj| Rc::ptr_eq(obj, vm.persistent(handle).unwrap())));
+  }
+}
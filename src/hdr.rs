@@ -0,0 +1,327 @@
+// Pause times in an HdrHistogram-style histogram: every pause from 1µs to
+// an hour is kept to two significant digits, in a fixed 27KB, and can be
+// written out in the HdrHistogram interchange formats so other tools can
+// merge and plot it. The layout (and so the encoding) follows the
+// reference implementation, with values in nanoseconds:
+//
+//   counts are grouped in buckets of SUB_BUCKETS/2 slots; each bucket
+//   covers twice the range of the one before at half the resolution.
+//
+// Encoded histograms are the V2 format, compressed with zlib. Compression
+// here is just zlib's stored blocks, which any inflater reads.
+
+use core::fmt::{self, Write};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use time::{Duration, SystemTime, UNIX_EPOCH};
+use VM;
+
+const DIGITS: u32 = 2;
+const LOWEST: u64 = 1_000;
+const HIGHEST: u64 = 3_600_000_000_000;
+
+// Derived from the three above as the reference implementation does.
+const UNIT_MAGNITUDE: u32 = 9;
+const SUB_BUCKET_MAGNITUDE: u32 = 8;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_MAGNITUDE;
+const SUB_BUCKET_HALF_MAGNITUDE: u32 = SUB_BUCKET_MAGNITUDE - 1;
+const SUB_BUCKET_HALF: u64 = SUB_BUCKETS / 2;
+const SUB_BUCKET_MASK: u64 = (SUB_BUCKETS - 1) << UNIT_MAGNITUDE;
+const BUCKETS: usize = 26;
+const COUNTS: usize = (BUCKETS + 1) * SUB_BUCKET_HALF as usize;
+
+const V2_COOKIE: u32 = 0x1c84_9313;
+const V2_COMPRESSED_COOKIE: u32 = 0x1c84_9314;
+
+/// Pause times, from when recording began.
+#[derive(Clone)]
+pub struct PauseHistogram {
+  counts: Vec<u64>,
+  total: u64,
+  max: u64,
+  start: Duration
+}
+
+fn index(value: u64) -> usize {
+  let bucket = 64 - UNIT_MAGNITUDE - SUB_BUCKET_MAGNITUDE - (value | SUB_BUCKET_MASK).leading_zeros();
+  let sub_bucket = value >> (bucket + UNIT_MAGNITUDE);
+  (((bucket as u64 + 1) << SUB_BUCKET_HALF_MAGNITUDE) + sub_bucket - SUB_BUCKET_HALF) as usize
+}
+
+// The smallest value counted at `index`, and the size of its range.
+fn range(index: usize) -> (u64, u64) {
+  let mut bucket = (index as i64 >> SUB_BUCKET_HALF_MAGNITUDE) - 1;
+  let mut sub_bucket = (index as u64 & (SUB_BUCKET_HALF - 1)) + SUB_BUCKET_HALF;
+  if bucket < 0 {
+    sub_bucket -= SUB_BUCKET_HALF;
+    bucket = 0;
+  }
+  (sub_bucket << (bucket as u32 + UNIT_MAGNITUDE), 1 << (bucket as u32 + UNIT_MAGNITUDE))
+}
+
+fn now() -> Duration {
+  SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+impl PauseHistogram {
+  pub fn new() -> PauseHistogram {
+    PauseHistogram { counts: vec![0; COUNTS], total: 0, max: 0, start: now() }
+  }
+
+  /// Counts one pause. Anything over an hour counts as an hour.
+  pub fn record(&mut self, pause: Duration) {
+    let value = (pause.as_nanos() as u64).min(HIGHEST);
+    self.counts[index(value)] += 1;
+    self.total += 1;
+    self.max = self.max.max(value);
+  }
+
+  /// Adds in another histogram's pauses, as from another run.
+  pub fn add(&mut self, other: &PauseHistogram) {
+    for (count, n) in self.counts.iter_mut().zip(&other.counts) {
+      *count += n;
+    }
+    self.total += other.total;
+    self.max = self.max.max(other.max);
+    self.start = self.start.min(other.start);
+  }
+
+  pub fn len(&self) -> u64 {
+    self.total
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.total == 0
+  }
+
+  /// The longest pause, exactly.
+  pub fn max(&self) -> Duration {
+    Duration::from_nanos(self.max)
+  }
+
+  /// The pause `percentile` percent of pauses were no longer than, to two
+  /// significant digits. Zero if nothing was recorded.
+  pub fn value_at_percentile(&self, percentile: f64) -> Duration {
+    let percentile = percentile.clamp(0.0, 100.0);
+    let wanted = ((percentile / 100.0 * self.total as f64 + 0.5) as u64).max(1);
+
+    let mut seen = 0;
+    for (i, &count) in self.counts.iter().enumerate() {
+      seen += count;
+      if seen >= wanted {
+        let (lowest, size) = range(i);
+        return Duration::from_nanos(if percentile == 0.0 { lowest } else { lowest + size - 1 });
+      }
+    }
+    Duration::ZERO
+  }
+
+  /// The V2 encoding, uncompressed.
+  fn encode(&self) -> Vec<u8> {
+    let mut payload = Vec::new();
+    let limit = if self.total == 0 { 0 } else { index(self.max) + 1 };
+    let mut i = 0;
+
+    // Counts as zigzag LEB128, with runs of zeros as one negative count.
+    while i < limit {
+      let count = self.counts[i];
+      i += 1;
+
+      let mut value = count as i64;
+      if count == 0 {
+        let mut zeros = 1;
+        while i < limit && self.counts[i] == 0 {
+          zeros += 1;
+          i += 1;
+        }
+        if zeros > 1 {
+          value = -zeros;
+        }
+      }
+
+      let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+      while zigzag >= 0x80 {
+        payload.push(zigzag as u8 | 0x80);
+        zigzag >>= 7;
+      }
+      payload.push(zigzag as u8);
+    }
+
+    let mut out = Vec::with_capacity(40 + payload.len());
+    out.extend_from_slice(&V2_COOKIE.to_be_bytes());
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(&0u32.to_be_bytes());
+    out.extend_from_slice(&DIGITS.to_be_bytes());
+    out.extend_from_slice(&LOWEST.to_be_bytes());
+    out.extend_from_slice(&HIGHEST.to_be_bytes());
+    out.extend_from_slice(&1.0f64.to_be_bytes());
+    out.extend_from_slice(&payload);
+    out
+  }
+
+  /// The histogram in HdrHistogram's compressed V2 encoding, as read by
+  /// `decodeFromCompressedByteBuffer` and friends.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let zlib = zlib_stored(&self.encode());
+    let mut out = Vec::with_capacity(8 + zlib.len());
+    out.extend_from_slice(&V2_COMPRESSED_COOKIE.to_be_bytes());
+    out.extend_from_slice(&(zlib.len() as u32).to_be_bytes());
+    out.extend_from_slice(&zlib);
+    out
+  }
+
+  /// A histogram log with one interval, from the VM's creation until now,
+  /// for `HistogramLogReader`, `HistogramLogProcessor` and the plotters.
+  /// Interval_Max is in milliseconds, the tools' default.
+  pub fn to_log(&self) -> String {
+    let start = self.start.as_secs_f64();
+    let length = now().as_secs_f64() - start;
+
+    let mut out = String::new();
+    let _ = writeln!(out, "#[Histogram log format version 1.3]");
+    let _ = writeln!(out, "#[StartTime: {:.3} (seconds since epoch)]", start);
+    let _ = writeln!(out, "\"StartTimestamp\",\"Interval_Length\",\"Interval_Max\",\"Interval_Compressed_Histogram\"");
+    let _ = writeln!(out, "{:.3},{:.3},{:.3},{}", 0.0, length.max(0.0), self.max as f64 / 1e6, base64(&self.to_bytes()));
+    out
+  }
+}
+
+impl Default for PauseHistogram {
+  fn default() -> PauseHistogram {
+    PauseHistogram::new()
+  }
+}
+
+impl fmt::Debug for PauseHistogram {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("PauseHistogram")
+      .field("len", &self.total)
+      .field("p50", &self.value_at_percentile(50.0))
+      .field("max", &self.max())
+      .finish()
+  }
+}
+
+// zlib framing around uncompressed deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+  let mut out = vec![0x78, 0x01];
+  let mut chunks = data.chunks(0xffff).peekable();
+  while let Some(chunk) = chunks.next() {
+    out.push(if chunks.peek().is_none() { 1 } else { 0 });
+    out.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+    out.extend_from_slice(&(!(chunk.len() as u16)).to_le_bytes());
+    out.extend_from_slice(chunk);
+  }
+
+  let (mut a, mut b) = (1u32, 0u32);
+  for &byte in data {
+    a = (a + byte as u32) % 65521;
+    b = (b + a) % 65521;
+  }
+  out.extend_from_slice(&((b << 16) | a).to_be_bytes());
+  out
+}
+
+fn base64(data: &[u8]) -> String {
+  const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+  let mut out = String::new();
+  for chunk in data.chunks(3) {
+    let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+    for i in 0..4 {
+      if i <= chunk.len() {
+        out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+      } else {
+        out.push('=');
+      }
+    }
+  }
+  out
+}
+
+impl VM {
+  /// Every pause so far, in an HDR histogram.
+  pub fn pause_hdr(&self) -> &PauseHistogram {
+    &self.stats.pause_hdr
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn percentiles_to_two_digits() {
+    println!("Percentiles come back to within 1%.");
+
+    let mut h = PauseHistogram::new();
+    assert!(h.value_at_percentile(50.0) == Duration::ZERO);
+
+    for us in 1..=1000 {
+      h.record(Duration::from_micros(us));
+    }
+    assert!(h.len() == 1000);
+    assert!(h.max() == Duration::from_micros(1000));
+
+    for &(p, us) in &[(50.0, 500.0), (90.0, 900.0), (99.0, 990.0), (100.0, 1000.0)] {
+      let got = h.value_at_percentile(p).as_nanos() as f64 / 1000.0;
+      assert!((got - us).abs() / us < 0.01);
+    }
+
+    let mut other = PauseHistogram::new();
+    other.record(Duration::from_secs(2));
+    h.add(&other);
+    assert!(h.len() == 1001 && h.max() == Duration::from_secs(2));
+  }
+
+  #[test]
+  fn encodes_like_the_reference() {
+    println!("The V2 encoding matches HdrHistogram's byte for byte.");
+
+    let mut h = PauseHistogram::new();
+    h.record(Duration::from_micros(1));
+    h.record(Duration::from_micros(1));
+    h.record(Duration::from_micros(100));
+
+    let encoded = h.encode();
+    assert!(encoded[..4] == [0x1c, 0x84, 0x93, 0x13]);
+    assert!(encoded[12..16] == [0, 0, 0, 2]);
+    assert!(encoded[16..24] == 1000u64.to_be_bytes());
+    assert!(encoded[24..32] == 3_600_000_000_000u64.to_be_bytes());
+    assert!(encoded[32..40] == 1.0f64.to_be_bytes());
+    // 1µs is slot 1; 100µs is slot 195. Zero, then two, then a run of 193
+    // zeros, then one.
+    assert!(encoded[40..] == [0x00, 0x04, 0x81, 0x03, 0x02]);
+    assert!(encoded[4..8] == 5u32.to_be_bytes());
+
+    let bytes = h.to_bytes();
+    assert!(bytes[..4] == [0x1c, 0x84, 0x93, 0x14]);
+    assert!(bytes[8..10] == [0x78, 0x01]);
+    assert!(bytes[10] == 1);
+    assert!(bytes[15..15 + encoded.len()] == encoded[..]);
+  }
+
+  #[test]
+  fn logs_one_interval() {
+    println!("The log is a header and one base64 interval.");
+
+    assert!(base64(b"hdr") == "aGRy");
+    assert!(base64(b"hdrh") == "aGRyaA==");
+
+    let mut vm = VM::new();
+    vm.gc();
+    let log = vm.pause_hdr().to_log();
+    let lines: Vec<&str> = log.lines().collect();
+    assert!(lines.len() == 4);
+    assert!(lines[0] == "#[Histogram log format version 1.3]");
+    assert!(lines[3].starts_with("0.000,"));
+    assert!(lines[3].split(',').nth(3).unwrap() == base64(&vm.pause_hdr().to_bytes()));
+  }
+}
@@ -0,0 +1,97 @@
+// `assert_heap!`, for saying what a heap should hold in one line:
+//
+//   assert_heap!(vm, live: 7, ints: 4, pairs: 3, reachable: [a, b]);
+//
+// Counts are `live` objects (what a collection would keep), `ints` and
+// `pairs` among them, everything in the `heap`, `roots` on the stack and
+// `persistent` handles. `reachable` and `unreachable` list objects that
+// should or shouldn't be reachable from the roots. Each check panics with
+// what it found, so a failure needs no println! debugging.
+
+use alloc::collections::BTreeSet;
+
+use graph::walk;
+use {addr, Sobject, VM, Vobject};
+
+/// Asserts counts of, and reachability in, a VM's heap; see the module
+/// source for the keys.
+#[macro_export]
+macro_rules! assert_heap {
+  ($vm:expr, $($key:ident: $val:tt),+ $(,)*) => {{
+    let vm = &$vm;
+    $( assert_heap!(@check vm, $key, $val); )+
+  }};
+  (@check $vm:ident, reachable, [$($obj:expr),*]) => {
+    $( assert!($crate::heap_assert::reachable($vm, &$obj),
+               "assert_heap!: {} is unreachable", stringify!($obj)); )*
+  };
+  (@check $vm:ident, unreachable, [$($obj:expr),*]) => {
+    $( assert!(!$crate::heap_assert::reachable($vm, &$obj),
+               "assert_heap!: {} is reachable", stringify!($obj)); )*
+  };
+  (@check $vm:ident, live, $n:tt) => { assert_heap!(@count live, $n, $vm.iter_live().count()) };
+  (@check $vm:ident, ints, $n:tt) => { assert_heap!(@count ints, $n, $crate::heap_assert::kinds($vm).0) };
+  (@check $vm:ident, pairs, $n:tt) => { assert_heap!(@count pairs, $n, $crate::heap_assert::kinds($vm).1) };
+  (@check $vm:ident, heap, $n:tt) => { assert_heap!(@count heap, $n, $vm.iter_heap().count()) };
+  (@check $vm:ident, roots, $n:tt) => { assert_heap!(@count roots, $n, $vm.iter_roots().count()) };
+  (@check $vm:ident, persistent, $n:tt) => { assert_heap!(@count persistent, $n, $vm.iter_persistent().count()) };
+  (@count $key:ident, $n:tt, $found:expr) => {{
+    let found: usize = $found;
+    assert!(found == $n, "assert_heap!: expected {} {}, found {}", $n, stringify!($key), found);
+  }};
+}
+
+// Whether `obj` is reachable from the roots.
+#[doc(hidden)]
+pub fn reachable(vm: &VM, obj: &Sobject) -> bool {
+  let found: BTreeSet<usize> = walk(vm.roots().map(|(_, obj)| obj)).iter().map(addr).collect();
+  found.contains(&addr(obj))
+}
+
+// Live ints and pairs.
+#[doc(hidden)]
+pub fn kinds(vm: &VM) -> (usize, usize) {
+  vm.iter_live().fold((0, 0), |(ints, pairs), obj| match obj.1.borrow().val {
+    Vobject::Int(_) => (ints + 1, pairs),
+    Vobject::Pair(..) => (ints, pairs + 1),
+    Vobject::Native(_) => (ints, pairs)
+  })
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use VM;
+
+  #[test]
+  fn counts_and_reachability() {
+    println!("assert_heap! checks counts and reachability in one go.");
+
+    let mut vm = VM::new();
+    let a = vm.push_int(1).unwrap();
+    let b = vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    let c = vm.push_int(3).unwrap();
+    vm.pop();
+    vm.persist(&c);
+    let d = vm.push_int(4).unwrap();
+    vm.pop();
+
+    assert_heap!(vm, live: 4, ints: 3, pairs: 1, heap: 5, roots: 1, persistent: 1,
+                 reachable: [a, b, p, c], unreachable: [d]);
+  }
+
+  #[test]
+  #[should_panic(expected = "assert_heap!: expected 2 live, found 1")]
+  fn failures_say_what_was_found() {
+    println!("A failed count reports the number found.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    assert_heap!(vm, live: 2);
+  }
+}
@@ -0,0 +1,124 @@
+// How big the heap is, for monitoring and tests: the objects held, the
+// room made for them, the bytes they cost and where the next full
+// collection comes. Objects are counted live or not yet collected, across
+// every generation and any open region. Every object costs the same, so
+// counts by kind are all it takes to split the bytes up too.
+
+use alloc::vec::Vec;
+
+use {Object, VM, Vobject};
+
+/// The heap at a moment, as returned by `VM::heap_info`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapInfo {
+  pub len: usize,
+  pub capacity: usize,
+  pub bytes: usize,
+  pub threshold: usize
+}
+
+/// The objects the VM holds by kind, as returned by `VM::heap_kinds`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapKinds {
+  pub ints: usize,
+  pub pairs: usize,
+  pub natives: usize,
+  /// Held mutably borrowed by the host, so of no kind that can be seen.
+  pub borrowed: usize
+}
+
+impl VM {
+  /// Objects the VM holds.
+  pub fn heap_len(&self) -> usize {
+    self.objects()
+  }
+
+  /// Objects the VM's lists have room for before they next grow; see
+  /// `VMConfig::reserve_objects`.
+  pub fn heap_capacity(&self) -> usize {
+    self.heap.capacity() + self.nursery.capacity() + self.middle.iter().map(Vec::capacity).sum::<usize>()
+      + self.sweeping.len() + self.region.capacity()
+  }
+
+  /// Bytes the objects the VM holds cost, as the allocator was told.
+  pub fn bytes_allocated(&self) -> usize {
+    self.objects() * Object::size()
+  }
+
+  /// The heap size at which the next full collection is due.
+  pub fn threshold(&self) -> usize {
+    self.heap_max
+  }
+
+  /// Counts the objects the VM holds by kind. Unlike the other figures
+  /// this looks at every object.
+  pub fn heap_kinds(&self) -> HeapKinds {
+    let mut kinds = HeapKinds::default();
+    for obj in self.iter_objects() {
+      match obj.1.try_borrow() {
+        Ok(o) => match o.val {
+          Vobject::Int(_) => kinds.ints += 1,
+          Vobject::Pair(..) => kinds.pairs += 1,
+          Vobject::Native(_) => kinds.natives += 1
+        },
+        Err(_) => kinds.borrowed += 1
+      }
+    }
+    kinds
+  }
+
+  pub fn heap_info(&self) -> HeapInfo {
+    HeapInfo {
+      len: self.heap_len(),
+      capacity: self.heap_capacity(),
+      bytes: self.bytes_allocated(),
+      threshold: self.threshold()
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {GcStrategy, VMConfig};
+
+  #[test]
+  fn heap_info_tracks_the_heap() {
+    println!("Heap figures follow allocation and collection.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).threshold(8).reserve_objects(16));
+      assert!(vm.heap_info() == HeapInfo { len: 0, capacity: vm.heap_capacity(), bytes: 0, threshold: 8 });
+      assert!(vm.heap_capacity() >= 16);
+
+      vm.push_value(&(1u32, 2u32)).unwrap();
+      vm.push_int(3).unwrap();
+      vm.pop();
+      let info = vm.heap_info();
+      assert!(info.len == 4 && info.bytes == 4 * Object::size());
+
+      vm.gc_full();
+      assert!(vm.heap_len() == 3 && vm.bytes_allocated() == 3 * Object::size());
+      assert!(vm.threshold() == 8);
+    }
+  }
+
+  #[test]
+  fn heap_kinds_count_each_kind() {
+    println!("Objects are counted by kind, borrowed ones apart.");
+
+    let mut vm = VM::new();
+    vm.push_value(&(1u32, (2u32, 3u32))).unwrap();
+    let four = vm.push_int(4).unwrap();
+    assert!(vm.heap_kinds() == HeapKinds { ints: 4, pairs: 2, natives: 0, borrowed: 0 });
+
+    let held = four.1.borrow_mut();
+    assert!(vm.heap_kinds() == HeapKinds { ints: 3, pairs: 2, natives: 0, borrowed: 1 });
+    drop(held);
+  }
+}
@@ -0,0 +1,153 @@
+// The last few heaps, as they stood after each collection, for working out
+// what changed between them. Objects are keyed by `VM::object_id`, which
+// outlives collections, so the same object can be followed from snapshot
+// to snapshot. Taking one walks the whole heap, so this is off unless
+// `VMConfig::history` asks for it.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use {VM, Vobject};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotValue {
+  Int(u32),
+  /// The ids of the head and tail.
+  Pair(u64, u64),
+  /// A native value's type name. What it holds isn't recorded.
+  Native(&'static str)
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeapSnapshot {
+  /// Counts the snapshots this VM has taken, from 0.
+  pub seq: u64,
+  /// `"minor"` or `"full"`.
+  pub kind: &'static str,
+  /// Ids of the stack slots, bottom first.
+  pub stack: Vec<u64>,
+  /// Every object the VM held, by id.
+  pub objects: BTreeMap<u64, SnapshotValue>
+}
+
+/// What happened to the heap between two snapshots, as lists of ids.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SnapshotDiff {
+  /// Objects in the later snapshot only.
+  pub added: Vec<u64>,
+  /// Objects in the earlier snapshot only.
+  pub freed: Vec<u64>,
+  /// Pairs in both whose head or tail was changed in between.
+  pub changed: Vec<u64>
+}
+
+impl HeapSnapshot {
+  /// How the heap got from this snapshot to `later`.
+  pub fn diff(&self, later: &HeapSnapshot) -> SnapshotDiff {
+    let mut diff = SnapshotDiff::default();
+
+    for (&id, value) in &later.objects {
+      match self.objects.get(&id) {
+        None => diff.added.push(id),
+        Some(old) if old != value => diff.changed.push(id),
+        Some(_) => {}
+      }
+    }
+    diff.freed = self.objects.keys().filter(|id| !later.objects.contains_key(id)).cloned().collect();
+
+    diff
+  }
+}
+
+impl VM {
+  /// Snapshots taken after the last `VMConfig::history` collections,
+  /// oldest first.
+  pub fn history<'a>(&'a self) -> impl DoubleEndedIterator<Item = &'a HeapSnapshot> + ExactSizeIterator + 'a {
+    self.history.iter()
+  }
+
+  pub(crate) fn record_history(&mut self, kind: &'static str) {
+    if self.config.history == 0 {
+      return;
+    }
+
+    let snapshot = HeapSnapshot {
+      seq: self.history_seq,
+      kind,
+      stack: self.stack.iter().map(|obj| self.object_id(obj)).collect(),
+      objects: self.iter_objects().map(|obj| {
+        let value = match obj.1.borrow().val {
+          Vobject::Int(n) => SnapshotValue::Int(n),
+          Vobject::Pair(ref head, ref tail) => SnapshotValue::Pair(self.object_id(head), self.object_id(tail)),
+          Vobject::Native(ref native) => SnapshotValue::Native(native.type_name())
+        };
+        (self.object_id(obj), value)
+      }).collect()
+    };
+    self.history_seq += 1;
+
+    if self.history.len() == self.config.history {
+      self.history.pop_front();
+    }
+    self.history.push_back(snapshot);
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use VMConfig;
+
+  #[test]
+  fn keeps_the_last_few() {
+    println!("The history holds a snapshot per collection, up to its size.");
+
+    let mut vm = VM::new();
+    vm.gc();
+    assert!(vm.history().len() == 0);
+
+    let mut vm = VM::with_config(VMConfig::new().history(2));
+    for _ in 0..3 {
+      vm.gc();
+    }
+    let seqs: Vec<u64> = vm.history().map(|s| s.seq).collect();
+    assert!(seqs == [1, 2]);
+    assert!(vm.history().all(|s| s.kind == "full"));
+  }
+
+  #[test]
+  fn diffs_show_what_changed() {
+    println!("Diffing snapshots shows allocations, frees and mutations.");
+
+    let mut vm = VM::with_config(VMConfig::new().history(4));
+    let a = vm.push_int(1).unwrap();
+    let b = vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    vm.gc();
+
+    let c = vm.push_int(3).unwrap();
+    vm.pop();
+    vm.push_int(4).unwrap();
+    vm.push_int(5).unwrap();
+    let q = vm.push_pair().unwrap();
+    if let Vobject::Pair(_, ref mut tail) = p.1.borrow_mut().val { *tail = q.clone() }
+    vm.write_barrier(&p);
+    vm.gc();
+
+    let first = vm.history().next().unwrap();
+    let last = vm.history().last().unwrap();
+    assert!(first.stack == [vm.object_id(&p)]);
+    assert!(last.objects[&vm.object_id(&p)] == SnapshotValue::Pair(vm.object_id(&a), vm.object_id(&q)));
+
+    let diff = first.diff(last);
+    assert!(diff.added == [vm.object_id(&q) - 2, vm.object_id(&q) - 1, vm.object_id(&q)]);
+    assert!(diff.freed == [vm.object_id(&b)]);
+    assert!(diff.changed == [vm.object_id(&p)]);
+    assert!(!vm.history().any(|s| s.objects.contains_key(&vm.object_id(&c))));
+  }
+}
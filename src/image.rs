@@ -0,0 +1,344 @@
+// Heap images: the whole heap and stack in a compact binary form, for
+// booting a VM where another left off. The version and each object's tag
+// are single bytes; every other integer is a little-endian u32:
+//
+//   "BGCI" version objects stack
+//   then each object:  tag (0 int, 1 pair; bit 7 set if old)
+//                      value | head tail
+//   then each stack slot: id
+//
+// Ids are positions in the image. The VM has no interned tables yet, so
+// there is nothing else to save, and native values can't be saved at all.
+// Like the serde snapshot, an image holds no configuration: a loaded VM
+// starts from the default `VMConfig`.
+
+use alloc::rc::Rc;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::{Cell, RefCell};
+#[cfg(feature = "std")]
+use std::fs;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use error::ImageError;
+use {addr, GCHeader, Object, Sobject, VM, Visitor, Vobject};
+
+const MAGIC: &[u8; 4] = b"BGCI";
+const VERSION: u8 = 1;
+
+const TAG_INT: u8 = 0;
+const TAG_PAIR: u8 = 1;
+const TAG_OLD: u8 = 0x80;
+
+// An object as saved, pointing at other objects by id.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "lowercase"))]
+pub(crate) enum Node {
+  Int { value: u32, old: bool },
+  Pair { head: usize, tail: usize, old: bool },
+  // Described for heap snapshots, but never saved or loaded.
+  #[cfg_attr(feature = "serde", serde(skip))]
+  Native { name: String, held: Vec<usize>, old: bool }
+}
+
+impl VM {
+  // The stack and every object as ids and nodes.
+  pub(crate) fn nodes(&self) -> (Vec<usize>, Vec<Node>) {
+    let ids = self.object_ids();
+    let id = |obj: &Sobject| ids[&addr(obj)];
+
+    let nodes = self.dumped().iter().map(|obj| {
+      let old = obj.0.get().old();
+      match obj.1.borrow().val {
+        Vobject::Int(value) => Node::Int { value, old },
+        Vobject::Pair(ref head, ref tail) => Node::Pair { head: id(head), tail: id(tail), old },
+        Vobject::Native(ref native) => {
+          let mut held = Vec::new();
+          native.trace(&mut Visitor::new(&mut |obj| held.push(id(obj))));
+          Node::Native { name: native.type_name().to_string(), held, old }
+        }
+      }
+    }).collect();
+
+    (self.stack.iter().map(id).collect(), nodes)
+  }
+
+  // Rebuilds a VM from `nodes`, failing on the first id that names no node.
+  pub(crate) fn from_nodes(stack: &[usize], nodes: &[Node]) -> Result<VM, usize> {
+    let lookup = |objs: &[Sobject], id: usize| objs.get(id).cloned().ok_or(id);
+
+    // Allocate everything first so pairs can point anywhere, then fill
+    // them in.
+    let objs: Vec<Sobject> = nodes.iter().enumerate().map(|(id, node)| {
+      let old = match *node { Node::Int { old, .. } | Node::Pair { old, .. } | Node::Native { old, .. } => old };
+      let gch = GCHeader::new(old);
+      Rc::new((Cell::new(gch), RefCell::new(Object { val: Vobject::Int(0), tag: None }), id as u64))
+    }).collect();
+
+    for (obj, node) in objs.iter().zip(nodes) {
+      obj.1.borrow_mut().val = match *node {
+        Node::Int { value, .. } => Vobject::Int(value),
+        Node::Pair { head, tail, .. } => Vobject::Pair(lookup(&objs, head)?, lookup(&objs, tail)?),
+        Node::Native { .. } => unreachable!("native values are never loaded")
+      };
+    }
+
+    let mut vm = VM::new();
+    vm.next_id = nodes.len() as u64;
+    for &id in stack {
+      vm.stack.push(lookup(&objs, id)?);
+    }
+    for obj in objs {
+      if obj.0.get().old() {
+        // Images don't record ages, so loaded objects start again at 0.
+        obj.0.set(obj.0.get().with_slot(vm.heap.len()));
+        vm.stats.age_histogram[0] += 1;
+        vm.heap.push(obj);
+      } else {
+        vm.nursery.push(obj);
+      }
+    }
+
+    // Old objects may have been saved pointing into the nursery.
+    for i in 0..vm.heap.len() {
+      let obj = vm.heap[i].clone();
+      let young = obj.1.borrow().val.children().any(|child| !child.0.get().old());
+      if young {
+        vm.dirty_card(&obj);
+      }
+    }
+
+    Ok(vm)
+  }
+
+  /// The heap and stack as a binary image. Fails with
+  /// `ImageError::Native` if the VM holds a native value, which images
+  /// have no way to save. Panics if it holds more than `u32::MAX` objects.
+  pub fn to_image(&self) -> Result<Vec<u8>, ImageError> {
+    let native = self.dumped().iter().find_map(|obj| match obj.1.borrow().val {
+      Vobject::Native(ref native) => Some(native.type_name()),
+      _ => None
+    });
+    if let Some(name) = native {
+      return Err(ImageError::Native(name));
+    }
+
+    let (stack, nodes) = self.nodes();
+    let mut out = Vec::with_capacity(13 + 9 * nodes.len() + 4 * stack.len());
+
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    put(&mut out, nodes.len());
+    put(&mut out, stack.len());
+
+    for node in &nodes {
+      match *node {
+        Node::Int { value, old } => {
+          out.push(TAG_INT | if old { TAG_OLD } else { 0 });
+          put(&mut out, value as usize);
+        }
+        Node::Pair { head, tail, old } => {
+          out.push(TAG_PAIR | if old { TAG_OLD } else { 0 });
+          put(&mut out, head);
+          put(&mut out, tail);
+        }
+        Node::Native { .. } => unreachable!("natives are refused above")
+      }
+    }
+
+    for &id in &stack {
+      put(&mut out, id);
+    }
+
+    Ok(out)
+  }
+
+  /// Boots a VM from an image made by `to_image`.
+  pub fn from_image(image: &[u8]) -> Result<VM, ImageError> {
+    let mut r = Reader { rest: image };
+
+    if r.bytes(4)? != MAGIC {
+      return Err(ImageError::BadMagic);
+    }
+    let version = r.byte()?;
+    if version != VERSION {
+      return Err(ImageError::UnsupportedVersion(version));
+    }
+
+    let n = r.u32()? as usize;
+    let stack_len = r.u32()? as usize;
+
+    // Don't trust the counts for allocation; a bad header shouldn't be
+    // able to ask for gigabytes.
+    let mut nodes = Vec::with_capacity(n.min(r.rest.len() / 5));
+    for _ in 0..n {
+      let tag = r.byte()?;
+      let old = tag & TAG_OLD != 0;
+
+      nodes.push(match tag & !TAG_OLD {
+        TAG_INT => Node::Int { value: r.u32()?, old },
+        TAG_PAIR => Node::Pair { head: r.u32()? as usize, tail: r.u32()? as usize, old },
+        _ => return Err(ImageError::BadTag(tag))
+      });
+    }
+
+    let mut stack = Vec::with_capacity(stack_len.min(r.rest.len() / 4));
+    for _ in 0..stack_len {
+      stack.push(r.u32()? as usize);
+    }
+
+    if !r.rest.is_empty() {
+      return Err(ImageError::TrailingBytes);
+    }
+
+    VM::from_nodes(&stack, &nodes).map_err(ImageError::DanglingId)
+  }
+
+  /// Writes `to_image` to `path`. A VM holding a native value fails with
+  /// `io::ErrorKind::InvalidInput`, writing nothing.
+  #[cfg(feature = "std")]
+  pub fn save_image<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+    let image = self.to_image().map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    fs::write(path, image)
+  }
+
+  /// Boots a VM from an image file written by `save_image`.
+  #[cfg(feature = "std")]
+  pub fn load_image<P: AsRef<Path>>(path: P) -> io::Result<VM> {
+    VM::from_image(&fs::read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+  }
+}
+
+fn put(out: &mut Vec<u8>, n: usize) {
+  assert!(n <= u32::MAX as usize, "heap too large for an image");
+  out.extend_from_slice(&(n as u32).to_le_bytes());
+}
+
+struct Reader<'a> {
+  rest: &'a [u8]
+}
+
+impl<'a> Reader<'a> {
+  fn bytes(&mut self, n: usize) -> Result<&'a [u8], ImageError> {
+    if self.rest.len() < n {
+      return Err(ImageError::Truncated);
+    }
+
+    let (bytes, rest) = self.rest.split_at(n);
+    self.rest = rest;
+    Ok(bytes)
+  }
+
+  fn byte(&mut self) -> Result<u8, ImageError> {
+    Ok(self.bytes(1)?[0])
+  }
+
+  fn u32(&mut self) -> Result<u32, ImageError> {
+    let b = self.bytes(4)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  #[cfg(feature = "std")]
+  use {NativeObject, Trace};
+
+  #[test]
+  #[cfg(feature = "std")]
+  fn images_round_trip() {
+    println!("A saved image boots an equivalent VM, cycles and all.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let a = vm.push_pair().unwrap();
+    if let Vobject::Pair(_, ref mut x) = a.1.borrow_mut().val { *x = a.clone() }
+    vm.gc();
+    vm.push_int(3).unwrap();
+
+    let path = std::env::temp_dir().join(format!("babygc-{}.image", std::process::id()));
+    vm.save_image(&path).unwrap();
+    let mut copy = VM::load_image(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert!(copy.heap_dump_json() == vm.heap_dump_json());
+    assert!(copy.heap.len() == 2 && copy.nursery.len() == 1);
+
+    let a = copy.stack[0].clone();
+    if let Vobject::Pair(_, ref tail) = a.1.borrow().val {
+      assert!(Rc::ptr_eq(tail, &a));
+    }
+
+    copy.stack.clear();
+    assert!(copy.gc() == 3);
+  }
+
+  #[test]
+  #[cfg(feature = "std")]
+  fn natives_refuse_to_be_saved() {
+    println!("A VM holding a native value can't be saved, and says why.");
+
+    struct Opaque;
+
+    impl Trace for Opaque {
+      fn trace(&self, _visitor: &mut Visitor<'_>) {}
+    }
+
+    impl NativeObject for Opaque {
+      fn type_name(&self) -> &'static str {
+        "widget"
+      }
+    }
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_native(Opaque).unwrap();
+    assert!(vm.to_image() == Err(ImageError::Native("widget")));
+
+    let path = std::env::temp_dir().join(format!("babygc-native-{}.image", std::process::id()));
+    let err = vm.save_image(&path).unwrap_err();
+    assert!(err.kind() == std::io::ErrorKind::InvalidInput && !path.exists());
+    assert!(err.to_string() == "a widget can't be saved in an image");
+  }
+
+  #[test]
+  fn rejects_bad_images() {
+    println!("Corrupt images are refused with a reason.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    vm.push_pair().unwrap();
+    let image = vm.to_image().unwrap();
+
+    assert!(VM::from_image(b"nope").unwrap_err() == ImageError::BadMagic);
+    assert!(VM::from_image(&image[..image.len() - 1]).unwrap_err() == ImageError::Truncated);
+
+    let mut bad = image.clone();
+    bad[4] = 9;
+    assert!(VM::from_image(&bad).unwrap_err() == ImageError::UnsupportedVersion(9));
+
+    // Point the pair's tail past the end of the heap.
+    let mut bad = image.clone();
+    let tail = 13 + 5 + 5 + 1 + 4;
+    bad[tail] = 7;
+    assert!(VM::from_image(&bad).unwrap_err() == ImageError::DanglingId(7));
+
+    let mut bad = image;
+    bad.push(0);
+    assert!(VM::from_image(&bad).unwrap_err() == ImageError::TrailingBytes);
+  }
+}
@@ -0,0 +1,68 @@
+// Debug labels: names for objects, kept in a side table by object id so
+// objects don't grow a field for them. The printer, DOT export and tracer
+// show an object's label next to it, so a session can talk about "the
+// list head" rather than #17. An object's label goes when it is freed.
+
+use alloc::string::{String, ToString};
+
+use {Sobject, VM};
+
+impl VM {
+  /// Names `obj` for debugging output, replacing any name it had.
+  pub fn set_label(&mut self, obj: &Sobject, label: &str) {
+    self.labels.insert(self.object_id(obj), label.to_string());
+  }
+
+  pub fn label(&self, obj: &Sobject) -> Option<&str> {
+    self.label_of(self.object_id(obj))
+  }
+
+  pub fn clear_label(&mut self, obj: &Sobject) {
+    self.labels.remove(&self.object_id(obj));
+  }
+
+  pub(crate) fn label_of(&self, id: u64) -> Option<&str> {
+    self.labels.get(&id).map(String::as_str)
+  }
+
+  // Forgets the label of `obj`, which is being freed.
+  #[inline]
+  pub(crate) fn unlabel(&mut self, obj: &Sobject) {
+    if !self.labels.is_empty() {
+      self.labels.remove(&obj.2);
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {GcStrategy, VMConfig};
+
+  #[test]
+  fn labels_go_with_their_objects() {
+    println!("A label stays while its object lives and goes when it's freed.");
+
+    for strategy in [GcStrategy::MarkSweep, GcStrategy::Generational] {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy));
+      let kept = vm.push_int(1).unwrap();
+      let freed = vm.push_int(2).unwrap();
+      vm.set_label(&kept, "kept");
+      vm.set_label(&freed, "freed");
+      vm.pop();
+
+      vm.gc_minor();
+      vm.gc_full();
+      assert!(vm.label(&kept) == Some("kept") && vm.label(&freed).is_none());
+      assert!(vm.labels.len() == 1);
+
+      vm.clear_label(&kept);
+      assert!(vm.label(&kept).is_none());
+    }
+  }
+}
@@ -0,0 +1,155 @@
+// What objects cost on this runtime. Every object is one `Rc` allocation:
+// two reference counts, then the collector's header word, the value in
+// its `RefCell` with the embedder's tag, and the id. Ints and pairs are
+// the same size, since `Vobject` is as big as its largest variant; a
+// native value's payload is a separate allocation on top.
+
+use core::cell::{Cell, RefCell};
+use core::fmt;
+use core::mem;
+
+use {GCHeader, Object, Sobject, VM, Vobject};
+
+/// An object's kind and how many objects it points to; see
+/// `VM::shape_of`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shape {
+  /// "int", "pair", "freed", or a native value's type name.
+  pub kind: &'static str,
+  pub children: usize
+}
+
+/// Where an object's bytes go, and what the VM's objects cost in all; see
+/// `VM::layout_report`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LayoutReport {
+  /// Bytes per object, the sum of the five below.
+  pub object_bytes: usize,
+  pub refcount_bytes: usize,
+  pub header_bytes: usize,
+  /// The `RefCell`'s borrow flag and the tag, with their padding.
+  pub cell_bytes: usize,
+  pub value_bytes: usize,
+  /// The id, with any padding after it.
+  pub id_bytes: usize,
+  pub objects: usize,
+  /// What the objects cost, native payloads included.
+  pub total_bytes: usize,
+  /// The part of `total_bytes` that isn't values or payloads.
+  pub overhead_bytes: usize
+}
+
+impl VM {
+  /// Bytes `obj` costs by itself, not counting what it points to: the
+  /// object, and a native value's payload.
+  pub fn size_of(&self, obj: &Sobject) -> usize {
+    match obj.1.borrow().val {
+      Vobject::Native(ref native) => Object::size() + mem::size_of_val(&**native),
+      _ => Object::size()
+    }
+  }
+
+  pub fn shape_of(&self, obj: &Sobject) -> Shape {
+    if obj.0.get().freed() {
+      return Shape { kind: "freed", children: 0 };
+    }
+    let o = obj.1.borrow();
+    let kind = match o.val {
+      Vobject::Int(_) => "int",
+      Vobject::Pair(..) => "pair",
+      Vobject::Native(ref native) => native.type_name()
+    };
+    Shape { kind, children: o.val.children().count() }
+  }
+
+  pub fn layout_report(&self) -> LayoutReport {
+    let object_bytes = Object::size();
+    let refcount_bytes = 2 * mem::size_of::<usize>();
+    let header_bytes = mem::size_of::<Cell<GCHeader>>();
+    let value_bytes = mem::size_of::<Vobject>();
+    let cell_bytes = mem::size_of::<RefCell<Object>>() - value_bytes;
+
+    let objects = self.objects();
+    let total_bytes = self.iter_objects().map(|obj| self.size_of(obj)).sum();
+    LayoutReport {
+      object_bytes,
+      refcount_bytes,
+      header_bytes,
+      cell_bytes,
+      value_bytes,
+      id_bytes: object_bytes - refcount_bytes - header_bytes - cell_bytes - value_bytes,
+      objects,
+      total_bytes,
+      overhead_bytes: objects * (object_bytes - value_bytes)
+    }
+  }
+}
+
+impl fmt::Display for LayoutReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    writeln!(f, "object      {:>5} bytes", self.object_bytes)?;
+    writeln!(f, "  refcounts {:>5}", self.refcount_bytes)?;
+    writeln!(f, "  header    {:>5}", self.header_bytes)?;
+    writeln!(f, "  cell      {:>5}", self.cell_bytes)?;
+    writeln!(f, "  value     {:>5}", self.value_bytes)?;
+    writeln!(f, "  id        {:>5}", self.id_bytes)?;
+    write!(f, "{} objects, {} bytes, {} of them overhead", self.objects, self.total_bytes, self.overhead_bytes)
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {NativeObject, Trace, Visitor};
+
+  struct Block([u8; 100]);
+
+  impl Trace for Block {
+    fn trace(&self, _: &mut Visitor<'_>) {}
+  }
+
+  impl NativeObject for Block {}
+
+  #[test]
+  fn sizes_and_shapes() {
+    println!("Objects report their own size and shape.");
+
+    let mut vm = VM::new();
+    let p = vm.push_value(&(1u32, 2u32)).unwrap();
+    let (one, _) = vm.as_pair(&p).unwrap();
+    let block = vm.push_native(Block([0; 100])).unwrap();
+
+    assert!(vm.size_of(&p) == Object::size() && vm.size_of(&one) == Object::size());
+    assert!(vm.size_of(&block) == Object::size() + 100);
+    assert!(vm.with_native(&block, |b: &Block| b.0.len()) == Ok(100));
+    assert!(vm.shape_of(&p) == Shape { kind: "pair", children: 2 });
+    assert!(vm.shape_of(&one) == Shape { kind: "int", children: 0 });
+    assert!(vm.shape_of(&block).children == 0 && vm.shape_of(&block).kind.ends_with("Block"));
+
+    vm.truncate_stack(0);
+    vm.gc();
+    assert!(vm.shape_of(&p) == Shape { kind: "freed", children: 0 });
+  }
+
+  #[test]
+  fn layout_report_adds_up() {
+    println!("The layout report accounts for every byte.");
+
+    let mut vm = VM::new();
+    vm.push_ints(&[1, 2, 3]).unwrap();
+    vm.push_native(Block([0; 100])).unwrap();
+
+    let report = vm.layout_report();
+    assert!(report.object_bytes == Object::size());
+    assert!(report.objects == 4 && report.total_bytes == 4 * Object::size() + 100);
+    assert!(report.overhead_bytes == 4 * (report.object_bytes - report.value_bytes));
+    assert!(report.id_bytes >= mem::size_of::<u64>());
+    let summary = format!("4 objects, {} bytes, {} of them overhead", report.total_bytes, report.overhead_bytes);
+    assert!(report.to_string().ends_with(&summary));
+  }
+}
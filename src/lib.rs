@@ -0,0 +1,2001 @@
+// A port of Bob Nystrom's "Baby's First Garbage Collector" to Rust
+// http://journal.stuffwithstuff.com/2013/12/08/babys-first-garbage-collector/
+//
+// The `std` feature (on by default) adds everything that needs a clock or
+// an OS: timed collection, logging and `VMConfig::from_env`. Without it the
+// crate is `no_std` and needs only `alloc`.
+//
+// Everything that can fail returns a `Result` rather than panicking. The
+// few operations that panic on misuse (`pop`, `write_barrier`,
+// `push_constant`) have `try_` forms that return a `VmError` instead, for
+// hosts that can't let a panic unwind through them. What's left is
+// reading or storing into an object the host holds borrowed across the
+// call, and persisting an object a collection already freed. Collections
+// step around borrowed objects: marking waits for the host to let go.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+#[cfg(any(feature = "std", test))]
+extern crate core;
+extern crate alloc;
+#[cfg(all(feature = "std", target_arch = "wasm32", target_os = "unknown"))]
+extern crate web_time;
+#[cfg(feature = "metrics-facade")]
+extern crate metrics_facade;
+#[cfg(feature = "python")]
+extern crate pyo3;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "signal-dump")]
+extern crate signal_hook;
+#[cfg(feature = "wasm")]
+extern crate wasm_bindgen;
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::cell::RefCell;
+use core::fmt;
+use core::mem;
+
+// First, so the macros are in scope for the modules after them.
+#[macro_use]
+#[doc(hidden)]
+pub mod heap_assert;
+#[macro_use]
+#[doc(hidden)]
+pub mod construct;
+
+mod allocator;
+mod arith;
+mod ascii;
+mod async_gc;
+#[cfg(feature = "async")]
+mod background;
+#[cfg(feature = "read-barrier")]
+mod barrier;
+mod batch;
+mod builder;
+mod cancel;
+mod cards;
+mod census;
+#[cfg(feature = "std")]
+mod cgroup;
+mod checkpoint;
+mod compact;
+mod compare;
+mod config;
+pub mod conformance;
+mod constants;
+mod counters;
+#[cfg(feature = "std")]
+mod deadline;
+mod debug_stack;
+mod devtools;
+mod dot;
+mod dump;
+mod error;
+#[cfg(feature = "metrics-facade")]
+mod facade;
+pub mod ffi;
+mod flags;
+mod freed;
+mod freeze;
+mod gc_cell;
+#[cfg(feature = "std")]
+mod gclog;
+mod generations;
+#[cfg(all(test, feature = "std"))]
+mod golden;
+mod graph;
+mod handles;
+#[cfg(feature = "hdr")]
+mod hdr;
+mod heap_info;
+mod history;
+mod image;
+mod labels;
+mod layout;
+mod marshal;
+mod metadata;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod native;
+mod operand;
+#[cfg(feature = "std")]
+mod panic_dump;
+mod pool;
+mod print;
+#[cfg(feature = "process-memory")]
+mod process_memory;
+#[cfg(feature = "python")]
+pub mod python;
+mod region;
+#[cfg(feature = "std")]
+mod registry;
+mod retained;
+mod roots;
+mod shuffle;
+mod shutdown;
+#[cfg(all(feature = "signal-dump", unix))]
+mod signal_dump;
+mod sizing;
+#[cfg(feature = "serde")]
+mod snapshot;
+mod stack;
+mod stats;
+mod swap;
+#[cfg(feature = "std")]
+mod telemetry;
+pub mod time;
+#[cfg(feature = "std")]
+mod timeline;
+mod tracer;
+mod transfer;
+mod verify;
+#[cfg(feature = "std")]
+mod visual;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+mod workload;
+
+#[cfg(feature = "std")]
+use time::{Duration, Instant};
+#[cfg(feature = "std")]
+use timeline::Timeline;
+
+pub use allocator::{GlobalAllocator, ObjectAllocator};
+pub use arith::OverflowPolicy;
+pub use async_gc::{GcAsync, RunAsync};
+#[cfg(feature = "async")]
+pub use background::{Alloc, Background, SharedVm};
+#[cfg(feature = "read-barrier")]
+pub use barrier::ReadBarrier;
+pub use builder::{ListBuilder, StringBuilder};
+pub use cancel::CancelToken;
+pub use census::{Census, CensusEntry};
+pub use checkpoint::Checkpoint;
+pub use compact::Compaction;
+pub use compare::HASH_NODES;
+pub use config::{GcStrategy, VMConfig};
+pub use constants::ConstantSpace;
+pub use counters::GcCounters;
+pub use debug_stack::{DebugNode, DebugValue};
+pub use error::{ConfigError, ImageError, LogLineError, TypeError, VmError};
+pub use freed::POISON;
+pub use gc_cell::{GcCell, GcRefMut};
+#[cfg(feature = "std")]
+pub use gclog::GcLogLine;
+pub use generations::Generation;
+pub use handles::PersistentHandle;
+#[cfg(feature = "hdr")]
+pub use hdr::PauseHistogram;
+pub use heap_info::{HeapInfo, HeapKinds};
+pub use history::{HeapSnapshot, SnapshotDiff, SnapshotValue};
+pub use layout::{LayoutReport, Shape};
+pub use marshal::{FromValue, ToValue};
+pub use metadata::Metadata;
+pub use native::{Children, NativeObject, Trace, Visitor};
+pub use operand::Operand;
+#[cfg(feature = "std")]
+pub use panic_dump::PANIC_EVENTS;
+pub use pool::VmPool;
+pub use print::ValueDisplay;
+#[cfg(feature = "process-memory")]
+pub use process_memory::ProcessMemory;
+#[cfg(feature = "std")]
+pub use registry::{registry_stats, RegistryStats, VmSummary};
+pub use retained::{ExternalRefs, Retained};
+pub use roots::{ProviderId, RootProvider};
+pub use sizing::{DoublingPolicy, GrowthPolicy, SizingPolicy};
+pub use stats::{GcStats, PAUSE_BUCKETS};
+#[cfg(feature = "std")]
+pub use telemetry::GcEvent;
+#[cfg(feature = "std")]
+pub use tracer::{PrintTracer, SlowMotion, Step};
+pub use tracer::{Source, TraceEvent, Tracer};
+pub use transfer::Transfer;
+#[cfg(feature = "std")]
+pub use visual::Visual;
+pub use workload::{Action, Workload};
+
+// Objects traced or swept between deadline checks in gc_step.
+const GC_STEP_WORK: usize = 64;
+
+// The gray worklist is cut back after a collection that used less than
+// this fraction of it.
+const GRAY_SLACK: usize = 4;
+
+// In host-driven mode allocation fails once the heap reaches this multiple
+// of its collection threshold without the host having ticked a cycle home.
+const STARVATION_FACTOR: usize = 2;
+
+// An object: the collector's header, the value, and the object's id. The
+// collector reads the header and id without borrowing the `RefCell`, so a
+// host holding the value borrowed across a VM call doesn't make a
+// collection panic.
+pub type Sobject = Rc<(Cell<GCHeader>, RefCell<Object>, u64)>;
+
+// Identity of an object, for side tables keyed by object.
+fn addr(obj: &Sobject) -> usize {
+  Rc::as_ptr(obj) as *const () as usize
+}
+
+// What `obj` points to. If the host holds it mutably borrowed that can't
+// be read, and `blocked` is set instead.
+fn children(obj: &Sobject, blocked: &mut bool) -> Children {
+  match obj.1.try_borrow() {
+    Ok(o) => o.val.children(),
+    Err(_) => {
+      *blocked = true;
+      Children::default()
+    }
+  }
+}
+
+// The collector's per-object state, packed into one word:
+//
+//   bit 0       marked
+//   bit 1       old
+//   bit 2       constant, in a ConstantSpace rather than any heap
+//   bits 3-7    collections survived, up to MAX_AGE
+//   bits 8-11   young generation
+//   bit 12      frozen, refusing stores
+//   bit 13      freed by a collection
+//   bits 14-17  the embedder's own flags, never looked at here
+//   bits 18-    index in the old generation, for the card table
+//
+// There is no type tag: the `Vobject` variant already is one, and a copy
+// here would go stale whenever a caller stored into `val`.
+//
+// The word is 64 bits, or 32 with the `compact-headers` feature, which
+// leaves room for 2^14 slots and so caps the heap at `MAX_OBJECTS`.
+#[derive(Clone, Copy)]
+pub struct GCHeader(Word);
+
+#[cfg(not(feature = "compact-headers"))]
+type Word = u64;
+#[cfg(feature = "compact-headers")]
+type Word = u32;
+
+const MARKED: Word = 1 << 0;
+const OLD: Word = 1 << 1;
+const CONSTANT: Word = 1 << 2;
+const AGE_SHIFT: u32 = 3;
+const MAX_AGE: u32 = 31;
+const GEN_SHIFT: u32 = 8;
+const MAX_GENERATION: Word = 15;
+const FROZEN: Word = 1 << 12;
+const FREED: Word = 1 << 13;
+const FLAG_SHIFT: u32 = 14;
+const SLOT_SHIFT: u32 = FLAG_SHIFT + USER_FLAGS;
+
+/// How many flag bits each object's header has for the embedder; see
+/// `VM::set_flag`.
+pub const USER_FLAGS: u32 = 4;
+
+/// The most objects a VM can hold: as many as the header has slots for,
+/// or as `usize` can count.
+pub const MAX_OBJECTS: usize = slot_limit(Word::BITS, usize::BITS) as usize;
+
+// The slots a header of `word_bits` bits has room for, on a target whose
+// `usize` has `usize_bits`.
+const fn slot_limit(word_bits: u32, usize_bits: u32) -> u64 {
+  let bits = word_bits - SLOT_SHIFT;
+  if bits < usize_bits { 1 << bits } else { u64::MAX >> (64 - usize_bits) }
+}
+
+impl GCHeader {
+  fn new(old: bool) -> GCHeader {
+    GCHeader(if old { OLD } else { 0 })
+  }
+
+  fn marked(self) -> bool {
+    self.0 & MARKED != 0
+  }
+
+  fn old(self) -> bool {
+    self.0 & OLD != 0
+  }
+
+  fn constant(self) -> bool {
+    self.0 & CONSTANT != 0
+  }
+
+  fn frozen(self) -> bool {
+    self.0 & FROZEN != 0
+  }
+
+  fn freed(self) -> bool {
+    self.0 & FREED != 0
+  }
+
+  // A no-op cast with compact headers.
+  #[allow(clippy::unnecessary_cast)]
+  fn age(self) -> u32 {
+    ((self.0 >> AGE_SHIFT) as u32) & MAX_AGE
+  }
+
+  // One more collection survived, sticking at MAX_AGE.
+  fn aged(self) -> GCHeader {
+    let age = Word::from((self.age() + 1).min(MAX_AGE));
+    GCHeader((self.0 & !(Word::from(MAX_AGE) << AGE_SHIFT)) | (age << AGE_SHIFT))
+  }
+
+  fn generation(self) -> usize {
+    ((self.0 >> GEN_SHIFT) & MAX_GENERATION) as usize
+  }
+
+  fn with_generation(self, g: usize) -> GCHeader {
+    GCHeader((self.0 & !(MAX_GENERATION << GEN_SHIFT)) | ((g as Word) << GEN_SHIFT))
+  }
+
+  fn slot(self) -> usize {
+    (self.0 >> SLOT_SHIFT) as usize
+  }
+
+  fn with_marked(self, marked: bool) -> GCHeader {
+    GCHeader(if marked { self.0 | MARKED } else { self.0 & !MARKED })
+  }
+
+  fn with_frozen(self, frozen: bool) -> GCHeader {
+    GCHeader(if frozen { self.0 | FROZEN } else { self.0 & !FROZEN })
+  }
+
+  fn with_freed(self) -> GCHeader {
+    GCHeader(self.0 | FREED)
+  }
+
+  fn flag(self, flag: u32) -> bool {
+    self.0 & (1 << (FLAG_SHIFT + flag)) != 0
+  }
+
+  fn with_flag(self, flag: u32, on: bool) -> GCHeader {
+    let bit = 1 << (FLAG_SHIFT + flag);
+    GCHeader(if on { self.0 | bit } else { self.0 & !bit })
+  }
+
+  fn with_old(self) -> GCHeader {
+    GCHeader(self.0 | OLD)
+  }
+
+  fn with_slot(self, slot: usize) -> GCHeader {
+    debug_assert!(slot < MAX_OBJECTS, "slot {} doesn't fit in the header", slot);
+    GCHeader((self.0 & ((1 << SLOT_SHIFT) - 1)) | ((slot as Word) << SLOT_SHIFT))
+  }
+}
+
+impl fmt::Debug for GCHeader {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_struct("GCHeader")
+      .field("marked", &self.marked())
+      .field("old", &self.old())
+      .field("constant", &self.constant())
+      .field("frozen", &self.frozen())
+      .field("freed", &self.freed())
+      .field("age", &self.age())
+      .field("generation", &self.generation())
+      .field("flags", &((self.0 >> FLAG_SHIFT) & ((1 << USER_FLAGS) - 1)))
+      .field("slot", &self.slot())
+      .finish()
+  }
+}
+
+pub enum Vobject {
+  Int(u32),
+  Pair(Sobject, Sobject),
+  Native(Box<dyn NativeObject>)
+}
+
+#[derive(Debug)]
+pub struct Object {
+  pub val: Vobject,
+  tag: Option<u64>
+}
+
+// Where the current collection cycle is. Outside of gc_step a cycle is
+// started and run to completion in one go.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Phase {
+  Idle,
+  Mark,
+  Sweep
+}
+
+#[derive(Debug)]
+pub struct VM {
+  stack: Vec<Sobject>,
+  // What an allocation under way holds onto, rooted until it is done.
+  temp_roots: Vec<Sobject>,
+  heap:  Vec<Sobject>,
+  nursery: Vec<Sobject>,
+  // Young generations after the nursery, youngest first.
+  middle: Vec<Vec<Sobject>>,
+  heap_max: usize,
+  config: VMConfig,
+  pause_depth: usize,
+  gc_pending: bool,
+  phase: Phase,
+  gray: Vec<Sobject>,
+  // The longest `gray` has been this collection.
+  gray_peak: usize,
+  sweeping: vec::IntoIter<Sobject>,
+  cycle_len: usize,
+  cycle_freed: usize,
+  // The fraction of the heap the last full collection kept, until a
+  // collection is skipped on the strength of it.
+  survival: Option<f64>,
+  // Marking last stopped at an object the host holds mutably borrowed.
+  blocked: bool,
+  #[cfg(feature = "std")]
+  pace: usize,
+  // What started the collection about to run, and the cycle under way,
+  // for the GC log.
+  #[cfg(feature = "std")]
+  trigger: &'static str,
+  #[cfg(feature = "std")]
+  cycle_trigger: &'static str,
+  // When the current pause began, and time already spent on the cycle
+  // under way in earlier ones.
+  #[cfg(feature = "std")]
+  slice_start: Instant,
+  #[cfg(feature = "std")]
+  cycle_pause: Duration,
+  stats: GcStats,
+  quota_objects: u64,
+  quota_bytes: u64,
+  next_id: u64,
+  cards: Vec<bool>,
+  unswept_writes: Vec<Sobject>,
+  history: VecDeque<HeapSnapshot>,
+  history_seq: u64,
+  // Objects allocated in the current region, the first id it handed out,
+  // how deeply regions are nested, and what was stored into meanwhile.
+  region: Vec<Sobject>,
+  region_start: u64,
+  region_depth: usize,
+  region_writes: Vec<Sobject>,
+  persistent: BTreeMap<u64, Sobject>,
+  next_handle: u64,
+  // Debug labels by object id.
+  labels: BTreeMap<u64, String>,
+  // Host data attached with `metadata`, a table per type.
+  metadata: metadata::Tables,
+  // Objects whose `GcCell`s were written to, for the barrier.
+  cell_writes: gc_cell::Writes,
+  providers: roots::Providers,
+  // Tells this VM's checkpoints from other VMs'.
+  identity: Rc<()>,
+  shuffle: Option<shuffle::Shuffle>,
+  #[cfg(all(feature = "signal-dump", unix))]
+  signal_dump: Option<signal_dump::SignalDump>,
+  // This VM's entry in the process-wide registry, if it joined.
+  #[cfg(feature = "std")]
+  registered: Option<std::sync::Arc<registry::Entry>>,
+  // What `install_panic_dump`'s hook writes.
+  #[cfg(feature = "std")]
+  panic_dump: Option<std::sync::Arc<std::sync::Mutex<panic_dump::Report>>>,
+  // Pictures drawn so far in visual mode.
+  #[cfg(feature = "std")]
+  frames: u64,
+  #[cfg(feature = "std")]
+  timeline: Option<Timeline>,
+  // Paranoid mode, if this build honours it, and what it expects each
+  // object to hold.
+  #[cfg(feature = "std")]
+  paranoid: bool,
+  #[cfg(feature = "std")]
+  shadow: Option<BTreeMap<u64, SnapshotValue>>
+}
+
+impl VM {
+  pub fn new() -> VM {
+    VM::with_config(VMConfig::new())
+  }
+
+  pub fn with_config(config: VMConfig) -> VM {
+    #[cfg(feature = "std")]
+    let timeline = if config.timeline { Some(Timeline::new()) } else { None };
+    #[cfg(feature = "std")]
+    let config_paranoid = config.paranoid && cfg!(any(debug_assertions, feature = "paranoid"));
+    let shuffle = config.shuffle.map(shuffle::Shuffle::new);
+    VM {
+      stack: Vec::new(),
+      temp_roots: Vec::new(),
+      heap:  Vec::with_capacity(config.reserve),
+      nursery: Vec::with_capacity(nursery_reserve(&config)),
+      middle: config.generations[1..].iter().map(|_| Vec::new()).collect(),
+      heap_max: config.threshold,
+      config,
+      pause_depth: 0,
+      gc_pending: false,
+      phase: Phase::Idle,
+      gray: Vec::new(),
+      gray_peak: 0,
+      sweeping: Vec::new().into_iter(),
+      cycle_len: 0,
+      cycle_freed: 0,
+      survival: None,
+      blocked: false,
+      #[cfg(feature = "std")]
+      pace: 0,
+      #[cfg(feature = "std")]
+      trigger: "explicit",
+      #[cfg(feature = "std")]
+      cycle_trigger: "explicit",
+      #[cfg(feature = "std")]
+      slice_start: Instant::now(),
+      #[cfg(feature = "std")]
+      cycle_pause: Duration::ZERO,
+      stats: GcStats::default(),
+      quota_objects: 0,
+      quota_bytes: 0,
+      next_id: 0,
+      cards: Vec::new(),
+      unswept_writes: Vec::new(),
+      history: VecDeque::new(),
+      history_seq: 0,
+      region: Vec::new(),
+      region_start: 0,
+      region_depth: 0,
+      region_writes: Vec::new(),
+      persistent: BTreeMap::new(),
+      next_handle: 0,
+      labels: BTreeMap::new(),
+      metadata: metadata::Tables::default(),
+      cell_writes: Rc::default(),
+      providers: roots::Providers::default(),
+      identity: Rc::new(()),
+      shuffle,
+      #[cfg(all(feature = "signal-dump", unix))]
+      signal_dump: None,
+      #[cfg(feature = "std")]
+      registered: None,
+      #[cfg(feature = "std")]
+      panic_dump: None,
+      #[cfg(feature = "std")]
+      frames: 0,
+      #[cfg(feature = "std")]
+      timeline,
+      #[cfg(feature = "std")]
+      paranoid: config_paranoid,
+      #[cfg(feature = "std")]
+      shadow: None
+    }
+  }
+
+  // Every object the VM owns, wherever it currently lives.
+  fn objects(&self) -> usize {
+    self.heap.len() + self.nursery.len() + self.middle.iter().map(Vec::len).sum::<usize>() + self.sweeping.len()
+      + self.region.len()
+  }
+
+  fn iter_objects(&self) -> impl Iterator<Item = &Sobject> {
+    self.heap.iter()
+      .chain(self.nursery.iter())
+      .chain(self.middle.iter().flatten())
+      .chain(self.sweeping.as_slice().iter())
+      .chain(self.region.iter())
+  }
+
+  fn mark(&mut self) {
+    let roots: Vec<(Source, Sobject)> = self.roots().collect();
+    for (source, obj) in &roots {
+      self.note_mark(obj, *source, None);
+      Object::mark(obj, &mut self.gray);
+    }
+  }
+
+  // Traces up to `work` gray objects. Returns true once nothing is gray.
+  // Objects the host holds mutably borrowed stay gray; if nothing else is
+  // left, `blocked` says the cycle can't get further for now.
+  fn trace(&mut self, work: usize) -> bool {
+    let mut busy = Vec::new();
+    for _ in 0..work {
+      self.gray_peak = self.gray_peak.max(self.gray.len());
+      let obj = match self.gray.pop() {
+        Some(obj) => obj,
+        None => break
+      };
+
+      let mut blocked = false;
+      for child in children(&obj, &mut blocked) {
+        self.note_mark(&child, Source::Object(obj.2), None);
+        Object::mark(&child, &mut self.gray);
+      }
+      if blocked {
+        busy.push(obj);
+      }
+    }
+
+    self.blocked = !busy.is_empty() && self.gray.is_empty();
+    self.gray.append(&mut busy);
+    self.gray.is_empty()
+  }
+
+  // The gray worklist is kept from one collection to the next, so marking
+  // stops allocating once it has grown enough. One unusually deep
+  // collection shouldn't hold on to its memory for good, though.
+  fn trim_gray(&mut self) {
+    if self.gray.capacity() > GRAY_SLACK * self.gray_peak {
+      self.gray.shrink_to(2 * self.gray_peak);
+    }
+    self.gray_peak = 0;
+  }
+
+  // Everything allocated so far is swept this cycle; objects allocated
+  // while the sweep is in progress land in a fresh nursery.
+  fn start_sweep(&mut self) {
+    self.trim_gray();
+    let mut objs = mem::replace(&mut self.heap, Vec::with_capacity(self.config.reserve));
+    objs.append(&mut self.nursery);
+    for gen in &mut self.middle {
+      objs.append(gen);
+    }
+    if let Some(ref mut shuffle) = self.shuffle {
+      shuffle.shuffle(&mut objs);
+    }
+    self.cards.clear();
+    self.stats.age_histogram = [0; 8];
+    self.unmark_region();
+
+    self.sweeping = objs.into_iter();
+    self.phase = Phase::Sweep;
+    self.record_sweep("full");
+  }
+
+  // Sweeps up to `work` objects. Returns true once the cycle is over.
+  // Survivors are moved into the fresh old generation as they are found,
+  // so nothing is shifted down over the dead.
+  fn sweep(&mut self, work: usize) -> bool {
+    for _ in 0..work {
+      let obj = match self.sweeping.next() {
+        Some(obj) => obj,
+        None => break
+      };
+
+      self.note_sweep(&obj);
+      if obj.0.get().marked() {
+        self.promote(obj);
+      } else {
+        self.cycle_freed += 1;
+        self.free(&obj);
+      }
+    }
+
+    if self.sweeping.len() > 0 {
+      return false;
+    }
+
+    let threshold = self.heap_max;
+    let next = self.config.sizing.next_threshold(self.cycle_len, self.heap.len());
+    self.heap_max = self.resize_heap(threshold, next);
+    self.survival = Some(self.heap.len() as f64 / self.cycle_len.max(1) as f64);
+    self.phase = Phase::Idle;
+    self.card_unswept_writes();
+    if let Some(budget) = self.config.compact_budget {
+      self.stats.bytes_compacted += self.compact_lists(budget).recovered as u64;
+    }
+    self.stats.full_collections += 1;
+    self.count_collection(self.cycle_freed);
+    self.record_done("full", self.cycle_freed);
+    self.log("full", self.cycle_freed, threshold);
+    self.record_history("full");
+    true
+  }
+
+  // The new threshold, once the allocator has had its say.
+  fn resize_heap(&self, old: usize, new: usize) -> usize {
+    if new == old {
+      return old;
+    }
+
+    let ok = self.config.allocator.resize(old * Object::size(), new * Object::size());
+    if new > old && !ok { old } else { new }
+  }
+
+  fn start_cycle(&mut self) {
+    self.cycle_len = self.objects();
+    self.cycle_freed = 0;
+    #[cfg(feature = "std")]
+    {
+      self.cycle_trigger = self.trigger;
+    }
+    self.phase = Phase::Mark;
+    self.record_mark("full");
+    self.mark();
+  }
+
+  // Does up to `work` units of the current cycle. Returns true once the
+  // cycle has finished.
+  fn cycle_step(&mut self, work: usize) -> bool {
+    match self.phase {
+      Phase::Idle => true,
+      Phase::Mark => {
+        if self.trace(work) {
+          // Roots pushed since the cycle began still need tracing.
+          self.mark();
+
+          if self.gray.is_empty() {
+            self.start_sweep();
+          }
+        }
+        false
+      }
+      Phase::Sweep => self.sweep(work)
+    }
+  }
+
+  /// Collects according to the configured strategy. Under `MarkSweep`
+  /// this is always a full collection; under `Generational` it scavenges
+  /// the nursery and only falls back to a full collection once the old
+  /// generation outgrows its threshold. Returns the number of objects freed.
+  pub fn gc(&mut self) -> usize {
+    self.set_trigger("explicit");
+    let freed = self.timed(VM::collect);
+    self.after("gc");
+    freed
+  }
+
+  /// Collects only the nursery, promoting survivors that reach its
+  /// promotion age. Older objects are never freed by a minor collection.
+  /// If an incremental cycle is in progress it is finished instead.
+  pub fn gc_minor(&mut self) -> usize {
+    self.set_trigger("explicit");
+    let freed = self.timed(VM::collect_minor);
+    self.after("gc_minor");
+    freed
+  }
+
+  /// Collects the whole heap, finishing any incremental cycle in progress.
+  pub fn gc_full(&mut self) -> usize {
+    self.set_trigger("explicit");
+    let freed = self.timed(VM::collect_full);
+    self.after("gc_full");
+    freed
+  }
+
+  /// Advances the collector by one tick's worth of work in host-driven
+  /// mode (see `VMConfig::host_driven`). Returns true if no cycle is in
+  /// progress afterwards.
+  pub fn tick(&mut self) -> bool {
+    if self.phase == Phase::Idle {
+      return true;
+    }
+
+    let work = self.config.tick_work.unwrap_or(GC_STEP_WORK);
+    self.timed(|vm| vm.cycle_step(work))
+  }
+
+  pub fn stats(&self) -> &GcStats {
+    &self.stats
+  }
+
+  /// Objects and bytes allocated against the quota since the VM was
+  /// created or `reset_quota` was last called.
+  pub fn quota_used(&self) -> (u64, u64) {
+    (self.quota_objects, self.quota_bytes)
+  }
+
+  pub fn reset_quota(&mut self) {
+    self.quota_objects = 0;
+    self.quota_bytes = 0;
+  }
+
+  /// A number identifying `obj` for as long as it lives, handed out in
+  /// allocation order and never reused by this VM. Unlike an address it
+  /// would survive a collector that moves objects. Images don't record
+  /// ids, so a loaded VM numbers its objects afresh.
+  pub fn object_id(&self, obj: &Sobject) -> u64 {
+    obj.2
+  }
+
+  /// Whether an incremental collection is under way.
+  pub fn collecting(&self) -> bool {
+    self.phase != Phase::Idle
+  }
+
+  /// Whether the collection under way has found `obj` reachable yet.
+  /// Always false between collections.
+  pub fn is_marked(&self, obj: &Sobject) -> bool {
+    self.collecting() && obj.0.get().marked()
+  }
+
+  /// How many collections `obj` has survived. Minor collections only age
+  /// the nursery; counting stops at 31.
+  pub fn age(&self, obj: &Sobject) -> u32 {
+    obj.0.get().age()
+  }
+
+  /// Attaches an embedder's own word to `obj`, replacing any earlier one.
+  /// The collector carries it along and never looks at it; images and
+  /// clones leave it behind.
+  pub fn set_tag(&self, obj: &Sobject, tag: u64) {
+    obj.1.borrow_mut().tag = Some(tag);
+  }
+
+  pub fn get_tag(&self, obj: &Sobject) -> Option<u64> {
+    obj.1.borrow().tag
+  }
+
+  fn collect(&mut self) -> usize {
+    match self.config.strategy {
+      GcStrategy::MarkSweep => self.collect_full(),
+      GcStrategy::Generational => {
+        let freed = self.collect_young();
+
+        if self.config.sizing.should_collect(self.heap.len(), self.heap_max) && !self.skip_full(self.heap.len()) {
+          freed + self.collect_full()
+        } else {
+          freed
+        }
+      }
+    }
+  }
+
+  // Whether to put off a full collection that is due, with `live` objects
+  // counted against the threshold, under `VMConfig::skip_survival`.
+  fn skip_full(&mut self, live: usize) -> bool {
+    let skip = match (self.config.skip_survival, self.survival) {
+      (Some(ratio), Some(survival)) => survival >= ratio,
+      _ => false
+    };
+    if skip {
+      self.survival = None;
+      let next = self.config.sizing.next_threshold(live, live);
+      #[cfg(feature = "std")]
+      let threshold = self.heap_max;
+      self.heap_max = self.resize_heap(self.heap_max, next);
+      self.stats.skipped_collections += 1;
+      #[cfg(feature = "std")]
+      self.send_threshold(threshold);
+    }
+    skip
+  }
+
+  // Stops early, leaving the cycle in progress, if the host cancels or
+  // has borrowed an object marking needs to look inside.
+  fn collect_full(&mut self) -> usize {
+    if self.phase == Phase::Idle {
+      self.start_cycle();
+    }
+
+    let work = if self.config.cancel.is_some() { GC_STEP_WORK } else { usize::MAX };
+    while !self.cycle_step(work) {
+      if self.cancelled() || self.blocked {
+        break;
+      }
+    }
+
+    self.cycle_freed
+  }
+
+  // Runs a piece of collector work as one pause for the stats.
+  fn timed<F, R>(&mut self, f: F) -> R
+    where F: FnOnce(&mut VM) -> R
+  {
+    self.apply_cell_writes();
+    #[cfg(feature = "std")]
+    let start = Instant::now();
+    #[cfg(feature = "std")]
+    {
+      self.slice_start = start;
+    }
+    let result = f(self);
+
+    self.stats.pauses += 1;
+    #[cfg(feature = "std")]
+    {
+      self.record_pause(start.elapsed());
+      self.end_slice();
+    }
+
+    result
+  }
+
+  /// Must be called after storing into a pair in place (as through
+  /// `borrow_mut`), so an incremental cycle in progress traces the new
+  /// referent and the next minor collection sees it.
+  pub fn write_barrier(&mut self, obj: &Sobject) {
+    assert!(!self.is_freed(obj), "freed objects can't be stored into");
+    assert!(self.try_write_barrier(obj).is_ok(), "frozen objects can't be stored into");
+  }
+
+  /// Like `write_barrier`, but fails with `VmError::Frozen` for a frozen
+  /// object or a constant, or `VmError::Freed` for a freed one, rather
+  /// than panicking.
+  pub fn try_write_barrier(&mut self, obj: &Sobject) -> Result<(), VmError> {
+    if self.is_freed(obj) {
+      return Err(VmError::Freed);
+    }
+    if self.is_frozen(obj) {
+      return Err(VmError::Frozen);
+    }
+
+    self.barrier(obj);
+    self.after("store");
+    Ok(())
+  }
+
+  // Tells the collector `obj` was stored into.
+  fn barrier(&mut self, obj: &Sobject) {
+    if self.phase == Phase::Mark && obj.0.get().marked() {
+      self.gray.push(obj.clone());
+    }
+    self.dirty_card(obj);
+    self.note_region_write(obj);
+    self.record_store(obj);
+  }
+
+  /// Points the head of `pair` at `val`, through the write barrier. `val`
+  /// is a handle or a Rust int, which is allocated first. Fails with
+  /// `VmError::Type` if `pair` isn't a pair, `VmError::Frozen` if it is
+  /// frozen, or `VmError::Freed` if either was freed, in each case storing
+  /// and allocating nothing.
+  pub fn set_head<V: Operand>(&mut self, pair: &Sobject, val: V) -> Result<(), VmError> {
+    self.store(pair, val, true)
+  }
+
+  /// Like `set_head`, for the tail.
+  pub fn set_tail<V: Operand>(&mut self, pair: &Sobject, val: V) -> Result<(), VmError> {
+    self.store(pair, val, false)
+  }
+
+  fn store<V: Operand>(&mut self, pair: &Sobject, val: V, head: bool) -> Result<(), VmError> {
+    self.check_store(pair)?;
+
+    // The pair may be held only by the host, so it is rooted while an int
+    // is allocated.
+    let n = self.temp_roots.len();
+    self.temp_roots.push(pair.clone());
+    let val = val.into_object(self);
+    self.temp_roots.truncate(n);
+    let val = val?;
+    if self.is_freed(&val) {
+      return Err(VmError::Freed);
+    }
+
+    match pair.1.borrow_mut().val {
+      Vobject::Pair(ref mut h, _) if head => *h = val,
+      Vobject::Pair(_, ref mut t) => *t = val,
+      Vobject::Int(_) | Vobject::Native(_) => unreachable!()
+    }
+
+    self.write_barrier(pair);
+    Ok(())
+  }
+
+  // Whether `pair` can be stored into: a pair neither freed nor frozen.
+  fn check_store(&self, pair: &Sobject) -> Result<(), VmError> {
+    if self.is_freed(pair) {
+      return Err(VmError::Freed);
+    }
+    match pair.1.borrow().val {
+      Vobject::Pair(..) => {}
+      ref val => return Err(VmError::Type(marshal::mismatch(val, "pair")))
+    }
+    if self.is_frozen(pair) {
+      return Err(VmError::Frozen);
+    }
+    Ok(())
+  }
+
+  // Reports a finished collection to stderr, if asked, to the GC log and
+  // to any `metrics` recorder. `threshold` is the full-collection
+  // threshold it started with.
+  #[cfg(feature = "std")]
+  fn log(&mut self, kind: &'static str, freed: usize, threshold: usize) {
+    if self.config.log {
+      eprintln!("[gc] {} collection freed {}, {} live, next full at {}",
+                kind, freed, self.objects(), self.heap_max);
+    }
+
+    self.write_gc_log(kind, freed, threshold);
+    self.send_end(kind, freed, threshold);
+    self.update_panic_dump(kind, freed, threshold);
+    self.update_registry();
+
+    #[cfg(feature = "metrics-facade")]
+    facade::collected(self, kind, freed);
+  }
+
+  #[cfg(not(feature = "std"))]
+  fn log(&mut self, _kind: &'static str, _freed: usize, _threshold: usize) {}
+
+  #[cfg(feature = "std")]
+  fn set_trigger(&mut self, trigger: &'static str) {
+    self.trigger = trigger;
+  }
+
+  #[cfg(not(feature = "std"))]
+  fn set_trigger(&mut self, _trigger: &'static str) {}
+
+  #[cfg(not(feature = "std"))]
+  #[inline(always)]
+  fn frame(&mut self, _op: &str) {}
+
+  // Visual and paranoid modes' look at the heap after an operation.
+  pub(crate) fn after(&mut self, op: &str) {
+    self.frame(op);
+    self.check_paranoid(op);
+    #[cfg(all(feature = "signal-dump", unix))]
+    self.check_signal_dump();
+    #[cfg(feature = "std")]
+    self.update_registry();
+  }
+
+  #[cfg(not(feature = "std"))]
+  #[inline(always)]
+  pub(crate) fn record_alloc(&mut self, _obj: &Sobject) {}
+  #[cfg(not(feature = "std"))]
+  #[inline(always)]
+  pub(crate) fn record_store(&mut self, _obj: &Sobject) {}
+  #[cfg(not(feature = "std"))]
+  #[inline(always)]
+  pub(crate) fn record_free(&mut self, _obj: &Sobject) {}
+  #[cfg(not(feature = "std"))]
+  #[inline(always)]
+  pub(crate) fn record_mark(&mut self, _kind: &'static str) {}
+  #[cfg(not(feature = "std"))]
+  #[inline(always)]
+  pub(crate) fn record_sweep(&mut self, _kind: &'static str) {}
+  #[cfg(not(feature = "std"))]
+  #[inline(always)]
+  pub(crate) fn record_done(&mut self, _kind: &'static str, _freed: usize) {}
+
+  #[cfg(not(feature = "read-barrier"))]
+  #[inline(always)]
+  fn read_barrier(&self, _obj: &Sobject) {}
+
+  fn collect_if_needed(&mut self) -> Result<(), VmError> {
+    self.set_trigger(if self.config.stress { "stress" } else { "threshold" });
+    let due = self.config.stress || match self.config.strategy {
+      GcStrategy::MarkSweep => {
+        let live = self.objects();
+        self.config.sizing.should_collect(live, self.heap_max) && (self.phase != Phase::Idle || !self.skip_full(live))
+      }
+      GcStrategy::Generational => self.nursery.len() >= self.config.generations[0].size
+    };
+
+    if self.config.tick_work.is_some() {
+      // Only start a cycle; the host's ticks do the work.
+      if due && self.phase == Phase::Idle {
+        self.start_cycle();
+      }
+
+      if self.objects() >= self.heap_max.saturating_mul(STARVATION_FACTOR) {
+        return Err(VmError::GcStarved);
+      }
+
+      return Ok(());
+    }
+
+    if due && self.pause_depth > 0 {
+      self.gc_pending = true;
+      return Ok(());
+    }
+
+    #[cfg(feature = "std")]
+    {
+      if let Some(target) = self.config.pause_target {
+        self.paced_collect(target, due);
+        return Ok(());
+      }
+    }
+
+    if due {
+      self.timed(VM::collect);
+    }
+
+    Ok(())
+  }
+
+  /// Runs `f` with automatic collection disabled. Allocation still works;
+  /// a collection that would have been triggered inside `f` runs once the
+  /// outermost `gc_paused` returns. Explicit calls to `gc` are not affected.
+  pub fn gc_paused<F, R>(&mut self, f: F) -> R
+    where F: FnOnce(&mut VM) -> R
+  {
+    self.pause_depth += 1;
+    let result = f(self);
+    self.pause_depth -= 1;
+
+    if self.pause_depth == 0 && self.gc_pending {
+      self.gc_pending = false;
+      self.set_trigger("deferred");
+      self.timed(VM::collect);
+    }
+
+    result
+  }
+
+  /// Panics if the stack is empty.
+  pub fn pop(&mut self) -> Sobject {
+    let obj = self.stack.pop().unwrap();
+    self.after("pop");
+    obj
+  }
+
+  pub fn try_pop(&mut self) -> Result<Sobject, VmError> {
+    let obj = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+    self.after("pop");
+    Ok(obj)
+  }
+
+  pub fn push_int(&mut self, val: u32) -> Result<Sobject, VmError> {
+    let obj = Object::new(self, Vobject::Int(val))?;
+    self.stack.push(obj.clone());
+    self.after("push_int");
+    Ok(obj)
+  }
+
+  /// Replaces the top two stack slots (head, then tail) with a pair of
+  /// them. If the pair can't be allocated the operands are left in place.
+  /// Fails with `VmError::StackUnderflow` if there are fewer than two.
+  pub fn push_pair(&mut self) -> Result<Sobject, VmError> {
+    let n = self.stack.len();
+    if n < 2 {
+      return Err(VmError::StackUnderflow);
+    }
+
+    // The allocation roots its operands itself, so they can come off the
+    // stack first.
+    let operands = self.stack.split_off(n - 2);
+    let obj = match Object::new(self, Vobject::Pair(operands[0].clone(), operands[1].clone())) {
+      Ok(obj) => obj,
+      Err(e) => {
+        self.stack.extend(operands);
+        return Err(e);
+      }
+    };
+
+    self.stack.push(obj.clone());
+    self.after("push_pair");
+    Ok(obj)
+  }
+
+  /// Pushes a pair of `head` and `tail`, which needn't be on the stack:
+  /// they are rooted while the pair is allocated. Fails with
+  /// `VmError::Freed` if either was freed.
+  pub fn push_pair_from(&mut self, head: &Sobject, tail: &Sobject) -> Result<Sobject, VmError> {
+    if self.is_freed(head) || self.is_freed(tail) {
+      return Err(VmError::Freed);
+    }
+
+    let obj = Object::new(self, Vobject::Pair(head.clone(), tail.clone()))?;
+    self.stack.push(obj.clone());
+    self.after("push_pair_from");
+    Ok(obj)
+  }
+}
+
+// Room to make in the nursery each time it is emptied. Under the
+// generational strategy it never holds more than its size; otherwise it
+// can hold everything.
+fn nursery_reserve(config: &VMConfig) -> usize {
+  match config.strategy {
+    GcStrategy::MarkSweep => config.reserve,
+    GcStrategy::Generational => config.reserve.min(config.generations[0].size)
+  }
+}
+
+impl Default for VM {
+  fn default() -> VM {
+    VM::new()
+  }
+}
+
+impl Object {
+  // Allocates `val`. A pair's head and tail are temporary roots until it
+  // exists, so a collection the allocation sets off keeps them even if
+  // nothing else does; in the middle of marking they are shaded, since
+  // the new pair is black and won't be traced.
+  fn new(vm: &mut VM, val: Vobject) -> Result<Sobject, VmError> {
+    let n = vm.temp_roots.len();
+    vm.temp_roots.extend(val.children());
+
+    let obj = Object::reserve(vm, 1).map(|()| Object::place(vm, val));
+    let operands = vm.temp_roots.split_off(n);
+    if vm.phase == Phase::Mark {
+      for child in &operands {
+        Object::mark(child, &mut vm.gray);
+      }
+    }
+    obj
+  }
+
+  // Makes room for `n` objects, collecting first if that is due. Whatever
+  // they will point to has to be rooted already.
+  fn reserve(vm: &mut VM, n: usize) -> Result<(), VmError> {
+    if vm.cancelled() {
+      return Err(VmError::Cancelled);
+    }
+
+    let bytes = Object::size() as u64 * n as u64;
+    let over_objects = vm.config.object_quota.is_some_and(|quota| vm.quota_objects.saturating_add(n as u64) > quota);
+    let over_bytes = vm.config.byte_quota.is_some_and(|quota| vm.quota_bytes.saturating_add(bytes) > quota);
+    if over_objects || over_bytes {
+      return Err(VmError::QuotaExceeded);
+    }
+
+    vm.collect_if_needed()?;
+
+    let max = vm.config.max_heap.map_or(MAX_OBJECTS, |max| max.min(MAX_OBJECTS));
+    if vm.objects() + n > max && vm.pause_depth == 0 && vm.config.tick_work.is_none() {
+      vm.set_trigger("max-heap");
+      vm.timed(VM::collect_full);
+    }
+
+    if vm.objects() + n > max {
+      return Err(VmError::OutOfMemory);
+    }
+
+    if !vm.config.allocator.allocate(bytes as usize) {
+      if vm.pause_depth == 0 && vm.config.tick_work.is_none() {
+        vm.set_trigger("allocator");
+        vm.timed(VM::collect_full);
+      }
+
+      if !vm.config.allocator.allocate(bytes as usize) {
+        return Err(VmError::OutOfMemory);
+      }
+    }
+    Ok(())
+  }
+
+  // Creates an object in room `reserve` made.
+  fn place(vm: &mut VM, val: Vobject) -> Sobject {
+    let bytes = Object::size() as u64;
+
+    // Objects allocated while marking are black so the cycle keeps them.
+    let gch = GCHeader::new(false).with_marked(vm.phase == Phase::Mark);
+
+    let obj = Rc::new((Cell::new(gch), RefCell::new(Object { val, tag: None }), vm.next_id));
+    vm.next_id += 1;
+
+    if vm.region_depth > 0 {
+      vm.region.push(obj.clone());
+    } else {
+      vm.nursery.push(obj.clone());
+      if let Some(ref mut shuffle) = vm.shuffle {
+        shuffle.scatter_last(&mut vm.nursery);
+      }
+    }
+    vm.stats.peak_objects = vm.stats.peak_objects.max(vm.objects());
+    vm.record_alloc(&obj);
+    if vm.config.reserve > 0 && vm.objects() > vm.config.reserve {
+      vm.stats.reservation_exceeded += 1;
+    }
+    vm.quota_objects = vm.quota_objects.saturating_add(1);
+    vm.quota_bytes = vm.quota_bytes.saturating_add(bytes);
+    vm.bind_cells(&obj);
+    obj
+  }
+
+  // Bytes one object costs, counting the Rc's reference counts.
+  fn size() -> usize {
+    2 * mem::size_of::<usize>() + mem::size_of::<(Cell<GCHeader>, RefCell<Object>, u64)>()
+  }
+
+  // Shades an object gray; its children are traced when it is popped
+  // off the worklist.
+  fn mark(obj: &Sobject, gray: &mut Vec<Sobject>) {
+    let (ref gch, _, _) = **obj;
+
+    if gch.get().marked() {
+      return;
+    }
+
+    gch.set(gch.get().with_marked(true));
+    gray.push(obj.clone());
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test1() {
+    println!("Test 1: Objects on stack are preserved.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+
+    vm.gc();
+
+    assert!(vm.heap.len() == 2);
+  }
+
+  #[test]
+  fn test2() {
+    println!("Test 2: Unreachable objects are collected.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+
+    vm.pop();
+    vm.pop();
+
+    vm.gc();
+
+    assert!(vm.heap.is_empty());
+  }
+
+  #[test]
+  fn test3() {
+    println!("Test 3: Nested objects are reachable.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    vm.push_pair().unwrap();
+
+    vm.push_int(3).unwrap();
+    vm.push_int(4).unwrap();
+    vm.push_pair().unwrap();
+
+    vm.push_pair().unwrap();
+
+    vm.gc();
+
+    assert!(vm.heap.len() == 7);
+  }
+
+  #[test]
+  fn test4() {
+    println!("Test 4: Handle cycles.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let a = vm.push_pair().unwrap();
+
+    vm.push_int(3).unwrap();
+    vm.push_int(4).unwrap();
+    let b = vm.push_pair().unwrap();
+
+    // set up a cycle
+    vm.set_tail(&a, &a).unwrap();
+    vm.set_tail(&b, &b).unwrap();
+
+    vm.gc();
+
+    assert!(vm.heap.len() == 4);
+  }
+
+  #[test]
+  fn perftest() {
+    println!("Performance Test.");
+
+    let mut vm = VM::new();
+
+    for i in 0..1000 {
+      for _ in 0..20 {
+        vm.push_int(i).unwrap();
+      }
+
+      for _ in 0..20 {
+        vm.pop();
+      }
+    }
+
+    vm.gc();
+
+    assert!(vm.heap.is_empty());
+  }
+
+  #[test]
+  fn gc_paused_defers_collection() {
+    println!("Paused GC defers collection until the closure returns.");
+
+    let mut vm = VM::new();
+
+    vm.gc_paused(|vm| {
+      for i in 0..50 {
+        vm.push_int(i).unwrap();
+        vm.pop();
+      }
+
+      assert!(vm.nursery.len() == 50);
+      vm.gc_paused(|vm| vm.push_int(50).unwrap());
+      assert!(vm.nursery.len() == 51);
+    });
+
+    assert!(vm.heap.len() == 1);
+    assert!(vm.nursery.is_empty());
+  }
+
+  #[test]
+  fn max_heap_reports_out_of_memory() {
+    println!("Allocation past max_heap fails, and the VM recovers.");
+
+    let mut vm = VM::with_config(VMConfig::new().max_heap(5));
+    for i in 0..5 {
+      vm.push_int(i).unwrap();
+    }
+
+    assert!(vm.push_int(5).unwrap_err() == VmError::OutOfMemory);
+    assert!(vm.push_pair().unwrap_err() == VmError::OutOfMemory);
+    assert!(vm.stack.len() == 5);
+
+    vm.pop();
+    vm.pop();
+    vm.push_int(6).unwrap();
+    vm.push_pair().unwrap();
+    assert!(vm.heap.len() + vm.nursery.len() == 5);
+  }
+
+  #[test]
+  fn misuse_is_reported_not_panicked() {
+    println!("The try_ forms turn misuse into errors.");
+
+    let mut vm = VM::with_config(VMConfig::new().object_quota(u64::MAX));
+    assert!(vm.try_pop().unwrap_err() == VmError::StackUnderflow);
+    let a = vm.push_int(1).unwrap();
+    assert!(vm.push_pair().unwrap_err() == VmError::StackUnderflow);
+    assert!(vm.try_push_constant(&a).unwrap_err() == VmError::NotConstant);
+
+    let space = ConstantSpace::new();
+    let one = space.int(1);
+    let pair = space.pair(&one, &one);
+    vm.try_push_constant(&pair).unwrap();
+    assert!(vm.try_write_barrier(&pair).unwrap_err() == VmError::Frozen);
+    assert!(Rc::ptr_eq(&vm.try_pop().unwrap(), &pair));
+
+    // Counters stick at their limits rather than overflowing.
+    vm.quota_objects = u64::MAX;
+    vm.push_int(2).unwrap();
+    assert!(vm.quota_used().0 == u64::MAX);
+  }
+
+  #[test]
+  fn collections_wait_out_host_borrows() {
+    println!("A collection steps around an object the host has mutably borrowed.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    let x = vm.push_int(3).unwrap();
+    vm.pop();
+
+    let mut o = p.1.borrow_mut();
+    assert!(vm.gc() == 0 && vm.collecting());
+    if let Vobject::Pair(_, ref mut tail) = o.val { *tail = x.clone() }
+    drop(o);
+    vm.write_barrier(&p);
+
+    assert!(vm.gc() == 1 && !vm.collecting());
+    assert!(vm.extract::<(u32, u32)>(&p) == Ok((1, 3)));
+    vm.verify().unwrap();
+  }
+
+  #[test]
+  fn incremental_marking_with_a_borrowed_object() {
+    println!("Ticks make no progress past a borrowed object, which can be stored into meanwhile.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(1).threshold(8));
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    let loose = vm.push_int(3).unwrap();
+    vm.pop();
+
+    let mut o = p.1.borrow_mut();
+    while !vm.collecting() {
+      vm.push_int(0).unwrap();
+      vm.pop();
+    }
+    for _ in 0..20 {
+      assert!(!vm.tick());
+    }
+
+    if let Vobject::Pair(ref mut head, _) = o.val { *head = loose.clone() }
+    drop(o);
+    vm.write_barrier(&p);
+    while !vm.tick() {}
+    assert!(vm.extract::<(u32, u32)>(&p) == Ok((3, 2)) && !vm.is_freed(&loose));
+    vm.verify().unwrap();
+  }
+
+  #[test]
+  fn minor_collections_wait_out_host_borrows() {
+    println!("A minor collection that meets a borrowed object is put off.");
+
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational));
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    vm.push_int(3).unwrap();
+    vm.pop();
+
+    let o = p.1.borrow_mut();
+    assert!(vm.gc_minor() == 0 && vm.nursery.len() == 4);
+    drop(o);
+    vm.verify().unwrap();
+    assert!(vm.gc_minor() == 1);
+  }
+
+  #[test]
+  fn reservations_are_kept() {
+    println!("Reserved room survives collections; going past it is counted.");
+
+    for strategy in [GcStrategy::MarkSweep, GcStrategy::Generational] {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).reserve_objects(20).threshold(100));
+      for i in 0..19 {
+        vm.push_int(i).unwrap();
+      }
+      vm.gc_full();
+      vm.push_int(20).unwrap();
+      vm.gc_minor();
+      assert!(vm.heap.capacity() >= 20 && vm.stats().reservation_exceeded == 0);
+
+      vm.push_int(21).unwrap();
+      assert!(vm.stats().reservation_exceeded == 1);
+    }
+
+    let vm = VM::with_config(VMConfig::new().reserve_bytes(8 * Object::size()));
+    assert!(vm.heap.capacity() >= 8 && vm.nursery.capacity() >= 8);
+  }
+
+  #[test]
+  fn collections_and_peak_are_counted() {
+    println!("Stats count each kind of collection and the most objects held.");
+
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational).threshold(100));
+    for i in 0..5 {
+      vm.push_int(i).unwrap();
+    }
+    vm.gc_minor();
+    vm.stack.truncate(2);
+    vm.gc_full();
+    vm.push_int(5).unwrap();
+
+    let stats = vm.stats();
+    assert!(stats.minor_collections == 1 && stats.full_collections == 1);
+    assert!(stats.peak_objects == 5 && vm.iter_heap().count() == 3);
+  }
+
+  #[test]
+  fn stress_collects_on_every_allocation() {
+    println!("Stress mode leaves no garbage behind any allocation.");
+
+    let mut vm = VM::with_config(VMConfig::new().stress(true));
+    vm.push_int(1).unwrap();
+    vm.pop();
+    vm.push_int(2).unwrap();
+    vm.push_int(3).unwrap();
+    vm.push_pair().unwrap();
+
+    assert!(vm.heap.len() == 2);
+    assert!(vm.nursery.len() == 1);
+  }
+
+  #[test]
+  fn allocations_root_their_operands() {
+    println!("A pair's operands survive the collection its allocation sets off, off the stack or not.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).stress(true));
+      let list: Vec<u32> = (0..50).collect();
+      let obj = vm.push_value(&list).unwrap();
+      assert!(vm.extract::<Vec<u32>>(&obj) == Ok(list));
+
+      vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+      let p = vm.push_pair().unwrap();
+      let (head, tail) = vm.as_pair(&p).unwrap();
+      assert!(!vm.is_freed(&head) && !vm.is_freed(&tail));
+      vm.verify().unwrap();
+    }
+
+    // Pairs allocated black mid-cycle still get their operands traced.
+    let mut vm = VM::with_config(VMConfig::new().host_driven(2).threshold(4));
+    vm.push_int(0).unwrap();
+    for i in 0..50 {
+      vm.push_int(i).unwrap();
+      vm.push_pair().unwrap();
+      for _ in 0..4 {
+        vm.tick();
+      }
+      vm.verify().unwrap();
+    }
+  }
+
+  #[test]
+  fn pairs_from_handles() {
+    println!("Pairs can be built from handles held off the stack.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).stress(true));
+      let one = vm.push_int(1).unwrap();
+      let two = vm.push_int(2).unwrap();
+      vm.truncate_stack(0);
+      let p = vm.push_pair_from(&one, &two).unwrap();
+      let q = vm.push_pair_from(&p, &p).unwrap();
+      assert!(vm.stack.len() == 2 && Rc::ptr_eq(&vm.stack[1], &q));
+      assert!(vm.extract::<(u32, u32)>(&p) == Ok((1, 2)));
+      vm.verify().unwrap();
+
+      vm.truncate_stack(0);
+      vm.gc_full();
+      assert!(vm.push_pair_from(&p, &one).err() == Some(VmError::Freed));
+    }
+  }
+
+  #[test]
+  fn gray_worklist_is_reused_and_trimmed() {
+    println!("Marking reuses its worklist, cutting it back after a deep collection.");
+
+    let mut vm = VM::new();
+    let list: Vec<u32> = (0..1000).collect();
+    vm.push_value(&list).unwrap();
+    vm.gc();
+    let deep = vm.gray.capacity();
+    assert!(deep >= 1000);
+    vm.gc();
+    assert!(vm.gray.capacity() == deep);
+
+    vm.pop();
+    vm.push_int(1).unwrap();
+    vm.gc();
+    assert!(vm.gray.capacity() < 16);
+  }
+
+  #[test]
+  fn header_is_one_word() {
+    println!("Header fields pack into a word without disturbing each other.");
+
+    let gch = GCHeader::new(true).with_slot(MAX_OBJECTS - 1).with_marked(true);
+    assert!(mem::size_of::<GCHeader>() == mem::size_of::<Word>());
+    assert!(gch.marked() && gch.old() && gch.slot() == MAX_OBJECTS - 1);
+
+    let gch = gch.with_marked(false).with_slot(3).aged().aged();
+    assert!(!gch.marked() && gch.old() && gch.slot() == 3 && gch.age() == 2);
+  }
+
+  #[test]
+  fn slot_limits_by_width() {
+    println!("The heap cap follows the header and pointer widths.");
+
+    // Compact headers, on 32- and 64-bit targets.
+    assert!(slot_limit(32, 32) == 1 << 14);
+    assert!(slot_limit(32, 64) == 1 << 14);
+    // Full headers, where a 32-bit usize is the tighter limit.
+    assert!(slot_limit(64, 32) == u64::from(u32::MAX));
+    assert!(slot_limit(64, 64) == 1 << 46);
+  }
+
+  #[test]
+  #[cfg(feature = "compact-headers")]
+  fn compact_heaps_stop_at_max_objects() {
+    println!("With compact headers the heap can't outgrow the header's slots.");
+
+    let mut vm = VM::with_config(VMConfig::new().threshold(MAX_OBJECTS));
+    for i in 0..MAX_OBJECTS {
+      vm.push_int(i as u32).unwrap();
+    }
+    assert!(vm.push_int(0).unwrap_err() == VmError::OutOfMemory);
+    vm.pop();
+    vm.push_int(0).unwrap();
+    vm.verify().unwrap();
+  }
+
+  #[test]
+  fn ages_count_collections_survived() {
+    println!("Objects age once per collection that keeps them.");
+
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational));
+    let a = vm.push_int(1).unwrap();
+    assert!(vm.age(&a) == 0);
+
+    vm.gc_minor();
+    let b = vm.push_int(2).unwrap();
+    vm.gc_minor();
+    assert!(vm.age(&a) == 1 && vm.age(&b) == 1);
+    assert!(vm.stats().age_histogram[1] == 2);
+
+    vm.gc_full();
+    assert!(vm.age(&a) == 2 && vm.age(&b) == 2);
+    assert!(vm.stats().age_histogram == [0, 0, 2, 0, 0, 0, 0, 0]);
+
+    for _ in 0..40 {
+      vm.gc_full();
+    }
+    assert!(vm.age(&a) == 31);
+    assert!(vm.stats().age_histogram[7] == 2);
+  }
+
+  #[test]
+  fn custom_sizing_policy() {
+    println!("A sizing policy decides the next threshold.");
+
+    #[derive(Debug)]
+    struct Fixed(usize);
+
+    impl SizingPolicy for Fixed {
+      fn next_threshold(&self, _before: usize, _after: usize) -> usize {
+        self.0
+      }
+    }
+
+    let mut vm = VM::with_config(VMConfig::new().sizing(Fixed(3)).threshold(3));
+    for i in 0..10 {
+      vm.push_int(i).unwrap();
+    }
+
+    // Collections at 3, 4, 5, ... objects, none of which freed anything.
+    assert!(vm.heap_max == 3);
+    assert!(vm.heap.len() == 9);
+    assert!(vm.nursery.len() == 1);
+  }
+
+  #[test]
+  fn collections_that_would_free_nothing_are_skipped() {
+    println!("After a collection that kept nearly everything, the next is put off once.");
+
+    let mut vm = VM::with_config(VMConfig::new().threshold(4).skip_survival(0.9));
+    let mut counts = Vec::new();
+    for i in 0..40 {
+      vm.push_int(i).unwrap();
+      counts.push((vm.stats().full_collections, vm.stats().skipped_collections));
+    }
+
+    // Collections at 4 and 16 objects, skips at 8 and 32, each doubling.
+    assert!(counts[4] == (1, 0) && counts[8] == (1, 1) && counts[16] == (2, 1) && counts[32] == (2, 2));
+    assert!(vm.heap_max == 64);
+
+    // Once a collection frees plenty, the next one goes ahead.
+    vm.stack.truncate(4);
+    while vm.stats().full_collections < 3 {
+      vm.push_int(0).unwrap();
+      vm.pop();
+    }
+    while vm.stats().full_collections < 4 {
+      vm.push_int(0).unwrap();
+      vm.pop();
+    }
+    assert!(vm.stats().skipped_collections == 2);
+  }
+
+  #[test]
+  fn custom_allocator() {
+    println!("An allocator sees every allocation and free, and can refuse.");
+
+    #[derive(Debug, Default)]
+    struct Budget {
+      left: Cell<usize>
+    }
+
+    impl ObjectAllocator for Budget {
+      fn allocate(&self, bytes: usize) -> bool {
+        let ok = self.left.get() >= bytes;
+        if ok {
+          self.left.set(self.left.get() - bytes);
+        }
+        ok
+      }
+
+      fn free(&self, bytes: usize) {
+        self.left.set(self.left.get() + bytes);
+      }
+    }
+
+    let budget = Rc::new(Budget { left: Cell::new(3 * Object::size()) });
+    let mut vm = VM::with_config(VMConfig::new().allocator(budget.clone()).threshold(100));
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    vm.pop();
+    vm.push_int(3).unwrap();
+    assert!(budget.left.get() == 0);
+
+    // Refused, so the VM collects the dead 2 and tries again.
+    vm.push_int(4).unwrap();
+    assert!(vm.objects() == 3);
+    assert!(vm.push_int(5).unwrap_err() == VmError::OutOfMemory);
+  }
+
+  #[test]
+  fn allocator_can_refuse_growth() {
+    println!("The allocator hears of every resize and can keep the heap small.");
+
+    #[derive(Debug, Default)]
+    struct Capped {
+      resizes: RefCell<Vec<(usize, usize)>>
+    }
+
+    impl ObjectAllocator for Capped {
+      fn allocate(&self, _bytes: usize) -> bool {
+        true
+      }
+
+      fn resize(&self, old_bytes: usize, new_bytes: usize) -> bool {
+        self.resizes.borrow_mut().push((old_bytes, new_bytes));
+        new_bytes <= 8 * Object::size()
+      }
+    }
+
+    let capped = Rc::new(Capped::default());
+    let mut vm = VM::with_config(VMConfig::new().allocator(capped.clone()).threshold(4));
+    for i in 0..6 {
+      vm.push_int(i).unwrap();
+    }
+    assert!(vm.heap_max == 8);
+
+    vm.push_int(6).unwrap();
+    vm.push_int(7).unwrap();
+    vm.push_int(8).unwrap();
+    assert!(vm.heap_max == 8);
+    let size = Object::size();
+    assert!(capped.resizes.borrow()[..2] == [(4 * size, 8 * size), (8 * size, 16 * size)]);
+
+    vm.stack.clear();
+    assert!(vm.gc_full() == 9);
+    assert!(vm.heap_max == 8 && capped.resizes.borrow().len() == 3);
+  }
+
+  #[test]
+  fn host_driven_collects_only_on_tick() {
+    println!("Host-driven mode collects only when ticked.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(4));
+    for i in 0..15 {
+      vm.push_int(i).unwrap();
+      vm.pop();
+    }
+
+    assert!(vm.objects() == 15);
+    assert!(vm.phase == Phase::Mark);
+
+    let mut ticks = 0;
+    while !vm.tick() {
+      ticks += 1;
+    }
+
+    assert!(ticks > 1);
+    assert!(vm.objects() == 5);
+  }
+
+  #[test]
+  fn host_driven_starvation() {
+    println!("Allocation fails if the host never ticks.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(4));
+    for i in 0..20 {
+      vm.push_int(i).unwrap();
+      vm.pop();
+    }
+
+    assert!(vm.push_int(20).unwrap_err() == VmError::GcStarved);
+
+    while !vm.tick() {}
+    vm.push_int(20).unwrap();
+  }
+
+  #[test]
+  fn allocation_quotas() {
+    println!("Quotas cap total allocation, live or not.");
+
+    let mut vm = VM::with_config(VMConfig::new().object_quota(3));
+    for i in 0..3 {
+      vm.push_int(i).unwrap();
+      vm.pop();
+    }
+    vm.gc();
+
+    assert!(vm.push_int(3).unwrap_err() == VmError::QuotaExceeded);
+    assert!(vm.quota_used() == (3, 3 * Object::size() as u64));
+
+    vm.reset_quota();
+    vm.push_int(3).unwrap();
+
+    let mut vm = VM::with_config(VMConfig::new().byte_quota(2 * Object::size() as u64));
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    assert!(vm.push_pair().unwrap_err() == VmError::QuotaExceeded);
+    assert!(vm.stack.len() == 2);
+  }
+
+  #[test]
+  fn object_ids_are_stable() {
+    println!("Object ids follow allocation order and outlive collections.");
+
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational));
+    let a = vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    vm.pop();
+    let c = vm.push_int(3).unwrap();
+    assert!(vm.object_id(&a) == 0 && vm.object_id(&c) == 2);
+
+    vm.gc_full();
+    let d = vm.push_int(4).unwrap();
+    assert!(vm.object_id(&a) == 0 && vm.object_id(&c) == 2);
+    assert!(vm.object_id(&d) == 3);
+  }
+
+  #[test]
+  fn tags_survive_collection() {
+    println!("User tags are kept through minor and full collections.");
+
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational));
+    let a = vm.push_int(1).unwrap();
+    let b = vm.push_int(2).unwrap();
+    vm.set_tag(&a, 0xfeed);
+    assert!(vm.get_tag(&b).is_none());
+
+    vm.gc_minor();
+    vm.gc_full();
+    assert!(vm.get_tag(&a) == Some(0xfeed));
+  }
+
+  #[test]
+  fn minor_collects_only_the_nursery() {
+    println!("Minor collection leaves old garbage alone.");
+
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational));
+    vm.push_int(1).unwrap();
+    vm.gc_minor();
+    vm.pop();
+
+    vm.push_int(2).unwrap();
+    vm.pop();
+
+    assert!(vm.gc_minor() == 1);
+    assert!(vm.heap.len() == 1);
+
+    assert!(vm.gc_full() == 1);
+    assert!(vm.heap.is_empty());
+  }
+
+  #[test]
+  fn minor_scans_old_to_young_references() {
+    println!("Minor collection keeps young objects referenced from old ones.");
+
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational));
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let a = vm.push_pair().unwrap();
+    vm.gc_minor();
+
+    let x = vm.push_int(3).unwrap();
+    vm.pop();
+    if let Vobject::Pair(_, ref mut tail) = a.1.borrow_mut().val { *tail = x.clone() }
+    vm.write_barrier(&a);
+
+    assert!(vm.gc_minor() == 0);
+    assert!(vm.heap.len() == 4);
+  }
+
+  #[test]
+  fn generational_perftest() {
+    println!("Generational performance test.");
+
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational));
+
+    for i in 0..1000 {
+      for _ in 0..20 {
+        vm.push_int(i).unwrap();
+      }
+
+      for _ in 0..20 {
+        vm.pop();
+      }
+    }
+
+    vm.gc_full();
+
+    assert!(vm.heap.is_empty());
+  }
+}
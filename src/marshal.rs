@@ -0,0 +1,483 @@
+// Converting Rust data to object graphs and back. The VM only has u32 ints
+// and pairs, so everything is encoded in those; the Rust type on the way
+// back says how to read it:
+//
+//   u8, u16, u32, char, bool   an int
+//   i32                        an int holding its bits
+//   u64, i64, usize            a pair of ints, high half first
+//   ()                         the int 0
+//   (A, B), (A, B, C)          pairs, nested to the right for three
+//   Option<T>                  0 for None, (x, 0) for Some(x)
+//   Vec<T>                     a list of pairs ending in 0, not in a cycle
+//
+// For a single object without the encoding, `u32`, `i64` and
+// `(Sobject, Sobject)` also convert to and from `Vobject` with `TryFrom`.
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::num::TryFromIntError;
+
+use error::TypeError;
+use {addr, Sobject, VM, VmError, Vobject};
+
+/// Rust values that can be built on a VM's heap.
+pub trait ToValue {
+  /// Allocates the value and pushes it onto the stack.
+  fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError>;
+}
+
+/// Rust values that can be read back off the heap.
+pub trait FromValue: Sized {
+  fn from_value(obj: &Sobject) -> Result<Self, TypeError>;
+}
+
+impl VM {
+  /// Builds `value` on the heap and pushes it. Its parts stay rooted while
+  /// it is being built.
+  pub fn push_value<T: ToValue + ?Sized>(&mut self, value: &T) -> Result<Sobject, VmError> {
+    value.push_to(self)
+  }
+
+  /// Reads `obj` as a `T`.
+  pub fn extract<T: FromValue>(&self, obj: &Sobject) -> Result<T, TypeError> {
+    self.read_barrier(obj);
+    T::from_value(obj)
+  }
+
+  /// The value of an int, without borrowing through the `RefCell` by hand.
+  pub fn as_int(&self, obj: &Sobject) -> Result<u32, TypeError> {
+    self.read_barrier(obj);
+    int(obj, "int")
+  }
+
+  /// The head and tail of a pair.
+  pub fn as_pair(&self, obj: &Sobject) -> Result<(Sobject, Sobject), TypeError> {
+    self.read_barrier(obj);
+    pair(obj, "pair")
+  }
+
+  // Pairs up the top two stack slots, tail on top. Leaves the stack alone
+  // if the allocation fails.
+  fn push_swapped_pair(&mut self) -> Result<Sobject, VmError> {
+    let n = self.stack.len();
+    self.stack.swap(n - 1, n - 2);
+
+    self.push_pair().inspect_err(|_| self.stack.swap(n - 1, n - 2))
+  }
+}
+
+pub(crate) fn mismatch(val: &Vobject, expected: &'static str) -> TypeError {
+  let found = match *val {
+    Vobject::Int(_) => "int",
+    Vobject::Pair(..) => "pair",
+    Vobject::Native(ref native) => native.type_name()
+  };
+  TypeError { expected, found }
+}
+
+fn int(obj: &Sobject, expected: &'static str) -> Result<u32, TypeError> {
+  if obj.0.get().freed() {
+    return Err(TypeError { expected, found: "freed" });
+  }
+  match obj.1.borrow().val {
+    Vobject::Int(n) => Ok(n),
+    ref val => Err(mismatch(val, expected))
+  }
+}
+
+fn pair(obj: &Sobject, expected: &'static str) -> Result<(Sobject, Sobject), TypeError> {
+  if obj.0.get().freed() {
+    return Err(TypeError { expected, found: "freed" });
+  }
+  match obj.1.borrow().val {
+    Vobject::Pair(ref head, ref tail) => Ok((head.clone(), tail.clone())),
+    ref val => Err(mismatch(val, expected))
+  }
+}
+
+// An int that is the wrong value for `expected`.
+fn bad_int(expected: &'static str) -> TypeError {
+  TypeError { expected, found: "int" }
+}
+
+macro_rules! small_int {
+  ($($t:ty => $name:expr),*) => {$(
+    impl ToValue for $t {
+      fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+        vm.push_int(*self as u32)
+      }
+    }
+
+    impl FromValue for $t {
+      fn from_value(obj: &Sobject) -> Result<$t, TypeError> {
+        let n = int(obj, $name)?;
+        if n > <$t>::MAX as u32 {
+          return Err(bad_int($name));
+        }
+        Ok(n as $t)
+      }
+    }
+  )*}
+}
+
+small_int!(u8 => "u8", u16 => "u16", u32 => "u32");
+
+impl ToValue for i32 {
+  fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+    vm.push_int(*self as u32)
+  }
+}
+
+impl FromValue for i32 {
+  fn from_value(obj: &Sobject) -> Result<i32, TypeError> {
+    int(obj, "i32").map(|n| n as i32)
+  }
+}
+
+impl ToValue for bool {
+  fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+    vm.push_int(*self as u32)
+  }
+}
+
+impl FromValue for bool {
+  fn from_value(obj: &Sobject) -> Result<bool, TypeError> {
+    match int(obj, "bool")? {
+      0 => Ok(false),
+      1 => Ok(true),
+      _ => Err(bad_int("bool"))
+    }
+  }
+}
+
+impl ToValue for char {
+  fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+    vm.push_int(*self as u32)
+  }
+}
+
+impl FromValue for char {
+  fn from_value(obj: &Sobject) -> Result<char, TypeError> {
+    core::char::from_u32(int(obj, "char")?).ok_or(bad_int("char"))
+  }
+}
+
+macro_rules! wide_int {
+  ($($t:ty => $name:expr),*) => {$(
+    impl ToValue for $t {
+      fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+        let bits = *self as u64;
+        (((bits >> 32) as u32), (bits as u32)).push_to(vm)
+      }
+    }
+
+    impl FromValue for $t {
+      fn from_value(obj: &Sobject) -> Result<$t, TypeError> {
+        let (hi, lo) = pair(obj, $name)?;
+        let bits = (u64::from(int(&hi, $name)?) << 32) | u64::from(int(&lo, $name)?);
+        Ok(bits as $t)
+      }
+    }
+  )*}
+}
+
+wide_int!(u64 => "u64", i64 => "i64", usize => "usize");
+
+impl ToValue for () {
+  fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+    vm.push_int(0)
+  }
+}
+
+impl FromValue for () {
+  fn from_value(obj: &Sobject) -> Result<(), TypeError> {
+    match int(obj, "()")? {
+      0 => Ok(()),
+      _ => Err(bad_int("()"))
+    }
+  }
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+  fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+    (**self).push_to(vm)
+  }
+}
+
+impl<A: ToValue, B: ToValue> ToValue for (A, B) {
+  fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+    let n = vm.stack.len();
+    let pushed = self.0.push_to(vm).and_then(|_| self.1.push_to(vm)).and_then(|_| vm.push_pair());
+
+    if pushed.is_err() {
+      vm.stack.truncate(n);
+    }
+    pushed
+  }
+}
+
+impl<A: FromValue, B: FromValue> FromValue for (A, B) {
+  fn from_value(obj: &Sobject) -> Result<(A, B), TypeError> {
+    let (a, b) = pair(obj, "pair")?;
+    Ok((A::from_value(&a)?, B::from_value(&b)?))
+  }
+}
+
+impl<A: ToValue, B: ToValue, C: ToValue> ToValue for (A, B, C) {
+  fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+    (&self.0, (&self.1, &self.2)).push_to(vm)
+  }
+}
+
+impl<A: FromValue, B: FromValue, C: FromValue> FromValue for (A, B, C) {
+  fn from_value(obj: &Sobject) -> Result<(A, B, C), TypeError> {
+    let (a, (b, c)) = <(A, (B, C))>::from_value(obj)?;
+    Ok((a, b, c))
+  }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+  fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+    match *self {
+      None => vm.push_int(0),
+      Some(ref x) => (x, ()).push_to(vm)
+    }
+  }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+  fn from_value(obj: &Sobject) -> Result<Option<T>, TypeError> {
+    if obj.0.get().freed() {
+      return Err(TypeError { expected: "option", found: "freed" });
+    }
+    match obj.1.borrow().val {
+      Vobject::Int(0) => Ok(None),
+      Vobject::Pair(ref x, _) => T::from_value(x).map(Some),
+      ref val => Err(mismatch(val, "option"))
+    }
+  }
+}
+
+impl<T: ToValue> ToValue for [T] {
+  fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+    let n = vm.stack.len();
+
+    // Built back to front, so each new pair's tail is already on the
+    // stack.
+    let mut list = vm.push_int(0);
+    for x in self.iter().rev() {
+      list = list.and_then(|_| x.push_to(vm)).and_then(|_| vm.push_swapped_pair());
+    }
+
+    if list.is_err() {
+      vm.stack.truncate(n);
+    }
+    list
+  }
+}
+
+impl<T: ToValue> ToValue for Vec<T> {
+  fn push_to(&self, vm: &mut VM) -> Result<Sobject, VmError> {
+    self[..].push_to(vm)
+  }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+  fn from_value(obj: &Sobject) -> Result<Vec<T>, TypeError> {
+    let mut items = Vec::new();
+    let mut spine = BTreeSet::new();
+    let mut obj = obj.clone();
+
+    loop {
+      if !spine.insert(addr(&obj)) {
+        return Err(TypeError { expected: "list", found: "cycle" });
+      }
+      if obj.0.get().freed() {
+        return Err(TypeError { expected: "list", found: "freed" });
+      }
+      let next = match obj.1.borrow().val {
+        Vobject::Int(0) => return Ok(items),
+        Vobject::Pair(ref x, ref rest) => {
+          items.push(T::from_value(x)?);
+          rest.clone()
+        }
+        ref val => return Err(mismatch(val, "list"))
+      };
+      obj = next;
+    }
+  }
+}
+
+impl TryFrom<&Vobject> for u32 {
+  type Error = TypeError;
+
+  fn try_from(val: &Vobject) -> Result<u32, TypeError> {
+    match *val {
+      Vobject::Int(n) => Ok(n),
+      _ => Err(mismatch(val, "int"))
+    }
+  }
+}
+
+impl TryFrom<&Vobject> for i64 {
+  type Error = TypeError;
+
+  fn try_from(val: &Vobject) -> Result<i64, TypeError> {
+    u32::try_from(val).map(i64::from)
+  }
+}
+
+impl TryFrom<&Vobject> for (Sobject, Sobject) {
+  type Error = TypeError;
+
+  fn try_from(val: &Vobject) -> Result<(Sobject, Sobject), TypeError> {
+    match *val {
+      Vobject::Pair(ref head, ref tail) => Ok((head.clone(), tail.clone())),
+      _ => Err(mismatch(val, "pair"))
+    }
+  }
+}
+
+impl From<u32> for Vobject {
+  fn from(n: u32) -> Vobject {
+    Vobject::Int(n)
+  }
+}
+
+/// Fails outside the range of a u32.
+impl TryFrom<i64> for Vobject {
+  type Error = TryFromIntError;
+
+  fn try_from(n: i64) -> Result<Vobject, TryFromIntError> {
+    u32::try_from(n).map(Vobject::Int)
+  }
+}
+
+impl From<(Sobject, Sobject)> for Vobject {
+  fn from((head, tail): (Sobject, Sobject)) -> Vobject {
+    Vobject::Pair(head, tail)
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use VMConfig;
+
+  #[test]
+  fn values_round_trip() {
+    println!("Rust values survive a trip through the heap, even under stress.");
+
+    let mut vm = VM::with_config(VMConfig::new().stress(true));
+
+    let nums: Vec<i64> = vec![-1, 0, 1 << 40, i64::MIN];
+    let list = vm.push_value(&nums).unwrap();
+    assert!(vm.stack.len() == 1);
+    assert!(vm.extract::<Vec<i64>>(&list).unwrap() == nums);
+
+    let value = (7u8, Some(vec![(true, 'x')]), (None::<u32>, (), -3i32));
+    let obj = vm.push_value(&value).unwrap();
+    vm.gc();
+    assert!(vm.extract::<(u8, Option<Vec<(bool, char)>>, (Option<u32>, (), i32))>(&obj).unwrap() == value);
+    assert!(vm.stack.len() == 2);
+  }
+
+  #[test]
+  fn wrong_types_are_reported() {
+    println!("Extracting the wrong type names what was expected.");
+
+    let mut vm = VM::new();
+    let n = vm.push_value(&300u32).unwrap();
+    assert!(vm.extract::<u8>(&n).unwrap_err().expected == "u8");
+    assert!(vm.extract::<(u32, u32)>(&n).unwrap_err().expected == "pair");
+
+    let list = vm.push_value(&vec![1u32, 2]).unwrap();
+    assert!(vm.extract::<Vec<bool>>(&list).unwrap_err().expected == "bool");
+    assert!(vm.extract::<Vec<u32>>(&n).unwrap_err().to_string() == "expected list, found int");
+  }
+
+  #[test]
+  fn stale_options_and_lists_fail() {
+    println!("A freed object isn't read as None or an empty list.");
+
+    let mut vm = VM::new();
+    let none = vm.push_value(&None::<u32>).unwrap();
+    let list = vm.push_value(&vec![1u32]).unwrap();
+    vm.truncate_stack(0);
+    vm.gc();
+
+    assert!(vm.extract::<Option<u32>>(&none) == Err(TypeError { expected: "option", found: "freed" }));
+    assert!(vm.extract::<Vec<u32>>(&list) == Err(TypeError { expected: "list", found: "freed" }));
+  }
+
+  #[test]
+  fn cyclic_lists_fail() {
+    println!("A list whose spine loops back is reported rather than read forever.");
+
+    let mut vm = VM::new();
+    let list = vm.push_value(&vec![1u32, 2, 3]).unwrap();
+    let (_, rest) = vm.as_pair(&list).unwrap();
+    vm.set_tail(&rest, &list).unwrap();
+
+    assert!(vm.extract::<Vec<u32>>(&list) == Err(TypeError { expected: "list", found: "cycle" }));
+    assert!(vm.extract::<Vec<u32>>(&rest).unwrap_err().found == "cycle");
+  }
+
+  #[test]
+  fn try_from_objects() {
+    println!("Single objects convert with TryFrom and report what they were.");
+
+    let mut vm = VM::new();
+    let n = vm.push_int(7).unwrap();
+    let p = vm.push_value(&(1u32, 2u32)).unwrap();
+
+    assert!(i64::try_from(&n.1.borrow().val) == Ok(7));
+    assert!(u32::try_from(&p.1.borrow().val) == Err(TypeError { expected: "int", found: "pair" }));
+
+    let (head, tail) = <(Sobject, Sobject)>::try_from(&p.1.borrow().val).unwrap();
+    assert!(vm.extract::<(u32, u32)>(&p).unwrap() == (1, 2));
+    assert!(<(Sobject, Sobject)>::try_from(&n.1.borrow().val).unwrap_err().found == "int");
+
+    // Writing one back needs the write barrier, as any store does.
+    p.1.borrow_mut().val = Vobject::from((tail, head));
+    vm.write_barrier(&p);
+    assert!(vm.extract::<(u32, u32)>(&p).unwrap() == (2, 1));
+
+    assert!(Vobject::try_from(-1i64).is_err());
+    assert!(matches!(Vobject::try_from(5i64), Ok(Vobject::Int(5))));
+    assert!(matches!(Vobject::from(3u32), Vobject::Int(3)));
+  }
+
+  #[test]
+  fn typed_accessors() {
+    println!("as_int and as_pair read objects or say what they were.");
+
+    let mut vm = VM::new();
+    let n = vm.push_int(7).unwrap();
+    let p = vm.push_value(&(1u32, 2u32)).unwrap();
+
+    assert!(vm.as_int(&n) == Ok(7));
+    assert!(vm.as_int(&p).unwrap_err().found == "pair");
+
+    let (head, tail) = vm.as_pair(&p).unwrap();
+    assert!(vm.as_int(&head) == Ok(1) && vm.as_int(&tail) == Ok(2));
+    assert!(vm.as_pair(&n).unwrap_err() == TypeError { expected: "pair", found: "int" });
+  }
+
+  #[test]
+  fn failed_pushes_leave_the_stack_alone() {
+    println!("A value that doesn't fit the heap is not half-pushed.");
+
+    let mut vm = VM::with_config(VMConfig::new().max_heap(5));
+    vm.push_int(1).unwrap();
+
+    assert!(vm.push_value(&vec![1u32, 2, 3]).unwrap_err() == VmError::OutOfMemory);
+    assert!(vm.stack.len() == 1);
+  }
+}
@@ -0,0 +1,133 @@
+// Host data attached to objects. `VM::metadata::<T>()` is a side table of
+// `T`s keyed by object id, one per type, so an embedder can hang its own
+// bookkeeping on values without a field in every object. Entries hold no
+// reference to their objects and go when the objects are freed, as debug
+// labels do.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use core::any::{Any, TypeId};
+use core::fmt;
+
+use {Sobject, VM};
+
+/// The `T`s attached to objects, as returned by `VM::metadata`.
+#[derive(Debug)]
+pub struct Metadata<T> {
+  entries: BTreeMap<u64, T>
+}
+
+impl<T> Metadata<T> {
+  /// Attaches `val` to `obj`, returning what it had before. Panics if
+  /// `obj` was freed.
+  pub fn insert(&mut self, obj: &Sobject, val: T) -> Option<T> {
+    assert!(!obj.0.get().freed(), "freed objects can't be given metadata");
+    self.entries.insert(obj.2, val)
+  }
+
+  pub fn get(&self, obj: &Sobject) -> Option<&T> {
+    self.entries.get(&obj.2)
+  }
+
+  pub fn get_mut(&mut self, obj: &Sobject) -> Option<&mut T> {
+    self.entries.get_mut(&obj.2)
+  }
+
+  pub fn remove(&mut self, obj: &Sobject) -> Option<T> {
+    self.entries.remove(&obj.2)
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.entries.is_empty()
+  }
+}
+
+// A `Metadata<T>` with its type forgotten, so the VM can keep one per
+// type and clear an object out of all of them.
+trait Table {
+  fn forget(&mut self, id: u64);
+  fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> Table for Metadata<T> {
+  fn forget(&mut self, id: u64) {
+    self.entries.remove(&id);
+  }
+
+  fn as_any_mut(&mut self) -> &mut dyn Any {
+    self
+  }
+}
+
+// Every table the VM has, by type.
+#[derive(Default)]
+pub(crate) struct Tables(BTreeMap<TypeId, Box<dyn Table>>);
+
+impl fmt::Debug for Tables {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Tables({})", self.0.len())
+  }
+}
+
+impl VM {
+  /// The table of `T`s attached to objects, made empty the first time it
+  /// is asked for.
+  pub fn metadata<T: 'static>(&mut self) -> &mut Metadata<T> {
+    self.metadata.0.entry(TypeId::of::<T>())
+      .or_insert_with(|| Box::new(Metadata::<T> { entries: BTreeMap::new() }))
+      .as_any_mut()
+      .downcast_mut()
+      .unwrap()
+  }
+
+  // Drops whatever was attached to `obj`, which is being freed.
+  #[inline]
+  pub(crate) fn forget_metadata(&mut self, obj: &Sobject) {
+    for table in self.metadata.0.values_mut() {
+      table.forget(obj.2);
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use alloc::string::{String, ToString};
+  use {GcStrategy, VMConfig};
+
+  #[test]
+  fn metadata_goes_with_its_objects() {
+    println!("Attached values stay while their object lives and are dropped when it's freed.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy));
+      let kept = vm.push_int(1).unwrap();
+      let freed = vm.push_int(2).unwrap();
+      let owned = Rc::new(());
+      vm.metadata::<String>().insert(&kept, "kept".to_string());
+      vm.metadata::<String>().insert(&freed, "freed".to_string());
+      vm.metadata::<Rc<()>>().insert(&freed, owned.clone());
+      vm.pop();
+
+      vm.gc_minor();
+      vm.gc_full();
+      assert!(vm.metadata::<String>().get(&kept).map(String::as_str) == Some("kept"));
+      assert!(vm.metadata::<String>().get(&freed).is_none() && vm.metadata::<String>().len() == 1);
+      assert!(vm.metadata::<Rc<()>>().is_empty() && Rc::strong_count(&owned) == 1);
+
+      *vm.metadata::<String>().get_mut(&kept).unwrap() += "!";
+      assert!(vm.metadata::<String>().remove(&kept) == Some("kept!".to_string()));
+      assert!(vm.metadata::<u32>().is_empty());
+    }
+  }
+}
@@ -0,0 +1,136 @@
+// Collector metrics in the Prometheus text exposition format, plus a tiny
+// HTTP responder for hosts that don't already run a web server. VMs can't
+// move between threads, so rather than spawning a server the host calls
+// `serve_metrics` from its own loop.
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use stats::PAUSE_BUCKETS;
+use time::Duration;
+use VM;
+
+impl VM {
+  /// Heap size, collection counts and the pause histogram, in the
+  /// Prometheus text format.
+  pub fn metrics_text(&self) -> String {
+    let stats = &self.stats;
+    let mut out = String::new();
+
+    let _ = writeln!(out, "# HELP babygc_heap_bytes Bytes held by heap objects, live or not yet collected.");
+    let _ = writeln!(out, "# TYPE babygc_heap_bytes gauge");
+    let _ = writeln!(out, "babygc_heap_bytes {}", self.bytes_allocated());
+
+    #[cfg(feature = "process-memory")]
+    {
+      if let Some(resident) = self.process_memory().resident {
+        let _ = writeln!(out, "# HELP babygc_process_resident_bytes Bytes of the whole process in RAM.");
+        let _ = writeln!(out, "# TYPE babygc_process_resident_bytes gauge");
+        let _ = writeln!(out, "babygc_process_resident_bytes {}", resident);
+      }
+    }
+
+    let _ = writeln!(out, "# HELP babygc_objects Objects on the heap, live or not yet collected.");
+    let _ = writeln!(out, "# TYPE babygc_objects gauge");
+    let _ = writeln!(out, "babygc_objects {}", self.objects());
+
+    let _ = writeln!(out, "# HELP babygc_gc_pauses_total Collector pauses, counting incremental slices.");
+    let _ = writeln!(out, "# TYPE babygc_gc_pauses_total counter");
+    let _ = writeln!(out, "babygc_gc_pauses_total {}", stats.pauses);
+
+    let _ = writeln!(out, "# HELP babygc_gc_pause_seconds Collector pause durations.");
+    let _ = writeln!(out, "# TYPE babygc_gc_pause_seconds histogram");
+    let mut count = 0;
+    for (i, max) in PAUSE_BUCKETS.iter().enumerate() {
+      count += stats.pause_histogram[i];
+      let _ = writeln!(out, "babygc_gc_pause_seconds_bucket{{le=\"{}\"}} {}", max.as_secs_f64(), count);
+    }
+    count += stats.pause_histogram[PAUSE_BUCKETS.len()];
+    let _ = writeln!(out, "babygc_gc_pause_seconds_bucket{{le=\"+Inf\"}} {}", count);
+    let _ = writeln!(out, "babygc_gc_pause_seconds_sum {}", stats.total_pause.as_secs_f64());
+    let _ = writeln!(out, "babygc_gc_pause_seconds_count {}", count);
+
+    out
+  }
+
+  /// Answers every connection waiting on `listener` with `metrics_text`,
+  /// whatever was asked for, and returns how many there were. Never
+  /// blocks waiting for new connections: the listener is switched to
+  /// non-blocking mode.
+  pub fn serve_metrics(&self, listener: &TcpListener) -> io::Result<usize> {
+    listener.set_nonblocking(true)?;
+    let mut served = 0;
+
+    loop {
+      match listener.accept() {
+        Ok((stream, _)) => {
+          self.respond(stream)?;
+          served += 1;
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(served),
+        Err(e) => return Err(e)
+      }
+    }
+  }
+
+  fn respond(&self, mut stream: TcpStream) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+    // Read (and ignore) the request head so the client isn't reset.
+    let mut head = Vec::new();
+    let mut buf = [0; 512];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < 8192 {
+      match stream.read(&mut buf) {
+        Ok(0) | Err(_) => break,
+        Ok(n) => head.extend_from_slice(&buf[..n])
+      }
+    }
+
+    let body = self.metrics_text();
+    write!(stream, "HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+           body.len(), body)
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use Object;
+
+  #[test]
+  fn metrics_over_http() {
+    println!("Metrics are served in the Prometheus text format.");
+
+    let mut vm = VM::new();
+    for i in 0..30 {
+      vm.push_int(i).unwrap();
+      vm.pop();
+    }
+    vm.push_int(1).unwrap();
+    vm.gc();
+
+    let text = vm.metrics_text();
+    assert!(text.contains("\nbabygc_objects 1\n"));
+    assert!(text.contains(&format!("\nbabygc_heap_bytes {}\n", Object::size())));
+    assert!(text.contains(&format!("babygc_gc_pause_seconds_bucket{{le=\"+Inf\"}} {}\n", vm.stats().pauses)));
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    assert!(vm.serve_metrics(&listener).unwrap() == 0);
+
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").unwrap();
+    assert!(vm.serve_metrics(&listener).unwrap() == 1);
+
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+    assert!(response.ends_with(&text));
+  }
+}
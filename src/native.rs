@@ -0,0 +1,364 @@
+// Objects defined by native extensions: a compiled regex, an open file, a
+// matrix. A `NativeObject` lives in a `Vobject::Native` like any other
+// value and is dropped when the sweep frees it. Any objects it holds are
+// reported through `Trace`, which the collector calls by dynamic dispatch
+// when it reaches the native object, so what it refers to stays alive.
+// Values can hold objects in `GcCell`s, which see to the write barrier
+// themselves, and otherwise only change what they hold through
+// `with_native_mut`.
+
+use alloc::boxed::Box;
+use alloc::vec::{self, Vec};
+use core::any::{self, Any};
+
+use gc_cell::Binding;
+use marshal::mismatch;
+use {Object, Sobject, TypeError, VM, VmError, Vobject};
+
+/// Reports the objects a native value holds.
+pub trait Trace {
+  /// Visits each object held, and traces each `GcCell`. Holding an object
+  /// that isn't visited leaves it to be collected while still in use.
+  fn trace(&self, visitor: &mut Visitor<'_>);
+}
+
+impl Trace for Sobject {
+  fn trace(&self, visitor: &mut Visitor<'_>) {
+    visitor.visit(self);
+  }
+}
+
+impl<T: Trace> Trace for Option<T> {
+  fn trace(&self, visitor: &mut Visitor<'_>) {
+    if let Some(ref x) = *self {
+      x.trace(visitor);
+    }
+  }
+}
+
+impl<T: Trace> Trace for Vec<T> {
+  fn trace(&self, visitor: &mut Visitor<'_>) {
+    for x in self {
+      x.trace(visitor);
+    }
+  }
+}
+
+/// Where `Trace::trace` reports what a value holds.
+pub struct Visitor<'a> {
+  visit: &'a mut dyn FnMut(&Sobject),
+  // The object being traced, when its `GcCell`s are to be bound to it.
+  binding: Option<&'a Binding>
+}
+
+impl<'a> Visitor<'a> {
+  pub fn new(visit: &'a mut dyn FnMut(&Sobject)) -> Visitor<'a> {
+    Visitor { visit, binding: None }
+  }
+
+  pub fn visit(&mut self, obj: &Sobject) {
+    (self.visit)(obj)
+  }
+
+  pub(crate) fn binding(&self) -> Option<&Binding> {
+    self.binding
+  }
+}
+
+/// A value defined outside the VM, stored in a `Vobject::Native`.
+pub trait NativeObject: Any + Trace {
+  /// What dumps and printing call the value.
+  fn type_name(&self) -> &'static str {
+    any::type_name::<Self>()
+  }
+
+  /// A copy for `deep_clone`, `Transfer::copy` and checkpoints, holding
+  /// `translate(obj)` in place of each `obj` this one holds. By default
+  /// native values can't be copied: copying a graph containing one fails,
+  /// and rolling back leaves it as it is, or fails if it was freed since.
+  fn clone_native(&self, _translate: &mut dyn FnMut(&Sobject) -> Sobject) -> Option<Box<dyn NativeObject>> {
+    None
+  }
+}
+
+impl dyn NativeObject {
+  pub fn downcast_ref<T: NativeObject>(&self) -> Option<&T> {
+    (self as &dyn Any).downcast_ref()
+  }
+
+  pub fn downcast_mut<T: NativeObject>(&mut self) -> Option<&mut T> {
+    (self as &mut dyn Any).downcast_mut()
+  }
+}
+
+/// What a value points to, as returned by `Vobject::children`: a pair's
+/// head then tail, or what a native object traces, in the order it does.
+#[derive(Debug, Default)]
+pub struct Children {
+  head: Option<Sobject>,
+  tail: Option<Sobject>,
+  rest: vec::IntoIter<Sobject>
+}
+
+impl Iterator for Children {
+  type Item = Sobject;
+
+  fn next(&mut self) -> Option<Sobject> {
+    self.head.take().or_else(|| self.tail.take()).or_else(|| self.rest.next())
+  }
+}
+
+impl Vobject {
+  // A copy of the value, holding `translate(obj)` in place of each `obj`,
+  // unless it is a native value that can't be copied.
+  pub(crate) fn copy_with(&self, translate: &mut dyn FnMut(&Sobject) -> Sobject) -> Option<Vobject> {
+    match *self {
+      Vobject::Int(n) => Some(Vobject::Int(n)),
+      Vobject::Pair(ref head, ref tail) => Some(Vobject::Pair(translate(head), translate(tail))),
+      Vobject::Native(ref native) => native.clone_native(translate).map(Vobject::Native)
+    }
+  }
+
+  pub fn children(&self) -> Children {
+    match *self {
+      Vobject::Int(_) => Children::default(),
+      Vobject::Pair(ref head, ref tail) =>
+        Children { head: Some(head.clone()), tail: Some(tail.clone()), rest: Vec::new().into_iter() },
+      Vobject::Native(ref native) => {
+        let mut rest = Vec::new();
+        native.trace(&mut Visitor::new(&mut |child| rest.push(child.clone())));
+        Children { head: None, tail: None, rest: rest.into_iter() }
+      }
+    }
+  }
+}
+
+impl VM {
+  // Binds the `GcCell`s in `obj`'s native value, if it has one, to `obj`.
+  pub(crate) fn bind_cells(&self, obj: &Sobject) {
+    if let Vobject::Native(ref native) = obj.1.borrow().val {
+      let binding = Binding::new(obj, &self.cell_writes);
+      native.trace(&mut Visitor { visit: &mut |_| {}, binding: Some(&binding) });
+    }
+  }
+
+  /// Pushes `native` as a new object.
+  pub fn push_native<T: NativeObject>(&mut self, native: T) -> Result<Sobject, VmError> {
+    // Roots what it holds across the allocation, and shades it mid-mark,
+    // as for a pair.
+    let obj = Object::new(self, Vobject::Native(Box::new(native)))?;
+    self.stack.push(obj.clone());
+    self.after("push_native");
+    Ok(obj)
+  }
+
+  /// Calls `f` on `obj`'s native value, if it is a `T`.
+  pub fn with_native<T: NativeObject, R>(&self, obj: &Sobject, f: impl FnOnce(&T) -> R) -> Result<R, TypeError> {
+    let expected = any::type_name::<T>();
+    self.read_barrier(obj);
+    if obj.0.get().freed() {
+      return Err(TypeError { expected, found: "freed" });
+    }
+    let o = obj.1.borrow();
+    match o.val {
+      Vobject::Native(ref native) => native.downcast_ref().map(f).ok_or_else(|| mismatch(&o.val, expected)),
+      ref val => Err(mismatch(val, expected))
+    }
+  }
+
+  /// Calls `f` on `obj`'s native value, if it is a `T`, and then runs the
+  /// write barrier, so it can change what the value holds. Fails with
+  /// `VmError::Frozen` for a frozen object.
+  pub fn with_native_mut<T: NativeObject, R>(&mut self, obj: &Sobject, f: impl FnOnce(&mut T) -> R)
+                                             -> Result<R, VmError> {
+    let expected = any::type_name::<T>();
+    if self.is_freed(obj) {
+      return Err(VmError::Freed);
+    }
+    if self.is_frozen(obj) {
+      return Err(VmError::Frozen);
+    }
+    let result = {
+      let mut o = obj.1.borrow_mut();
+      match o.val {
+        Vobject::Native(ref mut native) => native.downcast_mut().map(f),
+        _ => None
+      }
+    };
+    match result {
+      Some(result) => {
+        self.bind_cells(obj);
+        self.write_barrier(obj);
+        Ok(result)
+      }
+      None => Err(VmError::Type(mismatch(&obj.1.borrow().val, expected)))
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use alloc::vec::Vec;
+  use core::cell::Cell;
+  use {GcStrategy, VMConfig};
+
+  struct Matrix {
+    cells: Vec<Sobject>,
+    drops: Rc<Cell<usize>>
+  }
+
+  impl Trace for Matrix {
+    fn trace(&self, visitor: &mut Visitor<'_>) {
+      self.cells.trace(visitor);
+    }
+  }
+
+  impl NativeObject for Matrix {}
+
+  impl Drop for Matrix {
+    fn drop(&mut self) {
+      self.drops.set(self.drops.get() + 1);
+    }
+  }
+
+  #[test]
+  fn natives_hold_their_children_while_allocated() {
+    println!("What a new native holds survives the collection its allocation sets off.");
+
+    let mut vm = VM::with_config(VMConfig::new().stress(true));
+    let drops = Rc::new(Cell::new(0));
+    let cells = vec![vm.push_int(1).unwrap()];
+    vm.pop();
+    let m = vm.push_native(Matrix { cells, drops }).unwrap();
+
+    vm.verify().unwrap();
+    assert!(vm.with_native(&m, |m: &Matrix| vm.as_int(&m.cells[0])) == Ok(Ok(1)));
+  }
+
+  #[test]
+  fn natives_trace_and_drop() {
+    println!("Native objects keep what they hold and are dropped when swept.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy));
+      let drops = Rc::new(Cell::new(0));
+      let cells = vec![vm.push_int(1).unwrap(), vm.push_int(2).unwrap()];
+      vm.truncate_stack(0);
+      let m = vm.push_native(Matrix { cells, drops: drops.clone() }).unwrap();
+
+      vm.gc_full();
+      vm.verify().unwrap();
+      assert!(vm.objects() == 3 && drops.get() == 0);
+      let sum = vm.with_native(&m, |m: &Matrix| m.cells.iter().map(|c| vm.as_int(c).unwrap()).sum::<u32>());
+      assert!(sum == Ok(3));
+      assert!(vm.with_native(&m, |_: &Matrix| ()).is_ok());
+
+      vm.pop();
+      vm.gc_full();
+      assert!(vm.objects() == 0 && drops.get() == 1);
+    }
+  }
+
+  #[test]
+  fn natives_mid_cycle_shade_what_they_hold() {
+    println!("A native object made while marking keeps what it holds.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(1).threshold(8));
+    let p = vm.push_value(&(1u32, 2u32)).unwrap();
+    while !vm.collecting() {
+      vm.push_int(0).unwrap();
+      vm.pop();
+    }
+
+    // 1 is held only by the still-gray pair until the store, and by the
+    // native object after it.
+    let (one, _) = vm.as_pair(&p).unwrap();
+    let three = vm.push_int(3).unwrap();
+    vm.pop();
+    vm.set_head(&p, &three).unwrap();
+    vm.push_native(Matrix { cells: vec![one], drops: Rc::new(Cell::new(0)) }).unwrap();
+    drop(three);
+
+    while !vm.tick() {}
+    vm.verify().unwrap();
+    let one = vm.with_native(vm.stack.last().unwrap(), |m: &Matrix| m.cells[0].clone()).unwrap();
+    assert!(vm.as_int(&one) == Ok(1));
+  }
+
+  #[test]
+  fn natives_are_typed() {
+    println!("Natives can only be read as the type they are.");
+
+    struct Other;
+    impl Trace for Other {
+      fn trace(&self, _: &mut Visitor<'_>) {}
+    }
+    impl NativeObject for Other {}
+
+    let mut vm = VM::new();
+    let other = vm.push_native(Other).unwrap();
+    let int = vm.push_int(1).unwrap();
+    let matrix = any::type_name::<Matrix>();
+    let found = any::type_name::<Other>();
+    assert!(vm.with_native(&other, |_: &Matrix| ()) == Err(TypeError { expected: matrix, found }));
+    assert!(vm.with_native(&int, |_: &Other| ()) == Err(TypeError { expected: found, found: "int" }));
+    assert!(vm.as_int(&other) == Err(TypeError { expected: "int", found }));
+    assert!(vm.set_head(&other, 2) == Err(VmError::Type(TypeError { expected: "pair", found })));
+    assert!(vm.deep_clone(&other).err() == Some(VmError::Type(TypeError { expected: "copyable value", found })));
+  }
+
+  #[test]
+  fn natives_copy_when_they_can() {
+    println!("A native value that can be copied is copied with what it holds.");
+
+    struct Boxed(Sobject);
+    impl Trace for Boxed {
+      fn trace(&self, visitor: &mut Visitor<'_>) {
+        visitor.visit(&self.0);
+      }
+    }
+    impl NativeObject for Boxed {
+      fn clone_native(&self, translate: &mut dyn FnMut(&Sobject) -> Sobject) -> Option<Box<dyn NativeObject>> {
+        Some(Box::new(Boxed(translate(&self.0))))
+      }
+    }
+
+    let mut vm = VM::new();
+    let one = vm.push_int(1).unwrap();
+    vm.pop();
+    let b = vm.push_native(Boxed(one.clone())).unwrap();
+    let copy = vm.deep_clone(&b).unwrap();
+    let held = vm.with_native(&copy, |b: &Boxed| b.0.clone()).unwrap();
+    assert!(!Rc::ptr_eq(&held, &one) && vm.as_int(&held) == Ok(1));
+    assert!(vm.objects() == 4);
+  }
+
+  #[test]
+  fn natives_can_change_what_they_hold() {
+    println!("An old native value given a young object keeps it through minor collections.");
+
+    let mut vm = VM::with_config(VMConfig::new().strategy(GcStrategy::Generational));
+    let drops = Rc::new(Cell::new(0));
+    let m = vm.push_native(Matrix { cells: Vec::new(), drops }).unwrap();
+    vm.gc_full();
+    assert!(m.0.get().old());
+
+    let young = vm.push_int(7).unwrap();
+    vm.pop();
+    vm.with_native_mut(&m, |m: &mut Matrix| m.cells.push(young)).unwrap();
+    vm.gc();
+    vm.verify().unwrap();
+    let cell = vm.with_native(&m, |m: &Matrix| m.cells[0].clone()).unwrap();
+    assert!(vm.as_int(&cell) == Ok(7));
+
+    vm.freeze(&m);
+    assert!(vm.with_native_mut(&m, |m: &mut Matrix| m.cells.clear()) == Err(VmError::Frozen));
+  }
+}
@@ -0,0 +1,86 @@
+// What can be stored into a pair with `set_head` and `set_tail`: an object
+// the host already has a handle to, or a Rust int, which is allocated on
+// the spot.
+
+use {Object, Sobject, VM, VmError, Vobject};
+
+/// A value `VM::set_head` and `VM::set_tail` can store.
+pub trait Operand {
+  /// The object to store, allocating it if need be.
+  fn into_object(self, vm: &mut VM) -> Result<Sobject, VmError>;
+}
+
+impl Operand for &Sobject {
+  fn into_object(self, _vm: &mut VM) -> Result<Sobject, VmError> {
+    Ok(self.clone())
+  }
+}
+
+impl Operand for Sobject {
+  fn into_object(self, _vm: &mut VM) -> Result<Sobject, VmError> {
+    Ok(self)
+  }
+}
+
+impl Operand for u32 {
+  fn into_object(self, vm: &mut VM) -> Result<Sobject, VmError> {
+    Object::new(vm, Vobject::Int(self))
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use {GcStrategy, TypeError, VMConfig};
+
+  #[test]
+  fn ints_are_stored_by_value() {
+    println!("Storing a Rust int allocates it and keeps it alive through the pair.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy));
+      let p = vm.push_value(&(1u32, 2u32)).unwrap();
+      vm.gc_full();
+      vm.set_head(&p, 7).unwrap();
+      vm.set_tail(&p, p.clone()).unwrap();
+      vm.gc();
+      vm.gc_full();
+      vm.verify().unwrap();
+
+      let (head, tail) = vm.as_pair(&p).unwrap();
+      assert!(vm.as_int(&head) == Ok(7) && Rc::ptr_eq(&tail, &p));
+      assert!(vm.objects() == 2);
+    }
+  }
+
+  #[test]
+  fn bad_targets_allocate_nothing() {
+    println!("Storing into an int or a frozen pair fails before allocating.");
+
+    let mut vm = VM::new();
+    let one = vm.push_int(1).unwrap();
+    assert!(vm.set_head(&one, 2) == Err(VmError::Type(TypeError { expected: "pair", found: "int" })));
+    let p = vm.push_value(&(1u32, 2u32)).unwrap();
+    vm.freeze(&p);
+    assert!(vm.set_tail(&p, 3) == Err(VmError::Frozen));
+    assert!(vm.objects() == 4);
+  }
+
+  #[test]
+  fn the_pair_stays_rooted_while_the_int_is_allocated() {
+    println!("A pair only the host holds survives the collection its new int triggers.");
+
+    let mut vm = VM::with_config(VMConfig::new().stress(true));
+    let p = vm.push_value(&(1u32, 2u32)).unwrap();
+    vm.pop();
+    vm.set_tail(&p, 3).unwrap();
+    assert!(!vm.is_freed(&p));
+    assert!(vm.extract::<(u32, u32)>(&p) == Ok((1, 3)));
+  }
+}
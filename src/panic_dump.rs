@@ -0,0 +1,136 @@
+// Post-mortems for panics. A panic hook runs on whatever thread panicked
+// and can't reach the VM, which isn't `Send`, so after
+// `VM::install_panic_dump(path)` the VM keeps a report up to date for it
+// instead: its counters and heap size (`VM::counters_json`) and a line for
+// each of its last `PANIC_EVENTS` collections, refreshed as each one
+// ends. On a panic the hook writes
+//
+//   {"panic": "index out of bounds ...", "location": "src/main.rs:10:5",
+//    "counters": {...},
+//    "events": [{"kind": "full", "freed": 12, "live": 30, "threshold": 20}, ...]}
+//
+// to `path` and then runs the hook that was there before. Writing is best
+// effort: a report another thread holds locked, or a file that can't be
+// written, is skipped. Once the VM is gone its hook does nothing.
+
+use std::boxed::Box;
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::fs;
+use std::panic::{self, PanicHookInfo};
+use std::path::Path;
+use std::string::String;
+use std::sync::{Arc, Mutex};
+
+use VM;
+
+/// Collections `VM::install_panic_dump` keeps a line for.
+pub const PANIC_EVENTS: usize = 32;
+
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+  counters: String,
+  events: VecDeque<String>
+}
+
+impl VM {
+  /// Registers a panic hook that writes a report on this VM to `path`;
+  /// see the module source.
+  pub fn install_panic_dump<P: AsRef<Path>>(&mut self, path: P) {
+    let report = Arc::new(Mutex::new(Report { counters: self.counters_json(), events: VecDeque::new() }));
+    let weak = Arc::downgrade(&report);
+    self.panic_dump = Some(report);
+
+    let path = path.as_ref().to_path_buf();
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+      if let Some(report) = weak.upgrade() {
+        if let Ok(report) = report.try_lock() {
+          write_report(&path, &report, info);
+        }
+      }
+      previous(info);
+    }));
+  }
+
+  // Brings the report up to date after a collection of `kind`.
+  pub(crate) fn update_panic_dump(&self, kind: &'static str, freed: usize, threshold: usize) {
+    let report = match self.panic_dump {
+      Some(ref report) => report,
+      None => return
+    };
+    let counters = self.counters_json();
+    let event = format!("{{\"kind\": \"{}\", \"freed\": {}, \"live\": {}, \"threshold\": {}}}",
+                        kind, freed, self.objects(), threshold);
+
+    if let Ok(mut report) = report.lock() {
+      report.counters = counters;
+      if report.events.len() == PANIC_EVENTS {
+        report.events.pop_front();
+      }
+      report.events.push_back(event);
+    }
+  }
+}
+
+fn write_report(path: &Path, report: &Report, info: &PanicHookInfo) {
+  let payload = info.payload();
+  let message = payload.downcast_ref::<&str>().copied()
+    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+    .unwrap_or("");
+  let location = info.location().map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column())).unwrap_or_default();
+
+  let mut out = String::new();
+  let _ = write!(out, "{{\"panic\": {:?}, \"location\": {:?},\n \"counters\": {},\n \"events\": [",
+                 message, location, report.counters);
+  for (i, event) in report.events.iter().enumerate() {
+    if i > 0 {
+      out.push_str(",\n            ");
+    }
+    out.push_str(event);
+  }
+  out.push_str("]}\n");
+  let _ = fs::write(path, out);
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  extern crate serde_json;
+
+  use self::serde_json::Value;
+  use super::*;
+  use std::env;
+  use std::process;
+
+  #[test]
+  fn panics_leave_a_report() {
+    println!("A panic writes the VM's counters and last collections to disk.");
+
+    let path = env::temp_dir().join(format!("babygc-panic-{}.json", process::id()));
+    let _ = fs::remove_file(&path);
+    let mut vm = VM::new();
+    vm.install_panic_dump(&path);
+    for i in 0..PANIC_EVENTS as u32 + 5 {
+      vm.push_int(i).unwrap();
+      vm.gc();
+    }
+
+    assert!(panic::catch_unwind(|| panic!("boom {}", 7)).is_err());
+    let doc: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert!(doc["panic"] == "boom 7" && doc["location"].as_str().unwrap().starts_with("src/panic_dump.rs:"));
+    assert!(doc["counters"]["collections"] == PANIC_EVENTS as u64 + 5);
+    let events = doc["events"].as_array().unwrap();
+    assert!(events.len() == PANIC_EVENTS && events[PANIC_EVENTS - 1]["live"] == PANIC_EVENTS as u64 + 5);
+
+    // A dropped VM's hook stays quiet.
+    fs::remove_file(&path).unwrap();
+    drop(vm);
+    assert!(panic::catch_unwind(|| panic!("again")).is_err());
+    assert!(!path.exists());
+  }
+}
@@ -0,0 +1,174 @@
+// Reusing VMs, for servers that give each request a fresh one. `reset`
+// frees every object, as dropping the VM would, and puts the VM back as
+// `with_config` made it, but keeps the buffers its lists grew, so the
+// next request doesn't grow them again. `VmPool` keeps reset VMs for
+// checking out again. VMs can't leave their thread, so each thread has
+// its own pool.
+//
+// A reset keeps what the host set up around the VM: its config, a
+// registry name, signal and panic dumps. Persistent handles and root
+// providers from before are gone, and handle numbers are never handed
+// out again.
+
+use alloc::vec::Vec;
+use core::mem;
+
+use {Sobject, VMConfig, VM};
+
+impl VM {
+  /// Frees every object and starts over with the same config, ending any
+  /// collection or region under way. Handles the host kept are stale
+  /// afterwards, as after dropping the VM, and the stats start again.
+  pub fn reset(&mut self) {
+    let objects: Vec<Sobject> = self.iter_objects().cloned().collect();
+    for obj in &objects {
+      self.free(obj);
+    }
+
+    let fresh = VM::with_config(self.config.clone());
+    let mut old = mem::replace(self, fresh);
+    old.sweeping = Vec::new().into_iter();
+
+    reuse(&mut self.stack, &mut old.stack);
+    reuse(&mut self.temp_roots, &mut old.temp_roots);
+    reuse(&mut self.heap, &mut old.heap);
+    reuse(&mut self.nursery, &mut old.nursery);
+    for (gen, old_gen) in self.middle.iter_mut().zip(&mut old.middle) {
+      reuse(gen, old_gen);
+    }
+    reuse(&mut self.gray, &mut old.gray);
+    reuse(&mut self.cards, &mut old.cards);
+    reuse(&mut self.unswept_writes, &mut old.unswept_writes);
+    reuse(&mut self.region, &mut old.region);
+    reuse(&mut self.region_writes, &mut old.region_writes);
+
+    self.next_handle = old.next_handle;
+    #[cfg(all(feature = "signal-dump", unix))]
+    {
+      self.signal_dump = old.signal_dump.take();
+    }
+    #[cfg(feature = "std")]
+    {
+      self.registered = old.registered.take();
+      self.panic_dump = old.panic_dump.take();
+    }
+    self.after("reset");
+  }
+}
+
+// Empties `old` and keeps it in place of `fresh` if it has more room.
+fn reuse<T>(fresh: &mut Vec<T>, old: &mut Vec<T>) {
+  old.clear();
+  if old.capacity() > fresh.capacity() {
+    mem::swap(fresh, old);
+  }
+}
+
+/// VMs kept between requests: `checkout` hands one out, reset and ready,
+/// and `checkin` takes it back, keeping at most `max_idle` of them.
+#[derive(Debug)]
+pub struct VmPool {
+  config: VMConfig,
+  idle: Vec<VM>,
+  max_idle: usize
+}
+
+impl VmPool {
+  /// A pool making VMs with `config` as it runs out.
+  pub fn new(config: VMConfig, max_idle: usize) -> VmPool {
+    VmPool { config, idle: Vec::new(), max_idle }
+  }
+
+  /// An idle VM, or a new one if there are none.
+  pub fn checkout(&mut self) -> VM {
+    self.idle.pop().unwrap_or_else(|| VM::with_config(self.config.clone()))
+  }
+
+  /// Resets `vm` and keeps it for the next `checkout`, unless the pool is
+  /// full, when it is dropped.
+  pub fn checkin(&mut self, mut vm: VM) {
+    if self.idle.len() < self.max_idle {
+      vm.reset();
+      self.idle.push(vm);
+    }
+  }
+
+  /// VMs waiting to be checked out.
+  pub fn idle(&self) -> usize {
+    self.idle.len()
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use {GcStrategy, Vobject};
+
+  #[test]
+  fn reset_starts_over_in_the_same_room() {
+    println!("A reset VM is empty, configured as before, and keeps its lists' room.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).threshold(50));
+      let vals: Vec<u32> = (0..200).collect();
+      vm.push_ints(&vals).unwrap();
+      vm.push_pairs(50).unwrap();
+      let p = vm.stack[0].clone();
+      if let Vobject::Pair(_, ref mut tail) = p.1.borrow_mut().val { *tail = p.clone() }
+      vm.write_barrier(&p);
+      let handle = vm.persist(&p);
+      vm.gc();
+      let capacity = vm.heap_capacity();
+
+      vm.reset();
+      assert!(vm.is_freed(&p) && vm.objects() == 0 && vm.stack_len() == 0);
+      assert!(vm.stats().pauses == 0 && vm.threshold() == 50 && vm.strategy_name() == strategy.name());
+      assert!(vm.heap_capacity() >= capacity);
+      vm.verify().unwrap();
+
+      let one = vm.push_int(1).unwrap();
+      assert!(one.2 == 0 && vm.persist(&one) != handle);
+      vm.gc();
+      vm.verify().unwrap();
+    }
+  }
+
+  #[test]
+  fn reset_mid_cycle() {
+    println!("Resetting during an incremental cycle abandons it.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(1).threshold(4));
+    while !vm.collecting() {
+      vm.push_int(0).unwrap();
+    }
+    vm.reset();
+    assert!(!vm.collecting() && vm.objects() == 0);
+    vm.push_value(&(1u32, 2u32)).unwrap();
+    vm.verify().unwrap();
+  }
+
+  #[test]
+  fn pools_hand_back_reset_vms() {
+    println!("A pool reuses the VMs checked in, up to its limit.");
+
+    let mut pool = VmPool::new(VMConfig::new().threshold(100), 1);
+    let mut a = pool.checkout();
+    let mut b = pool.checkout();
+    a.push_ints(&[1, 2, 3]).unwrap();
+    b.push_int(4).unwrap();
+    let stack = a.stack.as_ptr();
+
+    pool.checkin(a);
+    pool.checkin(b);
+    assert!(pool.idle() == 1);
+
+    let a = pool.checkout();
+    assert!(a.objects() == 0 && a.stack.as_ptr() == stack && a.threshold() == 100);
+    assert!(pool.idle() == 0);
+  }
+}
@@ -0,0 +1,248 @@
+// Printing values as s-expressions. Chains of pairs print as dotted lists:
+//
+//   (1 2 . 3)
+//
+// and anything reached more than once gets a label the first time and a
+// reference after that, so cycles and shared structure print finitely:
+//
+//   #0=(1 . #0#)
+//
+// Objects with a debug label have it in angle brackets in front:
+//
+//   <list head>(1 2 . 3)
+//
+// A native value prints as its type name, `#<regex::Regex>`, and a stale
+// handle to an object a collection freed as `#<freed>`.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use core::fmt;
+
+use {addr, Sobject, VM, Vobject};
+
+/// Displays a value; see `VM::display`.
+pub struct ValueDisplay<'a> {
+  vm: &'a VM,
+  obj: &'a Sobject,
+  max_depth: Option<usize>,
+  max_width: Option<usize>
+}
+
+impl VM {
+  /// A `Display` for `obj` that copes with cycles and sharing.
+  pub fn display<'a>(&'a self, obj: &'a Sobject) -> ValueDisplay<'a> {
+    ValueDisplay { vm: self, obj, max_depth: None, max_width: None }
+  }
+}
+
+impl<'a> ValueDisplay<'a> {
+  /// Print pairs nested deeper than `n` as `...`.
+  pub fn max_depth(mut self, n: usize) -> ValueDisplay<'a> {
+    self.max_depth = Some(n);
+    self
+  }
+
+  /// Print at most `n` elements of any list, then `...`.
+  pub fn max_width(mut self, n: usize) -> ValueDisplay<'a> {
+    self.max_width = Some(n);
+    self
+  }
+}
+
+// Objects reachable from `root` more than once.
+fn shared(root: &Sobject) -> BTreeSet<usize> {
+  let mut seen = BTreeSet::new();
+  let mut shared = BTreeSet::new();
+  let mut todo = Vec::new();
+  todo.push(root.clone());
+
+  while let Some(obj) = todo.pop() {
+    if !seen.insert(addr(&obj)) {
+      shared.insert(addr(&obj));
+      continue;
+    }
+
+    if let Vobject::Pair(ref head, ref tail) = obj.1.borrow().val {
+      todo.push(tail.clone());
+      todo.push(head.clone());
+    }
+  }
+
+  shared
+}
+
+struct Printer<'a, 'b: 'a> {
+  f: &'a mut fmt::Formatter<'b>,
+  vm: &'a VM,
+  shared: BTreeSet<usize>,
+  labels: BTreeMap<usize, usize>,
+  max_depth: Option<usize>,
+  max_width: Option<usize>
+}
+
+impl<'a, 'b> Printer<'a, 'b> {
+  // Writes a label or back reference for shared objects. Returns false if
+  // the object was printed already and needs nothing more.
+  fn label(&mut self, obj: &Sobject) -> Result<bool, fmt::Error> {
+    let key = addr(obj);
+    if !self.shared.contains(&key) {
+      return Ok(true);
+    }
+
+    if let Some(&n) = self.labels.get(&key) {
+      write!(self.f, "#{}#", n)?;
+      return Ok(false);
+    }
+
+    let n = self.labels.len();
+    self.labels.insert(key, n);
+    write!(self.f, "#{}=", n)?;
+    Ok(true)
+  }
+
+  // Whether the rest of a list starting at `obj` can print as more of the
+  // list it ends: it must be printed here, and have no name of its own.
+  fn inline(&self, obj: &Sobject) -> bool {
+    !self.shared.contains(&addr(obj)) && self.vm.label(obj).is_none()
+  }
+
+  fn value(&mut self, obj: &Sobject, depth: usize) -> fmt::Result {
+    if self.vm.is_freed(obj) {
+      return write!(self.f, "#<freed>");
+    }
+    if let Vobject::Pair(..) = obj.1.borrow().val {
+      if self.max_depth.is_some_and(|max| depth >= max) {
+        return write!(self.f, "...");
+      }
+    }
+
+    if !self.label(obj)? {
+      return Ok(());
+    }
+    if let Some(name) = self.vm.label(obj) {
+      write!(self.f, "<{}>", name)?;
+    }
+
+    let (head, mut rest) = match obj.1.borrow().val {
+      Vobject::Int(n) => return write!(self.f, "{}", n),
+      Vobject::Pair(ref head, ref tail) => (head.clone(), tail.clone()),
+      Vobject::Native(ref native) => return write!(self.f, "#<{}>", native.type_name())
+    };
+
+    write!(self.f, "(")?;
+    self.value(&head, depth + 1)?;
+
+    // Walk down the tails while they are unshared pairs, so long lists
+    // don't recurse.
+    let mut width = 1;
+    loop {
+      let next = match rest.1.borrow().val {
+        Vobject::Pair(ref head, ref tail) if self.inline(&rest) => {
+          Some((head.clone(), tail.clone()))
+        }
+        _ => None
+      };
+
+      match next {
+        Some(_) if self.max_width.is_some_and(|max| width >= max) => {
+          return write!(self.f, " ...)");
+        }
+        Some((head, tail)) => {
+          write!(self.f, " ")?;
+          self.value(&head, depth + 1)?;
+          rest = tail;
+          width += 1;
+        }
+        None => break
+      }
+    }
+
+    write!(self.f, " . ")?;
+    self.value(&rest, depth + 1)?;
+    write!(self.f, ")")
+  }
+}
+
+impl<'a> fmt::Display for ValueDisplay<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    Printer {
+      f,
+      vm: self.vm,
+      shared: shared(self.obj),
+      labels: BTreeMap::new(),
+      max_depth: self.max_depth,
+      max_width: self.max_width
+    }.value(self.obj, 0)
+  }
+}
+
+// The derived Debug would follow pairs forever on a cycle, so pairs show
+// only the addresses of their fields.
+impl fmt::Debug for Vobject {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Vobject::Int(n) => write!(f, "Int({})", n),
+      Vobject::Pair(ref head, ref tail) => write!(f, "Pair({:#x}, {:#x})", addr(head), addr(tail)),
+      Vobject::Native(ref native) => write!(f, "Native({})", native.type_name())
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn prints_cycles_and_sharing() {
+    println!("Cycles and shared structure print with labels.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let a = vm.push_pair().unwrap();
+    assert!(vm.display(&a).to_string() == "(1 . 2)");
+
+    vm.stack.push(a.clone());
+    let b = vm.push_pair().unwrap();
+    assert!(vm.display(&b).to_string() == "(#0=(1 . 2) . #0#)");
+
+    if let Vobject::Pair(_, ref mut x) = a.1.borrow_mut().val { *x = a.clone() }
+    assert!(vm.display(&a).to_string() == "#0=(1 . #0#)");
+    assert!(format!("{:?}", vm).contains("Pair("));
+  }
+
+  #[test]
+  fn prints_lists_within_limits() {
+    println!("Lists print flat, cut off at the width and depth limits.");
+
+    let mut vm = VM::new();
+    let list = vm.push_value(&vec![1u32, 2, 3]).unwrap();
+    assert!(vm.display(&list).to_string() == "(1 2 3 . 0)");
+    assert!(vm.display(&list).max_width(2).to_string() == "(1 2 ...)");
+
+    let nested = vm.push_value(&(1u32, ((2u32, 3u32), 4u32))).unwrap();
+    assert!(vm.display(&nested).to_string() == "(1 (2 . 3) . 4)");
+    assert!(vm.display(&nested).max_depth(1).to_string() == "(1 ... . 4)");
+  }
+
+  #[test]
+  fn prints_debug_labels() {
+    println!("Labelled objects print with their names.");
+
+    let mut vm = VM::new();
+    let list = vm.push_value(&vec![1u32, 2, 3]).unwrap();
+    let (one, rest) = vm.as_pair(&list).unwrap();
+    vm.set_label(&list, "list head");
+    vm.set_label(&one, "one");
+    vm.set_label(&rest, "rest");
+    assert!(vm.display(&list).to_string() == "<list head>(<one>1 . <rest>(2 3 . 0))");
+
+    vm.set_tail(&rest, &list).unwrap();
+    assert!(vm.display(&list).to_string() == "#0=<list head>(<one>1 . <rest>(2 . #0#))");
+  }
+}
@@ -0,0 +1,75 @@
+// What the process really uses, next to what the VM thinks its objects
+// cost. The VM only knows `Object::size()` per object; the allocator adds
+// its own headers, rounding and free lists on top, and doesn't always give
+// freed memory back. The kernel's count of resident memory is the number
+// that gets a process killed, so it is read from /proc/self/status on
+// Linux. Elsewhere, and if /proc isn't mounted, it is unknown.
+
+use std::fs;
+
+use VM;
+
+/// Memory as the VM counts it and as the OS does; see
+/// `VM::process_memory`. Resident figures cover the whole process, not
+/// just this VM.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProcessMemory {
+  /// `VM::bytes_allocated`.
+  pub logical: usize,
+  /// Bytes of the process in RAM now, and at most so far.
+  pub resident: Option<usize>,
+  pub peak_resident: Option<usize>
+}
+
+impl VM {
+  pub fn process_memory(&self) -> ProcessMemory {
+    let (resident, peak_resident) = fs::read_to_string("/proc/self/status")
+      .map(|status| parse_status(&status))
+      .unwrap_or((None, None));
+    ProcessMemory { logical: self.bytes_allocated(), resident, peak_resident }
+  }
+}
+
+// VmRSS and VmHWM, in bytes, from the text of /proc/self/status.
+fn parse_status(status: &str) -> (Option<usize>, Option<usize>) {
+  let field = |name: &str| {
+    status.lines()
+      .find_map(|line| line.strip_prefix(name))
+      .and_then(|rest| rest.trim().strip_suffix("kB"))
+      .and_then(|kb| kb.trim().parse::<usize>().ok())
+      .map(|kb| kb * 1024)
+  };
+  (field("VmRSS:"), field("VmHWM:"))
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn status_is_parsed() {
+    println!("Resident and peak sizes are read from /proc/self/status.");
+
+    let status = "Name:\tbabygc\nVmHWM:\t    2048 kB\nVmRSS:\t    1536 kB\nThreads:\t1\n";
+    assert!(parse_status(status) == (Some(1536 * 1024), Some(2048 * 1024)));
+    assert!(parse_status("Name:\tbabygc\n") == (None, None));
+  }
+
+  #[test]
+  fn logical_size_is_the_vms() {
+    println!("The logical size is the VM's own count; the OS's comes where there is one.");
+
+    let mut vm = VM::new();
+    vm.push_ints(&[1, 2, 3]).unwrap();
+    let memory = vm.process_memory();
+    assert!(memory.logical == vm.bytes_allocated());
+    if cfg!(target_os = "linux") {
+      assert!(memory.resident.unwrap() > 0 && memory.peak_resident >= memory.resident);
+    }
+  }
+}
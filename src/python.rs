@@ -0,0 +1,161 @@
+// pyo3 extension module, for driving a VM from Python:
+//
+//   import simple_gc
+//   vm = simple_gc.VM("generational", threshold=8)
+//   vm.push_int(1); vm.push_int(2)
+//   pair = vm.push_pair()
+//   vm.set_tail(pair, pair)
+//   vm.gc(), vm.stats()
+//
+// Build with `maturin develop`; pyproject.toml picks the features. Like
+// the C API's handles, `Object`s keep their object readable but don't
+// root it.
+
+use pyo3::exceptions::{PyIndexError, PyMemoryError, PyOverflowError, PyReferenceError, PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use {GcStrategy, Object, Sobject, VM, VMConfig, Vobject, VmError};
+
+fn vm_error(e: VmError) -> PyErr {
+  match e {
+    VmError::OutOfMemory | VmError::QuotaExceeded => PyMemoryError::new_err(e.to_string()),
+    VmError::GcStarved | VmError::Cancelled => PyRuntimeError::new_err(e.to_string()),
+    VmError::StackUnderflow => PyIndexError::new_err(e.to_string()),
+    VmError::Frozen | VmError::Type(_) | VmError::NotConstant => PyTypeError::new_err(e.to_string()),
+    VmError::Freed => PyReferenceError::new_err(e.to_string()),
+    VmError::Overflow => PyOverflowError::new_err(e.to_string()),
+    VmError::ForeignCheckpoint => PyValueError::new_err(e.to_string())
+  }
+}
+
+#[pyclass(unsendable, name = "VM")]
+pub struct PyVm {
+  vm: VM
+}
+
+#[pyclass(unsendable, name = "Object")]
+pub struct PyHandle(Sobject);
+
+#[pymethods]
+impl PyVm {
+  /// `strategy` is "mark-sweep" or "generational".
+  #[new]
+  #[pyo3(signature = (strategy = "mark-sweep", threshold = None))]
+  fn new(strategy: &str, threshold: Option<usize>) -> PyResult<PyVm> {
+    let strategy: GcStrategy = strategy.parse()
+      .map_err(|_| PyValueError::new_err(format!("unknown strategy {:?}", strategy)))?;
+    let mut config = VMConfig::new().strategy(strategy);
+    if let Some(n) = threshold {
+      config = config.threshold(n);
+    }
+
+    Ok(PyVm { vm: VM::with_config(config) })
+  }
+
+  fn push_int(&mut self, val: u32) -> PyResult<PyHandle> {
+    self.vm.push_int(val).map(PyHandle).map_err(vm_error)
+  }
+
+  /// Replaces the top two stack slots (head, then tail) with a pair.
+  fn push_pair(&mut self) -> PyResult<PyHandle> {
+    if self.vm.stack.len() < 2 {
+      return Err(PyIndexError::new_err("push_pair needs two values on the stack"));
+    }
+
+    self.vm.push_pair().map(PyHandle).map_err(vm_error)
+  }
+
+  fn pop(&mut self) -> PyResult<PyHandle> {
+    self.vm.stack.pop().map(PyHandle).ok_or_else(|| PyIndexError::new_err("pop from empty stack"))
+  }
+
+  fn set_head(&mut self, pair: &PyHandle, val: &PyHandle) -> PyResult<()> {
+    self.vm.set_head(&pair.0, &val.0).map_err(vm_error)
+  }
+
+  fn set_tail(&mut self, pair: &PyHandle, val: &PyHandle) -> PyResult<()> {
+    self.vm.set_tail(&pair.0, &val.0).map_err(vm_error)
+  }
+
+  fn gc(&mut self) -> usize {
+    self.vm.gc()
+  }
+
+  fn gc_minor(&mut self) -> usize {
+    self.vm.gc_minor()
+  }
+
+  fn gc_full(&mut self) -> usize {
+    self.vm.gc_full()
+  }
+
+  /// Heap size and collector totals as a dict.
+  fn stats<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+    let stats = self.vm.stats();
+    let dict = PyDict::new(py);
+    dict.set_item("objects", self.vm.objects())?;
+    dict.set_item("heap_bytes", self.vm.objects() * Object::size())?;
+    dict.set_item("stack", self.vm.stack.len())?;
+    dict.set_item("pauses", stats.pauses)?;
+    dict.set_item("max_pause", stats.max_pause.as_secs_f64())?;
+    dict.set_item("total_pause", stats.total_pause.as_secs_f64())?;
+    Ok(dict)
+  }
+
+  /// See `VM::heap_dump_json`.
+  fn heap_dump(&self) -> String {
+    self.vm.heap_dump_json()
+  }
+}
+
+#[pymethods]
+impl PyHandle {
+  fn is_pair(&self) -> bool {
+    matches!(self.0.1.borrow().val, Vobject::Pair(..))
+  }
+
+  /// The int's value; TypeError for pairs.
+  #[getter]
+  fn value(&self) -> PyResult<u32> {
+    match self.0.1.borrow().val {
+      Vobject::Int(n) => Ok(n),
+      _ => Err(PyTypeError::new_err("not an int"))
+    }
+  }
+
+  #[getter]
+  fn head(&self) -> PyResult<PyHandle> {
+    self.child(true)
+  }
+
+  #[getter]
+  fn tail(&self) -> PyResult<PyHandle> {
+    self.child(false)
+  }
+
+  fn __repr__(&self) -> String {
+    match self.0.1.borrow().val {
+      Vobject::Int(n) => format!("Object(int {})", n),
+      Vobject::Pair(..) => "Object(pair)".to_string(),
+      Vobject::Native(ref native) => format!("Object(native {})", native.type_name())
+    }
+  }
+}
+
+impl PyHandle {
+  fn child(&self, head: bool) -> PyResult<PyHandle> {
+    match self.0.1.borrow().val {
+      Vobject::Pair(ref h, _) if head => Ok(PyHandle(h.clone())),
+      Vobject::Pair(_, ref t) => Ok(PyHandle(t.clone())),
+      _ => Err(PyTypeError::new_err("not a pair"))
+    }
+  }
+}
+
+#[pymodule]
+fn simple_gc(m: &Bound<'_, PyModule>) -> PyResult<()> {
+  m.add_class::<PyVm>()?;
+  m.add_class::<PyHandle>()?;
+  Ok(())
+}
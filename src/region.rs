@@ -0,0 +1,156 @@
+// Regions: arena-style bulk freeing on top of the collector. Everything
+// allocated inside `VM::in_region` goes to the region rather than the
+// nursery, and stays alive until the region ends, whatever collections run
+// meanwhile: the region's objects are roots. When it ends, the stack,
+// persistent handles and the objects stored into during the region (the
+// ones `write_barrier` was called on) are checked for references into it. If there are none its
+// objects are dropped at once, with no marking or sweeping; otherwise they
+// join the nursery and are collected like any others.
+//
+// Region objects have ids from `region_start` on; constants, the only
+// other objects with ids that high, are never in one. Handles to region
+// objects held by the host past the region's end aren't looked for, and
+// refer to objects the VM no longer counts.
+
+use core::mem;
+
+use tracer::Source;
+use {Sobject, VM};
+
+impl VM {
+  /// Runs `f` with its allocations in a region, freed all together when
+  /// `f` returns unless something outside the region still points into
+  /// it. Nested regions are part of the outermost one.
+  pub fn in_region<F, R>(&mut self, f: F) -> R
+    where F: FnOnce(&mut VM) -> R
+  {
+    self.region_depth += 1;
+    if self.region_depth == 1 {
+      self.region_start = self.next_id;
+    }
+
+    let result = f(self);
+
+    self.region_depth -= 1;
+    if self.region_depth == 0 {
+      self.end_region();
+    }
+    result
+  }
+
+  fn in_region_space(&self, obj: &Sobject) -> bool {
+    !obj.0.get().constant() && obj.2 >= self.region_start
+  }
+
+  fn region_escaped(&self) -> bool {
+    if self.roots().any(|(source, obj)| source != Source::Region && self.in_region_space(&obj)) {
+      return true;
+    }
+
+    self.region_writes.iter().filter(|obj| !self.in_region_space(obj)).any(|obj| {
+      obj.1.borrow().val.children().any(|child| self.in_region_space(&child))
+    })
+  }
+
+  fn end_region(&mut self) {
+    self.apply_cell_writes();
+    let escaped = self.region_escaped();
+    self.region_writes.clear();
+    let objs = mem::take(&mut self.region);
+
+    if escaped {
+      self.stats.regions_escaped += 1;
+      self.nursery.extend(objs);
+    } else {
+      self.stats.regions_dropped += 1;
+      for obj in &objs {
+        self.free(obj);
+      }
+    }
+  }
+
+  // Collections keep every region object, and whatever they point to.
+  // Region objects aren't swept, so their marks are cleared here instead.
+  pub(crate) fn unmark_region(&mut self) {
+    for obj in &self.region {
+      obj.0.set(obj.0.get().with_marked(false));
+    }
+  }
+
+  pub(crate) fn note_region_write(&mut self, obj: &Sobject) {
+    if self.region_depth > 0 {
+      self.region_writes.push(obj.clone());
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use {GcStrategy, VMConfig, Vobject};
+
+  #[test]
+  fn regions_drop_wholesale() {
+    println!("A region nothing points into is freed when it ends.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    let sum = vm.in_region(|vm| {
+      for i in 0..20 {
+        vm.push_int(i).unwrap();
+        vm.push_int(i).unwrap();
+        vm.push_pair().unwrap();
+        vm.gc();
+        vm.pop();
+      }
+      vm.push_int(7).unwrap();
+      vm.push_int(8).unwrap();
+      vm.push_pair().unwrap();
+      let pair = vm.pop();
+      assert!(vm.iter_heap().count() == 64);
+      vm.extract::<(u32, u32)>(&pair).unwrap()
+    });
+
+    assert!(sum == (7, 8));
+    assert!(vm.iter_heap().count() == 1 && vm.stats().regions_dropped == 1);
+  }
+
+  #[test]
+  fn escapes_are_collected_normally() {
+    println!("A region with references from outside joins the heap.");
+
+    for strategy in [GcStrategy::MarkSweep, GcStrategy::Generational] {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy));
+      vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+      let outer = vm.push_pair().unwrap();
+      vm.gc();
+
+      vm.in_region(|vm| {
+        let inner = vm.push_int(3).unwrap();
+        vm.push_int(4).unwrap();
+        vm.pop();
+        if let Vobject::Pair(_, ref mut tail) = outer.1.borrow_mut().val { *tail = inner }
+        vm.write_barrier(&outer);
+        vm.pop();
+      });
+
+      assert!(vm.stats().regions_escaped == 1);
+      vm.gc_full();
+      assert!(vm.extract::<(u32, u32)>(&outer) == Ok((1, 3)));
+      assert!(vm.iter_heap().count() == 3);
+    }
+
+    let mut vm = VM::new();
+    let kept = vm.in_region(|vm| vm.in_region(|vm| vm.push_int(5).unwrap()));
+    assert!(vm.stats().regions_escaped == 1 && vm.stats().regions_dropped == 0);
+    vm.gc_full();
+    assert!(vm.iter_roots().next().map(|obj| Rc::ptr_eq(obj, &kept)) == Some(true));
+  }
+}
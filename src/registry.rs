@@ -0,0 +1,132 @@
+// A process-wide list of VMs, for hosts running many small ones. A VM
+// joins with `VM::register(name)` and leaves when dropped. Each keeps a
+// few figures in atomics, refreshed after every operation and collection,
+// so `registry_stats` can read them from any thread without touching the
+// VMs themselves; it is the one place an exporter needs to look.
+
+use std::string::{String, ToString};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::vec::Vec;
+
+use time::Duration;
+use {Object, VM};
+
+static REGISTRY: Mutex<Vec<Weak<Entry>>> = Mutex::new(Vec::new());
+
+// A registered VM's figures.
+#[derive(Debug)]
+pub(crate) struct Entry {
+  name: String,
+  objects: AtomicUsize,
+  collections: AtomicU64,
+  pause_nanos: AtomicU64
+}
+
+/// A registered VM, as of its last operation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VmSummary {
+  pub name: String,
+  pub objects: usize,
+  pub bytes: usize,
+  pub collections: u64,
+  pub total_pause: Duration
+}
+
+/// Every registered VM, and their totals; see `registry_stats`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RegistryStats {
+  pub vms: Vec<VmSummary>,
+  pub objects: usize,
+  pub bytes: usize
+}
+
+impl VM {
+  /// Adds the VM to the process-wide registry under `name`, which needn't
+  /// be unique. Registering again renames it.
+  pub fn register(&mut self, name: &str) {
+    let entry = Arc::new(Entry {
+      name: name.to_string(),
+      objects: AtomicUsize::new(0),
+      collections: AtomicU64::new(0),
+      pause_nanos: AtomicU64::new(0)
+    });
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner()).push(Arc::downgrade(&entry));
+    self.registered = Some(entry);
+    self.update_registry();
+  }
+
+  #[inline]
+  pub(crate) fn update_registry(&self) {
+    if let Some(ref entry) = self.registered {
+      entry.objects.store(self.objects(), Ordering::Relaxed);
+      entry.collections.store(self.stats.full_collections + self.stats.minor_collections, Ordering::Relaxed);
+      entry.pause_nanos.store(self.stats.total_pause.as_nanos() as u64, Ordering::Relaxed);
+    }
+  }
+}
+
+/// Figures for every registered VM still alive, oldest registration
+/// first, with their totals.
+pub fn registry_stats() -> RegistryStats {
+  let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+  registry.retain(|entry| entry.strong_count() > 0);
+
+  let mut stats = RegistryStats::default();
+  for entry in registry.iter().filter_map(Weak::upgrade) {
+    let objects = entry.objects.load(Ordering::Relaxed);
+    stats.objects += objects;
+    stats.bytes += objects * Object::size();
+    stats.vms.push(VmSummary {
+      name: entry.name.clone(),
+      objects,
+      bytes: objects * Object::size(),
+      collections: entry.collections.load(Ordering::Relaxed),
+      total_pause: Duration::from_nanos(entry.pause_nanos.load(Ordering::Relaxed))
+    });
+  }
+  stats
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::thread;
+
+  // Other tests may register VMs of their own at the same time.
+  fn named(stats: &RegistryStats, prefix: &str) -> Vec<VmSummary> {
+    stats.vms.iter().filter(|vm| vm.name.starts_with(prefix)).cloned().collect()
+  }
+
+  #[test]
+  fn registered_vms_are_summed() {
+    println!("The registry sees VMs on every thread, and forgets dropped ones.");
+
+    let mut a = VM::new();
+    a.register("registry-test a");
+    a.push_ints(&[1, 2, 3]).unwrap();
+    a.gc();
+
+    let done = thread::spawn(|| {
+      let mut b = VM::new();
+      b.register("registry-test b");
+      b.push_int(4).unwrap();
+      let stats = registry_stats();
+      let ours = named(&stats, "registry-test");
+      assert!(ours.len() == 2 && ours[0].objects == 3 && ours[1].objects == 1);
+      assert!(ours[0].collections == 1 && ours[0].bytes == 3 * Object::size());
+      assert!(stats.objects >= 4 && stats.bytes == stats.objects * Object::size());
+    });
+    done.join().unwrap();
+
+    let ours = named(&registry_stats(), "registry-test");
+    assert!(ours.len() == 1 && ours[0].name == "registry-test a");
+    drop(a);
+    assert!(named(&registry_stats(), "registry-test").is_empty());
+  }
+}
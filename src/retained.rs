@@ -0,0 +1,195 @@
+// Finding host-side leaks. Handles are `Rc` clones, so an object the VM
+// has finished with stays allocated while the host holds one; a
+// collection empties it, but the host's data structures keep the shell.
+// `VM::externally_retained` lists the objects nothing in the VM reaches
+// that are still held from outside: the reference count, less the VM's
+// own references, is what the host (or a checkpoint, or a transfer
+// session) holds. `VM::external_refs` gives the same count for every
+// object, live or not, with the persistent handles to it, biggest first,
+// for finding which of the host's handles keep the live set large.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::fmt;
+
+use graph::walk;
+use {addr, VM};
+
+/// An unreachable object the host still holds handles to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Retained<'a> {
+  pub id: u64,
+  /// References from outside the VM.
+  pub handles: usize,
+  pub label: Option<&'a str>
+}
+
+impl<'a> fmt::Display for Retained<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "#{}", self.id)?;
+    if let Some(label) = self.label {
+      write!(f, " <{}>", label)?;
+    }
+    write!(f, ": {} handle{}", self.handles, if self.handles == 1 { "" } else { "s" })
+  }
+}
+
+/// An object held from outside the VM, as listed by `VM::external_refs`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExternalRefs<'a> {
+  pub id: u64,
+  /// References from outside the VM.
+  pub handles: usize,
+  /// Persistent handles to it, which the VM holds for the host.
+  pub persistent: usize,
+  /// Whether the roots reach it.
+  pub live: bool,
+  pub label: Option<&'a str>
+}
+
+impl<'a> fmt::Display for ExternalRefs<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "#{}", self.id)?;
+    if let Some(label) = self.label {
+      write!(f, " <{}>", label)?;
+    }
+    write!(f, ": {} handle{}", self.handles, if self.handles == 1 { "" } else { "s" })?;
+    if self.persistent > 0 {
+      write!(f, ", {} persistent", self.persistent)?;
+    }
+    if !self.live {
+      write!(f, " (unreachable)")?;
+    }
+    Ok(())
+  }
+}
+
+impl VM {
+  /// Objects unreachable from the stack, persistent handles and any open
+  /// region that are still held from outside the VM, in `iter_heap`
+  /// order. The next collection frees them, but the host keeps their
+  /// memory until it drops its handles.
+  pub fn externally_retained(&self) -> Vec<Retained<'_>> {
+    let live = self.live_addrs();
+    let internal = self.internal_refs();
+
+    self.iter_objects().filter(|obj| !live.contains(&addr(obj))).filter_map(|obj| {
+      let handles = Rc::strong_count(obj) - internal[&addr(obj)];
+      if handles == 0 {
+        return None;
+      }
+      let id = obj.2;
+      Some(Retained { id, handles, label: self.label_of(id) })
+    }).collect()
+  }
+
+  /// Every object held from outside the VM, by handles or persistent
+  /// handles, most handles first (then by id). Walks the whole heap.
+  pub fn external_refs(&self) -> Vec<ExternalRefs<'_>> {
+    let live = self.live_addrs();
+    let internal = self.internal_refs();
+    let mut persistent: BTreeMap<usize, usize> = BTreeMap::new();
+    for obj in self.persistent.values() {
+      *persistent.entry(addr(obj)).or_insert(0) += 1;
+    }
+
+    let mut refs: Vec<ExternalRefs<'_>> = self.iter_objects().filter_map(|obj| {
+      let handles = Rc::strong_count(obj) - internal[&addr(obj)];
+      let persistent = persistent.get(&addr(obj)).cloned().unwrap_or(0);
+      if handles == 0 && persistent == 0 {
+        return None;
+      }
+      let id = obj.2;
+      Some(ExternalRefs { id, handles, persistent, live: live.contains(&addr(obj)), label: self.label_of(id) })
+    }).collect();
+    refs.sort_by_key(|r| (Reverse(r.handles + r.persistent), r.id));
+    refs
+  }
+
+  fn live_addrs(&self) -> BTreeSet<usize> {
+    walk(self.roots().map(|(_, obj)| obj)).iter().map(addr).collect()
+  }
+
+  // Every reference the VM itself holds, by object.
+  fn internal_refs(&self) -> BTreeMap<usize, usize> {
+    let mut internal: BTreeMap<usize, usize> = BTreeMap::new();
+    let held = self.iter_objects().chain(&self.stack).chain(&self.temp_roots).chain(self.persistent.values()).chain(&self.gray)
+      .chain(&self.unswept_writes).chain(&self.region_writes);
+    for obj in held {
+      *internal.entry(addr(obj)).or_insert(0) += 1;
+    }
+    for obj in self.iter_objects() {
+      for child in obj.1.borrow().val.children() {
+        *internal.entry(addr(&child)).or_insert(0) += 1;
+      }
+    }
+    internal
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::string::ToString;
+
+  #[test]
+  fn host_handles_are_found() {
+    println!("Unreachable objects held by the host are listed with their handle counts.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    let two = vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    let copy = p.clone();
+    vm.set_tail(&p, &p).unwrap();
+    vm.push_int(3).unwrap();
+    vm.pop();
+    vm.set_label(&p, "loop");
+    assert!(vm.externally_retained() == [Retained { id: 1, handles: 1, label: None }]);
+
+    vm.pop();
+    let found = vm.externally_retained();
+    assert!(found == [Retained { id: 1, handles: 1, label: None }, Retained { id: 2, handles: 2, label: Some("loop") }]);
+    assert!(found[1].to_string() == "#2 <loop>: 2 handles");
+
+    drop((two, copy));
+    assert!(vm.externally_retained() == [Retained { id: 2, handles: 1, label: Some("loop") }]);
+  }
+
+  #[test]
+  fn external_refs_are_ranked() {
+    println!("Every object the host holds is listed, most handles first.");
+
+    let mut vm = VM::new();
+    let one = vm.push_int(1).unwrap();
+    let two = vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    let copies = (p.clone(), p.clone());
+    let kept = vm.persist(&one);
+    vm.set_label(&p, "pair");
+    vm.push_int(3).unwrap();
+    vm.pop();
+    drop(one);
+
+    let refs = vm.external_refs();
+    assert!(refs == [
+      ExternalRefs { id: 2, handles: 3, persistent: 0, live: true, label: Some("pair") },
+      ExternalRefs { id: 0, handles: 0, persistent: 1, live: true, label: None },
+      ExternalRefs { id: 1, handles: 1, persistent: 0, live: true, label: None }
+    ]);
+    assert!(refs[0].to_string() == "#2 <pair>: 3 handles" && refs[1].to_string() == "#0: 0 handles, 1 persistent");
+
+    vm.pop();
+    drop((two, copies));
+    vm.release(kept);
+    assert!(vm.external_refs() == [ExternalRefs { id: 2, handles: 1, persistent: 0, live: false, label: Some("pair") }]);
+    assert!(vm.external_refs()[0].to_string() == "#2 <pair>: 1 handle (unreachable)");
+  }
+}
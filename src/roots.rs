@@ -0,0 +1,192 @@
+// Roots the host keeps in its own data structures: a cache, a scheduler's
+// run queue, an embedding framework's object table. Rather than copying
+// their handles onto the stack or taking a persistent handle for each,
+// the host registers a `RootProvider` and every collection, full or
+// minor, asks it for them when marking starts, and again as incremental
+// marking finishes, as it rescans the stack. The provider runs in the
+// middle of a collection and is given no VM, so it can only hand over
+// handles. Handles to objects already freed are passed over.
+//
+// `VM::roots` is the whole root set, providers and all, for marking and
+// for everything else that asks what a collection would keep.
+
+use alloc::collections::BTreeMap;
+use alloc::rc::Rc;
+use alloc::vec::Vec;
+use core::fmt;
+
+use tracer::Source;
+use {Sobject, VM};
+
+/// A source of roots outside the VM, registered with
+/// `VM::add_root_provider`.
+pub trait RootProvider {
+  /// Calls `visitor` with each object to keep alive.
+  fn provide_roots(&self, visitor: &mut dyn FnMut(&Sobject));
+}
+
+/// Names a registered `RootProvider`, for `VM::remove_root_provider`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProviderId(u64);
+
+#[derive(Default)]
+pub(crate) struct Providers {
+  next: u64,
+  providers: BTreeMap<u64, Rc<dyn RootProvider>>
+}
+
+impl fmt::Debug for Providers {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "Providers({})", self.providers.len())
+  }
+}
+
+impl VM {
+  /// Has every collection from now on keep what `provider` provides, until
+  /// it is removed.
+  pub fn add_root_provider(&mut self, provider: Rc<dyn RootProvider>) -> ProviderId {
+    let id = self.providers.next;
+    self.providers.next += 1;
+    self.providers.providers.insert(id, provider);
+    ProviderId(id)
+  }
+
+  /// Stops asking the provider for roots. Returns false if it was already
+  /// removed.
+  pub fn remove_root_provider(&mut self, id: ProviderId) -> bool {
+    self.providers.providers.remove(&id.0).is_some()
+  }
+
+  // Every root and where it comes from, in the order a collection marks
+  // them: the stack from the bottom, temporary roots, persistent handles,
+  // what the providers provide and the open region.
+  pub(crate) fn roots(&self) -> impl Iterator<Item = (Source, Sobject)> + '_ {
+    let stack = self.stack.iter().enumerate().map(|(i, obj)| (Source::Stack(i), obj.clone()));
+    let temp = self.temp_roots.iter().map(|obj| (Source::Temp, obj.clone()));
+    let persistent = self.persistent.values().map(|obj| (Source::Persistent, obj.clone()));
+    let provided = self.provided_roots().into_iter().map(|obj| (Source::Provider, obj));
+    let region = self.region.iter().map(|obj| (Source::Region, obj.clone()));
+    stack.chain(temp).chain(persistent).chain(provided).chain(region)
+  }
+
+  // What every provider provides right now, oldest provider first.
+  fn provided_roots(&self) -> Vec<Sobject> {
+    let mut roots = Vec::new();
+    for provider in self.providers.providers.values() {
+      provider.provide_roots(&mut |obj| {
+        if !obj.0.get().freed() {
+          roots.push(obj.clone());
+        }
+      });
+    }
+    roots
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use core::cell::RefCell;
+  use {GcStrategy, VMConfig};
+
+  #[derive(Default)]
+  struct Cache {
+    entries: RefCell<Vec<Sobject>>
+  }
+
+  impl RootProvider for Cache {
+    fn provide_roots(&self, visitor: &mut dyn FnMut(&Sobject)) {
+      for obj in self.entries.borrow().iter() {
+        visitor(obj);
+      }
+    }
+  }
+
+  #[test]
+  fn providers_root_what_they_hold() {
+    println!("Objects a provider hands over survive every kind of collection.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).threshold(100));
+      let cache = Rc::new(Cache::default());
+      let id = vm.add_root_provider(cache.clone());
+
+      let p = vm.push_value(&(1u32, (2u32, 3u32))).unwrap();
+      cache.entries.borrow_mut().push(vm.pop());
+      vm.push_int(4).unwrap();
+      vm.pop();
+
+      vm.gc_minor();
+      vm.gc();
+      vm.gc_full();
+      vm.verify().unwrap();
+      assert!(vm.extract::<(u32, (u32, u32))>(&p) == Ok((1, (2, 3))));
+      assert!(vm.iter_live().count() == 5 && vm.objects() == 5);
+      assert!(vm.census().objects == 5);
+
+      assert!(vm.remove_root_provider(id) && !vm.remove_root_provider(id));
+      vm.gc_full();
+      assert!(vm.is_freed(&p) && vm.objects() == 0);
+    }
+  }
+
+  #[test]
+  fn providers_mid_cycle() {
+    println!("Incremental marking starts from a provider's roots too.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(1).threshold(4));
+    let cache = Rc::new(Cache::default());
+    vm.add_root_provider(cache.clone());
+    let kept = vm.push_value(&(5u32, 6u32)).unwrap();
+    cache.entries.borrow_mut().push(vm.pop());
+    while !vm.collecting() {
+      vm.push_int(0).unwrap();
+      vm.pop();
+    }
+
+    while !vm.tick() {}
+    vm.verify().unwrap();
+    assert!(vm.extract::<(u32, u32)>(&kept) == Ok((5, 6)));
+
+    // Ints allocated mid-cycle were black; the next cycle takes them.
+    vm.gc_full();
+    assert!(vm.objects() == 3);
+  }
+
+  #[test]
+  fn every_view_of_the_roots_agrees() {
+    println!("What counts as live is the same set a collection marks from.");
+
+    let mut vm = VM::new();
+    let cache = Rc::new(Cache::default());
+    vm.add_root_provider(cache.clone());
+    vm.push_int(1).unwrap();
+    cache.entries.borrow_mut().push(vm.push_int(2).unwrap());
+    vm.pop();
+    vm.in_region(|vm| {
+      vm.push_int(3).unwrap();
+      vm.pop();
+      assert!(vm.iter_live().count() == 3 && vm.census().objects == 3);
+      assert!(vm.roots().map(|(source, _)| source).eq([Source::Stack(0), Source::Provider, Source::Region]));
+    });
+  }
+
+  #[test]
+  fn stale_handles_are_passed_over() {
+    println!("A provider still holding a freed object doesn't bring it back.");
+
+    let mut vm = VM::new();
+    let one = vm.push_int(1).unwrap();
+    vm.pop();
+    vm.gc();
+    let cache = Rc::new(Cache { entries: RefCell::new(vec![one]) });
+    vm.add_root_provider(cache);
+    vm.gc();
+    vm.verify().unwrap();
+  }
+}
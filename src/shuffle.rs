@@ -0,0 +1,118 @@
+// Shuffle mode, for tests: the order objects sit in the VM's lists is
+// scrambled from a seed, so code that quietly depends on it shows up. A
+// new object lands at a random place in the nursery, and every sweep,
+// full or minor, visits its objects in a random order, which in turn
+// scrambles the old generation's slots and the order `iter_heap` gives.
+// Nothing the collector promises changes. Problems `VM::verify` and
+// paranoid mode report name the seed, so a failing run can be repeated.
+
+use alloc::format;
+use alloc::string::String;
+
+use {Sobject, VM};
+
+// xorshift64*, as workloads use.
+#[derive(Clone, Debug)]
+pub(crate) struct Shuffle {
+  seed: u64,
+  state: u64
+}
+
+impl Shuffle {
+  pub(crate) fn new(seed: u64) -> Shuffle {
+    Shuffle { seed, state: seed.max(1) }
+  }
+
+  fn below(&mut self, n: usize) -> usize {
+    self.state ^= self.state >> 12;
+    self.state ^= self.state << 25;
+    self.state ^= self.state >> 27;
+    (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 33) as usize % n
+  }
+
+  // Fisher-Yates.
+  pub(crate) fn shuffle(&mut self, list: &mut [Sobject]) {
+    for i in (1..list.len()).rev() {
+      let j = self.below(i + 1);
+      list.swap(i, j);
+    }
+  }
+
+  // Swaps the object just pushed onto `list` with a random one.
+  pub(crate) fn scatter_last(&mut self, list: &mut [Sobject]) {
+    let n = list.len();
+    if n > 1 {
+      let j = self.below(n);
+      list.swap(n - 1, j);
+    }
+  }
+}
+
+impl VM {
+  /// The seed `VMConfig::shuffle` was given, if any.
+  pub fn shuffle_seed(&self) -> Option<u64> {
+    self.shuffle.as_ref().map(|shuffle| shuffle.seed)
+  }
+
+  // `problem`, naming the seed if there is one.
+  pub(crate) fn with_seed(&self, problem: String) -> String {
+    match self.shuffle_seed() {
+      Some(seed) => format!("{} (shuffle seed {})", problem, seed),
+      None => problem
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::vec::Vec;
+  use {GcStrategy, VMConfig, Workload};
+
+  fn heap_order(strategy: GcStrategy, seed: Option<u64>) -> Vec<u64> {
+    let mut config = VMConfig::new().strategy(strategy).threshold(16);
+    if let Some(seed) = seed {
+      config = config.shuffle(seed);
+    }
+    let mut vm = VM::with_config(config);
+    let w: Workload = "list=2 gc=0 ops=500 max_stack=64".parse().unwrap();
+    w.run(&mut vm, |vm, _| vm.verify().unwrap()).unwrap();
+    vm.gc();
+    vm.verify().unwrap();
+    vm.iter_heap().map(|obj| obj.2).collect()
+  }
+
+  #[test]
+  fn shuffling_scrambles_the_order_only() {
+    println!("Shuffled heaps hold the same objects in a different, repeatable order.");
+
+    for strategy in GcStrategy::ALL {
+      let plain = heap_order(strategy, None);
+      let shuffled = heap_order(strategy, Some(7));
+      assert!(shuffled != plain && shuffled == heap_order(strategy, Some(7)));
+      assert!(heap_order(strategy, Some(8)) != shuffled);
+
+      let (mut a, mut b) = (plain, shuffled);
+      a.sort();
+      b.sort();
+      assert!(a == b);
+    }
+  }
+
+  #[test]
+  fn problems_name_the_seed() {
+    println!("A broken invariant is reported with the seed that found it.");
+
+    let mut vm = VM::with_config(VMConfig::new().shuffle(42));
+    assert!(vm.shuffle_seed() == Some(42));
+    let one = vm.push_int(1).unwrap();
+    one.0.set(one.0.get().with_marked(true));
+    assert!(vm.verify() == Err(String::from("#0 is still marked after the collection (shuffle seed 42)")));
+    assert!(VM::new().shuffle_seed().is_none());
+  }
+}
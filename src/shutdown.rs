@@ -0,0 +1,88 @@
+// Tearing a VM down. Objects are `Rc`s, so a cycle still live when the VM
+// goes away would keep itself allocated for good. Dropping the VM frees
+// every object it owns, as a collection would: each drops what it points
+// to, so cycles come apart, and the allocator gets every byte back. A
+// handle the host still holds outlives the VM as a freed object.
+
+use alloc::vec::Vec;
+
+use {Sobject, VM};
+
+impl Drop for VM {
+  fn drop(&mut self) {
+    let objects: Vec<Sobject> = self.iter_objects().cloned().collect();
+    for obj in &objects {
+      self.free(obj);
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use core::cell::Cell;
+  use {GcStrategy, ObjectAllocator, VMConfig, Vobject};
+
+  #[derive(Debug, Default)]
+  struct Counting {
+    live: Cell<usize>
+  }
+
+  impl ObjectAllocator for Counting {
+    fn allocate(&self, bytes: usize) -> bool {
+      self.live.set(self.live.get() + bytes);
+      true
+    }
+
+    fn free(&self, bytes: usize) {
+      self.live.set(self.live.get() - bytes);
+    }
+  }
+
+  #[test]
+  fn dropping_the_vm_breaks_cycles() {
+    println!("Live cycles are reclaimed when the VM goes away.");
+
+    for strategy in [GcStrategy::MarkSweep, GcStrategy::Generational] {
+      let counting = Rc::new(Counting::default());
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).allocator(counting.clone()));
+      vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+      let a = vm.push_pair().unwrap();
+      vm.push_int(3).unwrap();
+      vm.push_int(4).unwrap();
+      let b = vm.push_pair().unwrap();
+
+      // As in test4, but with both still on the stack.
+      if let Vobject::Pair(_, ref mut x) = a.1.borrow_mut().val { *x = b.clone() }
+      if let Vobject::Pair(_, ref mut x) = b.1.borrow_mut().val { *x = a.clone() }
+      vm.gc();
+      let weak = (Rc::downgrade(&a), Rc::downgrade(&b));
+
+      drop((a, b));
+      drop(vm);
+      assert!(weak.0.upgrade().is_none() && weak.1.upgrade().is_none());
+      assert!(counting.live.get() == 0);
+    }
+  }
+
+  #[test]
+  fn handles_outlive_the_vm_as_freed() {
+    println!("A handle kept past the VM holds a freed object and nothing else.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    let one = Rc::downgrade(&vm.push_int(2).unwrap());
+    let p = vm.push_pair().unwrap();
+    drop(vm);
+
+    assert!(p.0.get().freed());
+    assert!(one.upgrade().is_none());
+  }
+}
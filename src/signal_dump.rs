@@ -0,0 +1,98 @@
+// Heap dumps on demand from outside the process: after
+// `VM::dump_on_signal(path)`, `kill -USR1 <pid>` makes the VM write
+//
+//   {"stats": {"strategy": "mark-sweep", "objects": 3, ...},
+//    "heap": <heap_dump_json>}
+//
+// to `path`, replacing what was there. The signal handler only sets a
+// flag; the VM isn't safe to touch from one, so the dump is written by the
+// next operation that goes through the VM (a push, pop, store or
+// collection). A VM sitting idle dumps when it is next used.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::string::String;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use signal_hook::consts::SIGUSR1;
+use signal_hook::flag;
+
+use VM;
+
+// Where to dump, and the flag the handler raises.
+#[derive(Debug)]
+pub(crate) struct SignalDump {
+  path: PathBuf,
+  raised: Arc<AtomicBool>
+}
+
+impl VM {
+  /// Writes a heap dump and collector stats to `path` each time the
+  /// process gets SIGUSR1; see the module source. Replaces SIGUSR1's
+  /// default action, which is to exit.
+  pub fn dump_on_signal<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+    let raised = Arc::new(AtomicBool::new(false));
+    flag::register(SIGUSR1, raised.clone())?;
+    self.signal_dump = Some(SignalDump { path: path.as_ref().to_path_buf(), raised });
+    Ok(())
+  }
+
+  // Writes the dump if the signal came since the last look.
+  #[inline]
+  pub(crate) fn check_signal_dump(&mut self) {
+    let path = match self.signal_dump {
+      Some(ref dump) if dump.raised.swap(false, Ordering::Relaxed) => dump.path.clone(),
+      _ => return
+    };
+
+    let mut out = String::from("{\"stats\": ");
+    out.push_str(&self.counters_json());
+    out.push_str(",\n \"heap\": ");
+    out.push_str(&self.heap_dump_json());
+    out.push_str("}\n");
+    if let Err(e) = fs::write(&path, out) {
+      eprintln!("[gc] heap dump to {} failed: {}", path.display(), e);
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  extern crate serde_json;
+
+  use self::serde_json::Value;
+  use super::*;
+  use signal_hook::low_level::raise;
+  use std::env;
+  use std::process;
+
+  #[test]
+  fn sigusr1_dumps_the_heap() {
+    println!("SIGUSR1 makes the VM's next operation write a dump.");
+
+    let path = env::temp_dir().join(format!("babygc-signal-{}.json", process::id()));
+    let _ = fs::remove_file(&path);
+    let mut vm = VM::new();
+    vm.dump_on_signal(&path).unwrap();
+    vm.push_value(&(1u32, 2u32)).unwrap();
+    assert!(!path.exists());
+
+    raise(SIGUSR1).unwrap();
+    vm.push_int(3).unwrap();
+    let doc: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert!(doc["stats"]["objects"] == 4 && doc["stats"]["strategy"] == "mark-sweep");
+    assert!(doc["heap"]["stack"].as_array().unwrap().len() == 2);
+
+    // Once per signal.
+    fs::remove_file(&path).unwrap();
+    vm.pop();
+    assert!(!path.exists());
+  }
+}
@@ -0,0 +1,37 @@
+// Heap sizing: when allocation should trigger a full collection, and what
+// the threshold becomes afterwards.
+
+use core::fmt;
+
+pub trait SizingPolicy: fmt::Debug {
+  /// Whether a heap holding `live` objects has outgrown `threshold`.
+  fn should_collect(&self, live: usize, threshold: usize) -> bool {
+    live >= threshold
+  }
+
+  /// The threshold after a full collection that began with `before`
+  /// objects and left `after` alive.
+  fn next_threshold(&self, before: usize, after: usize) -> usize;
+}
+
+/// The original rule: collect once the heap reaches the threshold, then
+/// double the pre-collection size.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DoublingPolicy;
+
+impl SizingPolicy for DoublingPolicy {
+  fn next_threshold(&self, before: usize, _after: usize) -> usize {
+    before.saturating_mul(2)
+  }
+}
+
+/// Like `DoublingPolicy`, growing the pre-collection size by any factor.
+/// `GrowthPolicy(2.0)` doubles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GrowthPolicy(pub f64);
+
+impl SizingPolicy for GrowthPolicy {
+  fn next_threshold(&self, before: usize, _after: usize) -> usize {
+    (before as f64 * self.0).max(1.0) as usize
+  }
+}
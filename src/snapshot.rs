@@ -0,0 +1,99 @@
+// Serde support for saving and restoring a VM's heap. Objects are written
+// once each and refer to one another by id, so shared structure and cycles
+// survive the round trip:
+//
+//   {"stack": [2],
+//    "objects": [{"kind": "int", "value": 1, "old": false},
+//                {"kind": "int", "value": 2, "old": false},
+//                {"kind": "pair", "head": 0, "tail": 2, "old": false}]}
+//
+// Only the stack and heap are saved. A restored VM starts from the default
+// `VMConfig` with no collection in progress. A heap holding native values
+// fails to serialize.
+
+use alloc::vec::Vec;
+
+use serde::de::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use image::Node;
+use VM;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+  stack: &'a [usize],
+  objects: &'a [Node]
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+  stack: Vec<usize>,
+  objects: Vec<Node>
+}
+
+impl Serialize for VM {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let (stack, objects) = self.nodes();
+    SnapshotRef { stack: &stack, objects: &objects }.serialize(serializer)
+  }
+}
+
+impl<'de> Deserialize<'de> for VM {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<VM, D::Error> {
+    let snapshot = Snapshot::deserialize(deserializer)?;
+    VM::from_nodes(&snapshot.stack, &snapshot.objects)
+      .map_err(|id| D::Error::custom(format_args!("no object with id {}", id)))
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  extern crate serde_json;
+
+  use super::*;
+  use alloc::rc::Rc;
+  use Vobject;
+
+  #[test]
+  fn cycles_round_trip() {
+    println!("Shared structure and cycles survive serialization.");
+
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    let two = vm.push_int(2).unwrap();
+    let a = vm.push_pair().unwrap();
+    if let Vobject::Pair(_, ref mut x) = a.1.borrow_mut().val { *x = a.clone() }
+    vm.stack.push(two);
+    vm.gc();
+
+    let json = serde_json::to_string(&vm).unwrap();
+    let mut copy: VM = serde_json::from_str(&json).unwrap();
+    assert!(serde_json::to_string(&copy).unwrap() == json);
+
+    // The pair's tail is still the pair itself.
+    let a = copy.stack[0].clone();
+    if let Vobject::Pair(ref head, ref tail) = a.1.borrow().val {
+      assert!(Rc::ptr_eq(tail, &a));
+      assert!(matches!(head.1.borrow().val, Vobject::Int(1)));
+    } else {
+      panic!("expected a pair");
+    }
+
+    copy.pop();
+    assert!(copy.gc() == 1);
+    assert!(copy.heap.len() == 2);
+  }
+
+  #[test]
+  fn rejects_dangling_ids() {
+    println!("Snapshots that name missing objects are refused.");
+
+    let json = r#"{"stack": [0], "objects": [{"kind": "pair", "head": 0, "tail": 7, "old": true}]}"#;
+    assert!(serde_json::from_str::<VM>(json).is_err());
+  }
+}
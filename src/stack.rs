@@ -0,0 +1,149 @@
+// Read-only access to the stack for embedders and debuggers, a way to
+// drop several slots at once, and a copy of the roots as a list the
+// program itself can walk. Slots are numbered from the bottom, as in
+// `Source::Stack`.
+
+use alloc::vec::Vec;
+use core::slice;
+
+use tracer::Source;
+use {Object, Phase, Sobject, VM, VmError, Vobject};
+
+impl VM {
+  pub fn stack_len(&self) -> usize {
+    self.stack.len()
+  }
+
+  /// Slot `i`, counting from the bottom, if there is one.
+  pub fn stack_get(&self, i: usize) -> Option<&Sobject> {
+    self.stack.get(i)
+  }
+
+  /// The stack, bottom first.
+  pub fn iter_stack(&self) -> slice::Iter<'_, Sobject> {
+    self.stack.iter()
+  }
+
+  /// Pops slots until at most `n` are left. Whatever they alone kept alive
+  /// is garbage from then on.
+  pub fn truncate_stack(&mut self, n: usize) {
+    self.stack.truncate(n);
+    self.after("truncate_stack");
+  }
+
+  /// Pushes a list of the stack, bottom first, encoded as `Vec` is (pairs
+  /// ending in 0). With `persistent`, objects rooted by persistent handles
+  /// follow, oldest handle first. The list is allocated all at once, so
+  /// it doesn't include itself; if it can't be, nothing is.
+  pub fn roots_as_list(&mut self, persistent: bool) -> Result<Sobject, VmError> {
+    let n = self.stack.len() + if persistent { self.persistent.len() } else { 0 };
+    Object::reserve(self, n + 1)?;
+
+    let roots: Vec<Sobject> = self.roots().filter_map(|(source, obj)| match source {
+      Source::Stack(_) => Some(obj),
+      Source::Persistent if persistent => Some(obj),
+      _ => None
+    }).collect();
+    // The new pairs are black if marking is under way.
+    if self.phase == Phase::Mark {
+      for root in &roots {
+        Object::mark(root, &mut self.gray);
+      }
+    }
+
+    let mut list = Object::place(self, Vobject::Int(0));
+    for root in roots.into_iter().rev() {
+      list = Object::place(self, Vobject::Pair(root, list));
+    }
+    self.stack.push(list.clone());
+    self.after("roots_as_list");
+    Ok(list)
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use {GcStrategy, VMConfig};
+
+  #[test]
+  fn stack_is_readable_and_truncatable() {
+    println!("The stack can be read slot by slot and cut back.");
+
+    let mut vm = VM::new();
+    let one = vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    vm.push_int(3).unwrap();
+    assert!(vm.stack_len() == 3 && Rc::ptr_eq(vm.stack_get(0).unwrap(), &one) && vm.stack_get(3).is_none());
+    let ints: Vec<u32> = vm.iter_stack().rev().map(|obj| vm.as_int(obj).unwrap()).collect();
+    assert!(ints == [3, 2, 1]);
+
+    vm.truncate_stack(5);
+    assert!(vm.stack_len() == 3);
+    vm.truncate_stack(1);
+    assert!(vm.stack_len() == 1);
+    vm.gc();
+    assert!(vm.iter_heap().count() == 1);
+  }
+
+  #[test]
+  fn roots_become_a_list() {
+    println!("The roots can be copied into a list on the heap.");
+
+    for strategy in GcStrategy::ALL {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy));
+      vm.push_int(1).unwrap();
+      vm.push_int(2).unwrap();
+      let three = vm.push_int(3).unwrap();
+      vm.pop();
+      vm.persist(&three);
+
+      let list = vm.roots_as_list(false).unwrap();
+      assert!(vm.extract::<Vec<u32>>(&list) == Ok(vec![1, 2]));
+      let all = vm.roots_as_list(true).unwrap();
+      let (head, _) = vm.as_pair(&all).unwrap();
+      assert!(Rc::ptr_eq(&head, &vm.stack[0]));
+
+      // Only the list keeps the stack's old contents now.
+      let ints: Vec<Sobject> = vm.stack.drain(..2).collect();
+      vm.gc_full();
+      assert!(ints.iter().all(|int| !vm.is_freed(int)));
+      vm.verify().unwrap();
+      assert!(vm.stack.len() == 2 && vm.objects() == 3 + 3 + 5);
+    }
+  }
+
+  #[test]
+  fn roots_lists_are_all_or_nothing() {
+    println!("A list of the roots that doesn't fit isn't made at all.");
+
+    let mut vm = VM::with_config(VMConfig::new().max_heap(4));
+    vm.push_ints(&[1, 2]).unwrap();
+    assert!(vm.roots_as_list(false).err() == Some(VmError::OutOfMemory));
+    assert!(vm.stack.len() == 2 && vm.objects() == 2);
+  }
+
+  #[test]
+  fn truncating_mid_cycle() {
+    println!("Slots dropped during incremental marking are collected by the next cycle.");
+
+    let mut vm = VM::with_config(VMConfig::new().host_driven(1).threshold(4));
+    for i in 0..4 {
+      vm.push_int(i).unwrap();
+    }
+    vm.push_value(&(4u32, 5u32)).unwrap();
+    assert!(vm.collecting());
+    vm.truncate_stack(2);
+    while !vm.tick() {}
+    vm.verify().unwrap();
+
+    vm.gc();
+    assert!(vm.iter_heap().count() == 2);
+  }
+}
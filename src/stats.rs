@@ -0,0 +1,64 @@
+#[cfg(feature = "hdr")]
+use hdr::PauseHistogram;
+use time::Duration;
+#[cfg(feature = "std")]
+use time::Instant;
+
+/// Upper bounds of the `GcStats::pause_histogram` buckets. A last, unbounded
+/// bucket catches everything slower.
+pub const PAUSE_BUCKETS: [Duration; 6] = [
+  Duration::from_micros(10),
+  Duration::from_micros(100),
+  Duration::from_millis(1),
+  Duration::from_millis(10),
+  Duration::from_millis(100),
+  Duration::from_secs(1)
+];
+
+/// Running totals kept by the collector.
+#[derive(Clone, Debug, Default)]
+pub struct GcStats {
+  /// Collector pauses, automatic or requested. An incremental slice counts
+  /// as one pause.
+  pub pauses: u64,
+  /// Longest pause. Pauses are only timed with the `std` feature.
+  pub max_pause: Duration,
+  /// Sum of all pauses.
+  pub total_pause: Duration,
+  /// Pauses per `PAUSE_BUCKETS` bucket.
+  pub pause_histogram: [u64; 7],
+  /// Pauses longer than `VMConfig::pause_target`.
+  pub pause_target_misses: u64,
+  /// Collections finished, full and minor. Incremental slices of one
+  /// collection count once.
+  pub full_collections: u64,
+  pub minor_collections: u64,
+  /// Objects freed by collections, full and minor.
+  pub objects_freed: u64,
+  /// When the last collection finished. Only kept with the `std` feature.
+  #[cfg(feature = "std")]
+  pub last_collection: Option<Instant>,
+  /// Full collections put off under `VMConfig::skip_survival`.
+  pub skipped_collections: u64,
+  /// Most objects the VM has held at once.
+  pub peak_objects: usize,
+  /// Dirty cards scanned by minor collections, in total and in the last
+  /// one.
+  pub cards_scanned: u64,
+  pub last_cards_scanned: u64,
+  /// Old objects by collections survived, as of the last collection. The
+  /// last bucket counts 7 or more.
+  pub age_histogram: [u64; 8],
+  /// Regions freed wholesale, and regions that had to join the heap
+  /// because something outside pointed into them.
+  pub regions_dropped: u64,
+  pub regions_escaped: u64,
+  /// Allocations that found the VM already holding the objects
+  /// `VMConfig::reserve_objects` made room for.
+  pub reservation_exceeded: u64,
+  /// Bytes of list capacity given back under `VMConfig::compact_per_cycle`.
+  pub bytes_compacted: u64,
+  /// Every pause, to two significant digits.
+  #[cfg(feature = "hdr")]
+  pub pause_hdr: PauseHistogram
+}
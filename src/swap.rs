@@ -0,0 +1,124 @@
+// Rewriting both halves of a pair at once, for in-place list algorithms
+// such as reversal. Both writes go in before the one barrier call, so an
+// incremental cycle never sees the pair half-changed.
+
+use core::mem;
+
+use {Operand, Sobject, VM, VmError, Vobject};
+
+impl VM {
+  /// Exchanges the head and tail of `pair`. Fails as `set_head` does.
+  pub fn pair_swap(&mut self, pair: &Sobject) -> Result<(), VmError> {
+    self.check_store(pair)?;
+
+    if let Vobject::Pair(ref mut h, ref mut t) = pair.1.borrow_mut().val {
+      mem::swap(h, t);
+    }
+    self.write_barrier(pair);
+    Ok(())
+  }
+
+  /// Points the head of `pair` at `head` and its tail at `tail`. Either
+  /// can be a handle or a Rust int, as for `set_head`; nothing is stored
+  /// unless both can be.
+  pub fn pair_replace<H: Operand, T: Operand>(&mut self, pair: &Sobject, head: H, tail: T) -> Result<(), VmError> {
+    self.check_store(pair)?;
+
+    let n = self.temp_roots.len();
+    self.temp_roots.push(pair.clone());
+    let vals = head.into_object(self).and_then(|head| {
+      self.temp_roots.push(head.clone());
+      tail.into_object(self).map(|tail| (head, tail))
+    });
+    self.temp_roots.truncate(n);
+    let (head, tail) = vals?;
+    if self.is_freed(&head) || self.is_freed(&tail) {
+      return Err(VmError::Freed);
+    }
+
+    pair.1.borrow_mut().val = Vobject::Pair(head, tail);
+    self.write_barrier(pair);
+    Ok(())
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use alloc::vec::Vec;
+  use {GcStrategy, VMConfig};
+
+  // Reverses a list of pairs ending in 0 in place, as `Vec<u32>` encodes
+  // it, and returns the new first cell.
+  fn reverse(vm: &mut VM, list: Sobject, end: Sobject) -> Sobject {
+    let mut prev = end;
+    let mut cell = list;
+    while let Ok((head, next)) = vm.as_pair(&cell) {
+      vm.pair_replace(&cell, &head, &prev).unwrap();
+      prev = cell;
+      cell = next;
+    }
+    prev
+  }
+
+  #[test]
+  fn lists_reverse_in_place() {
+    println!("A list reversed in place mid-cycle keeps every cell.");
+
+    // Reversing after a few ticks points black cells at white ones; after
+    // more, it points cells still to be swept at new young objects.
+    for strategy in GcStrategy::ALL {
+      for &ticks in &[5, 40] {
+        let mut vm = VM::with_config(VMConfig::new().strategy(strategy).host_driven(1).threshold(64));
+        let vals: Vec<u32> = (0..20).collect();
+        let list = vm.push_value(&vals).unwrap();
+        while !vm.collecting() {
+          vm.push_int(0).unwrap();
+          vm.pop();
+        }
+        for _ in 0..ticks {
+          vm.tick();
+        }
+
+        let end = vm.push_int(0).unwrap();
+        let reversed = reverse(&mut vm, list, end);
+        vm.stack[0] = reversed.clone();
+        vm.pop();
+        while !vm.tick() {}
+        vm.verify().unwrap();
+        vm.gc_full();
+
+        let back: Vec<u32> = vm.extract(&reversed).unwrap();
+        assert!(back == (0..20).rev().collect::<Vec<u32>>());
+        assert!(vm.objects() == 41);
+      }
+    }
+  }
+
+  #[test]
+  fn swaps_and_replacements() {
+    println!("Swapping exchanges the halves; replacing sets both or neither.");
+
+    let mut vm = VM::with_config(VMConfig::new().max_heap(5));
+    let one = vm.push_int(1).unwrap();
+    let two = vm.push_int(2).unwrap();
+    let p = vm.push_pair_from(&one, &two).unwrap();
+    vm.pair_swap(&p).unwrap();
+    assert!(vm.extract::<(u32, u32)>(&p) == Ok((2, 1)));
+
+    vm.pair_replace(&p, 3, &p).unwrap();
+    assert!(vm.pair_replace(&p, 4, 5) == Err(VmError::OutOfMemory));
+    let (head, tail) = vm.as_pair(&p).unwrap();
+    assert!(vm.as_int(&head) == Ok(3) && Rc::ptr_eq(&tail, &p));
+
+    assert!(vm.pair_swap(&one).is_err());
+    vm.freeze(&p);
+    assert!(vm.pair_swap(&p) == Err(VmError::Frozen));
+  }
+}
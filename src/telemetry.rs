@@ -0,0 +1,114 @@
+// Collector events sent down a channel, for a monitoring thread to pick
+// up in its own time. Sending never blocks, so nothing the monitor does
+// lengthens a pause, and a monitor that has hung up is ignored. Events
+// go in the order they happen: an `Alloc` for every object allocated,
+// `GcStart` and `GcEnd` around each collection (the start of its first
+// slice and the end of its last, if incremental) and `ThresholdChange`
+// whenever the next full collection moves.
+
+use std::boxed::Box;
+use std::sync::mpsc::Sender;
+
+use {GcStats, Sobject, VM};
+
+/// What `VMConfig::telemetry` sends. `kind` is `full` or `minor`, as in
+/// the GC log.
+#[derive(Clone, Debug)]
+pub enum GcEvent {
+  Alloc { id: u64 },
+  GcStart { kind: &'static str },
+  /// `stats` are as of the end of the collection, before its last pause
+  /// is counted.
+  GcEnd { kind: &'static str, freed: usize, live: usize, stats: Box<GcStats> },
+  ThresholdChange { old: usize, new: usize }
+}
+
+// The sending end, kept by the VM's config.
+pub(crate) type Telemetry = Sender<GcEvent>;
+
+impl VM {
+  fn send(&self, event: GcEvent) {
+    if let Some(ref telemetry) = self.config.telemetry {
+      let _ = telemetry.send(event);
+    }
+  }
+
+  #[inline]
+  pub(crate) fn send_alloc(&self, obj: &Sobject) {
+    if self.config.telemetry.is_some() {
+      self.send(GcEvent::Alloc { id: obj.2 });
+    }
+  }
+
+  pub(crate) fn send_start(&self, kind: &'static str) {
+    self.send(GcEvent::GcStart { kind });
+  }
+
+  // A collection that started with `threshold` is done.
+  pub(crate) fn send_end(&self, kind: &'static str, freed: usize, threshold: usize) {
+    if self.config.telemetry.is_some() {
+      self.send(GcEvent::GcEnd { kind, freed, live: self.objects(), stats: Box::new(self.stats.clone()) });
+      self.send_threshold(threshold);
+    }
+  }
+
+  pub(crate) fn send_threshold(&self, old: usize) {
+    if old != self.heap_max {
+      self.send(GcEvent::ThresholdChange { old, new: self.heap_max });
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::mpsc;
+  use std::thread;
+  use std::vec::Vec;
+  use VMConfig;
+
+  #[test]
+  fn events_reach_another_thread() {
+    println!("A monitoring thread hears about allocations and collections.");
+
+    let (tx, rx) = mpsc::channel();
+    let monitor = thread::spawn(move || rx.iter().collect::<Vec<GcEvent>>());
+
+    let mut vm = VM::with_config(VMConfig::new().threshold(4).telemetry(tx));
+    for i in 0..5 {
+      vm.push_int(i).unwrap();
+    }
+    vm.truncate_stack(0);
+    vm.gc();
+    drop(vm);
+
+    let events = monitor.join().unwrap();
+    let allocs = events.iter().filter(|e| matches!(e, GcEvent::Alloc { .. })).count();
+    assert!(allocs == 5);
+    assert!(matches!(events[0], GcEvent::Alloc { id: 0 }));
+    assert!(matches!(events[4], GcEvent::GcStart { kind: "full" }));
+    match events[5] {
+      GcEvent::GcEnd { kind: "full", freed: 0, live: 4, ref stats } => assert!(stats.full_collections == 1),
+      ref e => panic!("expected the end of a collection, got {:?}", e)
+    }
+    assert!(matches!(events[6], GcEvent::ThresholdChange { old: 4, new: 8 }));
+    assert!(matches!(events[9], GcEvent::GcEnd { freed: 5, live: 0, .. }));
+    assert!(events.len() == 11);
+  }
+
+  #[test]
+  fn hung_up_monitors_are_ignored() {
+    println!("The VM carries on when nobody is listening.");
+
+    let (tx, rx) = mpsc::channel();
+    drop(rx);
+    let mut vm = VM::with_config(VMConfig::new().telemetry(tx));
+    vm.push_int(1).unwrap();
+    vm.gc();
+  }
+}
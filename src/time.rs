@@ -0,0 +1,11 @@
+// The clocks the collector uses for pauses, deadlines and the GC log. On the web it comes
+// from web-time, since std's Instant panics there; elsewhere it is std's.
+// Without `std` there is no clock at all.
+
+pub use core::time::Duration;
+
+#[cfg(all(feature = "std", not(all(target_arch = "wasm32", target_os = "unknown"))))]
+pub use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(all(feature = "std", target_arch = "wasm32", target_os = "unknown"))]
+pub use web_time::{Instant, SystemTime, UNIX_EPOCH};
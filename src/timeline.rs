@@ -0,0 +1,236 @@
+// A timeline of the heap's life for web visualizers: every allocation,
+// store and free, and each collection's phases, stamped with microseconds
+// since the VM was made. `VM::timeline_json` writes it as
+//
+//   {"version":1,"events":[
+//   {"t":0,"event":"alloc","id":0,"int":1},
+//   {"t":3,"event":"alloc","id":2,"head":0,"tail":1},
+//   {"t":5,"event":"store","id":2,"head":0,"tail":2},
+//   {"t":9,"event":"gc","phase":"mark","kind":"full","roots":[2]},
+//   {"t":12,"event":"gc","phase":"sweep","kind":"full"},
+//   {"t":13,"event":"free","id":1},
+//   {"t":14,"event":"gc","phase":"done","kind":"full","freed":1}
+//   ]}
+//
+// Objects are named by `VM::object_id`, constants included. An alloc or
+// store gives the object's whole value: `int`, or `head` and `tail`. A
+// collection's `mark` phase lists its roots, in the order it marks them,
+// the stack bottom first; `kind` is `full` or `minor`, as in the GC log.
+// Rolling back to a checkpoint shows up as allocs for the objects it
+// revives and stores for the rest.
+
+use std::fmt::Write;
+use std::string::String;
+use std::vec::Vec;
+
+use history::SnapshotValue;
+use time::Instant;
+use {Sobject, VM, Vobject};
+
+#[derive(Debug)]
+enum Event {
+  Alloc { id: u64, value: SnapshotValue },
+  Store { id: u64, value: SnapshotValue },
+  Free { id: u64 },
+  Mark { kind: &'static str, roots: Vec<u64> },
+  Sweep { kind: &'static str },
+  Done { kind: &'static str, freed: usize }
+}
+
+#[derive(Debug)]
+pub(crate) struct Timeline {
+  start: Instant,
+  events: Vec<(u64, Event)>
+}
+
+impl Timeline {
+  pub(crate) fn new() -> Timeline {
+    Timeline { start: Instant::now(), events: Vec::new() }
+  }
+}
+
+pub(crate) fn value(obj: &Sobject) -> SnapshotValue {
+  match obj.1.borrow().val {
+    Vobject::Int(n) => SnapshotValue::Int(n),
+    Vobject::Pair(ref head, ref tail) => SnapshotValue::Pair(head.2, tail.2),
+    Vobject::Native(ref native) => SnapshotValue::Native(native.type_name())
+  }
+}
+
+fn write_value(out: &mut String, value: SnapshotValue) {
+  let _ = match value {
+    SnapshotValue::Int(n) => write!(out, ",\"int\":{}", n),
+    SnapshotValue::Pair(head, tail) => write!(out, ",\"head\":{},\"tail\":{}", head, tail),
+    SnapshotValue::Native(name) => write!(out, ",\"native\":\"{}\"", name)
+  };
+}
+
+impl VM {
+  /// The timeline recorded since the VM was made, as a JSON document, if
+  /// `VMConfig::timeline` asked for one.
+  pub fn timeline_json(&self) -> Option<String> {
+    let timeline = self.timeline.as_ref()?;
+    let mut out = String::from("{\"version\":1,\"events\":[\n");
+
+    for (i, &(t, ref event)) in timeline.events.iter().enumerate() {
+      if i > 0 {
+        out.push_str(",\n");
+      }
+      let _ = write!(out, "{{\"t\":{},", t);
+      let _ = match *event {
+        Event::Alloc { id, value } => {
+          let _ = write!(out, "\"event\":\"alloc\",\"id\":{}", id);
+          write_value(&mut out, value);
+          Ok(())
+        }
+        Event::Store { id, value } => {
+          let _ = write!(out, "\"event\":\"store\",\"id\":{}", id);
+          write_value(&mut out, value);
+          Ok(())
+        }
+        Event::Free { id } => write!(out, "\"event\":\"free\",\"id\":{}", id),
+        Event::Mark { kind, ref roots } => {
+          let roots: Vec<String> = roots.iter().map(u64::to_string).collect();
+          write!(out, "\"event\":\"gc\",\"phase\":\"mark\",\"kind\":\"{}\",\"roots\":[{}]", kind, roots.join(","))
+        }
+        Event::Sweep { kind } => write!(out, "\"event\":\"gc\",\"phase\":\"sweep\",\"kind\":\"{}\"", kind),
+        Event::Done { kind, freed } =>
+          write!(out, "\"event\":\"gc\",\"phase\":\"done\",\"kind\":\"{}\",\"freed\":{}", kind, freed)
+      };
+      out.push('}');
+    }
+
+    out.push_str("\n]}\n");
+    Some(out)
+  }
+
+  fn record(&mut self, event: Event) {
+    if let Some(ref mut timeline) = self.timeline {
+      let t = timeline.start.elapsed().as_micros() as u64;
+      timeline.events.push((t, event));
+    }
+  }
+
+  // These three also keep paranoid mode's shadow heap up to date.
+  #[inline]
+  pub(crate) fn record_alloc(&mut self, obj: &Sobject) {
+    self.send_alloc(obj);
+    if let Some(ref mut shadow) = self.shadow {
+      shadow.insert(obj.2, value(obj));
+    }
+    if self.timeline.is_some() {
+      self.record(Event::Alloc { id: obj.2, value: value(obj) });
+    }
+  }
+
+  #[inline]
+  pub(crate) fn record_store(&mut self, obj: &Sobject) {
+    if let Some(ref mut shadow) = self.shadow {
+      shadow.insert(obj.2, value(obj));
+    }
+    if self.timeline.is_some() {
+      self.record(Event::Store { id: obj.2, value: value(obj) });
+    }
+  }
+
+  #[inline]
+  pub(crate) fn record_free(&mut self, obj: &Sobject) {
+    if let Some(ref mut shadow) = self.shadow {
+      shadow.remove(&obj.2);
+    }
+    if self.timeline.is_some() {
+      self.record(Event::Free { id: obj.2 });
+    }
+  }
+
+  // A collection of `kind` starting to mark.
+  pub(crate) fn record_mark(&mut self, kind: &'static str) {
+    self.send_start(kind);
+    if self.timeline.is_some() {
+      let roots = self.roots().map(|(_, obj)| obj.2).collect();
+      self.record(Event::Mark { kind, roots });
+    }
+  }
+
+  pub(crate) fn record_sweep(&mut self, kind: &'static str) {
+    self.record(Event::Sweep { kind });
+  }
+
+  pub(crate) fn record_done(&mut self, kind: &'static str, freed: usize) {
+    self.record(Event::Done { kind, freed });
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  extern crate serde_json;
+
+  use self::serde_json::Value;
+  use super::*;
+  use {GcStrategy, VMConfig};
+
+  fn events(vm: &VM) -> Vec<String> {
+    let doc: Value = serde_json::from_str(&vm.timeline_json().unwrap()).unwrap();
+    assert!(doc["version"] == 1);
+    doc["events"].as_array().unwrap().iter().map(|e| {
+      let mut e = e.clone();
+      assert!(e.as_object_mut().unwrap().remove("t").unwrap().is_u64());
+      e.to_string()
+    }).collect()
+  }
+
+  #[test]
+  fn records_the_heaps_life() {
+    println!("Allocations, stores, frees and collection phases are all recorded.");
+
+    let mut vm = VM::with_config(VMConfig::new().timeline(true));
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    vm.set_tail(&p, &p).unwrap();
+    vm.gc_full();
+
+    assert!(events(&vm) == [
+      r#"{"event":"alloc","id":0,"int":1}"#,
+      r#"{"event":"alloc","id":1,"int":2}"#,
+      r#"{"event":"alloc","head":0,"id":2,"tail":1}"#,
+      r#"{"event":"store","head":0,"id":2,"tail":2}"#,
+      r#"{"event":"gc","kind":"full","phase":"mark","roots":[2]}"#,
+      r#"{"event":"gc","kind":"full","phase":"sweep"}"#,
+      r#"{"event":"free","id":1}"#,
+      r#"{"event":"gc","freed":1,"kind":"full","phase":"done"}"#
+    ]);
+    assert!(VM::new().timeline_json().is_none());
+  }
+
+  #[test]
+  fn minor_collections_and_rollbacks() {
+    println!("Minor collections and rollbacks show up in the timeline too.");
+
+    let config = VMConfig::new().strategy(GcStrategy::Generational).timeline(true);
+    let mut vm = VM::with_config(config);
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    let checkpoint = vm.checkpoint();
+    vm.set_tail(&p, &p).unwrap();
+    vm.gc_minor();
+    vm.rollback(&checkpoint).unwrap();
+
+    assert!(events(&vm)[3..] == [
+      r#"{"event":"store","head":0,"id":2,"tail":2}"#,
+      r#"{"event":"gc","kind":"minor","phase":"mark","roots":[2]}"#,
+      r#"{"event":"gc","kind":"minor","phase":"sweep"}"#,
+      r#"{"event":"free","id":1}"#,
+      r#"{"event":"gc","freed":1,"kind":"minor","phase":"done"}"#,
+      r#"{"event":"store","id":0,"int":1}"#,
+      r#"{"event":"alloc","id":1,"int":2}"#,
+      r#"{"event":"store","head":0,"id":2,"tail":1}"#
+    ]);
+  }
+}
@@ -0,0 +1,349 @@
+// A running commentary on the collector, for teaching the algorithm: every
+// object it marks, passes over or sweeps, and why, as a `TraceEvent` handed
+// to the configured `Tracer`. With no tracer each decision costs a branch.
+//
+//   marking #5 (pair #3 #4) from stack[2]
+//   skipping #3, already marked
+//   sweeping #9 (int 4)
+//   keeping #12 <list head>
+//
+// `SlowMotion` streams the same events over a channel, pausing after each
+// one, to drive an animation of a collection as it happens.
+
+use core::fmt;
+#[cfg(feature = "std")]
+use std::string::{String, ToString};
+#[cfg(feature = "std")]
+use std::sync::mpsc::{self, Receiver, Sender};
+#[cfg(feature = "std")]
+use std::thread;
+
+use history::SnapshotValue;
+#[cfg(feature = "std")]
+use time::Duration;
+use {Sobject, VM, Vobject};
+
+/// Where the collector found an object.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+  /// A stack slot, counted from the bottom.
+  Stack(usize),
+  Persistent,
+  /// A `RootProvider`.
+  Provider,
+  /// The region under way, all of which is kept.
+  Region,
+  /// An operand of the allocation under way.
+  Temp,
+  /// The pair with this id.
+  Object(u64)
+}
+
+/// What the collector decided about object `id`, which has the debug
+/// label `label`, if any.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceEvent<'a> {
+  /// `id` is reachable, and its children will be looked at.
+  Mark { id: u64, label: Option<&'a str>, value: SnapshotValue, from: Source },
+  /// `id` was reached again after being marked.
+  AlreadyMarked { id: u64, label: Option<&'a str> },
+  /// A minor collection reached `id`, which is older than the generations
+  /// it collects.
+  TooOld { id: u64, label: Option<&'a str> },
+  /// `id` was marked, so it stays.
+  Keep { id: u64, label: Option<&'a str> },
+  /// `id` was never marked, and is freed.
+  Sweep { id: u64, label: Option<&'a str>, value: SnapshotValue }
+}
+
+/// Called in the middle of a collection, so it gets no VM to allocate
+/// with or collect.
+pub trait Tracer: fmt::Debug {
+  fn event(&self, event: &TraceEvent<'_>);
+}
+
+/// Prints each event to stderr, as `BABYGC_TRACE=1` does.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PrintTracer;
+
+#[cfg(feature = "std")]
+impl Tracer for PrintTracer {
+  fn event(&self, event: &TraceEvent<'_>) {
+    eprintln!("[gc] {}", event);
+  }
+}
+
+impl<'a> TraceEvent<'a> {
+  pub fn id(&self) -> u64 {
+    match *self {
+      TraceEvent::Mark { id, .. } | TraceEvent::AlreadyMarked { id, .. } | TraceEvent::TooOld { id, .. }
+        | TraceEvent::Keep { id, .. } | TraceEvent::Sweep { id, .. } => id
+    }
+  }
+
+  pub fn label(&self) -> Option<&'a str> {
+    match *self {
+      TraceEvent::Mark { label, .. } | TraceEvent::AlreadyMarked { label, .. } | TraceEvent::TooOld { label, .. }
+        | TraceEvent::Keep { label, .. } | TraceEvent::Sweep { label, .. } => label
+    }
+  }
+
+  /// The same event with a different label.
+  pub fn with_label<'b>(&self, label: Option<&'b str>) -> TraceEvent<'b> {
+    match *self {
+      TraceEvent::Mark { id, value, from, .. } => TraceEvent::Mark { id, label, value, from },
+      TraceEvent::AlreadyMarked { id, .. } => TraceEvent::AlreadyMarked { id, label },
+      TraceEvent::TooOld { id, .. } => TraceEvent::TooOld { id, label },
+      TraceEvent::Keep { id, .. } => TraceEvent::Keep { id, label },
+      TraceEvent::Sweep { id, value, .. } => TraceEvent::Sweep { id, label, value }
+    }
+  }
+}
+
+/// A `TraceEvent` that owns its label, as `SlowMotion` sends them.
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Step {
+  pub event: TraceEvent<'static>,
+  pub label: Option<String>
+}
+
+#[cfg(feature = "std")]
+impl Step {
+  /// The event with its label.
+  pub fn event(&self) -> TraceEvent<'_> {
+    self.event.with_label(self.label.as_deref())
+  }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for Step {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    self.event().fmt(f)
+  }
+}
+
+/// Sends every event down a channel as a `Step`, then waits `delay` so
+/// whoever is watching can keep up. Once the receiver is gone the
+/// collector runs at full speed again.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct SlowMotion {
+  tx: Sender<Step>,
+  delay: Duration
+}
+
+#[cfg(feature = "std")]
+impl SlowMotion {
+  /// A tracer pausing `delay` after each step, and the receiving end of
+  /// its channel.
+  pub fn channel(delay: Duration) -> (SlowMotion, Receiver<Step>) {
+    let (tx, rx) = mpsc::channel();
+    (SlowMotion { tx, delay }, rx)
+  }
+}
+
+#[cfg(feature = "std")]
+impl Tracer for SlowMotion {
+  fn event(&self, event: &TraceEvent<'_>) {
+    let step = Step { event: event.with_label(None), label: event.label().map(str::to_string) };
+    if self.tx.send(step).is_ok() && !self.delay.is_zero() {
+      thread::sleep(self.delay);
+    }
+  }
+}
+
+struct Name<'a>(u64, Option<&'a str>);
+
+impl<'a> fmt::Display for Name<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.1 {
+      Some(label) => write!(f, "#{} <{}>", self.0, label),
+      None => write!(f, "#{}", self.0)
+    }
+  }
+}
+
+struct Value(SnapshotValue);
+
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.0 {
+      SnapshotValue::Int(n) => write!(f, "(int {})", n),
+      SnapshotValue::Pair(head, tail) => write!(f, "(pair #{} #{})", head, tail),
+      SnapshotValue::Native(name) => write!(f, "(native {})", name)
+    }
+  }
+}
+
+impl<'a> fmt::Display for TraceEvent<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      TraceEvent::Mark { id, label, value, from } => {
+        write!(f, "marking {} {} from ", Name(id, label), Value(value))?;
+        match from {
+          Source::Stack(i) => write!(f, "stack[{}]", i),
+          Source::Persistent => write!(f, "a persistent handle"),
+          Source::Provider => write!(f, "a root provider"),
+          Source::Region => write!(f, "the region"),
+          Source::Temp => write!(f, "an allocation's operands"),
+          Source::Object(parent) => write!(f, "#{}", parent)
+        }
+      }
+      TraceEvent::AlreadyMarked { id, label } => write!(f, "skipping {}, already marked", Name(id, label)),
+      TraceEvent::TooOld { id, label } =>
+        write!(f, "skipping {}, too old for this collection", Name(id, label)),
+      TraceEvent::Keep { id, label } => write!(f, "keeping {}", Name(id, label)),
+      TraceEvent::Sweep { id, label, value } => write!(f, "sweeping {} {}", Name(id, label), Value(value))
+    }
+  }
+}
+
+fn value(obj: &Sobject) -> SnapshotValue {
+  match obj.1.borrow().val {
+    Vobject::Int(n) => SnapshotValue::Int(n),
+    Vobject::Pair(ref head, ref tail) => SnapshotValue::Pair(head.2, tail.2),
+    Vobject::Native(ref native) => SnapshotValue::Native(native.type_name())
+  }
+}
+
+impl VM {
+  // Reports what marking `obj`, found from `from`, is about to do. `young`
+  // is the oldest generation a minor collection is collecting.
+  #[inline]
+  pub(crate) fn note_mark(&self, obj: &Sobject, from: Source, young: Option<usize>) {
+    if let Some(ref tracer) = self.config.tracer {
+      let gch = obj.0.get();
+      let id = obj.2;
+      let label = self.label_of(id);
+      let event = if gch.marked() {
+        TraceEvent::AlreadyMarked { id, label }
+      } else if young.is_some_and(|k| gch.old() || gch.generation() > k) {
+        TraceEvent::TooOld { id, label }
+      } else {
+        TraceEvent::Mark { id, label, value: value(obj), from }
+      };
+      tracer.event(&event);
+    }
+  }
+
+  // Reports the sweep's verdict on `obj`.
+  #[inline]
+  pub(crate) fn note_sweep(&self, obj: &Sobject) {
+    if let Some(ref tracer) = self.config.tracer {
+      let id = obj.2;
+      let label = self.label_of(id);
+      let event = if obj.0.get().marked() {
+        TraceEvent::Keep { id, label }
+      } else {
+        TraceEvent::Sweep { id, label, value: value(obj) }
+      };
+      tracer.event(&event);
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use alloc::string::{String, ToString};
+  use alloc::vec::Vec;
+  use core::cell::RefCell;
+  use {GcStrategy, VMConfig};
+
+  #[derive(Debug, Default)]
+  struct Lines(RefCell<Vec<String>>);
+
+  impl Tracer for Rc<Lines> {
+    fn event(&self, event: &TraceEvent<'_>) {
+      self.0.borrow_mut().push(event.to_string());
+    }
+  }
+
+  #[test]
+  fn full_collections_explain_themselves() {
+    println!("Every mark and sweep decision is reported, in order.");
+
+    let lines = Rc::new(Lines::default());
+    let mut vm = VM::with_config(VMConfig::new().tracer(lines.clone()));
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    vm.set_tail(&p, &p).unwrap();
+    vm.set_label(&p, "loop");
+    vm.push_int(3).unwrap();
+    vm.pop();
+    vm.gc_full();
+
+    assert!(*lines.0.borrow() == [
+      "marking #2 <loop> (pair #0 #2) from stack[0]",
+      "marking #0 (int 1) from #2",
+      "skipping #2 <loop>, already marked",
+      // The roots are looked at again once the gray objects run out.
+      "skipping #2 <loop>, already marked",
+      "keeping #0",
+      "sweeping #1 (int 2)",
+      "keeping #2 <loop>",
+      "sweeping #3 (int 3)"
+    ]);
+  }
+
+  #[test]
+  fn minor_collections_pass_over_old_objects() {
+    println!("A minor collection says which objects it leaves alone.");
+
+    let lines = Rc::new(Lines::default());
+    let config = VMConfig::new().strategy(GcStrategy::Generational).tracer(lines.clone());
+    let mut vm = VM::with_config(config);
+    let old = vm.push_int(1).unwrap();
+    vm.gc_minor();
+    vm.persist(&old);
+    lines.0.borrow_mut().clear();
+
+    vm.push_int(2).unwrap();
+    vm.gc_minor();
+    assert!(*lines.0.borrow() == [
+      "skipping #0, too old for this collection",
+      "marking #1 (int 2) from stack[1]",
+      "skipping #0, too old for this collection",
+      "keeping #1"
+    ]);
+  }
+
+  #[test]
+  #[cfg(feature = "std")]
+  fn slow_motion_streams_each_step() {
+    println!("SlowMotion sends every step, label and all, down its channel.");
+
+    let (tracer, rx) = SlowMotion::channel(Duration::from_millis(1));
+    let mut vm = VM::with_config(VMConfig::new().tracer(tracer));
+    let one = vm.push_int(1).unwrap();
+    vm.set_label(&one, "one");
+    vm.push_int(2).unwrap();
+    vm.pop();
+
+    let start = std::time::Instant::now();
+    vm.gc_full();
+    let steps: Vec<Step> = rx.try_iter().collect();
+    assert!(start.elapsed() >= Duration::from_millis(steps.len() as u64));
+    assert!(steps[0].event == TraceEvent::Mark { id: 0, label: None, value: SnapshotValue::Int(1), from: Source::Stack(0) });
+    assert!(steps[0].event().label() == Some("one") && steps[0].to_string() == "marking #0 <one> (int 1) from stack[0]");
+    assert!(steps.last().unwrap().to_string() == "sweeping #1 (int 2)");
+
+    // Nobody watching, no waiting.
+    let (tracer, rx) = SlowMotion::channel(Duration::from_secs(10));
+    let mut vm = VM::with_config(VMConfig::new().tracer(tracer));
+    vm.push_int(1).unwrap();
+    drop(rx);
+    let start = std::time::Instant::now();
+    vm.gc_full();
+    assert!(start.elapsed() < Duration::from_secs(1));
+  }
+}
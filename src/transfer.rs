@@ -0,0 +1,195 @@
+// Copying values from one VM into another, for message passing between
+// VMs. A `Transfer` session remembers every object it has copied, so a
+// structure sent in several pieces, or sharing parts with one sent
+// earlier, arrives with its sharing intact and each part copied once.
+//
+// The translation table keys on the source objects, which it holds on to
+// so their addresses can't be reused, and keeps each copy alive in the
+// target VM with a persistent handle until the session ends. Constants
+// belong to no VM and are passed through as they are.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+use graph::copy_values;
+use handles::PersistentHandle;
+use {addr, Sobject, VM, VmError};
+
+/// A copying session into a VM; see `VM::transfer`.
+#[derive(Debug)]
+pub struct Transfer<'a> {
+  vm: &'a mut VM,
+  table: BTreeMap<usize, (Sobject, PersistentHandle)>
+}
+
+impl VM {
+  /// Starts a session copying values from other VMs into this one.
+  pub fn transfer(&mut self) -> Transfer<'_> {
+    Transfer { vm: self, table: BTreeMap::new() }
+  }
+}
+
+impl<'a> Transfer<'a> {
+  /// Copies `obj`, from another VM, and everything reachable from it that
+  /// this session hasn't copied yet, and pushes the copy. Copies keep the
+  /// frozen bit but not the tag. If the heap runs out partway nothing is
+  /// copied and the stack is left as it was. A freed `obj` fails with
+  /// `VmError::Freed`, and one reaching a native value that can't be
+  /// copied with `VmError::Type`.
+  pub fn copy(&mut self, obj: &Sobject) -> Result<Sobject, VmError> {
+    if obj.0.get().freed() {
+      return Err(VmError::Freed);
+    }
+    if let Some(copy) = self.lookup(obj) {
+      let copy = copy.clone();
+      self.vm.stack.push(copy.clone());
+      return Ok(copy);
+    }
+
+    let originals = self.uncopied(obj);
+    let n = self.vm.stack.len();
+
+    // As in `deep_clone`, the copies stay on the stack until all exist.
+    let mut index = BTreeMap::new();
+    for (i, orig) in originals.iter().enumerate() {
+      if let Err(e) = self.vm.push_int(0) {
+        self.vm.stack.truncate(n);
+        return Err(e);
+      }
+      index.insert(addr(orig), i);
+    }
+
+    let copies = self.vm.stack.split_off(n);
+    let vals = copy_values(&originals, &mut |obj| match index.get(&addr(obj)) {
+      Some(&i) => copies[i].clone(),
+      None => self.lookup(obj).cloned().unwrap_or_else(|| obj.clone())
+    });
+    for (copy, val) in copies.iter().zip(vals?) {
+      copy.1.borrow_mut().val = val;
+      self.vm.bind_cells(copy);
+    }
+
+    for (orig, copy) in originals.iter().zip(&copies) {
+      self.vm.write_barrier(copy);
+      if orig.0.get().frozen() {
+        self.vm.freeze(copy);
+      }
+      let handle = self.vm.persist(copy);
+      self.table.insert(addr(orig), (orig.clone(), handle));
+    }
+
+    self.vm.stack.push(copies[0].clone());
+    Ok(copies[0].clone())
+  }
+
+  /// The copy of `obj` this session made, if it made one.
+  pub fn lookup(&self, obj: &Sobject) -> Option<&Sobject> {
+    self.table.get(&addr(obj)).and_then(|&(_, handle)| self.vm.persistent(handle))
+  }
+
+  /// The VM being copied into, for working on it mid-session.
+  pub fn vm(&mut self) -> &mut VM {
+    self.vm
+  }
+
+  // `obj` and what it reaches, minus constants and anything already
+  // copied, `obj` first.
+  fn uncopied(&self, obj: &Sobject) -> Vec<Sobject> {
+    let mut seen = BTreeSet::new();
+    let mut found = Vec::new();
+    let mut todo = Vec::from([obj.clone()]);
+
+    while let Some(obj) = todo.pop() {
+      if obj.0.get().constant() || self.table.contains_key(&addr(&obj)) || !seen.insert(addr(&obj)) {
+        continue;
+      }
+
+      let at = todo.len();
+      todo.extend(obj.1.borrow().val.children());
+      todo[at..].reverse();
+      found.push(obj);
+    }
+
+    found
+  }
+}
+
+// Lets go of the copies; whatever the target VM doesn't otherwise hold is
+// collected as usual.
+impl<'a> Drop for Transfer<'a> {
+  fn drop(&mut self) {
+    for (_, (_, handle)) in core::mem::take(&mut self.table) {
+      self.vm.release(handle);
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use alloc::rc::Rc;
+  use {ConstantSpace, VMConfig};
+
+  #[test]
+  fn shared_parts_are_copied_once() {
+    println!("Pieces sent separately keep the sharing they had.");
+
+    let mut a = VM::new();
+    let shared = a.push_value(&(1u32, 2u32)).unwrap();
+    a.stack.push(shared.clone());
+    a.push_int(3).unwrap();
+    let x = a.push_pair().unwrap();
+    a.stack.push(shared.clone());
+    a.push_int(4).unwrap();
+    let y = a.push_pair().unwrap();
+    a.freeze(&y);
+
+    let mut b = VM::with_config(VMConfig::new().stress(true));
+    {
+      let mut t = b.transfer();
+      let bx = t.copy(&x).unwrap();
+      let by = t.copy(&y).unwrap();
+      assert!(Rc::ptr_eq(t.lookup(&x).unwrap(), &bx));
+      assert!(Rc::ptr_eq(&t.copy(&x).unwrap(), &bx));
+
+      let vm = t.vm();
+      assert!(vm.extract::<((u32, u32), u32)>(&by) == Ok(((1, 2), 4)));
+      assert!(Rc::ptr_eq(&vm.as_pair(&bx).unwrap().0, &vm.as_pair(&by).unwrap().0));
+      assert!(vm.is_frozen(&by) && !vm.is_frozen(&bx));
+      assert!(vm.iter_heap().count() == 7);
+      vm.stack.clear();
+    }
+
+    // The session's handles went with it.
+    assert!(b.iter_persistent().count() == 0);
+    assert!(b.gc_full() == 7);
+  }
+
+  #[test]
+  fn constants_pass_through() {
+    println!("Constants are shared, not copied, and cycles arrive whole.");
+
+    let space = ConstantSpace::new();
+    let one = space.int(1);
+    let mut a = VM::new();
+    a.push_constant(&one);
+    a.push_int(2).unwrap();
+    let p = a.push_pair().unwrap();
+    a.set_tail(&p, &p).unwrap();
+
+    let mut b = VM::new();
+    let mut t = b.transfer();
+    let bp = t.copy(&p).unwrap();
+    let (head, tail) = t.vm().as_pair(&bp).unwrap();
+    assert!(Rc::ptr_eq(&head, &one) && Rc::ptr_eq(&tail, &bp));
+    assert!(t.vm().iter_heap().count() == 1);
+    assert!(t.lookup(&one).is_none() && t.lookup(&p).is_some());
+    drop(t);
+    assert!(b.iter_roots().count() == 1 && b.iter_persistent().count() == 0);
+  }
+}
@@ -0,0 +1,194 @@
+// Checking the heap against the collector's own invariants, and paranoid
+// mode, which does so after every operation. `VM::verify` looks for:
+//
+//   - an object listed twice, listed after being freed, or reachable from
+//     a root but not listed at all, which means it was freed while still
+//     in use;
+//   - mark bits left set between collections;
+//   - young objects flagged old, old ones not at the slot their header
+//     names, or objects in the wrong generation;
+//   - an old object pointing at a young one from a clean card, which the
+//     next minor collection would miss.
+//
+// Paranoid mode also keeps a shadow heap: each object's value as of the
+// last allocation or store the VM saw. An object whose contents differ was
+// changed without going through `set_head`, `set_tail` or the write
+// barrier. Either kind of problem panics, naming the operation that
+// caused it (and any shuffle seed), rather than surfacing as a bad
+// collection later on.
+
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use alloc::collections::BTreeMap;
+
+#[cfg(feature = "std")]
+use history::SnapshotValue;
+#[cfg(feature = "std")]
+use timeline::value;
+use {addr, Phase, Sobject, VM};
+
+impl VM {
+  /// Checks the collector's invariants, returning the first one broken.
+  /// Costs a walk of the whole heap.
+  pub fn verify(&self) -> Result<(), String> {
+    self.check_invariants().map_err(|problem| self.with_seed(problem))
+  }
+
+  fn check_invariants(&self) -> Result<(), String> {
+    let name = |obj: &Sobject| format!("#{}", obj.2);
+
+    let mut listed = BTreeSet::new();
+    for obj in self.iter_objects() {
+      if !listed.insert(addr(obj)) {
+        return Err(format!("{} is in the heap twice", name(obj)));
+      }
+      if obj.0.get().freed() {
+        return Err(format!("{} was freed but is still in the heap", name(obj)));
+      }
+    }
+
+    let mut seen = BTreeSet::new();
+    let mut todo: Vec<Sobject> = self.roots().map(|(_, obj)| obj).chain(self.gray.iter().cloned()).collect();
+    while let Some(obj) = todo.pop() {
+      if obj.0.get().constant() || !seen.insert(addr(&obj)) {
+        continue;
+      }
+      if !listed.contains(&addr(&obj)) {
+        return Err(format!("{} is reachable but was freed", name(&obj)));
+      }
+      todo.extend(obj.1.borrow().val.children());
+    }
+
+    // The rest only hold between collections.
+    if self.phase != Phase::Idle {
+      return Ok(());
+    }
+
+    for obj in self.iter_objects() {
+      if obj.0.get().marked() {
+        return Err(format!("{} is still marked after the collection", name(obj)));
+      }
+    }
+
+    for (i, obj) in self.heap.iter().enumerate() {
+      let gch = obj.0.get();
+      if !gch.old() || gch.slot() != i {
+        return Err(format!("{} is in the old generation at {} but its header says {}", name(obj),
+                           i, if gch.old() { format!("slot {}", gch.slot()) } else { String::from("young") }));
+      }
+
+      let young = |o: &Sobject| !o.0.get().old() && !o.0.get().constant();
+      let card = self.cards.get(i / ::cards::CARD_SIZE).cloned().unwrap_or(false);
+      if !card && obj.1.borrow().val.children().any(|child| young(&child)) {
+        return Err(format!("old {} points at a young object from a clean card", name(obj)));
+      }
+    }
+
+    for g in 0..self.config.generations.len() {
+      let gen = if g == 0 { &self.nursery } else { &self.middle[g - 1] };
+      for obj in gen {
+        let gch = obj.0.get();
+        if gch.old() || gch.generation() != g {
+          return Err(format!("{} is in generation {} but its header says {}", name(obj), g,
+                             if gch.old() { String::from("old") } else { format!("{}", gch.generation()) }));
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  // In paranoid mode, checks the invariants and the shadow heap after
+  // `op`, panicking if either is off.
+  #[cfg(feature = "std")]
+  pub(crate) fn check_paranoid(&mut self, op: &str) {
+    if !self.paranoid {
+      return;
+    }
+
+    if let Err(problem) = self.check_invariants().and_then(|()| self.check_shadow()) {
+      panic!("paranoid check after {}: {}", op, self.with_seed(problem));
+    }
+  }
+
+  #[cfg(not(feature = "std"))]
+  #[inline(always)]
+  pub(crate) fn check_paranoid(&mut self, _op: &str) {}
+
+  // Compares the heap with the shadow, which starts as a copy of it the
+  // first time round.
+  #[cfg(feature = "std")]
+  fn check_shadow(&mut self) -> Result<(), String> {
+    let current: BTreeMap<u64, SnapshotValue> = self.iter_objects().map(|obj| (obj.2, value(obj))).collect();
+    let shadow = match self.shadow {
+      Some(ref shadow) => shadow,
+      None => {
+        self.shadow = Some(current);
+        return Ok(());
+      }
+    };
+
+    for (id, value) in &current {
+      match shadow.get(id) {
+        None => return Err(format!("#{} is in the heap but was never allocated", id)),
+        Some(v) if v != value => return Err(format!("#{} changed without a write barrier", id)),
+        Some(_) => {}
+      }
+    }
+    match shadow.keys().find(|id| !current.contains_key(id)) {
+      Some(id) => Err(format!("#{} left the heap without being swept", id)),
+      None => Ok(())
+    }
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+// Paranoid mode needs std.
+#[cfg(all(test, feature = "std"))]
+mod tests {
+  use super::*;
+  use std::panic::{self, AssertUnwindSafe};
+  use {GcStrategy, VMConfig, Vobject, Workload};
+
+  #[test]
+  fn workloads_keep_the_invariants() {
+    println!("Random workloads pass paranoid checks after every operation.");
+
+    let workload = Workload { list: 1, max_stack: 64, ops: 1_000, ..Workload::default() };
+    for strategy in [GcStrategy::MarkSweep, GcStrategy::Generational] {
+      let mut vm = VM::with_config(VMConfig::new().strategy(strategy).paranoid(true));
+      workload.run(&mut vm, |vm, _| vm.verify().unwrap()).unwrap();
+      vm.verify().unwrap();
+    }
+  }
+
+  #[test]
+  #[cfg_attr(not(any(debug_assertions, feature = "paranoid")), ignore)]
+  fn breakage_is_caught_where_it_happens() {
+    println!("Paranoid mode panics at the operation that broke the heap.");
+
+    let mut vm = VM::with_config(VMConfig::new().paranoid(true));
+    let one = vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+
+    // A store that skips the barrier.
+    if let Vobject::Pair(_, ref mut tail) = p.1.borrow_mut().val { *tail = one.clone() }
+    let result = panic::catch_unwind(AssertUnwindSafe(|| vm.push_int(3)));
+    let message = result.unwrap_err().downcast::<String>().unwrap();
+    assert!(*message == "paranoid check after push_int: #2 changed without a write barrier");
+
+    // An object dropped from the heap while still on the stack.
+    let mut vm = VM::new();
+    vm.push_int(1).unwrap();
+    vm.nursery.clear();
+    assert!(vm.verify() == Err(String::from("#0 is reachable but was freed")));
+  }
+}
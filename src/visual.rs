@@ -0,0 +1,80 @@
+// Visual mode: a picture of the stack and heap after every push, pop,
+// store and requested collection, numbered in order, so a whole program's
+// memory behaviour can be replayed frame by frame. Pictures go to stderr
+// as box drawings, or to a directory as one Graphviz file per frame.
+// Write errors are ignored, as with the GC log.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use VM;
+
+/// Where `VMConfig::visual` sends its pictures.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Visual {
+  /// `render_ascii` to stderr, under a `── n: op ──` heading.
+  Ascii,
+  /// `render_dot` to `00000.dot`, `00001.dot` and so on in this
+  /// directory, which must exist.
+  Dot(PathBuf)
+}
+
+impl VM {
+  // Draws the heap as it stands after `op`.
+  pub(crate) fn frame(&mut self, op: &str) {
+    let visual = match self.config.visual {
+      Some(ref visual) => visual,
+      None => return
+    };
+
+    let label = format!("{}: {}", self.frames, op);
+    match *visual {
+      Visual::Ascii => {
+        let _ = write!(io::stderr().lock(), "── {} ──\n{}", label, self.render_ascii());
+      }
+      Visual::Dot(ref dir) => {
+        let _ = fs::write(dir.join(format!("{:05}.dot", self.frames)), self.dot(Some(&label)));
+      }
+    }
+    self.frames += 1;
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::env;
+  use std::process;
+  use VMConfig;
+
+  #[test]
+  fn every_operation_gets_a_frame() {
+    println!("Pushes, pops, stores and collections each write a numbered picture.");
+
+    let dir = env::temp_dir().join(format!("babygc-visual-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut vm = VM::with_config(VMConfig::new().visual(Visual::Dot(dir.clone())));
+    vm.push_int(1).unwrap();
+    vm.push_int(2).unwrap();
+    let p = vm.push_pair().unwrap();
+    vm.set_tail(&p, &p).unwrap();
+    vm.pop();
+    vm.gc();
+
+    let mut names: Vec<String> = fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect();
+    names.sort();
+    assert!(names == ["00000.dot", "00001.dot", "00002.dot", "00003.dot", "00004.dot", "00005.dot"]);
+
+    let last = fs::read_to_string(dir.join("00005.dot")).unwrap();
+    assert!(last.contains("label=\"5: gc\"") && last.contains("stack [label=\"(empty)\"]"));
+    let store = fs::read_to_string(dir.join("00003.dot")).unwrap();
+    assert!(store.contains("label=\"3: store\"") && store.contains("n2:t -> n2;"));
+    fs::remove_dir_all(&dir).unwrap();
+  }
+}
@@ -0,0 +1,78 @@
+// wasm-bindgen wrapper around the VM for the browser demo in www/. The page
+// drives the VM through these calls and redraws from `heap_dump` after each.
+
+use wasm_bindgen::prelude::*;
+
+use time::Duration;
+use {GcStrategy, VMConfig, VM};
+
+#[wasm_bindgen]
+pub struct WasmVm {
+  vm: VM
+}
+
+fn js_error<E: ToString>(e: E) -> JsValue {
+  JsValue::from_str(&e.to_string())
+}
+
+#[wasm_bindgen]
+impl WasmVm {
+  /// `strategy` is "mark-sweep" or "generational".
+  #[wasm_bindgen(constructor)]
+  pub fn new(strategy: &str, threshold: usize) -> Result<WasmVm, JsValue> {
+    let strategy: GcStrategy = strategy.parse()
+      .map_err(|_| js_error(format!("unknown strategy {:?}", strategy)))?;
+    let config = VMConfig::new().strategy(strategy).threshold(threshold);
+
+    Ok(WasmVm { vm: VM::with_config(config) })
+  }
+
+  pub fn push_int(&mut self, val: u32) -> Result<(), JsValue> {
+    self.vm.push_int(val).map(|_| ()).map_err(js_error)
+  }
+
+  pub fn push_pair(&mut self) -> Result<(), JsValue> {
+    if self.vm.stack.len() < 2 {
+      return Err(js_error("push_pair needs two values on the stack"));
+    }
+
+    self.vm.push_pair().map(|_| ()).map_err(js_error)
+  }
+
+  /// Returns false if the stack was already empty.
+  pub fn pop(&mut self) -> bool {
+    self.vm.stack.pop().is_some()
+  }
+
+  /// Points the tail of the pair in stack slot `pair` at the object in
+  /// slot `target`, e.g. to build the cycles from the article's test 4.
+  pub fn set_tail(&mut self, pair: usize, target: usize) -> Result<(), JsValue> {
+    let obj = self.vm.stack.get(pair).cloned().ok_or_else(|| js_error("no such stack slot"))?;
+    let val = self.vm.stack.get(target).cloned().ok_or_else(|| js_error("no such stack slot"))?;
+
+    self.vm.set_tail(&obj, &val).map_err(js_error)
+  }
+
+  pub fn gc(&mut self) -> usize {
+    self.vm.gc()
+  }
+
+  pub fn gc_minor(&mut self) -> usize {
+    self.vm.gc_minor()
+  }
+
+  pub fn gc_full(&mut self) -> usize {
+    self.vm.gc_full()
+  }
+
+  /// Runs up to `ms` milliseconds of an incremental cycle; returns whether
+  /// it finished. Mark bits show up in `heap_dump` mid-cycle.
+  pub fn gc_step(&mut self, ms: f64) -> bool {
+    self.vm.gc_step(Duration::from_micros((ms * 1000.0) as u64))
+  }
+
+  /// See `VM::heap_dump_json`.
+  pub fn heap_dump(&self) -> String {
+    self.vm.heap_dump_json()
+  }
+}
@@ -0,0 +1,267 @@
+// Reproducible allocation patterns for comparing collectors, written as
+// space-separated `key=value` settings:
+//
+//   int=4 pair=2 list=1 mutate=1 drop=2 gc=0 depth=1..50 max_stack=256 ops=100000 seed=7
+//
+// `int` to `gc` are relative weights for what each operation does: push
+// an int, pair the top two slots, build a list of pairs with a length
+// drawn evenly from `depth`, point some pair on the stack at another slot,
+// pop, or run a full collection; parsed, they have to add up to no more
+// than a u32 holds. When the stack outgrows `max_stack` it is popped back
+// to half that. Settings left out keep their defaults, which are the mix
+// `babygc stress` runs by default. The VM has no vectors, so there is
+// nothing to weigh them with.
+
+use alloc::string::ToString;
+use core::fmt;
+use core::str::FromStr;
+
+use error::ConfigError;
+use {VM, VmError, Vobject};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+  Int,
+  Pair,
+  List,
+  Mutate,
+  Drop,
+  Gc
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Workload {
+  pub int: u32,
+  pub pair: u32,
+  pub list: u32,
+  pub mutate: u32,
+  pub drop: u32,
+  pub gc: u32,
+  /// Shortest and longest lists, in pairs.
+  pub depth: (usize, usize),
+  pub max_stack: usize,
+  pub ops: usize,
+  pub seed: u64
+}
+
+impl Default for Workload {
+  fn default() -> Workload {
+    Workload {
+      int: 4,
+      pair: 2,
+      list: 0,
+      mutate: 1,
+      drop: 2,
+      gc: 1,
+      depth: (1, 16),
+      max_stack: 256,
+      ops: 100_000,
+      seed: 1
+    }
+  }
+}
+
+// xorshift64*, so runs are the same everywhere without a dependency.
+struct Rng(u64);
+
+impl Rng {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 >> 12;
+    self.0 ^= self.0 << 25;
+    self.0 ^= self.0 >> 27;
+    self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 33
+  }
+
+  fn below(&mut self, n: usize) -> usize {
+    self.next() as usize % n
+  }
+}
+
+impl Workload {
+  /// Runs the workload's operations on `vm`, calling `after` once each is
+  /// done. Stops at the first allocation that fails, or once the host
+  /// cancels the VM.
+  pub fn run<F>(&self, vm: &mut VM, mut after: F) -> Result<(), VmError>
+    where F: FnMut(&mut VM, Action)
+  {
+    let weights = [
+      (self.int, Action::Int),
+      (self.pair, Action::Pair),
+      (self.list, Action::List),
+      (self.mutate, Action::Mutate),
+      (self.drop, Action::Drop),
+      (self.gc, Action::Gc)
+    ];
+    // Summed wide, since each weight can be as big as a u32.
+    let total: u64 = weights.iter().map(|w| u64::from(w.0)).sum();
+    if total == 0 {
+      return Ok(());
+    }
+
+    let mut rng = Rng(self.seed.max(1));
+    for i in 0..self.ops {
+      if vm.cancelled() {
+        return Err(VmError::Cancelled);
+      }
+
+      let mut pick = rng.next() % total;
+      let action = weights.iter().find(|w| {
+        let w = u64::from(w.0);
+        if pick < w { true } else { pick -= w; false }
+      }).unwrap().1;
+
+      let depth = vm.stack.len();
+      match action {
+        Action::Int => { vm.push_int(i as u32)?; }
+        Action::Pair if depth >= 2 => { vm.push_pair()?; }
+        Action::Pair => { vm.push_int(i as u32)?; }
+        Action::List => {
+          let (lo, hi) = self.depth;
+          let len = lo + rng.below(hi.saturating_sub(lo) + 1);
+          vm.push_int(0)?;
+          for n in 0..len {
+            vm.push_int(n as u32)?;
+            vm.push_pair()?;
+          }
+        }
+        Action::Mutate if depth >= 1 => {
+          let pair = vm.stack[rng.below(depth)].clone();
+          let target = vm.stack[rng.below(depth)].clone();
+          let head = rng.below(2) == 0;
+          if let Vobject::Pair(ref mut h, ref mut t) = pair.1.borrow_mut().val {
+            *(if head { h } else { t }) = target;
+          }
+          vm.write_barrier(&pair);
+        }
+        Action::Drop if depth >= 1 => { vm.pop(); }
+        Action::Gc => { vm.gc_full(); }
+        Action::Mutate | Action::Drop => {}
+      }
+
+      if vm.stack.len() > self.max_stack {
+        vm.stack.truncate(self.max_stack / 2);
+      }
+      after(vm, action);
+    }
+
+    Ok(())
+  }
+}
+
+fn bad(field: &str) -> ConfigError {
+  ConfigError { var: "workload", value: field.to_string() }
+}
+
+impl FromStr for Workload {
+  type Err = ConfigError;
+
+  fn from_str(s: &str) -> Result<Workload, ConfigError> {
+    let mut workload = Workload::default();
+
+    for field in s.split_whitespace() {
+      let (key, value) = field.split_once('=').ok_or_else(|| bad(field))?;
+      let n = || value.parse::<u64>().map_err(|_| bad(field));
+      let weight = || value.parse::<u32>().map_err(|_| bad(field));
+
+      match key {
+        "int" => workload.int = weight()?,
+        "pair" => workload.pair = weight()?,
+        "list" => workload.list = weight()?,
+        "mutate" => workload.mutate = weight()?,
+        "drop" => workload.drop = weight()?,
+        "gc" => workload.gc = weight()?,
+        "depth" => {
+          let (lo, hi) = value.split_once("..").unwrap_or((value, value));
+          match (lo.parse(), hi.parse()) {
+            (Ok(lo), Ok(hi)) if lo <= hi => workload.depth = (lo, hi),
+            _ => return Err(bad(field))
+          }
+        }
+        "max_stack" => workload.max_stack = n()? as usize,
+        "ops" => workload.ops = n()? as usize,
+        "seed" => workload.seed = n()?,
+        _ => return Err(bad(field))
+      }
+
+      // The weights have to add up to a u32 too.
+      let weights = [workload.int, workload.pair, workload.list, workload.mutate, workload.drop, workload.gc];
+      if weights.iter().try_fold(0u32, |total, &w| total.checked_add(w)).is_none() {
+        return Err(bad(field));
+      }
+    }
+
+    Ok(workload)
+  }
+}
+
+impl fmt::Display for Workload {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "int={} pair={} list={} mutate={} drop={} gc={} depth={}..{} max_stack={} ops={} seed={}",
+           self.int, self.pair, self.list, self.mutate, self.drop, self.gc,
+           self.depth.0, self.depth.1, self.max_stack, self.ops, self.seed)
+  }
+}
+
+
+//---------------------------------------------------------------------
+// Tests
+//---------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_and_prints() {
+    println!("Workloads read back what they print, and reject nonsense.");
+
+    let w: Workload = "list=3 depth=2..9 seed=7".parse().unwrap();
+    assert!(w.list == 3 && w.depth == (2, 9) && w.seed == 7 && w.int == 4);
+    assert!(w.to_string().parse::<Workload>().unwrap() == w);
+    assert!("depth=5".parse::<Workload>().unwrap().depth == (5, 5));
+    assert!("".parse::<Workload>().unwrap() == Workload::default());
+
+    for bad in &["vec=1", "int", "int=-1", "depth=9..2"] {
+      assert!(bad.parse::<Workload>().is_err());
+    }
+  }
+
+  #[test]
+  fn weights_fit_a_u32() {
+    println!("Weights too big for a u32, alone or together, are refused rather than cut down.");
+
+    let err = "int=4294967296".parse::<Workload>().unwrap_err();
+    assert!(err.value == "int=4294967296");
+    let err = "pair=0 mutate=0 drop=0 gc=0 int=4294967295 pair=1".parse::<Workload>().unwrap_err();
+    assert!(err.value == "pair=1");
+    let w: Workload = "mutate=0 drop=0 gc=0 int=4294967290 pair=1".parse().unwrap();
+    assert!(w.int == 4_294_967_290);
+
+    // Set directly, they can overflow a u32 and still run.
+    let w = Workload { int: u32::MAX, pair: u32::MAX, ops: 10, ..Workload::default() };
+    w.run(&mut VM::new(), |_, _| {}).unwrap();
+  }
+
+  #[test]
+  fn runs_are_reproducible() {
+    println!("The same workload builds the same heap every time.");
+
+    let w: Workload = "list=2 gc=0 ops=2000 max_stack=64".parse().unwrap();
+    let run = || {
+      let mut vm = VM::new();
+      let mut gcs = 0;
+      w.run(&mut vm, |vm, action| {
+        assert!(vm.iter_roots().count() <= 64);
+        if action == Action::Gc { gcs += 1 }
+      }).unwrap();
+      assert!(gcs == 0);
+      vm.gc_full();
+      vm.heap_dump_json()
+    };
+    assert!(run() == run());
+
+    let mut vm = VM::new();
+    let idle: Workload = "int=0 pair=0 mutate=0 drop=0 gc=0".parse().unwrap();
+    idle.run(&mut vm, |_, _| panic!("nothing to do")).unwrap();
+  }
+}
@@ -0,0 +1,94 @@
+// Drives a WasmVm from the buttons and redraws the heap dump after each step.
+
+import init, { WasmVm } from "./pkg/simple_gc.js";
+
+const SVG = "http://www.w3.org/2000/svg";
+const BOX_W = 70, BOX_H = 34, COLS = 9;
+
+let vm;
+
+function $(id) { return document.getElementById(id); }
+
+function status(text) { $("status").textContent = text; }
+
+function el(name, attrs, parent) {
+  const node = document.createElementNS(SVG, name);
+  for (const [k, v] of Object.entries(attrs)) node.setAttribute(k, v);
+  parent.appendChild(node);
+  return node;
+}
+
+function draw() {
+  const dump = JSON.parse(vm.heap_dump());
+  const scene = $("scene");
+  scene.replaceChildren();
+
+  // The stack runs down the left edge, the heap fills a grid to its right.
+  const pos = {};
+  dump.objects.forEach((obj, i) => {
+    pos[obj.id] = { x: 140 + (i % COLS) * (BOX_W + 14), y: 20 + Math.floor(i / COLS) * (BOX_H + 40) };
+  });
+
+  const edges = el("g", {}, scene);
+  const boxes = el("g", {}, scene);
+
+  dump.stack.forEach((id, i) => {
+    const y = 20 + i * (BOX_H + 6);
+    const slot = el("g", { class: "slot" }, boxes);
+    el("rect", { x: 10, y, width: 60, height: BOX_H }, slot);
+    el("text", { x: 18, y: y + 21 }, slot).textContent = "stack " + i;
+    if (id !== null) {
+      el("line", { class: "root", x1: 70, y1: y + BOX_H / 2, x2: pos[id].x, y2: pos[id].y + BOX_H / 2 }, edges);
+    }
+  });
+
+  for (const obj of dump.objects) {
+    const { x, y } = pos[obj.id];
+    const cls = "obj" + (obj.marked ? " marked" : "") + (obj.old ? " old" : "");
+    const g = el("g", { class: cls }, boxes);
+    el("rect", { x, y, width: BOX_W, height: BOX_H }, g);
+    el("text", { x: x + 6, y: y + 21 }, g).textContent =
+      obj.kind === "int" ? "#" + obj.id + " " + obj.value : "#" + obj.id + " pair";
+
+    if (obj.kind === "pair") {
+      for (const [child, dx] of [[obj.head, 0.25], [obj.tail, 0.75]]) {
+        if (child === null) continue;
+        const to = pos[child];
+        el("line", { x1: x + BOX_W * dx, y1: y + BOX_H, x2: to.x + BOX_W / 2, y2: to.y }, edges);
+      }
+    }
+  }
+
+  $("heap").setAttribute("height", Math.max(500, 60 + Math.ceil(dump.objects.length / COLS) * (BOX_H + 40)));
+}
+
+function run(label, f) {
+  try {
+    const result = f();
+    status(result === undefined ? label : label + ": " + result);
+  } catch (e) {
+    status(label + " failed: " + e);
+  }
+  draw();
+}
+
+function reset() {
+  vm = new WasmVm($("strategy").value, 10);
+  status("new " + $("strategy").value + " VM");
+  draw();
+}
+
+await init();
+reset();
+
+$("reset").onclick = reset;
+$("push-int").onclick = () => run("push int", () => { vm.push_int(Number($("value").value)); $("value").value++; });
+$("push-pair").onclick = () => run("push pair", () => vm.push_pair());
+$("pop").onclick = () => run("pop", () => vm.pop() ? undefined : "stack empty");
+$("cycle").onclick = () => run("cycle", () => {
+  const top = JSON.parse(vm.heap_dump()).stack.length - 1;
+  vm.set_tail(top, top);
+});
+$("step").onclick = () => run("gc step", () => vm.gc_step(0) ? "cycle complete" : "cycle in progress");
+$("minor").onclick = () => run("minor gc", () => vm.gc_minor() + " freed");
+$("full").onclick = () => run("full gc", () => vm.gc_full() + " freed");